@@ -0,0 +1,164 @@
+use std::ops::Range;
+
+use gfx_hal::{Backend, Device, MemoryTypeId};
+use gfx_hal::memory::Requirements;
+
+use relevant::Relevant;
+
+use {alignment_shift, freed, validate, MemoryAllocator, MemoryError};
+use block::{Block, RawBlock};
+use virt::VirtualAllocator;
+
+/// Allocator that sub-allocates memory adopted from outside, i.e. imported from another API
+/// or process.
+///
+/// Blocks are allocated with a first-fit free-list, see `VirtualAllocator`. Offsets and sizes
+/// of the blocks are multiples of the granularity the exporter of the memory requires.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+#[derive(Debug)]
+pub struct AdoptedAllocator<B: Backend> {
+    relevant: Relevant,
+    id: MemoryTypeId,
+    granularity: u64,
+    memory: Box<B::Memory>,
+    free: VirtualAllocator,
+}
+
+impl<B> AdoptedAllocator<B>
+where
+    B: Backend,
+{
+    /// Adopt the memory. The allocator takes ownership of the memory object.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory`: memory object to sub-allocate
+    /// - `id`: memory type of the memory object
+    /// - `range`: range of the memory object available for sub-allocation
+    /// - `granularity`: alignment of offsets and sizes required by the exporter, must be a
+    ///   power of two
+    ///
+    /// ### Returns
+    ///
+    /// Returns the memory back with `MemoryError::InvalidRange` if the range bounds are not
+    /// multiples of the granularity.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `granularity` is not a power of two.
+    pub fn adopt(
+        memory: B::Memory,
+        id: MemoryTypeId,
+        range: Range<u64>,
+        granularity: u64,
+    ) -> Result<Self, (B::Memory, MemoryError)> {
+        assert!(
+            granularity.is_power_of_two(),
+            "Granularity must be a power of two"
+        );
+        if range.start > range.end || alignment_shift(granularity, range.start) != 0
+            || alignment_shift(granularity, range.end) != 0
+        {
+            warn!(
+                "Adopted range {:?} of {:?} violates granularity {}",
+                range, id, granularity
+            );
+            return Err((memory, MemoryError::InvalidRange));
+        }
+        Ok(AdoptedAllocator {
+            relevant: Relevant,
+            id,
+            granularity,
+            memory: Box::new(memory),
+            free: VirtualAllocator::with_range(range),
+        })
+    }
+
+    /// Get memory type of the adopted memory.
+    pub fn memory_type(&self) -> MemoryTypeId {
+        self.id
+    }
+
+    /// Get granularity required by the exporter of the memory.
+    pub fn granularity(&self) -> u64 {
+        self.granularity
+    }
+
+    /// Get number of bytes currently allocated, including rounding to granularity.
+    pub fn used(&self) -> u64 {
+        self.free.used()
+    }
+
+    /// Give the memory back, i.e. to return it to the exporter.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `Err(self)` if blocks allocated from the memory are still in use.
+    pub fn release(self) -> Result<B::Memory, Self> {
+        if self.is_used() {
+            Err(self)
+        } else {
+            self.relevant.dispose();
+            Ok(*self.memory)
+        }
+    }
+}
+
+impl<B> MemoryAllocator<B> for AdoptedAllocator<B>
+where
+    B: Backend,
+{
+    type Request = ();
+    type Block = RawBlock<B>;
+
+    fn alloc(
+        &mut self,
+        _device: &B::Device,
+        _: (),
+        reqs: Requirements,
+    ) -> Result<RawBlock<B>, MemoryError> {
+        validate(&reqs, 1 << self.id.0)?;
+        let size = reqs.size + alignment_shift(self.granularity, reqs.size);
+        let alignment = reqs.alignment.max(self.granularity);
+        let range = self.free.alloc(size, alignment)?;
+        Ok(RawBlock::new(&*self.memory, range))
+    }
+
+    fn free(&mut self, device: &B::Device, block: RawBlock<B>) {
+        freed(self.try_free(device, block));
+    }
+
+    fn try_free(&mut self, _device: &B::Device, block: RawBlock<B>) -> Result<(), MemoryError> {
+        let range = block.range();
+        let owned = ::std::ptr::eq(block.memory(), &*self.memory) && range.end <= self.free.size();
+        unsafe { block.dispose() };
+        if !owned {
+            return Err(MemoryError::InvalidFree);
+        }
+        if alignment_shift(self.granularity, range.start) != 0 {
+            return Err(MemoryError::CorruptedBlock);
+        }
+        let size = range.end - range.start;
+        let end = range.start + size + alignment_shift(self.granularity, size);
+        self.free
+            .try_free(range.start..end)
+            .map_err(|_| MemoryError::InvalidFree)
+    }
+
+    fn is_used(&self) -> bool {
+        self.free.is_used()
+    }
+
+    fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        match self.release() {
+            Ok(memory) => {
+                device.free_memory(memory);
+                Ok(())
+            }
+            Err(allocator) => Err(allocator),
+        }
+    }
+}
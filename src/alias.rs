@@ -0,0 +1,75 @@
+use std::ops::Range;
+
+/// Transient resource placed in a block shared with other resources, i.e. a render target
+/// used only by some passes of a frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AliasedResource {
+    /// Range of the block the resource is bound to.
+    pub range: Range<u64>,
+
+    /// Indices of passes using the resource, from the first one to one past the last one.
+    pub passes: Range<u32>,
+}
+
+/// Resource that needs an aliasing barrier before its first use, because it shares memory
+/// with resources used before. See `alias_barriers`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AliasBarrier {
+    /// Index of the resource.
+    pub resource: usize,
+
+    /// Indices of resources last used before the resource and sharing memory with it,
+    /// the most recently used first.
+    pub previous: Vec<usize>,
+}
+
+/// Find which resources placed in the same block need aliasing barriers before their first
+/// use, so that renderers don't have to recompute overlaps of ranges and pass intervals.
+///
+/// ### Parameters:
+///
+/// - `resources`: resources placed in the same block
+///
+/// ### Returns
+///
+/// Returns hints for resources that share memory with resources used before them,
+/// ordered by resource index.
+///
+/// ### Panics
+///
+/// Panics if resources sharing memory are used by the same pass.
+pub fn alias_barriers(resources: &[AliasedResource]) -> Vec<AliasBarrier> {
+    resources
+        .iter()
+        .enumerate()
+        .filter_map(|(index, resource)| {
+            let mut previous: Vec<usize> = resources
+                .iter()
+                .enumerate()
+                .filter(|&(other, aliased)| {
+                    other != index && aliased.range.start < resource.range.end
+                        && resource.range.start < aliased.range.end
+                })
+                .filter(|&(other, aliased)| {
+                    assert!(
+                        aliased.passes.end <= resource.passes.start
+                            || resource.passes.end <= aliased.passes.start,
+                        "Resources {} and {} share memory and are used by the same pass",
+                        index,
+                        other
+                    );
+                    aliased.passes.end <= resource.passes.start
+                })
+                .map(|(other, _)| other)
+                .collect();
+            if previous.is_empty() {
+                return None;
+            }
+            previous.sort_by_key(|&other| !resources[other].passes.end);
+            Some(AliasBarrier {
+                resource: index,
+                previous,
+            })
+        })
+        .collect()
+}
@@ -5,11 +5,18 @@ use std::ops::Range;
 use gfx_hal::{Backend, MemoryTypeId};
 use gfx_hal::memory::Requirements;
 
-use {alignment_shift, MemoryAllocator, MemoryError, MemorySubAllocator};
+use {alignment_shift, freed, validate, MemoryAllocator, MemoryError, MemorySubAllocator};
 use block::{Block, RawBlock};
 
 /// Linear allocator that can be used for short-lived objects.
 ///
+/// Arenas are returned to the owner in allocation order, so one long-lived block pins all
+/// arenas allocated after its own. See `set_out_of_order_frees` for workloads where
+/// lifetimes are not roughly FIFO.
+///
+/// With epoch retirement enabled, arenas without blocks in use are kept until the device is
+/// done with the epoch they were retired in. See `set_epoch_retirement`.
+///
 /// ### Type parameters:
 ///
 /// - `B`: hal `Backend`
@@ -19,8 +26,19 @@ pub struct ArenaAllocator<T> {
     id: MemoryTypeId,
     arena_size: u64,
     freed: u64,
+    out_of_order: bool,
+    epochs: bool,
+    epoch: u64,
+    /// Arenas waiting for completion of the epoch they were retired in.
+    retiring: VecDeque<(u64, T)>,
     hot: Option<ArenaNode<T>>,
-    nodes: VecDeque<ArenaNode<T>>,
+    /// Retired arenas. Arenas returned out of order leave `None` in their slots,
+    /// so that indices of the following arenas are preserved.
+    nodes: VecDeque<Option<ArenaNode<T>>>,
+    /// Arenas recycled by `reset`, reused before allocating new ones from the owner.
+    spare: Vec<T>,
+    /// Sizes of arenas allocated from the owner since the last `take_growths`.
+    growths: Vec<u64>,
 }
 
 impl<T> ArenaAllocator<T> {
@@ -30,20 +48,33 @@ impl<T> ArenaAllocator<T> {
     ///
     /// - `arena_size`: size in bytes of the arena
     /// - `id`: hal memory type
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `arena_size` is `0`.
     pub fn new(arena_size: u64, id: MemoryTypeId) -> Self {
+        assert_ne!(arena_size, 0, "Arena size must not be 0");
         ArenaAllocator {
             id,
             arena_size,
             freed: 0,
+            out_of_order: false,
+            epochs: false,
+            epoch: 0,
+            retiring: VecDeque::new(),
             hot: None,
             nodes: VecDeque::new(),
+            spare: Vec::new(),
+            growths: Vec::new(),
         }
     }
 
     /// Check if any of the blocks allocated by this allocator are still in use.
     /// If this function returns `false`, the allocator can be `dispose`d.
+    ///
+    /// Arenas waiting for their epoch to complete count as used, see `maintain`.
     pub fn is_used(&self) -> bool {
-        !self.nodes.is_empty()
+        !self.nodes.is_empty() || !self.retiring.is_empty()
             || self.hot
                 .as_ref()
                 .map(|node| node.is_used())
@@ -55,34 +86,311 @@ impl<T> ArenaAllocator<T> {
         self.id
     }
 
+    /// Get number of blocks in use.
+    pub fn live_blocks(&self) -> usize {
+        self.hot
+            .iter()
+            .chain(self.nodes.iter().filter_map(Option::as_ref))
+            .map(|node| node.live)
+            .sum()
+    }
+
+    /// Take sizes of arenas allocated from the owner since the last call, i.e. to attribute
+    /// hitches to the allocator. Arenas reused from `reset` are not included.
+    pub fn take_growths(&mut self) -> Vec<u64> {
+        self.growths.drain(..).collect()
+    }
+
     /// Get size of the arena
     pub fn arena_size(&self) -> u64 {
         self.arena_size
     }
 
+    /// Set size of arenas allocated after the call.
+    /// Arenas kept for reuse by `reset` are returned to the owner once a new arena is needed.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `arena_size` is `0`.
+    pub fn set_arena_size(&mut self, arena_size: u64) {
+        assert_ne!(arena_size, 0, "Arena size must not be 0");
+        self.arena_size = arena_size;
+    }
+
+    /// Enable or disable out-of-order frees.
+    ///
+    /// When enabled, retired arenas are returned to the owner as soon as their last block is
+    /// freed, instead of waiting for all arenas allocated before them. A long-lived straggler
+    /// then pins only its own arena. Stragglers can be found with `pinned_arenas` and
+    /// migrated by the user to unpin the arena completely.
+    ///
+    /// Blocks allocated before the switch stay valid in either mode. Arenas that became unused
+    /// before out-of-order frees were enabled are returned by the next free or `maintain`.
+    pub fn set_out_of_order_frees(&mut self, out_of_order: bool) {
+        self.out_of_order = out_of_order;
+    }
+
+    /// Check if out-of-order frees are enabled.
+    pub fn out_of_order_frees(&self) -> bool {
+        self.out_of_order
+    }
+
+    /// Enable or disable epoch retirement.
+    ///
+    /// When enabled, arenas without blocks in use are not returned to the owner right away,
+    /// but tagged with the current epoch, see `set_epoch`, and returned by `maintain` once
+    /// the device is done with that epoch. This prevents freeing memory the device still
+    /// reads when blocks are freed right after commands using them are submitted.
+    pub fn set_epoch_retirement(&mut self, enabled: bool) {
+        self.epochs = enabled;
+    }
+
+    /// Set current epoch, i.e. index of the frame being recorded.
+    /// Epochs must not decrease.
+    pub fn set_epoch(&mut self, epoch: u64) {
+        debug_assert!(epoch >= self.epoch);
+        self.epoch = epoch;
+    }
+
+    /// Get current epoch.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Get number of arenas waiting for their epoch to complete.
+    pub fn retiring_arenas(&self) -> usize {
+        self.retiring.len()
+    }
+
+    /// Return arenas retired in completed epochs to the owner.
+    /// Unused arenas still waiting for arenas allocated before them are retired first,
+    /// if out-of-order frees are enabled.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the arenas
+    /// - `device`: same device that was used to allocate the arenas
+    /// - `completed`: last epoch the device is done with, i.e. of the last signalled fence
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of arenas returned to the owner.
+    pub fn maintain<B, A>(&mut self, owner: &mut A, device: &B::Device, completed: u64) -> usize
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        if self.out_of_order {
+            self.cleanup(owner, device);
+        }
+        let mut returned = 0;
+        while self.retiring
+            .front()
+            .map_or(false, |&(epoch, _)| epoch <= completed)
+        {
+            let (_, block) = self.retiring.pop_front().expect("Checked above");
+            owner.free(device, block);
+            returned += 1;
+        }
+        returned
+    }
+
+    /// Recycle all arenas for reuse without returning them to the owner, i.e. at the start of
+    /// a frame when lifetimes of blocks are managed externally.
+    ///
+    /// Arenas waiting for their epoch are recycled too, so the device must be done with them.
+    /// Arenas of other size than the current arena size are returned to the owner.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the arenas
+    /// - `device`: same device that was used to allocate the arenas
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of arenas available for reuse.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if blocks allocated from the arenas are still in use.
+    pub fn reset<B, A>(&mut self, owner: &mut A, device: &B::Device) -> usize
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        assert!(
+            self.nodes
+                .iter()
+                .all(|node| node.as_ref().map_or(true, |node| !node.is_used()))
+                && self.hot.as_ref().map_or(true, |hot| !hot.is_used()),
+            "Arenas of {:?} are reset while blocks are in use",
+            self.id
+        );
+        // Keep indices of arenas allocated afterwards unique.
+        self.freed += self.nodes.len() as u64;
+        let mut blocks: Vec<T> = self.nodes
+            .drain(..)
+            .filter_map(|node| node)
+            .map(|node| node.block)
+            .chain(self.retiring.drain(..).map(|(_, block)| block))
+            .collect();
+        if let Some(hot) = self.hot.take() {
+            if hot.block.size() == self.arena_size {
+                self.hot = Some(ArenaNode::new(hot.block));
+            } else {
+                blocks.push(hot.block);
+            }
+        }
+        for block in blocks {
+            if block.size() == self.arena_size {
+                self.spare.push(block);
+            } else {
+                owner.free(device, block);
+            }
+        }
+        self.spare.len() + self.hot.iter().count()
+    }
+
+    /// Get live block count of each retired arena that still has blocks in use.
+    pub fn pinned_arenas(&self) -> Vec<usize> {
+        self.nodes
+            .iter()
+            .filter_map(|node| node.as_ref())
+            .filter(|node| node.is_used())
+            .map(|node| node.live)
+            .collect()
+    }
+
+    /// Check if the hot arena is nearly exhausted.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `watermark`: fraction of the arena size left free at which the arena is considered
+    ///   nearly exhausted
+    ///
+    /// ### Returns
+    ///
+    /// Returns size of the next arena if the hot arena is nearly exhausted.
+    pub fn growth_hint<B>(&self, watermark: f32) -> Option<u64>
+    where
+        B: Backend,
+        T: Block<B>,
+    {
+        let hot = self.hot.as_ref()?;
+        let left = hot.block.size() - hot.used;
+        if left as f64 <= watermark as f64 * self.arena_size as f64 {
+            Some(self.arena_size)
+        } else {
+            None
+        }
+    }
+
+    /// Estimate how many bytes would be requested from the owner to allocate blocks
+    /// for all `reqs` one after another, starting from an empty allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `reqs`: requirements of the blocks
+    pub fn estimate_footprint(&self, reqs: &[Requirements]) -> u64 {
+        let mut total = 0;
+        let mut arena = 0;
+        let mut used = 0;
+        for reqs in reqs {
+            let size = reqs.size.saturating_add(alignment_shift(reqs.alignment, used));
+            if arena.saturating_sub(used) < size {
+                arena = (reqs.size.saturating_sub(1) / self.arena_size + 1)
+                    .saturating_mul(self.arena_size);
+                used = reqs.size;
+                total = total.saturating_add(arena);
+            } else {
+                used += size;
+            }
+        }
+        total
+    }
+
+    /// Dispose of this allocator, returning all memory to the owner, even if blocks allocated
+    /// from it are still in use.
+    ///
+    /// Blocks that are still in use are invalidated. They must not be used or freed afterwards
+    /// and should be leaked with `std::mem::forget`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the inner memory blocks
+    /// - `device`: must be the same device all allocations have been made against
+    pub fn force_dispose<B, A>(self, owner: &mut A, device: &B::Device)
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        for node in self.nodes.into_iter().filter_map(|node| node).chain(self.hot) {
+            owner.free(device, node.block);
+        }
+        for (_, block) in self.retiring {
+            owner.free(device, block);
+        }
+        for block in self.spare {
+            owner.free(device, block);
+        }
+    }
+
+    /// Return arena without blocks in use to the owner, or queue it until the current epoch
+    /// is completed.
+    fn retire<B, A>(&mut self, owner: &mut A, device: &B::Device, node: ArenaNode<T>)
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        if self.epochs {
+            debug_assert!(!node.is_used());
+            self.retiring.push_back((self.epoch, node.block));
+        } else {
+            node.dispose(owner, device).expect("Arena is not used");
+        }
+    }
+
     fn cleanup<B, A>(&mut self, owner: &mut A, device: &B::Device)
     where
         B: Backend,
         T: Block<B>,
         A: MemoryAllocator<B, Block = T>,
     {
+        let mut unused = Vec::new();
+        if self.out_of_order {
+            for slot in self.nodes.iter_mut().skip(1) {
+                if slot.as_ref().map_or(false, |node| !node.is_used()) {
+                    unused.push(slot.take().expect("Checked above"));
+                }
+            }
+        }
+
         while self.nodes
             .front()
-            .map(|node| !node.is_used())
+            .map(|node| node.as_ref().map_or(true, |node| !node.is_used()))
             .unwrap_or(false)
         {
-            if let Some(node) = self.nodes.pop_front() {
-                if let Some(ref mut hot) = self.hot {
-                    if hot.is_used() {
-                        self.nodes.push_back(replace(hot, node));
-                    } else {
-                        // No need to replace.
-                        node.dispose(owner, device).unwrap();
-                    }
+            if let Some(Some(node)) = self.nodes.pop_front() {
+                // Arenas retired by epoch must not be reused.
+                let swap = !self.epochs && self.hot.as_ref().map_or(false, |hot| hot.is_used());
+                if swap {
+                    let hot = self.hot.as_mut().expect("Checked above");
+                    self.nodes.push_back(Some(replace(hot, node)));
+                } else {
+                    // No need to replace.
+                    unused.push(node);
                 }
             }
             self.freed += 1;
         }
+
+        for node in unused {
+            self.retire(owner, device, node);
+        }
     }
 
     fn allocate_node<B, A>(
@@ -97,13 +405,33 @@ impl<T> ArenaAllocator<T> {
         T: Block<B>,
         A: MemoryAllocator<B, Block = T>,
     {
-        let arena_size = ((reqs.size - 1) / self.arena_size + 1) * self.arena_size;
+        let arena_size = ((reqs.size - 1) / self.arena_size + 1)
+            .checked_mul(self.arena_size)
+            .ok_or(MemoryError::OutOfMemory)?;
+        if arena_size == self.arena_size {
+            // Spares kept by `reset` before the arena size was changed are too small or
+            // wasteful, they are returned to the owner.
+            let (spare, stale): (Vec<T>, Vec<T>) = replace(&mut self.spare, Vec::new())
+                .into_iter()
+                .partition(|block| block.size() == arena_size);
+            self.spare = spare;
+            for block in stale {
+                owner.free(device, block);
+            }
+            let aligned = self.spare
+                .iter()
+                .position(|block| alignment_shift(reqs.alignment, block.range().start) == 0);
+            if let Some(index) = aligned {
+                return Ok(ArenaNode::new(self.spare.swap_remove(index)));
+            }
+        }
         let arena_requirements = Requirements {
             type_mask: 1 << self.id.0,
             size: arena_size,
             alignment: reqs.alignment,
         };
         let arena_block = owner.alloc(device, request, arena_requirements)?;
+        self.growths.push(arena_size);
         Ok(ArenaNode::new(arena_block))
     }
 }
@@ -124,9 +452,7 @@ where
         request: O::Request,
         reqs: Requirements,
     ) -> Result<ArenaBlock<B>, MemoryError> {
-        if (1 << self.id.0) & reqs.type_mask == 0 {
-            return Err(MemoryError::NoCompatibleMemoryType);
-        }
+        validate(&reqs, 1 << self.id.0)?;
         let index = self.freed + self.nodes.len() as u64;
         if let Some(ref mut hot) = self.hot.as_mut() {
             match hot.alloc(reqs) {
@@ -138,9 +464,10 @@ where
         let mut node = self.allocate_node(owner, device, request, reqs)?;
         let block = node.alloc(reqs).unwrap();
         if let Some(hot) = replace(&mut self.hot, Some(node)) {
-            match hot.dispose(owner, device) {
-                Ok(()) => {}
-                Err(hot) => self.nodes.push_back(hot),
+            if hot.is_used() {
+                self.nodes.push_back(Some(hot));
+            } else {
+                self.retire(owner, device, hot);
             }
         };
         let index = self.freed + self.nodes.len() as u64;
@@ -148,7 +475,33 @@ where
     }
 
     fn free(&mut self, owner: &mut O, device: &B::Device, block: ArenaBlock<B>) {
+        freed(self.try_free(owner, device, block));
+    }
+
+    fn try_free(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        block: ArenaBlock<B>,
+    ) -> Result<(), MemoryError> {
         let ArenaBlock(block, index) = block;
+        let contains = if index < self.freed {
+            false
+        } else {
+            match (index - self.freed) as usize {
+                index if index == self.nodes.len() => self.hot
+                    .as_ref()
+                    .map_or(false, |hot| hot.block.contains(&block)),
+                index if index < self.nodes.len() => self.nodes[index]
+                    .as_ref()
+                    .map_or(false, |node| node.block.contains(&block)),
+                _ => false,
+            }
+        };
+        if !contains {
+            unsafe { block.dispose() };
+            return Err(MemoryError::InvalidFree);
+        }
         let index = (index - self.freed) as usize;
 
         match self.nodes.len() {
@@ -156,11 +509,15 @@ where
                 self.hot.as_mut().unwrap().free(block);
             }
             len if len > index => {
-                self.nodes[index].free(block);
+                self.nodes[index]
+                    .as_mut()
+                    .expect("Checked above")
+                    .free(block);
                 self.cleanup(owner, device);
             }
             _ => unreachable!(),
         }
+        Ok(())
     }
 
     fn dispose(mut self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
@@ -170,6 +527,9 @@ where
             if let Some(hot) = self.hot.take() {
                 hot.dispose(owner, device).expect("Already checked");
             }
+            for block in self.spare.drain(..) {
+                owner.free(device, block);
+            }
             Ok(())
         }
     }
@@ -179,6 +539,8 @@ where
 struct ArenaNode<T> {
     used: u64,
     freed: u64,
+    /// Number of blocks in use.
+    live: usize,
     block: T,
 }
 
@@ -187,6 +549,7 @@ impl<T> ArenaNode<T> {
         ArenaNode {
             used: 0,
             freed: 0,
+            live: 0,
             block,
         }
     }
@@ -196,12 +559,13 @@ impl<T> ArenaNode<T> {
         T: Block<B>,
     {
         let offset = self.block.range().start + self.used;
-        let total_size = reqs.size + alignment_shift(reqs.alignment, offset);
+        let total_size = reqs.size.checked_add(alignment_shift(reqs.alignment, offset))?;
 
         if self.block.size() - self.used < total_size {
             None
         } else {
             self.used += total_size;
+            self.live += 1;
             Some(RawBlock::new(
                 self.block.memory(),
                 offset..total_size + offset,
@@ -215,6 +579,7 @@ impl<T> ArenaNode<T> {
     {
         assert!(self.block.contains(&block));
         self.freed += block.size();
+        self.live -= 1;
         unsafe { block.dispose() }
     }
 
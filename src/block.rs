@@ -5,6 +5,9 @@ use gfx_hal::Backend;
 
 use relevant::Relevant;
 
+use MemoryError;
+use mapping::{Coherence, MappedRange, MappedSlice, Pod};
+
 /// Trait for types that represent a block (`Range`) of `Memory`.
 pub trait Block<B: Backend>: Send + Sync + Debug {
     /// `Memory` instance of the block.
@@ -36,6 +39,114 @@ pub trait Block<B: Backend>: Send + Sync + Debug {
         eq(self.memory(), other.memory()) && self.range().start <= other.range().start
             && self.range().end >= other.range().end
     }
+
+    /// Map a range of the block as a slice of `T`.
+    ///
+    /// Memory is flushed and unmapped when the slice is dropped. The flushed range is not
+    /// widened to the non-coherent atom size, use `mapped_slice_with` unless the block is
+    /// aligned to it.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `range`: range to map, relative to the block start
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidRange` if the range is empty or out of the block bounds,
+    /// its size is not a multiple of `T` size, or the mapped pointer is not aligned for `T`.
+    /// Returns `MemoryError::MappingFailed` if the memory can't be mapped.
+    fn mapped_slice<'a, T>(
+        &'a mut self,
+        device: &'a B::Device,
+        range: Range<u64>,
+    ) -> Result<MappedSlice<'a, B, T>, MemoryError>
+    where
+        Self: Sized,
+        T: Pod,
+    {
+        MappedSlice::new(device, self, range, Coherence::NonCoherent { atom: 1 })
+    }
+
+    /// Map a range of the block as a slice of `T`, flushing it as the coherence requires.
+    /// See `SmartAllocator::coherence`.
+    ///
+    /// Memory is flushed, unless it is coherent, and unmapped when the slice is dropped.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `range`: range to map, relative to the block start
+    /// - `coherence`: coherence of the memory of the block
+    ///
+    /// ### Returns
+    ///
+    /// Returns errors of `mapped_slice`.
+    fn mapped_slice_with<'a, T>(
+        &'a mut self,
+        device: &'a B::Device,
+        range: Range<u64>,
+        coherence: Coherence,
+    ) -> Result<MappedSlice<'a, B, T>, MemoryError>
+    where
+        Self: Sized,
+        T: Pod,
+    {
+        MappedSlice::new(device, self, range, coherence)
+    }
+
+    /// Map a range of the block.
+    ///
+    /// The returned guard invalidates the range before reads and flushes it after writes.
+    /// Ranges are not widened to the non-coherent atom size, use `mapped_range_with` unless
+    /// the block is aligned to it.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `range`: range to map, relative to the block start
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidRange` if the range is empty or out of the block bounds.
+    /// Returns `MemoryError::MappingFailed` if the memory can't be mapped.
+    fn mapped_range<'a>(
+        &'a mut self,
+        device: &'a B::Device,
+        range: Range<u64>,
+    ) -> Result<MappedRange<'a, B>, MemoryError>
+    where
+        Self: Sized,
+    {
+        MappedRange::new(device, self, range, Coherence::NonCoherent { atom: 1 })
+    }
+
+    /// Map a range of the block, flushing and invalidating it as the coherence requires.
+    /// See `SmartAllocator::coherence`.
+    ///
+    /// The returned guard invalidates the range before reads and flushes it after writes,
+    /// unless the memory is coherent.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `range`: range to map, relative to the block start
+    /// - `coherence`: coherence of the memory of the block
+    ///
+    /// ### Returns
+    ///
+    /// Returns errors of `mapped_range`.
+    fn mapped_range_with<'a>(
+        &'a mut self,
+        device: &'a B::Device,
+        range: Range<u64>,
+        coherence: Coherence,
+    ) -> Result<MappedRange<'a, B>, MemoryError>
+    where
+        Self: Sized,
+    {
+        MappedRange::new(device, self, range, coherence)
+    }
 }
 
 /// Tagged block of memory.
@@ -54,15 +165,18 @@ pub struct RawBlock<B: Backend> {
     memory: *const B::Memory,
 }
 
+// `RawBlock` is a shared reference to the memory object in disguise.
 unsafe impl<B> Send for RawBlock<B>
 where
     B: Backend,
+    B::Memory: Sync,
 {
 }
 
 unsafe impl<B> Sync for RawBlock<B>
 where
     B: Backend,
+    B::Memory: Sync,
 {
 }
 
@@ -139,3 +253,21 @@ where
         self.0.range()
     }
 }
+
+/// Compile-time check that blocks can be moved to and shared with other threads.
+#[allow(dead_code)]
+fn blocks_are_send_sync<B: Backend>() {
+    use {Allocation, ArenaBlock, ChunkedBlock, CombinedBlock, FreeListBlock, SmartBlock,
+         SparseBlock};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<RawBlock<B>>();
+    assert_send_sync::<ArenaBlock<B>>();
+    assert_send_sync::<ChunkedBlock<B>>();
+    assert_send_sync::<FreeListBlock<B>>();
+    assert_send_sync::<SparseBlock<B>>();
+    assert_send_sync::<CombinedBlock<B>>();
+    assert_send_sync::<SmartBlock<B>>();
+    assert_send_sync::<Allocation<B>>();
+}
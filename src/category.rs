@@ -0,0 +1,88 @@
+use stats::CategoryStats;
+
+/// Category paths known to the allocator and their usage.
+#[derive(Debug, Default)]
+pub(crate) struct Categories {
+    stack: Vec<usize>,
+    entries: Vec<Category>,
+}
+
+#[derive(Debug)]
+struct Category {
+    path: String,
+    used: u64,
+    blocks: usize,
+}
+
+impl Categories {
+    /// Enter a child category of the current one.
+    pub(crate) fn push(&mut self, name: &str) {
+        assert!(!name.is_empty() && !name.contains('/'));
+        let path = match self.current() {
+            Some(parent) => format!("{}/{}", self.entries[parent].path, name),
+            None => name.to_owned(),
+        };
+        let index = match self.entries.iter().position(|entry| entry.path == path) {
+            Some(index) => index,
+            None => {
+                self.entries.push(Category {
+                    path,
+                    used: 0,
+                    blocks: 0,
+                });
+                self.entries.len() - 1
+            }
+        };
+        self.stack.push(index);
+    }
+
+    /// Leave the current category.
+    pub(crate) fn pop(&mut self) {
+        self.stack.pop().expect("No category to pop");
+    }
+
+    /// Get the current category.
+    pub(crate) fn current(&self) -> Option<usize> {
+        self.stack.last().cloned()
+    }
+
+    /// Get path of the category.
+    pub(crate) fn path(&self, index: usize) -> &str {
+        &self.entries[index].path
+    }
+
+    pub(crate) fn alloc(&mut self, index: usize, size: u64) {
+        self.entries[index].used += size;
+        self.entries[index].blocks += 1;
+    }
+
+    pub(crate) fn free(&mut self, index: usize, size: u64) {
+        self.entries[index].used -= size;
+        self.entries[index].blocks -= 1;
+    }
+
+    /// Collect usage of all categories.
+    /// Totals of the category include usage of all its descendants.
+    pub(crate) fn stats(&self) -> Vec<CategoryStats> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let prefix = format!("{}/", entry.path);
+                let mut stats = CategoryStats {
+                    path: entry.path.clone(),
+                    used: entry.used,
+                    blocks: entry.blocks,
+                    total_used: 0,
+                    total_blocks: 0,
+                };
+                for other in &self.entries {
+                    if other.path == entry.path || other.path.starts_with(&prefix) {
+                        stats.total_used += other.used;
+                        stats.total_blocks += other.blocks;
+                    }
+                }
+                stats
+            })
+            .collect()
+    }
+}
@@ -1,17 +1,108 @@
 use std::cmp::{max, min};
-use std::collections::VecDeque;
+use std::collections::BTreeMap;
+use std::mem::replace;
 use std::ops::Range;
 
 use gfx_hal::{Backend, MemoryTypeId};
 use gfx_hal::memory::Requirements;
 
-use {alignment_shift, MemoryAllocator, MemoryError, MemorySubAllocator};
+use {alignment_shift, freed, validate, MemoryAllocator, MemoryError, MemorySubAllocator};
 use block::{Block, RawBlock};
+use stats::SizeClassStats;
 
+/// Number of adjacent blocks of a smaller size class a spanning allocation takes.
+/// See `ChunkedAllocator::set_spanning`.
+const SPAN_BLOCKS: usize = 3;
+
+/// Chunk of memory allocated from the owner, split into equal sized blocks.
 #[derive(Debug)]
-struct FreeBlock {
-    block_index: usize,
-    chunk_index: u64,
+struct Chunk<T> {
+    block: T,
+    /// Occupancy bitset, set bits stand for blocks in use.
+    used: Vec<u64>,
+    /// Number of blocks in use.
+    count: usize,
+    /// Requested sizes of allocations in use, stored at their first block.
+    requested: Vec<u64>,
+    /// Number of blocks of allocations in use, stored at their first block.
+    spans: Vec<u8>,
+    /// Epoch a block of the chunk was last allocated or freed in.
+    last_used: u64,
+}
+
+impl<T> Chunk<T> {
+    fn new(block: T, blocks: usize, epoch: u64) -> Self {
+        Chunk {
+            block,
+            used: vec![0; (blocks + 63) / 64],
+            count: 0,
+            requested: vec![0; blocks],
+            spans: vec![0; blocks],
+            last_used: epoch,
+        }
+    }
+
+    fn is_taken(&self, index: usize) -> bool {
+        self.used[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Find `len` adjacent free blocks and mark them used.
+    fn take_span(&mut self, blocks: usize, len: usize) -> Option<usize> {
+        if len == 1 {
+            return self.take(blocks);
+        }
+        let mut run = 0;
+        for index in 0..blocks {
+            if self.is_taken(index) {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run == len {
+                let start = index + 1 - len;
+                for index in start..index + 1 {
+                    self.used[index / 64] |= 1 << (index % 64);
+                }
+                self.count += len;
+                return Some(start);
+            }
+        }
+        None
+    }
+
+    /// Find free block and mark it used.
+    fn take(&mut self, blocks: usize) -> Option<usize> {
+        for (index, word) in self.used.iter_mut().enumerate() {
+            let valid = match blocks - index * 64 {
+                bits if bits >= 64 => !0,
+                bits => (1u64 << bits) - 1,
+            };
+            let free = !*word & valid;
+            if free != 0 {
+                let bit = free.trailing_zeros() as usize;
+                *word |= 1 << bit;
+                self.count += 1;
+                return Some(index * 64 + bit);
+            }
+        }
+        None
+    }
+
+    /// Mark `len` blocks allocated together free.
+    /// Returns `false` if the blocks were not allocated together.
+    fn give(&mut self, index: usize, len: usize) -> bool {
+        if index + len > self.spans.len() || self.spans[index] as usize != len
+            || !(index..index + len).all(|index| self.is_taken(index))
+        {
+            return false;
+        }
+        for index in index..index + len {
+            self.used[index / 64] &= !(1 << (index % 64));
+        }
+        self.spans[index] = 0;
+        self.count -= len;
+        true
+    }
 }
 
 #[derive(Debug)]
@@ -19,33 +110,96 @@ struct ChunkedNode<T> {
     id: MemoryTypeId,
     block_size: u64,
     chunk_size: u64,
-    free: VecDeque<FreeBlock>,
-    blocks: Vec<T>,
+    /// Chunks allocated from the owner. Slots of trimmed chunks are reused by `grow`.
+    chunks: Vec<Option<Chunk<T>>>,
+    /// Number of blocks in use.
+    used: usize,
+    /// Bytes requested for blocks in use.
+    requested: u64,
+    /// Number of chunks allocated from the owner so far.
+    growths: usize,
+    /// Number of chunks allocated from the owner since the last `take_growths`.
+    pending: usize,
+    epoch: u64,
 }
 
 impl<T> ChunkedNode<T> {
-    fn new(chunk_size: u64, block_size: u64, id: MemoryTypeId) -> Self {
+    fn new(chunk_size: u64, block_size: u64, id: MemoryTypeId, epoch: u64) -> Self {
         ChunkedNode {
             id,
             chunk_size,
             block_size,
-            free: VecDeque::new(),
-            blocks: Vec::new(),
+            chunks: Vec::new(),
+            used: 0,
+            requested: 0,
+            growths: 0,
+            pending: 0,
+            epoch,
         }
     }
 
     fn is_used(&self) -> bool {
-        self.count() != self.free.len()
-    }
-
-    fn count(&self) -> usize {
-        self.blocks.len() * self.blocks_per_chunk()
+        self.used != 0
     }
 
     fn blocks_per_chunk(&self) -> usize {
         (self.chunk_size / self.block_size) as usize
     }
 
+    /// Get number of allocated chunks.
+    fn chunk_count(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_some()).count()
+    }
+
+    /// Get number of chunks without blocks in use.
+    fn free_chunks(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.as_ref().map_or(false, |chunk| chunk.count == 0))
+            .count()
+    }
+
+    fn stats(&self) -> SizeClassStats {
+        let chunks = self.chunks.iter().filter(|chunk| chunk.is_some()).count();
+        let free = self.free_chunks();
+        let epoch = self.epoch;
+        let cold_frames = self.chunks
+            .iter()
+            .filter_map(|chunk| chunk.as_ref())
+            .filter(|chunk| chunk.count == 0)
+            .map(|chunk| epoch.saturating_sub(chunk.last_used))
+            .max()
+            .unwrap_or(0);
+        SizeClassStats {
+            block_size: self.block_size,
+            blocks: self.used,
+            capacity: chunks * self.blocks_per_chunk(),
+            chunks_used: chunks - free,
+            chunks_free: free,
+            wasted: self.used as u64 * self.block_size - self.requested,
+            growths: self.growths,
+            cold_frames,
+        }
+    }
+
+    /// Return chunks without blocks in use to the owner.
+    fn trim<B, A>(&mut self, owner: &mut A, device: &B::Device) -> usize
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        let mut trimmed = 0;
+        for slot in &mut self.chunks {
+            if slot.as_ref().map_or(false, |chunk| chunk.count == 0) {
+                let chunk = slot.take().expect("Checked above");
+                owner.free(device, chunk.block);
+                trimmed += 1;
+            }
+        }
+        trimmed
+    }
+
     fn grow<B, A>(
         &mut self,
         owner: &mut A,
@@ -66,30 +220,72 @@ impl<T> ChunkedNode<T> {
         assert_eq!(0, alignment_shift(reqs.alignment, block.range().start));
         assert!(block.size() >= self.chunk_size);
 
-        let blocks_per_chunk = self.blocks_per_chunk();
-        let block_index = self.blocks.len();
-        self.free.extend((0..blocks_per_chunk).map(|i| FreeBlock {
-            block_index,
-            chunk_index: i as u64,
-        }));
-        self.blocks.push(block);
-
+        let chunk = Some(Chunk::new(block, self.blocks_per_chunk(), self.epoch));
+        match self.chunks.iter().position(Option::is_none) {
+            Some(index) => self.chunks[index] = chunk,
+            None => self.chunks.push(chunk),
+        }
+        self.growths += 1;
+        self.pending += 1;
         Ok(())
     }
 
-    fn alloc_no_grow<B>(&mut self) -> Option<ChunkedBlock<B>>
+    /// Allocate `len` adjacent blocks as one block from already allocated chunks.
+    fn alloc_no_grow<B>(&mut self, size: u64, len: usize) -> Option<ChunkedBlock<B>>
     where
         B: Backend,
         T: Block<B>,
     {
-        self.free.pop_front().map(|free_block| {
-            let offset = free_block.chunk_index * self.chunk_size;
-            let block = RawBlock::new(
-                self.blocks[free_block.block_index].memory(),
-                offset..self.chunk_size + offset,
-            );
-            ChunkedBlock(block, free_block.block_index)
-        })
+        let blocks = self.blocks_per_chunk();
+        let block_size = self.block_size;
+        let epoch = self.epoch;
+        for (chunk_index, slot) in self.chunks.iter_mut().enumerate() {
+            if let Some(ref mut chunk) = *slot {
+                if let Some(index) = chunk.take_span(blocks, len) {
+                    chunk.requested[index] = size;
+                    chunk.spans[index] = len as u8;
+                    chunk.last_used = epoch;
+                    self.used += len;
+                    self.requested += size;
+                    let offset = chunk.block.range().start + index as u64 * block_size;
+                    let end = offset + len as u64 * block_size;
+                    let block = RawBlock::new(chunk.block.memory(), offset..end);
+                    return Some(ChunkedBlock(block, chunk_index));
+                }
+            }
+        }
+        None
+    }
+
+    /// Allocate `len` adjacent blocks as one block, growing if necessary.
+    /// Chunks must hold at least `len` blocks.
+    fn alloc_span<B, A>(
+        &mut self,
+        owner: &mut A,
+        device: &B::Device,
+        request: A::Request,
+        reqs: Requirements,
+        len: usize,
+    ) -> Result<ChunkedBlock<B>, MemoryError>
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        debug_assert!(len <= self.blocks_per_chunk());
+        if (1 << self.id.0) & reqs.type_mask == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        let block = match self.alloc_no_grow(reqs.size, len) {
+            Some(block) => block,
+            None => {
+                self.grow(owner, device, request)?;
+                self.alloc_no_grow(reqs.size, len).expect("Just growed")
+            }
+        };
+        assert!(block.size() >= reqs.size);
+        assert_eq!(alignment_shift(reqs.alignment, block.range().start), 0);
+        Ok(block)
     }
 }
 
@@ -109,48 +305,55 @@ where
         request: O::Request,
         reqs: Requirements,
     ) -> Result<ChunkedBlock<B>, MemoryError> {
-        if (1 << self.id.0) & reqs.type_mask == 0 {
-            return Err(MemoryError::NoCompatibleMemoryType);
-        }
-        Ok(match self.alloc_no_grow() {
-            Some(block) => {
-                assert!(block.size() >= reqs.size);
-                assert_eq!(block.range().start & (reqs.alignment - 1), 0);
-                block
-            }
-            None => {
-                self.grow(owner, device, request)?;
-                self.alloc_no_grow().expect("Just growed")
-            }
-        })
+        self.alloc_span::<B, O>(owner, device, request, reqs, 1)
     }
 
-    fn free(&mut self, _owner: &mut O, _device: &B::Device, block: ChunkedBlock<B>) {
-        assert_eq!(block.range().start % self.chunk_size, 0);
-        assert_eq!(block.size(), self.chunk_size);
-        let offset = block.range().start;
-        let block_memory: *const B::Memory = block.memory();
-        let block_index = unsafe {
-            block.0.dispose();
-            block.1
+    fn free(&mut self, owner: &mut O, device: &B::Device, block: ChunkedBlock<B>) {
+        freed(self.try_free(owner, device, block));
+    }
+
+    fn try_free(
+        &mut self,
+        _owner: &mut O,
+        _device: &B::Device,
+        block: ChunkedBlock<B>,
+    ) -> Result<(), MemoryError> {
+        let ChunkedBlock(block, chunk_index) = block;
+        let block_size = self.block_size;
+        let chunk = match self.chunks.get_mut(chunk_index) {
+            Some(&mut Some(ref mut chunk)) => chunk,
+            _ => {
+                unsafe { block.dispose() };
+                return Err(MemoryError::InvalidFree);
+            }
         };
-        assert!(::std::ptr::eq(
-            self.blocks[block_index].memory(),
-            block_memory
-        ));
-        let chunk_index = offset / self.chunk_size;
-        self.free.push_front(FreeBlock {
-            block_index,
-            chunk_index,
-        });
+        if !chunk.block.contains(&block) {
+            unsafe { block.dispose() };
+            return Err(MemoryError::CorruptedBlock);
+        }
+        let offset = block.range().start - chunk.block.range().start;
+        let size = block.size();
+        unsafe { block.dispose() };
+        if offset % block_size != 0 || size % block_size != 0 {
+            return Err(MemoryError::CorruptedBlock);
+        }
+        let index = (offset / block_size) as usize;
+        let len = (size / block_size) as usize;
+        if !chunk.give(index, len) {
+            return Err(MemoryError::InvalidFree);
+        }
+        chunk.last_used = self.epoch;
+        self.used -= len;
+        self.requested -= replace(&mut chunk.requested[index], 0);
+        Ok(())
     }
 
     fn dispose(mut self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
         if self.is_used() {
             Err(self)
         } else {
-            for block in self.blocks.drain(..) {
-                owner.free(device, block);
+            for chunk in self.chunks.drain(..).filter_map(|chunk| chunk) {
+                owner.free(device, chunk.block);
             }
             Ok(())
         }
@@ -160,6 +363,10 @@ where
 /// Allocator that rounds up the requested size to the closest power of two and returns a block
 /// from a list of equal sized chunks.
 ///
+/// Optionally blocks just above a power of two are allocated as adjacent blocks of a smaller
+/// size class instead, see `set_spanning`, and tiny blocks are packed into shared chunks,
+/// see `set_micro_threshold`.
+///
 /// ### Type parameters:
 ///
 /// - `B`: hal `Backend`
@@ -170,7 +377,15 @@ pub struct ChunkedAllocator<T> {
     blocks_per_chunk: usize,
     min_block_size: u64,
     max_chunk_size: u64,
-    nodes: Vec<ChunkedNode<T>>,
+    spanning: bool,
+    low_watermark: usize,
+    micro_threshold: u64,
+    epoch: u64,
+    /// Nodes of size classes requested so far, keyed by class index.
+    nodes: BTreeMap<u8, ChunkedNode<T>>,
+    /// Nodes of micro blocks keyed by block size. Nodes of previous thresholds are kept
+    /// until their blocks are freed.
+    micro: BTreeMap<u64, ChunkedNode<T>>,
 }
 
 impl<T> ChunkedAllocator<T> {
@@ -198,14 +413,32 @@ impl<T> ChunkedAllocator<T> {
             blocks_per_chunk,
             min_block_size,
             max_chunk_size,
-            nodes: Vec::new(),
+            spanning: false,
+            low_watermark: 0,
+            micro_threshold: 0,
+            epoch: 0,
+            nodes: BTreeMap::new(),
+            micro: BTreeMap::new(),
         }
     }
 
     /// Check if any of the blocks allocated by this allocator are still in use.
     /// If this function returns `false`, the allocator can be `dispose`d.
     pub fn is_used(&self) -> bool {
-        self.nodes.iter().any(ChunkedNode::is_used)
+        self.nodes
+            .values()
+            .chain(self.micro.values())
+            .any(ChunkedNode::is_used)
+    }
+
+    /// Get number of blocks in use. Blocks spanning several blocks of a size class count once.
+    pub fn live_blocks(&self) -> usize {
+        self.nodes
+            .values()
+            .chain(self.micro.values())
+            .flat_map(|node| node.chunks.iter().filter_map(Option::as_ref))
+            .map(|chunk| chunk.spans.iter().filter(|&&len| len != 0).count())
+            .sum()
     }
 
     /// Get memory type of the allocator
@@ -228,6 +461,376 @@ impl<T> ChunkedAllocator<T> {
         self.blocks_per_chunk
     }
 
+    /// Set number of blocks per chunk of size classes used for the first time after the call.
+    /// Chunks of size classes already in use keep their size.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `blocks_per_chunk` is `0`.
+    pub fn set_blocks_per_chunk(&mut self, blocks_per_chunk: usize) {
+        assert_ne!(blocks_per_chunk, 0, "Blocks per chunk must not be 0");
+        self.blocks_per_chunk = blocks_per_chunk;
+    }
+
+    /// Enable or disable spanning allocations.
+    ///
+    /// When enabled, blocks no larger than three blocks of the size class two classes below
+    /// the one they round up to (i.e. 1.5 times the block size of the class below) are
+    /// allocated as three adjacent blocks of that class, when its chunks hold at least three
+    /// blocks. This way such blocks waste at most a quarter of the size class they would round
+    /// up to instead of almost a half.
+    pub fn set_spanning(&mut self, spanning: bool) {
+        self.spanning = spanning;
+    }
+
+    /// Set size at or below which blocks are allocated as micro blocks, zero disables them.
+    ///
+    /// Micro blocks are all `threshold` bytes and packed into shared chunks of the smallest
+    /// size class, i.e. thousands of small uniform blocks share a few chunks instead of taking
+    /// a whole `min_block_size` block each. Requests with alignment above the threshold are
+    /// allocated as usual.
+    ///
+    /// Micro blocks allocated with the previous threshold stay valid.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `threshold` is not zero and not a power of two below `min_block_size`.
+    pub fn set_micro_threshold(&mut self, threshold: u64) {
+        assert!(
+            threshold == 0 || (threshold.is_power_of_two() && threshold < self.min_block_size),
+            "Micro threshold {} must be a power of two below minimal block size {}",
+            threshold,
+            self.min_block_size
+        );
+        self.micro_threshold = threshold;
+    }
+
+    /// Get size at or below which blocks are allocated as micro blocks.
+    pub fn micro_threshold(&self) -> u64 {
+        self.micro_threshold
+    }
+
+    /// Set current epoch, i.e. index of the frame being recorded, that chunks are tagged with
+    /// when their blocks are allocated or freed. See `trim_cold`.
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.epoch = epoch;
+        for node in self.nodes.values_mut().chain(self.micro.values_mut()) {
+            node.epoch = epoch;
+        }
+    }
+
+    /// Set number of chunks without blocks in use a size class keeps ahead, see `pre_grow`.
+    /// Zero, the default, grows size classes only when they run out of blocks.
+    pub fn set_low_watermark(&mut self, chunks: usize) {
+        self.low_watermark = chunks;
+    }
+
+    /// Get number of chunks without blocks in use a size class keeps ahead.
+    pub fn low_watermark(&self) -> usize {
+        self.low_watermark
+    }
+
+    /// Grow the size class a block was allocated from by one chunk, if it has fewer chunks
+    /// without blocks in use than the low watermark.
+    ///
+    /// Calling it after allocations spreads growth over time, instead of growing exactly when
+    /// an allocation finds the class full, at the cost of keeping unused chunks around.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator to allocate the chunk from
+    /// - `device`: same device that was used to allocate the block
+    /// - `request`: request for the owner
+    /// - `reqs`: requirements the block was allocated with
+    ///
+    /// ### Returns
+    ///
+    /// Returns `true` if the class has grown.
+    pub fn pre_grow<B, A>(
+        &mut self,
+        owner: &mut A,
+        device: &B::Device,
+        request: A::Request,
+        reqs: &Requirements,
+    ) -> Result<bool, MemoryError>
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        let low_watermark = self.low_watermark;
+        let node = if self.is_micro(reqs) {
+            self.micro.get_mut(&self.micro_threshold)
+        } else {
+            let index = self.pick_node(max(reqs.size, reqs.alignment));
+            let index = self.pick_span_node(reqs, index).unwrap_or(index);
+            self.nodes.get_mut(&index)
+        };
+        match node {
+            Some(ref mut node) if node.free_chunks() < low_watermark => {
+                node.grow(owner, device, request)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Take chunks allocated from the owner since the last call as pairs of block size of
+    /// the size class and size of the chunk, i.e. to attribute hitches to size classes.
+    pub fn take_growths(&mut self) -> Vec<(u64, u64)> {
+        let mut growths = Vec::new();
+        for node in self.nodes.values_mut().chain(self.micro.values_mut()) {
+            for _ in 0..node.pending {
+                growths.push((node.block_size, node.chunk_size));
+            }
+            node.pending = 0;
+        }
+        growths
+    }
+
+    /// Get number of chunks per size class, ordered by block size, so that another allocator
+    /// can be warmed to the same layout with `warm`. Micro blocks are not included.
+    pub fn warm_state(&self) -> Vec<(u64, usize)> {
+        self.nodes
+            .values()
+            .map(|node| (node.block_size, node.chunk_count()))
+            .collect()
+    }
+
+    /// Grow the size class of the block size to at least `chunks` chunks, i.e. to restore
+    /// the layout recorded with `warm_state` in the previous run.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator to allocate the chunks from
+    /// - `device`: device to allocate the chunks from
+    /// - `request`: request for the owner
+    /// - `block_size`: block size of the size class, block sizes the allocator doesn't serve
+    ///   are skipped
+    /// - `chunks`: number of chunks the size class should have
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of chunks allocated.
+    pub fn warm<B, A>(
+        &mut self,
+        owner: &mut A,
+        device: &B::Device,
+        request: A::Request,
+        block_size: u64,
+        chunks: usize,
+    ) -> Result<usize, MemoryError>
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+        A::Request: Clone,
+    {
+        if block_size == 0 || block_size > self.max_chunk_size {
+            return Ok(0);
+        }
+        let index = self.pick_node(block_size);
+        let node = self.node(index);
+        let mut grown = 0;
+        while node.chunk_count() < chunks {
+            node.grow(owner, device, request.clone())?;
+            grown += 1;
+        }
+        Ok(grown)
+    }
+
+    /// Estimate how many bytes would be requested from the owner to allocate blocks
+    /// for all `reqs`, starting from an empty allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `reqs`: requirements of the blocks, sizes must not exceed `max_chunk_size`
+    pub fn estimate_footprint(&self, reqs: &[Requirements]) -> u64 {
+        let mut counts: Vec<u64> = Vec::new();
+        let mut micro = 0;
+        for reqs in reqs {
+            if self.is_micro(reqs) {
+                micro += 1;
+                continue;
+            }
+            let index = self.pick_node(max(reqs.size, reqs.alignment));
+            let (index, blocks) = match self.pick_span_node(reqs, index) {
+                Some(index) => (index as usize, SPAN_BLOCKS as u64),
+                None => (index as usize, 1),
+            };
+            if counts.len() <= index {
+                counts.resize(index + 1, 0);
+            }
+            counts[index] += blocks;
+        }
+        let classes: u64 = counts
+            .iter()
+            .enumerate()
+            .map(|(index, &count)| {
+                let chunk_size = self.nodes
+                    .get(&(index as u8))
+                    .map_or_else(|| self.chunk_size(index as u8), |node| node.chunk_size);
+                let per_chunk = max(1, chunk_size / self.block_size(index as u8));
+                (count + per_chunk - 1) / per_chunk * chunk_size
+            })
+            .sum();
+        let chunk_size = self.chunk_size(0);
+        let per_chunk = max(1, chunk_size / max(1, self.micro_threshold));
+        classes + (micro + per_chunk - 1) / per_chunk * chunk_size
+    }
+
+    /// Dispose of this allocator, returning all memory to the owner, even if blocks allocated
+    /// from it are still in use.
+    ///
+    /// Blocks that are still in use are invalidated. They must not be used or freed afterwards
+    /// and should be leaked with `std::mem::forget`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the inner memory blocks
+    /// - `device`: must be the same device all allocations have been made against
+    pub fn force_dispose<B, A>(self, owner: &mut A, device: &B::Device)
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        for node in self.nodes.into_iter().map(|(_, node)| node).chain(
+            self.micro.into_iter().map(|(_, node)| node),
+        ) {
+            for chunk in node.chunks.into_iter().filter_map(|chunk| chunk) {
+                owner.free(device, chunk.block);
+            }
+        }
+    }
+
+    /// Check if the size class a block would be allocated from is nearly exhausted.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `reqs`: requirements of the block, size must not exceed `max_chunk_size`
+    /// - `watermark`: fraction of blocks of a chunk left free in the size class at which the
+    ///   class is considered nearly exhausted
+    ///
+    /// ### Returns
+    ///
+    /// Returns size of the next chunk of the class if the class is nearly exhausted.
+    pub fn growth_hint(&self, reqs: &Requirements, watermark: f32) -> Option<u64> {
+        let node = if self.is_micro(reqs) {
+            self.micro.get(&self.micro_threshold)?
+        } else {
+            let index = self.pick_node(max(reqs.size, reqs.alignment));
+            let index = self.pick_span_node(reqs, index).unwrap_or(index);
+            self.nodes.get(&index)?
+        };
+        let stats = node.stats();
+        let free = stats.capacity - stats.blocks;
+        if free as f64 <= watermark as f64 * node.blocks_per_chunk() as f64 {
+            Some(node.chunk_size)
+        } else {
+            None
+        }
+    }
+
+    /// Get number of chunks without blocks in use.
+    pub fn free_chunks(&self) -> usize {
+        self.nodes
+            .values()
+            .chain(self.micro.values())
+            .map(ChunkedNode::free_chunks)
+            .sum()
+    }
+
+    /// Get statistics per size class, ordered by block size.
+    /// Only size classes that were requested so far are included, micro blocks come first.
+    pub fn size_class_stats(&self) -> Vec<SizeClassStats> {
+        self.micro
+            .values()
+            .chain(self.nodes.values())
+            .map(ChunkedNode::stats)
+            .collect()
+    }
+
+    /// Return chunks without blocks in use to the owner.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the chunks
+    /// - `device`: same device that was used to allocate the chunks
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of chunks returned to the owner.
+    pub fn trim<B, A>(&mut self, owner: &mut A, device: &B::Device) -> usize
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        self.nodes
+            .values_mut()
+            .chain(self.micro.values_mut())
+            .map(|node| node.trim::<B, A>(owner, device))
+            .sum()
+    }
+
+    /// Return chunks that have been without blocks in use for the longest time to the owner.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the chunks
+    /// - `device`: same device that was used to allocate the chunks
+    /// - `age`: number of epochs since the last block of a chunk was freed, after which the
+    ///   chunk may be returned
+    /// - `max`: maximum number of chunks to return
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of chunks returned to the owner.
+    pub fn trim_cold<B, A>(
+        &mut self,
+        owner: &mut A,
+        device: &B::Device,
+        age: u64,
+        max: usize,
+    ) -> usize
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        let epoch = self.epoch;
+        let mut cold = Vec::new();
+        {
+            let nodes = self.nodes
+                .iter()
+                .map(|(&index, node)| (false, index as u64, node))
+                .chain(self.micro.iter().map(|(&size, node)| (true, size, node)));
+            for (micro, key, node) in nodes {
+                for (slot, chunk) in node.chunks.iter().enumerate() {
+                    if let Some(ref chunk) = *chunk {
+                        if chunk.count == 0 && epoch.saturating_sub(chunk.last_used) >= age {
+                            cold.push((chunk.last_used, micro, key, slot));
+                        }
+                    }
+                }
+            }
+        }
+        cold.sort();
+        cold.truncate(max);
+        for &(_, micro, key, slot) in &cold {
+            let node = if micro {
+                self.micro.get_mut(&key)
+            } else {
+                self.nodes.get_mut(&(key as u8))
+            };
+            let node = node.expect("Collected above");
+            let chunk = node.chunks[slot].take().expect("Collected above");
+            owner.free(device, chunk.block);
+        }
+        cold.len()
+    }
+
     fn block_size(&self, index: u8) -> u64 {
         self.min_block_size * (1u64 << (index as u8))
     }
@@ -250,17 +853,52 @@ impl<T> ChunkedAllocator<T> {
         node
     }
 
-    fn grow(&mut self, index: u8) {
-        assert!(self.chunk_size(index) <= self.max_chunk_size);
-        let len = self.nodes.len() as u8;
+    /// Pick the size class for a spanning allocation of the block that would be allocated from
+    /// the `index` class otherwise, if spanning is enabled and saves memory.
+    fn pick_span_node(&self, reqs: &Requirements, index: u8) -> Option<u8> {
+        if !self.spanning || index < 2 {
+            return None;
+        }
+        let index = index - 2;
+        let block_size = self.block_size(index);
+        let chunk_size = self.nodes
+            .get(&index)
+            .map_or_else(|| self.chunk_size(index), |node| node.chunk_size);
+        if reqs.size <= block_size * SPAN_BLOCKS as u64 && reqs.alignment <= block_size
+            && chunk_size / block_size >= SPAN_BLOCKS as u64
+        {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Check if the block is allocated as a micro block.
+    fn is_micro(&self, reqs: &Requirements) -> bool {
+        reqs.size <= self.micro_threshold && reqs.alignment <= self.micro_threshold
+    }
+
+    /// Get node of micro blocks of the current threshold, creating it on first request.
+    /// Micro chunks are as large as chunks of the smallest size class.
+    fn micro_node(&mut self) -> &mut ChunkedNode<T> {
+        let chunk_size = self.chunk_size(0);
+        let block_size = self.micro_threshold;
         let id = self.id;
+        let epoch = self.epoch;
+        self.micro
+            .entry(block_size)
+            .or_insert_with(|| ChunkedNode::new(chunk_size, block_size, id, epoch))
+    }
 
-        let range = len..index + 1;
-        self.nodes.reserve(range.len());
-        for index in range {
-            let node = ChunkedNode::new(self.chunk_size(index), self.block_size(index), id);
-            self.nodes.push(node);
-        }
+    /// Get node of the size class, creating it on first request.
+    fn node(&mut self, index: u8) -> &mut ChunkedNode<T> {
+        let chunk_size = self.chunk_size(index);
+        let block_size = self.block_size(index);
+        let id = self.id;
+        let epoch = self.epoch;
+        self.nodes
+            .entry(index)
+            .or_insert_with(|| ChunkedNode::new(chunk_size, block_size, id, epoch))
     }
 }
 
@@ -280,24 +918,70 @@ where
         request: O::Request,
         reqs: Requirements,
     ) -> Result<ChunkedBlock<B>, MemoryError> {
+        validate(&reqs, 1 << self.id.0)?;
         if reqs.size > self.max_chunk_size {
             return Err(MemoryError::OutOfMemory);
         }
+        if self.is_micro(&reqs) {
+            return self.micro_node().alloc(owner, device, request, reqs);
+        }
         let index = self.pick_node(max(reqs.size, reqs.alignment));
-        self.grow(index);
-        self.nodes[index as usize].alloc(owner, device, request, reqs)
+        if let Some(index) = self.pick_span_node(&reqs, index) {
+            return self.node(index)
+                .alloc_span::<B, O>(owner, device, request, reqs, SPAN_BLOCKS);
+        }
+        self.node(index).alloc(owner, device, request, reqs)
     }
 
     fn free(&mut self, owner: &mut O, device: &B::Device, block: ChunkedBlock<B>) {
-        let index = self.pick_node(block.size());
-        self.nodes[index as usize].free(owner, device, block);
+        freed(self.try_free(owner, device, block));
+    }
+
+    fn try_free(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        block: ChunkedBlock<B>,
+    ) -> Result<(), MemoryError> {
+        if block.size() == 0 || block.size() > self.max_chunk_size {
+            unsafe { block.0.dispose() };
+            return Err(MemoryError::CorruptedBlock);
+        }
+        // Only micro blocks are smaller than the smallest size class.
+        let size = block.size();
+        if size < self.min_block_size {
+            return match self.micro.get_mut(&size) {
+                Some(node) => node.try_free(owner, device, block),
+                None => {
+                    unsafe { block.0.dispose() };
+                    Err(MemoryError::InvalidFree)
+                }
+            };
+        }
+        // Only spanning blocks don't match the block size of a size class.
+        let index = self.pick_node(size);
+        let index = if self.block_size(index) == size {
+            index
+        } else {
+            self.pick_node(max(1, size / SPAN_BLOCKS as u64))
+        };
+        match self.nodes.get_mut(&index) {
+            Some(node) => node.try_free(owner, device, block),
+            None => {
+                unsafe { block.0.dispose() };
+                Err(MemoryError::InvalidFree)
+            }
+        }
     }
 
     fn dispose(mut self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
         if self.is_used() {
             Err(self)
         } else {
-            for node in self.nodes.drain(..) {
+            for (_, node) in replace(&mut self.nodes, BTreeMap::new()) {
+                node.dispose(owner, device).unwrap();
+            }
+            for (_, node) in replace(&mut self.micro, BTreeMap::new()) {
                 node.dispose(owner, device).unwrap();
             }
             Ok(())
@@ -1,26 +1,44 @@
+use std::error::Error;
+use std::fmt;
+use std::mem::{forget, replace};
 use std::ops::Range;
+use std::process::abort;
+use std::sync::Arc;
+use std::thread::panicking;
 
 use gfx_hal::{Backend, MemoryTypeId};
 use gfx_hal::memory::Requirements;
 
-use {MemoryAllocator, MemoryError, MemorySubAllocator};
+use {alignment_shift, freed, validate, MemoryAllocator, MemoryError, MemorySubAllocator};
 use arena::{ArenaAllocator, ArenaBlock};
 use block::{Block, RawBlock};
 use chunked::{ChunkedAllocator, ChunkedBlock};
-use root::RootAllocator;
+use freelist::{FreeListAllocator, FreeListBlock};
+use host::HostAllocator;
+use plugin::{CustomSubAllocator, SubAllocatorFactory};
+use observer::{AllocRetry, SubAllocatorGrowth};
+use root::{RetryPolicy, RootAllocator};
+use stats::SizeClassStats;
 
 /// Controls what sub allocator is used for an allocation by `CombinedAllocator`
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Type {
     /// For short-lived objects, such as staging buffers.
     ShortLived,
 
     /// General purpose.
     General,
+
+    /// Served by the sub-allocator registered for the kind.
+    /// See `CombinedAllocator::register_sub_allocator`.
+    Custom(u32),
 }
 
-/// Combines `ArenaAllocator` and `ChunkedAllocator`, and allows the user to control which type of
-/// allocation to use.
+/// Combines `ArenaAllocator`, `ChunkedAllocator` and `FreeListAllocator`, and allows the user
+/// to control which type of allocation to use.
+///
+/// `Type::General` allocations are routed by size, see `Routing`. `Type::Custom` allocations
+/// are served by sub-allocators registered with `register_sub_allocator`.
 ///
 /// Use `RootAllocator` as the super allocator, which will handle the actual memory allocations
 /// from `Device`.
@@ -36,6 +54,175 @@ where
     root: RootAllocator<B>,
     arenas: ArenaAllocator<RawBlock<B>>,
     chunks: ChunkedAllocator<RawBlock<B>>,
+    free_list: FreeListAllocator<RawBlock<B>>,
+    custom: Vec<CustomSlot<B>>,
+    routing: Routing,
+    leak: LeakMode,
+    /// Number of live blocks with dedicated memory objects.
+    dedicated: usize,
+}
+
+/// Sub-allocator registered for a `Type::Custom` kind, created on the first request.
+#[derive(Debug)]
+struct CustomSlot<B: Backend> {
+    kind: u32,
+    factory: Arc<SubAllocatorFactory<B>>,
+    allocator: Option<Box<CustomSubAllocator<B>>>,
+    /// Number of live blocks.
+    live: usize,
+}
+
+impl<B> CustomSlot<B>
+where
+    B: Backend,
+{
+    fn is_used(&self) -> bool {
+        self.allocator
+            .as_ref()
+            .map_or(false, |allocator| allocator.is_used())
+    }
+}
+
+/// Number of largest free-list blocks that fit into a free-list page.
+const FREE_LIST_PAGE_BLOCKS: u64 = 4;
+
+/// Size thresholds for routing `Type::General` allocations.
+///
+/// Blocks not larger than `chunked` are allocated from the `ChunkedAllocator`, blocks not
+/// larger than `dedicated` are allocated from the `FreeListAllocator`, and larger blocks get
+/// dedicated memory objects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Routing {
+    /// Largest block size served by the `ChunkedAllocator`.
+    /// Clamped to `max_chunk_size` of the allocator.
+    pub chunked: u64,
+
+    /// Largest block size served by the `FreeListAllocator`.
+    /// The free-list is not used if it is not larger than `chunked`.
+    pub dedicated: u64,
+}
+
+impl Routing {
+    /// Routing of an allocator with specified `max_chunk_size` that doesn't use the free-list.
+    pub fn chunked(max_chunk_size: u64) -> Self {
+        Routing {
+            chunked: max_chunk_size,
+            dedicated: max_chunk_size,
+        }
+    }
+
+    /// Clamp thresholds to be valid for the chunked allocator.
+    pub(crate) fn clamp(&self, max_chunk_size: u64) -> Self {
+        let chunked = self.chunked.min(max_chunk_size);
+        Routing {
+            chunked,
+            dedicated: self.dedicated.max(chunked),
+        }
+    }
+
+    /// Get size of pages of the free-list, never `0`.
+    pub(crate) fn page_size(&self) -> u64 {
+        self.dedicated.saturating_mul(FREE_LIST_PAGE_BLOCKS).max(1)
+    }
+}
+
+/// What allocators do when dropped without being disposed.
+///
+/// Memory allocated from the device by such allocators is leaked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeakMode {
+    /// Panic with a report about the allocator. Logs a warning if the thread is already
+    /// panicking.
+    Panic,
+
+    /// Log a warning with a report about the allocator.
+    Warn,
+
+    /// Leak silently.
+    Silent,
+
+    /// Log an error with a report about the allocator and abort the process, i.e. for
+    /// crash handlers that must not unwind.
+    Abort,
+}
+
+impl Default for LeakMode {
+    /// `Panic` in debug builds, `Warn` otherwise.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            LeakMode::Panic
+        } else {
+            LeakMode::Warn
+        }
+    }
+}
+
+/// Sub-allocator of `CombinedAllocator`. See `CombinedAllocator::in_use`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SubAllocatorKind {
+    /// `ArenaAllocator` serving `Type::ShortLived` blocks.
+    Arena,
+
+    /// `ChunkedAllocator` serving small `Type::General` blocks.
+    Chunked,
+
+    /// `FreeListAllocator` serving medium `Type::General` blocks.
+    FreeList,
+
+    /// Dedicated memory objects of large `Type::General` blocks.
+    Dedicated,
+
+    /// Sub-allocator registered for the `Type::Custom` kind.
+    Custom(u32),
+}
+
+/// Error returned by `CombinedAllocator::try_dispose` if blocks are still in use.
+#[derive(Debug)]
+pub struct DisposeError<B: Backend> {
+    /// The allocator, left unchanged and usable.
+    pub allocator: CombinedAllocator<B>,
+
+    /// Sub-allocators with blocks in use and numbers of their live blocks.
+    pub in_use: Vec<(SubAllocatorKind, usize)>,
+}
+
+impl<B> fmt::Display for DisposeError<B>
+where
+    B: Backend,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "CombinedAllocator for {:?} is in use:",
+            self.allocator.memory_type()
+        )?;
+        for &(kind, blocks) in &self.in_use {
+            write!(fmt, " {:?} has {} live blocks;", kind, blocks)?;
+        }
+        Ok(())
+    }
+}
+
+impl<B> Error for DisposeError<B>
+where
+    B: Backend,
+{
+    fn description(&self) -> &str {
+        "Allocator is in use"
+    }
+}
+
+/// Report allocator dropped without being disposed.
+pub(crate) fn report_leak(mode: LeakMode, report: &str) {
+    match mode {
+        LeakMode::Panic if !panicking() => panic!("{}", report),
+        LeakMode::Panic | LeakMode::Warn => warn!("{}", report),
+        LeakMode::Silent => {}
+        LeakMode::Abort => {
+            error!("{}", report);
+            abort();
+        }
+    }
 }
 
 impl<B> CombinedAllocator<B>
@@ -67,13 +254,548 @@ where
                 max_chunk_size,
                 memory_type_id,
             ),
+            free_list: FreeListAllocator::new(
+                Routing::chunked(max_chunk_size).page_size(),
+                memory_type_id,
+            ),
+            custom: Vec::new(),
+            routing: Routing::chunked(max_chunk_size),
+            leak: LeakMode::default(),
+            dedicated: 0,
         }
     }
 
+    /// Register factory of the sub-allocator serving `Type::Custom(kind)` requests.
+    ///
+    /// The sub-allocator is created on the first request of the kind and allocates memory from
+    /// the same root as the built-in sub-allocators.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if a factory is already registered for the kind.
+    pub fn register_sub_allocator(&mut self, kind: u32, factory: Arc<SubAllocatorFactory<B>>) {
+        assert!(
+            self.custom.iter().all(|slot| slot.kind != kind),
+            "Sub-allocator for kind {} is already registered",
+            kind
+        );
+        self.custom.push(CustomSlot {
+            kind,
+            factory,
+            allocator: None,
+            live: 0,
+        });
+    }
+
+    /// Allocate from the sub-allocator registered for the kind.
+    fn alloc_custom(
+        &mut self,
+        device: &B::Device,
+        kind: u32,
+        reqs: Requirements,
+    ) -> Result<RawBlock<B>, MemoryError> {
+        let memory_type = self.root.memory_type();
+        let slot = match self.custom.iter_mut().find(|slot| slot.kind == kind) {
+            Some(slot) => slot,
+            None => {
+                warn!("No sub-allocator is registered for kind {}", kind);
+                return Err(MemoryError::NoCompatibleMemoryType);
+            }
+        };
+        if slot.allocator.is_none() {
+            slot.allocator = Some(slot.factory.create(memory_type));
+        }
+        let block = slot.allocator
+            .as_mut()
+            .expect("Just created")
+            .alloc(&mut self.root, device, reqs)?;
+        slot.live += 1;
+        Ok(block)
+    }
+
+    /// Set size thresholds for routing `Type::General` allocations.
+    ///
+    /// Blocks that are already allocated are freed to the sub-allocator they were allocated
+    /// from regardless of the routing.
+    pub fn set_routing(&mut self, routing: Routing) {
+        self.routing = routing.clamp(self.chunks.max_chunk_size());
+        self.free_list.set_page_size(self.routing.page_size());
+    }
+
+    /// Get size thresholds for routing `Type::General` allocations.
+    pub fn routing(&self) -> Routing {
+        self.routing
+    }
+
+    /// Allocate a dedicated memory object for the block regardless of the routing.
+    /// The block is freed with `free` as usual, its type is `Type::General`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `reqs`: the requirements of the block
+    pub fn alloc_dedicated(
+        &mut self,
+        device: &B::Device,
+        reqs: Requirements,
+    ) -> Result<CombinedBlock<B>, MemoryError> {
+        validate(&reqs, 1 << self.root.memory_type().0)?;
+        self.discard_events();
+        let block = self.root.alloc(device, (), reqs)?;
+        self.dedicated += 1;
+        Ok(CombinedBlock(block, CombinedTag::Root))
+    }
+
+    /// Set what the allocator does when dropped without being disposed.
+    pub fn set_leak_mode(&mut self, mode: LeakMode) {
+        self.leak = mode;
+    }
+
+    /// Serve allocations from host memory instead of the device.
+    /// See `RootAllocator::set_host`.
+    pub fn set_host(&mut self, host: Option<HostAllocator<B>>) {
+        self.root.set_host(host);
+    }
+
+    /// Get host allocator serving allocations, if any. See `RootAllocator::set_host`.
+    pub fn host(&self) -> Option<&HostAllocator<B>> {
+        self.root.host()
+    }
+
+    /// Get mutable host allocator serving allocations, if any.
+    /// See `RootAllocator::set_host`.
+    pub fn host_mut(&mut self) -> Option<&mut HostAllocator<B>> {
+        self.root.host_mut()
+    }
+
+    /// Get what the allocator does when dropped without being disposed.
+    pub fn leak_mode(&self) -> LeakMode {
+        self.leak
+    }
+
     /// Get memory type id
     pub fn memory_type(&self) -> MemoryTypeId {
         self.root.memory_type()
     }
+
+    /// Get total size of memory currently allocated from the device.
+    pub fn allocated(&self) -> u64 {
+        self.root.allocated()
+    }
+
+    /// Get total size of memory allocated from the device so far.
+    /// See `RootAllocator::grown`.
+    pub fn grown(&self) -> u64 {
+        self.root.grown()
+    }
+
+    /// Get number of memory objects currently allocated from the device.
+    pub fn allocations(&self) -> usize {
+        self.root.allocations()
+    }
+
+    /// Extend the block in place to fit the requirements, if possible.
+    ///
+    /// Blocks already large enough and aligned for the requirements fit as they are, blocks of
+    /// the free-list are extended if the space right after them is free.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `block`: block allocated from this allocator
+    /// - `reqs`: new requirements of the block, memory type is not checked
+    ///
+    /// ### Returns
+    ///
+    /// Returns `true` if the block fits the requirements afterwards.
+    pub fn grow_in_place(&mut self, block: &mut CombinedBlock<B>, reqs: &Requirements) -> bool {
+        if alignment_shift(reqs.alignment, block.range().start) != 0 {
+            return false;
+        }
+        if reqs.size <= block.size() {
+            return true;
+        }
+        match block.1 {
+            CombinedTag::FreeList(tag) => self.free_list.grow_raw(&mut block.0, tag, reqs.size),
+            _ => false,
+        }
+    }
+
+    /// Get memory objects in use with their sizes. See `RootAllocator::memories`.
+    pub fn memories(&self) -> Vec<(&B::Memory, u64)> {
+        self.root.memories()
+    }
+
+    /// Enable or disable deferred frees of memory objects. See `RootAllocator`.
+    pub fn set_deferred_frees(&mut self, deferred: bool) {
+        self.root.set_deferred_frees(deferred);
+    }
+
+    /// Set size of arenas allocated after the call. See `ArenaAllocator::set_arena_size`.
+    pub fn set_arena_size(&mut self, arena_size: u64) {
+        self.arenas.set_arena_size(arena_size);
+    }
+
+    /// Set number of blocks per chunk of new size classes.
+    /// See `ChunkedAllocator::set_blocks_per_chunk`.
+    pub fn set_blocks_per_chunk(&mut self, blocks_per_chunk: usize) {
+        self.chunks.set_blocks_per_chunk(blocks_per_chunk);
+    }
+
+    /// Enable or disable spanning allocations of chunked blocks.
+    /// See `ChunkedAllocator::set_spanning`.
+    pub fn set_spanning(&mut self, spanning: bool) {
+        self.chunks.set_spanning(spanning);
+    }
+
+    /// Set size at or below which chunked blocks are packed into shared micro chunks.
+    /// Thresholds not below the minimal block size disable micro blocks, as such blocks are
+    /// packed already. See `ChunkedAllocator::set_micro_threshold`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `threshold` is not zero and not a power of two.
+    pub fn set_micro_threshold(&mut self, threshold: u64) {
+        assert!(
+            threshold == 0 || threshold.is_power_of_two(),
+            "Micro threshold {} must be a power of two",
+            threshold
+        );
+        if threshold < self.chunks.min_block_size() {
+            self.chunks.set_micro_threshold(threshold);
+        } else {
+            self.chunks.set_micro_threshold(0);
+        }
+    }
+
+    /// Seal or unseal the allocator. See `RootAllocator::set_sealed`.
+    pub fn set_sealed(&mut self, sealed: bool) {
+        self.root.set_sealed(sealed);
+    }
+
+    /// Set policy of retrying allocations from the device.
+    /// See `RootAllocator::set_retry_policy`.
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.root.set_retry_policy(retry);
+    }
+
+    /// Take retries of allocations from the device made by the last allocation.
+    /// See `RootAllocator::take_retries`.
+    pub fn take_retries(&mut self) -> Vec<AllocRetry> {
+        self.root.take_retries()
+    }
+
+    /// Take blocks the arena and chunked sub-allocators allocated from the root allocator in the
+    /// last allocation or `warm` call.
+    /// See `ArenaAllocator::take_growths` and `ChunkedAllocator::take_growths`.
+    pub fn take_growths(&mut self) -> Vec<SubAllocatorGrowth> {
+        let memory_type = self.root.memory_type();
+        let arenas = self.arenas.take_growths().into_iter().map(|size| SubAllocatorGrowth {
+            memory_type,
+            kind: SubAllocatorKind::Arena,
+            block_size: None,
+            size,
+        });
+        let chunks = self.chunks
+            .take_growths()
+            .into_iter()
+            .map(|(block_size, size)| SubAllocatorGrowth {
+                memory_type,
+                kind: SubAllocatorKind::Chunked,
+                block_size: Some(block_size),
+                size,
+            });
+        arenas.chain(chunks).collect()
+    }
+
+    /// Enable or disable out-of-order frees of arenas. See `ArenaAllocator`.
+    pub fn set_out_of_order_frees(&mut self, out_of_order: bool) {
+        self.arenas.set_out_of_order_frees(out_of_order);
+    }
+
+    /// Enable or disable epoch retirement of arenas. See `ArenaAllocator`.
+    pub fn set_epoch_retirement(&mut self, enabled: bool) {
+        self.arenas.set_epoch_retirement(enabled);
+    }
+
+    /// Set current epoch. See `ArenaAllocator::set_epoch` and `ChunkedAllocator::set_epoch`.
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.arenas.set_epoch(epoch);
+        self.chunks.set_epoch(epoch);
+    }
+
+    /// Return chunks that have been unused for the longest time to the device.
+    /// See `ChunkedAllocator::trim_cold`.
+    pub fn trim_cold(&mut self, device: &B::Device, age: u64, max: usize) -> usize {
+        self.chunks.trim_cold(&mut self.root, device, age, max)
+    }
+
+    /// Return arenas retired in completed epochs. See `ArenaAllocator::maintain`.
+    pub fn maintain(&mut self, device: &B::Device, completed: u64) -> usize {
+        self.arenas.maintain(&mut self.root, device, completed)
+    }
+
+    /// Recycle all arenas for reuse. See `ArenaAllocator::reset`.
+    pub fn reset_arenas(&mut self, device: &B::Device) -> usize {
+        self.arenas.reset(&mut self.root, device)
+    }
+
+    /// Get live block count of each retired arena that still has blocks in use.
+    /// See `ArenaAllocator::pinned_arenas`.
+    pub fn pinned_arenas(&self) -> Vec<usize> {
+        self.arenas.pinned_arenas()
+    }
+
+    /// Get number of freed memory objects waiting for `flush_frees`.
+    pub fn pending_frees(&self) -> usize {
+        self.root.pending_frees()
+    }
+
+    /// Free queued memory objects. See `RootAllocator::flush_frees`.
+    pub fn flush_frees(&mut self, device: &B::Device, max: usize) -> usize {
+        self.root.flush_frees(device, max)
+    }
+
+    /// Get number of chunks without blocks in use. See `ChunkedAllocator::free_chunks`.
+    pub fn free_chunks(&self) -> usize {
+        self.chunks.free_chunks()
+    }
+
+    /// Get statistics of chunked blocks per size class.
+    /// See `ChunkedAllocator::size_class_stats`.
+    pub fn size_class_stats(&self) -> Vec<SizeClassStats> {
+        self.chunks.size_class_stats()
+    }
+
+    /// Set number of chunks without blocks in use a size class keeps ahead.
+    /// See `ChunkedAllocator::set_low_watermark`.
+    pub fn set_low_watermark(&mut self, chunks: usize) {
+        self.chunks.set_low_watermark(chunks);
+    }
+
+    /// Grow the size class of a chunked block ahead, if it is below the low watermark.
+    /// Failures are not fatal, the class grows when it runs out of blocks anyway.
+    /// Drop events not taken since the previous allocation, so they don't pile up if the owner
+    /// doesn't take them.
+    fn discard_events(&mut self) {
+        self.root.take_retries();
+        self.arenas.take_growths();
+        self.chunks.take_growths();
+    }
+
+    fn pre_grow(&mut self, device: &B::Device, reqs: &Requirements) {
+        if self.chunks.low_watermark() == 0 || self.root.is_sealed() {
+            return;
+        }
+        if let Err(error) = self.chunks.pre_grow(&mut self.root, device, (), reqs) {
+            debug!(
+                "Growing chunks of {:?} ahead failed: {}",
+                self.root.memory_type(),
+                error
+            );
+        }
+    }
+
+    /// Provide memory allocated from the device elsewhere. See `RootAllocator::provide_memory`.
+    pub fn provide_memory(&mut self, memory: B::Memory, size: u64) {
+        self.root.provide_memory(memory, size);
+    }
+
+    /// Check if the sub-allocator a block would be allocated from is nearly exhausted.
+    /// See `ArenaAllocator::growth_hint` and `ChunkedAllocator::growth_hint`.
+    ///
+    /// ### Returns
+    ///
+    /// Returns size of the memory the sub-allocator would allocate from the device next,
+    /// unless spare memory of that size was already provided.
+    pub fn growth_hint(&self, ty: Type, reqs: &Requirements, watermark: f32) -> Option<u64> {
+        let size = match ty {
+            Type::ShortLived => self.arenas.growth_hint::<B>(watermark),
+            Type::General if reqs.size <= self.routing.chunked => {
+                self.chunks.growth_hint(reqs, watermark)
+            }
+            Type::General | Type::Custom(_) => None,
+        }?;
+        if self.root.has_spare(size) {
+            None
+        } else {
+            Some(size)
+        }
+    }
+
+    /// Get number of chunks per size class. See `ChunkedAllocator::warm_state`.
+    pub fn warm_state(&self) -> Vec<(u64, usize)> {
+        self.chunks.warm_state()
+    }
+
+    /// Grow the size class of the block size to at least `chunks` chunks.
+    /// See `ChunkedAllocator::warm`.
+    pub fn warm(
+        &mut self,
+        device: &B::Device,
+        block_size: u64,
+        chunks: usize,
+    ) -> Result<usize, MemoryError> {
+        self.discard_events();
+        self.chunks
+            .warm(&mut self.root, device, (), block_size, chunks)
+    }
+
+    /// Return chunks without blocks in use to the device. See `ChunkedAllocator::trim`.
+    pub fn trim(&mut self, device: &B::Device) -> usize {
+        self.chunks.trim(&mut self.root, device)
+    }
+
+    /// Get sub-allocators with blocks in use and numbers of their live blocks.
+    /// Arenas waiting for their epoch to complete count as in use without live blocks.
+    pub fn in_use(&self) -> Vec<(SubAllocatorKind, usize)> {
+        let mut in_use = Vec::new();
+        if self.arenas.is_used() {
+            in_use.push((SubAllocatorKind::Arena, self.arenas.live_blocks()));
+        }
+        if self.chunks.is_used() {
+            in_use.push((SubAllocatorKind::Chunked, self.chunks.live_blocks()));
+        }
+        if self.free_list.is_used() {
+            in_use.push((SubAllocatorKind::FreeList, self.free_list.live_blocks()));
+        }
+        if self.dedicated != 0 {
+            in_use.push((SubAllocatorKind::Dedicated, self.dedicated));
+        }
+        for slot in &self.custom {
+            if slot.is_used() {
+                in_use.push((SubAllocatorKind::Custom(slot.kind), slot.live));
+            }
+        }
+        in_use
+    }
+
+    /// Attempt to dispose of this allocator. Nothing is freed unless all sub-allocators are
+    /// unused, so a failed attempt leaves the allocator fully usable.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    ///
+    /// ### Returns
+    ///
+    /// Returns `DisposeError` with the allocator and the sub-allocators that still have blocks
+    /// in use. See `in_use`.
+    pub fn try_dispose(self, device: &B::Device) -> Result<(), DisposeError<B>> {
+        let in_use = self.in_use();
+        if !in_use.is_empty() {
+            return Err(DisposeError {
+                allocator: self,
+                in_use,
+            });
+        }
+        let (mut root, arenas, chunks, free_list, custom) = self.into_parts();
+        for mut allocator in custom.into_iter().filter_map(|slot| slot.allocator) {
+            allocator.dispose(&mut root, device);
+        }
+        arenas.dispose(&mut root, device).expect("Checked above");
+        chunks.dispose(&mut root, device).expect("Checked above");
+        free_list.dispose(&mut root, device).expect("Checked above");
+        root.dispose(device).unwrap();
+        Ok(())
+    }
+
+    /// Dispose of this allocator, freeing all memory allocated from the device, even if blocks
+    /// allocated from it are still in use.
+    ///
+    /// Blocks that are still in use are invalidated. They must not be used or freed afterwards
+    /// and should be leaked with `std::mem::forget`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    pub fn force_dispose(self, device: &B::Device) {
+        let (mut root, arenas, chunks, free_list, custom) = self.into_parts();
+        for mut allocator in custom.into_iter().filter_map(|slot| slot.allocator) {
+            allocator.force_dispose(&mut root, device);
+        }
+        arenas.force_dispose(&mut root, device);
+        chunks.force_dispose(&mut root, device);
+        free_list.force_dispose(&mut root, device);
+        root.force_dispose(device);
+    }
+
+    /// Dispose of this allocator after the device is lost, leaking all memory allocated from
+    /// the device without reporting it. Blocks that are still in use are invalidated and
+    /// should be leaked with `std::mem::forget`.
+    pub fn dispose_lost(mut self) {
+        self.leak = LeakMode::Silent;
+    }
+
+    /// Move sub-allocators out, leaving empty ones behind, so that `Drop` has nothing to report.
+    fn into_parts(
+        mut self,
+    ) -> (
+        RootAllocator<B>,
+        ArenaAllocator<RawBlock<B>>,
+        ChunkedAllocator<RawBlock<B>>,
+        FreeListAllocator<RawBlock<B>>,
+        Vec<CustomSlot<B>>,
+    ) {
+        let id = self.root.memory_type();
+        let arenas = ArenaAllocator::new(self.arenas.arena_size(), id);
+        let chunks = ChunkedAllocator::new(
+            self.chunks.blocks_per_chunk(),
+            self.chunks.min_block_size(),
+            self.chunks.max_chunk_size(),
+            id,
+        );
+        let free_list = FreeListAllocator::new(self.free_list.page_size(), id);
+        (
+            replace(&mut self.root, RootAllocator::new(id)),
+            replace(&mut self.arenas, arenas),
+            replace(&mut self.chunks, chunks),
+            replace(&mut self.free_list, free_list),
+            replace(&mut self.custom, Vec::new()),
+        )
+    }
+
+    /// Estimate how many bytes would be allocated from the device to allocate blocks
+    /// for all `items`, starting from an empty allocator.
+    ///
+    /// Includes rounding of sizes and growth of sub-allocators.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `items`: types and requirements of the blocks
+    pub fn estimate_footprint(&self, items: &[(Type, Requirements)]) -> u64 {
+        estimate_footprint(
+            &self.arenas,
+            &self.chunks,
+            &self.free_list,
+            self.routing,
+            items,
+        )
+    }
+}
+
+/// Estimate footprint of `items` routed the same way `CombinedAllocator` routes allocations.
+pub(crate) fn estimate_footprint<T>(
+    arenas: &ArenaAllocator<T>,
+    chunks: &ChunkedAllocator<T>,
+    free_list: &FreeListAllocator<T>,
+    routing: Routing,
+    items: &[(Type, Requirements)],
+) -> u64 {
+    let mut arena_reqs = Vec::new();
+    let mut chunk_reqs = Vec::new();
+    let mut free_list_reqs = Vec::new();
+    let mut root = 0;
+    for &(ty, reqs) in items {
+        match ty {
+            Type::ShortLived => arena_reqs.push(reqs),
+            Type::General if reqs.size <= routing.chunked => chunk_reqs.push(reqs),
+            Type::General if reqs.size <= routing.dedicated => free_list_reqs.push(reqs),
+            // Footprint of custom sub-allocators is unknown, assume no rounding.
+            Type::General | Type::Custom(_) => root += reqs.size,
+        }
+    }
+    arenas.estimate_footprint(&arena_reqs) + chunks.estimate_footprint(&chunk_reqs)
+        + free_list.estimate_footprint(&free_list_reqs) + root
 }
 
 impl<B> MemoryAllocator<B> for CombinedAllocator<B>
@@ -89,76 +811,120 @@ where
         request: Type,
         reqs: Requirements,
     ) -> Result<CombinedBlock<B>, MemoryError> {
+        validate(&reqs, 1 << self.root.memory_type().0)?;
+        self.discard_events();
         match request {
             Type::ShortLived => self.arenas
                 .alloc(&mut self.root, device, (), reqs)
                 .map(|ArenaBlock(block, tag)| CombinedBlock(block, CombinedTag::Arena(tag))),
-            Type::General => {
-                if reqs.size > self.chunks.max_chunk_size() {
-                    self.root
-                        .alloc(device, (), reqs)
-                        .map(|block| CombinedBlock(block, CombinedTag::Root))
-                } else {
-                    self.chunks.alloc(&mut self.root, device, (), reqs).map(
-                        |ChunkedBlock(block, tag)| CombinedBlock(block, CombinedTag::Chunked(tag)),
-                    )
-                }
+            Type::General if reqs.size <= self.routing.chunked => {
+                let ChunkedBlock(block, tag) = self.chunks.alloc(&mut self.root, device, (), reqs)?;
+                self.pre_grow(device, &reqs);
+                Ok(CombinedBlock(block, CombinedTag::Chunked(tag)))
             }
+            Type::General if reqs.size <= self.routing.dedicated => self.free_list
+                .alloc(&mut self.root, device, (), reqs)
+                .map(|FreeListBlock(block, tag)| CombinedBlock(block, CombinedTag::FreeList(tag))),
+            Type::General => self.alloc_dedicated(device, reqs),
+            Type::Custom(kind) => self.alloc_custom(device, kind, reqs)
+                .map(|block| CombinedBlock(block, CombinedTag::Custom(kind))),
         }
     }
 
     fn free(&mut self, device: &B::Device, block: CombinedBlock<B>) {
+        freed(self.try_free(device, block));
+    }
+
+    fn try_free(
+        &mut self,
+        device: &B::Device,
+        block: CombinedBlock<B>,
+    ) -> Result<(), MemoryError> {
         match block.1 {
             CombinedTag::Arena(tag) => {
                 self.arenas
-                    .free(&mut self.root, device, ArenaBlock(block.0, tag))
+                    .try_free(&mut self.root, device, ArenaBlock(block.0, tag))
             }
             CombinedTag::Chunked(tag) => {
                 self.chunks
-                    .free(&mut self.root, device, ChunkedBlock(block.0, tag))
+                    .try_free(&mut self.root, device, ChunkedBlock(block.0, tag))
+            }
+            CombinedTag::FreeList(tag) => {
+                self.free_list
+                    .try_free(&mut self.root, device, FreeListBlock(block.0, tag))
+            }
+            CombinedTag::Root => {
+                self.root.try_free(device, block.0)?;
+                self.dedicated -= 1;
+                Ok(())
+            }
+            CombinedTag::Custom(kind) => {
+                match self.custom.iter_mut().find(|slot| slot.kind == kind) {
+                    Some(&mut CustomSlot {
+                        allocator: Some(ref mut allocator),
+                        ref mut live,
+                        ..
+                    }) => {
+                        allocator.try_free(&mut self.root, device, block.0)?;
+                        *live -= 1;
+                        Ok(())
+                    }
+                    _ => {
+                        unsafe { block.0.dispose() };
+                        Err(MemoryError::InvalidFree)
+                    }
+                }
             }
-            CombinedTag::Root => self.root.free(device, block.0),
         }
     }
 
     fn is_used(&self) -> bool {
-        let used = self.arenas.is_used() || self.chunks.is_used();
+        let used = self.arenas.is_used() || self.chunks.is_used() || self.free_list.is_used()
+            || self.dedicated != 0 || self.custom.iter().any(CustomSlot::is_used);
         assert_eq!(used, self.root.is_used());
         used
     }
 
-    fn dispose(mut self, device: &B::Device) -> Result<(), Self> {
-        let memory_type_id = self.root.memory_type();
-        let arena_size = self.arenas.arena_size();
-        let blocks_per_chunk = self.chunks.blocks_per_chunk();
-        let min_block_size = self.chunks.min_block_size();
-        let max_chunk_size = self.chunks.max_chunk_size();
-
-        let arenas = self.arenas.dispose(&mut self.root, device);
-        let chunks = self.chunks.dispose(&mut self.root, device);
-
-        if arenas.is_err() || chunks.is_err() {
-            let arenas = arenas
-                .err()
-                .unwrap_or_else(|| ArenaAllocator::new(arena_size, memory_type_id));
-            let chunks = chunks.err().unwrap_or_else(|| {
-                ChunkedAllocator::new(
-                    blocks_per_chunk,
-                    min_block_size,
-                    max_chunk_size,
-                    memory_type_id,
-                )
-            });
+    fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        self.try_dispose(device).map_err(|error| error.allocator)
+    }
+}
 
-            Err(CombinedAllocator {
-                root: self.root,
-                arenas,
-                chunks,
-            })
-        } else {
-            self.root.dispose(device).unwrap();
-            Ok(())
+impl<B> Drop for CombinedAllocator<B>
+where
+    B: Backend,
+{
+    fn drop(&mut self) {
+        if !self.root.is_used() {
+            // Disposed or nothing to leak, cached memory objects are reported by the root.
+            return;
+        }
+        let report = format!(
+            "CombinedAllocator for {:?} dropped without being disposed, \
+             leaking {} bytes in {} memory objects",
+            self.root.memory_type(),
+            self.root.allocated(),
+            self.root.allocations()
+        );
+        report_leak(self.leak, &report);
+
+        // Sub-allocators and their blocks are leaked along with the device memory.
+        let id = self.root.memory_type();
+        let arenas = ArenaAllocator::new(self.arenas.arena_size(), id);
+        forget(replace(&mut self.arenas, arenas));
+        let chunks = ChunkedAllocator::new(
+            self.chunks.blocks_per_chunk(),
+            self.chunks.min_block_size(),
+            self.chunks.max_chunk_size(),
+            id,
+        );
+        forget(replace(&mut self.chunks, chunks));
+        let free_list = FreeListAllocator::new(self.free_list.page_size(), id);
+        forget(replace(&mut self.free_list, free_list));
+        for slot in &mut self.custom {
+            forget(slot.allocator.take());
         }
+        self.root.leak();
     }
 }
 
@@ -169,11 +935,37 @@ where
 #[derive(Debug)]
 pub struct CombinedBlock<B: Backend>(pub(crate) RawBlock<B>, pub(crate) CombinedTag);
 
+impl<B> CombinedBlock<B>
+where
+    B: Backend,
+{
+    /// Get type of sub-allocator the block was allocated with.
+    pub(crate) fn ty(&self) -> Type {
+        match self.1 {
+            CombinedTag::Arena(_) => Type::ShortLived,
+            CombinedTag::Chunked(_) | CombinedTag::FreeList(_) | CombinedTag::Root => {
+                Type::General
+            }
+            CombinedTag::Custom(kind) => Type::Custom(kind),
+        }
+    }
+
+    /// Check if the block has its own memory object.
+    pub(crate) fn is_dedicated(&self) -> bool {
+        match self.1 {
+            CombinedTag::Root => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum CombinedTag {
     Arena(u64),
     Chunked(usize),
+    FreeList(usize),
     Root,
+    Custom(u32),
 }
 
 impl<B> Block<B> for CombinedBlock<B>
@@ -0,0 +1,216 @@
+use heap::HeapPolicy;
+use stats::RequestHistogram;
+use MemoryError;
+
+/// Largest chunk size `suggest_config` recommends.
+const MAX_SUGGESTED_CHUNK_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Range of blocks per chunk `suggest_config` recommends.
+const MIN_SUGGESTED_BLOCKS_PER_CHUNK: usize = 4;
+const MAX_SUGGESTED_BLOCKS_PER_CHUNK: usize = 256;
+
+/// Configuration of sub-allocators created by `SmartAllocator`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SmartAllocatorConfig {
+    /// Size of arenas serving `Type::ShortLived` requests. See `ArenaAllocator`.
+    pub arena_size: u64,
+
+    /// Number of blocks in each chunk. See `ChunkedAllocator`.
+    pub blocks_per_chunk: usize,
+
+    /// Smallest block size of chunked allocators. See `ChunkedAllocator`.
+    pub min_block_size: u64,
+
+    /// Largest chunk size of chunked allocators. See `ChunkedAllocator`.
+    pub max_chunk_size: u64,
+}
+
+impl SmartAllocatorConfig {
+    /// Suggest configuration for the observed workload, keeping values that can't be derived
+    /// from it.
+    ///
+    /// - `blocks_per_chunk` fits general requests of the most common size class made per frame
+    ///   on average.
+    /// - `min_block_size` covers the 10th percentile of general request sizes, but not less
+    ///   than the most common alignment.
+    /// - `max_chunk_size` fits `blocks_per_chunk` blocks of the 90th percentile of general
+    ///   request sizes.
+    /// - `arena_size` fits the short-lived bytes requested per frame on average, but not less
+    ///   than the largest short-lived request.
+    pub(crate) fn suggest(&self, requests: &RequestHistogram, frames: u64) -> Self {
+        let mut config = *self;
+
+        if let (Some(small), Some(large)) = (
+            percentile(&requests.general_sizes, 0.1),
+            percentile(&requests.general_sizes, 0.9),
+        ) {
+            if let Some(common) = mode(&requests.general_sizes) {
+                let per_frame = requests.general_sizes[common as usize] / (frames + 1);
+                config.blocks_per_chunk = (per_frame as usize)
+                    .next_power_of_two()
+                    .max(MIN_SUGGESTED_BLOCKS_PER_CHUNK)
+                    .min(MAX_SUGGESTED_BLOCKS_PER_CHUNK);
+            }
+            let alignment = mode(&requests.alignments).unwrap_or(0);
+            config.min_block_size = class_size(small.max(alignment));
+            config.max_chunk_size = class_size(large)
+                .saturating_mul(config.blocks_per_chunk as u64)
+                .min(MAX_SUGGESTED_CHUNK_SIZE)
+                .max(config.min_block_size);
+        }
+
+        if let Some(largest) = requests.short_lived_sizes.iter().rposition(|&count| count > 0) {
+            let per_frame = requests.short_lived_bytes / (frames + 1);
+            config.arena_size = per_frame
+                .checked_next_power_of_two()
+                .unwrap_or(!0)
+                .max(class_size(largest as u8));
+        }
+
+        config
+    }
+
+    /// Clamp every value of the configuration into the bounds.
+    /// `min_block_size` is then clamped to `max_chunk_size`, as chunked allocators can't serve
+    /// blocks larger than chunks.
+    pub(crate) fn clamp(&self, min: &Self, max: &Self) -> Self {
+        let max_chunk_size = self.max_chunk_size
+            .max(min.max_chunk_size)
+            .min(max.max_chunk_size);
+        SmartAllocatorConfig {
+            arena_size: self.arena_size.max(min.arena_size).min(max.arena_size),
+            blocks_per_chunk: self.blocks_per_chunk
+                .max(min.blocks_per_chunk)
+                .min(max.blocks_per_chunk),
+            min_block_size: self.min_block_size
+                .max(min.min_block_size)
+                .min(max.min_block_size)
+                .min(max_chunk_size),
+            max_chunk_size,
+        }
+    }
+
+    /// Check that sub-allocators can be created with the configuration.
+    ///
+    /// ### Returns
+    ///
+    /// `MemoryError::ZeroSize` if any value is `0`.
+    pub(crate) fn check(&self) -> Result<(), MemoryError> {
+        if self.arena_size == 0 || self.blocks_per_chunk == 0 || self.min_block_size == 0
+            || self.max_chunk_size == 0
+        {
+            return Err(MemoryError::ZeroSize);
+        }
+        Ok(())
+    }
+
+    /// Check if every value of the configuration is not greater than the one of `other`.
+    pub(crate) fn bounded_by(&self, other: &Self) -> bool {
+        self.arena_size <= other.arena_size && self.blocks_per_chunk <= other.blocks_per_chunk
+            && self.min_block_size <= other.min_block_size
+            && self.max_chunk_size <= other.max_chunk_size
+    }
+}
+
+/// Policy of returning unused chunks to the device and evicting old blocks in
+/// `SmartAllocator::maintain`. See `SmartAllocator::set_cold_eviction`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColdEviction {
+    /// Number of frames a chunk must stay without blocks in use before it is returned,
+    /// and a block must stay alive before it is offered for eviction.
+    pub age: u64,
+
+    /// Maximum number of chunks returned per pool in one `maintain` call.
+    /// Chunks unused for the longest time are returned first.
+    pub max_chunks: usize,
+}
+
+/// Step of returning unused memory to the device in `SmartAllocator::maintain` and
+/// `SmartAllocator::shrink`. See `SmartAllocator::set_trim_order`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrimStep {
+    /// Return all chunks without blocks in use. See `ChunkedAllocator::trim`.
+    ChunkCaches,
+
+    /// Free memory objects queued by deferred frees and dropped shared blocks.
+    /// See `SmartAllocator::flush_frees`.
+    RootCache,
+
+    /// Return chunks unused for longer than `ColdEviction::age`, at most
+    /// `ColdEviction::max_chunks` per pool. Skipped if cold eviction is disabled.
+    ColdChunks,
+
+    /// Ask observers to evict blocks allocated at least `ColdEviction::age` frames ago,
+    /// see `AllocObserver::on_evict`. Blocks are offered in the order of
+    /// `SmartAllocator::enumerate_eviction_candidates` until they cover the usage of heaps
+    /// above their soft targets, or the bytes `SmartAllocator::shrink` still has to return.
+    /// Memory is returned once the blocks are freed, not by the step itself.
+    /// Skipped if cold eviction is disabled.
+    ColdBlocks,
+}
+
+/// Changes of `SmartAllocatorConfig` that can be applied to an allocator with live blocks.
+/// See `SmartAllocator::reconfigure`.
+///
+/// `min_block_size` and `max_chunk_size` define size classes of live blocks and can't be
+/// changed this way.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartialConfig {
+    /// Size of arenas allocated afterwards, `None` keeps the current one.
+    pub arena_size: Option<u64>,
+
+    /// Number of blocks in each chunk of size classes used for the first time afterwards,
+    /// `None` keeps the current one.
+    pub blocks_per_chunk: Option<usize>,
+
+    /// Number of chunks without blocks in use each size class keeps ahead, `None` keeps the
+    /// current one. See `SmartAllocator::set_low_watermark`.
+    pub low_watermark: Option<usize>,
+
+    /// Policies, i.e. soft targets and hard limits, of heaps by index. Heaps not listed keep
+    /// their policies. See `SmartAllocator::set_heap_policy`.
+    pub heap_policies: Vec<(usize, HeapPolicy)>,
+}
+
+impl PartialConfig {
+    /// Replace values of the configuration with ones specified.
+    pub(crate) fn apply(&self, config: SmartAllocatorConfig) -> SmartAllocatorConfig {
+        SmartAllocatorConfig {
+            arena_size: self.arena_size.unwrap_or(config.arena_size),
+            blocks_per_chunk: self.blocks_per_chunk.unwrap_or(config.blocks_per_chunk),
+            ..config
+        }
+    }
+}
+
+/// Get the smallest size class that covers specified fraction of the recorded requests.
+fn percentile(histogram: &[u64], fraction: f64) -> Option<u8> {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let threshold = ((total as f64 * fraction).ceil() as u64).max(1);
+    let mut counted = 0;
+    histogram
+        .iter()
+        .position(|&count| {
+            counted += count;
+            counted >= threshold
+        })
+        .map(|class| class as u8)
+}
+
+/// Get the class with the most recorded requests.
+fn mode(histogram: &[u64]) -> Option<u8> {
+    histogram
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count > 0)
+        .max_by_key(|&(_, &count)| count)
+        .map(|(class, _)| class as u8)
+}
+
+/// Get the largest size of the class.
+fn class_size(class: u8) -> u64 {
+    1u64.checked_shl(class as u32).unwrap_or(!0)
+}
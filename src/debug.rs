@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::forget;
+
+use gfx_hal::{Backend, MemoryTypeId};
+use gfx_hal::memory::Requirements;
+
+use {alignment_shift, freed, MemoryAllocator, MemoryError};
+use block::Block;
+
+/// Allocator wrapper that validates blocks, as a development-time safety net around custom
+/// allocator stacks.
+///
+/// Every allocated block is checked against the request: its size must fit the requested
+/// size, its offset must be aligned, and its memory type must be allowed by the type mask.
+/// Violations panic, since the inner allocator is broken. Every freed block is checked to be
+/// allocated from this allocator and not freed yet, otherwise it is leaked and the free
+/// fails with `MemoryError::InvalidFree` before the inner allocator sees it.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `A`: wrapped allocator
+pub struct DebugAllocator<B: Backend, A: MemoryAllocator<B>> {
+    allocator: A,
+    memory_type: fn(&A::Block) -> MemoryTypeId,
+    /// Ends of live blocks by addresses of their memory objects and offsets.
+    live: BTreeMap<(usize, u64), u64>,
+    pd: PhantomData<B>,
+}
+
+impl<B, A> DebugAllocator<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    /// Wrap the allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator to validate
+    /// - `memory_type`: function to get memory type of a block, i.e. `SmartBlock::memory_type`,
+    ///   or one returning the only memory type of the allocator
+    pub fn new(allocator: A, memory_type: fn(&A::Block) -> MemoryTypeId) -> Self {
+        DebugAllocator {
+            allocator,
+            memory_type,
+            live: BTreeMap::new(),
+            pd: PhantomData,
+        }
+    }
+
+    /// Get the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.allocator
+    }
+
+    /// Get number of live blocks allocated through the wrapper.
+    pub fn live(&self) -> usize {
+        self.live.len()
+    }
+
+    /// Unwrap the allocator.
+    pub fn into_inner(self) -> A {
+        self.allocator
+    }
+}
+
+/// Get key of the block in the map of live blocks.
+fn key<B, T>(block: &T) -> (usize, u64)
+where
+    B: Backend,
+    T: Block<B>,
+{
+    (block.memory() as *const B::Memory as usize, block.range().start)
+}
+
+impl<B, A> MemoryAllocator<B> for DebugAllocator<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    type Request = A::Request;
+    type Block = A::Block;
+
+    fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        reqs: Requirements,
+    ) -> Result<A::Block, MemoryError> {
+        let block = self.allocator.alloc(device, request, reqs)?;
+        let range = block.range();
+        let memory_type = (self.memory_type)(&block);
+        assert!(
+            range.end - range.start >= reqs.size,
+            "Block {:?} is smaller than requested {} bytes",
+            range,
+            reqs.size
+        );
+        assert_eq!(
+            alignment_shift(reqs.alignment, range.start),
+            0,
+            "Block {:?} is not aligned to {} bytes",
+            range,
+            reqs.alignment
+        );
+        assert_ne!(
+            (1 << memory_type.0) & reqs.type_mask,
+            0,
+            "Block of {:?} doesn't match type mask {:#b}",
+            memory_type,
+            reqs.type_mask
+        );
+        let previous = self.live.insert(key::<B, _>(&block), range.end);
+        assert!(
+            previous.is_none(),
+            "Block {:?} is allocated while still in use",
+            range
+        );
+        Ok(block)
+    }
+
+    fn free(&mut self, device: &B::Device, block: A::Block) {
+        freed(self.try_free(device, block));
+    }
+
+    fn try_free(&mut self, device: &B::Device, block: A::Block) -> Result<(), MemoryError> {
+        let range = block.range();
+        let slot = key::<B, _>(&block);
+        if self.live.get(&slot) != Some(&range.end) {
+            warn!(
+                "Block {:?} is not allocated from this allocator or is already freed",
+                range
+            );
+            forget(block);
+            return Err(MemoryError::InvalidFree);
+        }
+        self.live.remove(&slot);
+        self.allocator.try_free(device, block)
+    }
+
+    fn is_used(&self) -> bool {
+        self.allocator.is_used()
+    }
+
+    fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        let DebugAllocator {
+            allocator,
+            memory_type,
+            live,
+            pd,
+        } = self;
+        allocator
+            .dispose(device)
+            .map_err(|allocator| DebugAllocator {
+                allocator,
+                memory_type,
+                live,
+                pd,
+            })
+    }
+}
+
+impl<B, A> fmt::Debug for DebugAllocator<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DebugAllocator")
+            .field("allocator", &self.allocator)
+            .field("live", &self.live.len())
+            .finish()
+    }
+}
@@ -0,0 +1,140 @@
+use gfx_hal::Backend;
+use gfx_hal::command::{BufferCopy, ImageCopy, RawCommandBuffer};
+use gfx_hal::image::ImageLayout;
+
+use smart::Realloc;
+
+/// Progress of a defragmentation pass, i.e. moving blocks out of sparsely used chunks with
+/// `SmartAllocator::realloc`. See `SmartAllocator::begin_compaction`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compaction {
+    /// Number of memory objects allocated when the pass began.
+    allocations: usize,
+    bytes_moved: u64,
+    blocks_relocated: usize,
+}
+
+impl Compaction {
+    pub(crate) fn new(allocations: usize) -> Self {
+        Compaction {
+            allocations,
+            bytes_moved: 0,
+            blocks_relocated: 0,
+        }
+    }
+
+    /// Record relocation of a block.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `size`: number of bytes copied to the new location
+    pub fn relocated(&mut self, size: u64) {
+        self.bytes_moved += size;
+        self.blocks_relocated += 1;
+    }
+
+    /// Record result of `SmartAllocator::realloc`. Blocks grown in place are not relocated.
+    pub fn realloc<B>(&mut self, realloc: &Realloc<B>)
+    where
+        B: Backend,
+    {
+        if let Realloc::Moved { ref copy, .. } = *realloc {
+            self.relocated(copy.size);
+        }
+    }
+
+    pub(crate) fn finish(self, allocations: usize, fragmentation: f32) -> CompactionReport {
+        CompactionReport {
+            bytes_moved: self.bytes_moved,
+            blocks_relocated: self.blocks_relocated,
+            root_blocks_released: self.allocations.saturating_sub(allocations),
+            fragmentation,
+        }
+    }
+}
+
+/// Benefit of a defragmentation pass, so that tools can show it and decide whether further
+/// passes are worthwhile. See `SmartAllocator::end_compaction`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionReport {
+    /// Number of bytes copied to new locations.
+    pub bytes_moved: u64,
+
+    /// Number of blocks moved to new locations.
+    pub blocks_relocated: usize,
+
+    /// Number of memory objects returned to the device over the pass, net of ones allocated.
+    pub root_blocks_released: usize,
+
+    /// Fraction of allocated bytes not used by live blocks after the pass.
+    pub fragmentation: f32,
+}
+
+/// Copy of a resource's contents from the resource bound to the block's old location to the
+/// one bound to its new location.
+#[derive(Debug)]
+pub enum MoveCopy<'a, B: Backend> {
+    /// Copy the whole buffer.
+    Buffer {
+        /// Buffer bound to the old location, created with `Usage::TRANSFER_SRC`.
+        src: &'a B::Buffer,
+
+        /// Buffer bound to the new location, created with `Usage::TRANSFER_DST`.
+        dst: &'a B::Buffer,
+
+        /// Size of the buffers.
+        size: u64,
+    },
+
+    /// Copy regions of the image, i.e. all mip levels and layers.
+    Image {
+        /// Image bound to the old location, in `ImageLayout::TransferSrcOptimal` layout.
+        src: &'a B::Image,
+
+        /// Image bound to the new location, in `ImageLayout::TransferDstOptimal` layout.
+        dst: &'a B::Image,
+
+        /// Regions to copy. Offsets are the same in both images.
+        regions: &'a [ImageCopy],
+    },
+}
+
+/// Record copy commands for moved blocks, so that resources can be rebound to new locations.
+///
+/// Layout transitions and barriers around the copies are left to the caller, since only it
+/// knows how the resources were used before and will be used after. Blocks pinned with
+/// `SmartAllocator::pin` must not be moved.
+///
+/// ### Parameters:
+///
+/// - `cbuf`: command buffer of a queue that supports transfer operations, in recording state
+/// - `copies`: copies to record
+pub fn record_move_copies<B>(cbuf: &mut B::CommandBuffer, copies: &[MoveCopy<B>])
+where
+    B: Backend,
+{
+    for copy in copies {
+        match *copy {
+            MoveCopy::Buffer { src, dst, size } => {
+                cbuf.copy_buffer(
+                    src,
+                    dst,
+                    Some(BufferCopy {
+                        src: 0,
+                        dst: 0,
+                        size,
+                    }),
+                );
+            }
+            MoveCopy::Image { src, dst, regions } => {
+                cbuf.copy_image(
+                    src,
+                    ImageLayout::TransferSrcOptimal,
+                    dst,
+                    ImageLayout::TransferDstOptimal,
+                    regions,
+                );
+            }
+        }
+    }
+}
@@ -103,6 +103,13 @@ pub struct Item<I, T> {
     block: T,
 }
 
+impl<I, T> Item<I, T> {
+    /// Get the block of memory the item is bound to.
+    pub fn block(&self) -> &T {
+        &self.block
+    }
+}
+
 impl<I, T> Borrow<I> for Item<I, T> {
     fn borrow(&self) -> &I {
         &self.raw
@@ -0,0 +1,421 @@
+//! C API for the `SmartAllocator`.
+//!
+//! Functions are generic over the backend and can't be exported directly.
+//! Use `gfx_mem_ffi!` macro to export them for the concrete backend:
+//!
+//! ```rust,ignore
+//! gfx_mem_ffi!(gfx_backend_vulkan::Backend);
+//! ```
+//!
+//! The macro exports following functions. All of them return `GFX_MEM_SUCCESS` or one of the
+//! error codes. `device` is a pointer to the hal `Device` all allocations are made against.
+//! Panics never unwind into C code, they are reported as `GFX_MEM_ERROR_PANIC`.
+//!
+//! - `gfx_mem_create(types, type_count, heaps, heap_count, config, out_allocator)`
+//! - `gfx_mem_destroy(allocator, device)`
+//! - `gfx_mem_alloc(allocator, device, request, out_block)`
+//! - `gfx_mem_free(allocator, device, handle)`
+//! - `gfx_mem_heap_stats(allocator, heap, out_stats)`
+//! - `gfx_mem_type_stats(allocator, memory_type, out_stats)`
+
+use std::collections::HashMap;
+use std::mem::replace;
+use std::os::raw::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::slice;
+
+use gfx_hal::{MemoryProperties, MemoryType};
+use gfx_hal::memory::{Properties, Requirements};
+
+use {MemoryAllocator, MemoryError};
+use block::Block;
+use combined::Type;
+use config::SmartAllocatorConfig;
+use smart::{SmartAllocator, SmartBlock, SmartRequest};
+
+#[doc(hidden)]
+pub use gfx_hal::Backend;
+
+/// Operation succeeded.
+pub const GFX_MEM_SUCCESS: i32 = 0;
+/// No compatible memory type. See `MemoryError::NoCompatibleMemoryType`.
+pub const GFX_MEM_ERROR_NO_COMPATIBLE_MEMORY_TYPE: i32 = -1;
+/// Out of memory. See `MemoryError::OutOfMemory`.
+pub const GFX_MEM_ERROR_OUT_OF_MEMORY: i32 = -2;
+/// Corrupted block. See `MemoryError::CorruptedBlock`.
+pub const GFX_MEM_ERROR_CORRUPTED_BLOCK: i32 = -3;
+/// Invalid free. See `MemoryError::InvalidFree`.
+pub const GFX_MEM_ERROR_INVALID_FREE: i32 = -4;
+/// Allocator can't be destroyed while blocks allocated from it are in use.
+pub const GFX_MEM_ERROR_IN_USE: i32 = -5;
+/// Null pointer or value out of range passed.
+pub const GFX_MEM_ERROR_INVALID_ARGUMENT: i32 = -6;
+/// Invalid range. See `MemoryError::InvalidRange`.
+pub const GFX_MEM_ERROR_INVALID_RANGE: i32 = -7;
+/// Mapping failed. See `MemoryError::MappingFailed`.
+pub const GFX_MEM_ERROR_MAPPING_FAILED: i32 = -8;
+/// Allocator is sealed. See `MemoryError::Sealed`.
+pub const GFX_MEM_ERROR_SEALED: i32 = -9;
+/// Requested size is zero. See `MemoryError::ZeroSize`.
+pub const GFX_MEM_ERROR_ZERO_SIZE: i32 = -10;
+/// Requested alignment is not a power of two. See `MemoryError::InvalidAlignment`.
+pub const GFX_MEM_ERROR_INVALID_ALIGNMENT: i32 = -11;
+/// The allocator panicked. Its state is unspecified, it should only be destroyed.
+pub const GFX_MEM_ERROR_PANIC: i32 = -12;
+/// Invalid device mask. See `MemoryError::InvalidDeviceMask`.
+pub const GFX_MEM_ERROR_INVALID_DEVICE_MASK: i32 = -13;
+
+/// `GfxMemRequest::ty` value for `Type::ShortLived`.
+pub const GFX_MEM_TYPE_SHORT_LIVED: u32 = 0;
+/// `GfxMemRequest::ty` value for `Type::General`.
+pub const GFX_MEM_TYPE_GENERAL: u32 = 1;
+
+/// Memory type description.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GfxMemType {
+    /// Bits of hal `memory::Properties`.
+    pub properties: u32,
+    /// Index of the heap the memory type belongs to.
+    pub heap_index: u32,
+}
+
+/// Allocator configuration. See `SmartAllocatorConfig`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GfxMemConfig {
+    /// See `SmartAllocatorConfig::arena_size`.
+    pub arena_size: u64,
+    /// See `SmartAllocatorConfig::blocks_per_chunk`.
+    pub blocks_per_chunk: u32,
+    /// See `SmartAllocatorConfig::min_block_size`.
+    pub min_block_size: u64,
+    /// See `SmartAllocatorConfig::max_chunk_size`.
+    pub max_chunk_size: u64,
+}
+
+/// Allocation request.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GfxMemRequest {
+    /// `GFX_MEM_TYPE_SHORT_LIVED` or `GFX_MEM_TYPE_GENERAL`.
+    pub ty: u32,
+    /// Bits of hal `memory::Properties` the memory type must have.
+    pub properties: u32,
+    /// Size of the block.
+    pub size: u64,
+    /// Alignment of the block.
+    pub alignment: u64,
+    /// Mask of compatible memory types.
+    pub type_mask: u64,
+}
+
+/// Allocated block.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GfxMemBlock {
+    /// Handle used to free the block.
+    pub handle: u64,
+    /// Pointer to the hal `Memory` of the block.
+    pub memory: *const c_void,
+    /// Offset of the block in the memory.
+    pub offset: u64,
+    /// Size of the block.
+    pub size: u64,
+}
+
+/// Memory heap statistics. See `HeapStats`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GfxMemHeapStats {
+    /// Size of the heap.
+    pub size: u64,
+    /// Bytes used by live blocks.
+    pub used: u64,
+    /// Bytes held back by reservations.
+    pub reserved: u64,
+}
+
+/// Memory type statistics. See `TypeStats`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GfxMemTypeStats {
+    /// Bytes used by live blocks.
+    pub used: u64,
+    /// Number of live blocks.
+    pub blocks: u64,
+    /// Bytes allocated from the device.
+    pub allocated: u64,
+    /// Number of memory objects allocated from the device.
+    pub allocations: u64,
+}
+
+/// Allocator handed out to C code. Keeps blocks by handle.
+#[derive(Debug)]
+pub struct FfiAllocator<B: Backend> {
+    allocator: SmartAllocator<B>,
+    blocks: HashMap<u64, SmartBlock<B>>,
+}
+
+fn error_code(error: MemoryError) -> i32 {
+    match error {
+        MemoryError::NoCompatibleMemoryType => GFX_MEM_ERROR_NO_COMPATIBLE_MEMORY_TYPE,
+        MemoryError::OutOfMemory => GFX_MEM_ERROR_OUT_OF_MEMORY,
+        MemoryError::CorruptedBlock => GFX_MEM_ERROR_CORRUPTED_BLOCK,
+        MemoryError::InvalidFree => GFX_MEM_ERROR_INVALID_FREE,
+        MemoryError::InvalidRange => GFX_MEM_ERROR_INVALID_RANGE,
+        MemoryError::MappingFailed => GFX_MEM_ERROR_MAPPING_FAILED,
+        MemoryError::Sealed => GFX_MEM_ERROR_SEALED,
+        MemoryError::ZeroSize => GFX_MEM_ERROR_ZERO_SIZE,
+        MemoryError::InvalidAlignment => GFX_MEM_ERROR_INVALID_ALIGNMENT,
+        MemoryError::InvalidDeviceMask => GFX_MEM_ERROR_INVALID_DEVICE_MASK,
+    }
+}
+
+/// Run the function, turning a panic into `GFX_MEM_ERROR_PANIC`, as unwinding into C code is
+/// undefined behavior.
+#[doc(hidden)]
+pub fn guard<F: FnOnce() -> i32>(function: F) -> i32 {
+    catch_unwind(AssertUnwindSafe(function)).unwrap_or(GFX_MEM_ERROR_PANIC)
+}
+
+#[doc(hidden)]
+pub unsafe fn create<B: Backend>(
+    types: *const GfxMemType,
+    type_count: usize,
+    heaps: *const u64,
+    heap_count: usize,
+    config: *const GfxMemConfig,
+    out: *mut *mut FfiAllocator<B>,
+) -> i32 {
+    if types.is_null() || heaps.is_null() || config.is_null() || out.is_null() {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    let types = slice::from_raw_parts(types, type_count);
+    let heaps = slice::from_raw_parts(heaps, heap_count);
+    if types.iter().any(|ty| ty.heap_index as usize >= heap_count) {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    let config = &*config;
+    let memory_properties = MemoryProperties {
+        memory_types: types
+            .iter()
+            .map(|ty| MemoryType {
+                properties: Properties::from_bits_truncate(ty.properties as _),
+                heap_index: ty.heap_index as usize,
+            })
+            .collect(),
+        memory_heaps: heaps.to_vec(),
+    };
+    let config = SmartAllocatorConfig {
+        arena_size: config.arena_size,
+        blocks_per_chunk: config.blocks_per_chunk as usize,
+        min_block_size: config.min_block_size,
+        max_chunk_size: config.max_chunk_size,
+    };
+    if config.check().is_err() {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    *out = Box::into_raw(Box::new(FfiAllocator {
+        allocator: SmartAllocator::with_config(memory_properties, config),
+        blocks: HashMap::new(),
+    }));
+    GFX_MEM_SUCCESS
+}
+
+#[doc(hidden)]
+pub unsafe fn destroy<B: Backend>(
+    allocator: *mut FfiAllocator<B>,
+    device: *const B::Device,
+) -> i32 {
+    if allocator.is_null() || device.is_null() {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    let ffi = &mut *allocator;
+    if !ffi.blocks.is_empty() {
+        return GFX_MEM_ERROR_IN_USE;
+    }
+    let empty = SmartAllocator::with_properties(Vec::new(), Vec::new(), ffi.allocator.config());
+    match replace(&mut ffi.allocator, empty).dispose(&*device) {
+        Ok(()) => {
+            drop(Box::from_raw(allocator));
+            GFX_MEM_SUCCESS
+        }
+        Err(used) => {
+            ffi.allocator = used;
+            GFX_MEM_ERROR_IN_USE
+        }
+    }
+}
+
+#[doc(hidden)]
+pub unsafe fn alloc<B: Backend>(
+    allocator: *mut FfiAllocator<B>,
+    device: *const B::Device,
+    request: *const GfxMemRequest,
+    out: *mut GfxMemBlock,
+) -> i32 {
+    if allocator.is_null() || device.is_null() || request.is_null() || out.is_null() {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    let allocator = &mut *allocator;
+    let request = &*request;
+    let ty = match request.ty {
+        GFX_MEM_TYPE_SHORT_LIVED => Type::ShortLived,
+        GFX_MEM_TYPE_GENERAL => Type::General,
+        _ => return GFX_MEM_ERROR_INVALID_ARGUMENT,
+    };
+    let properties = Properties::from_bits_truncate(request.properties as _);
+    let reqs = Requirements {
+        size: request.size,
+        alignment: request.alignment,
+        type_mask: request.type_mask,
+    };
+    match allocator
+        .allocator
+        .alloc(&*device, SmartRequest::new(ty, properties), reqs)
+    {
+        Ok(block) => {
+            let handle = block.id();
+            *out = GfxMemBlock {
+                handle,
+                memory: block.memory() as *const B::Memory as *const c_void,
+                offset: block.range().start,
+                size: block.size(),
+            };
+            allocator.blocks.insert(handle, block);
+            GFX_MEM_SUCCESS
+        }
+        Err(error) => error_code(error),
+    }
+}
+
+#[doc(hidden)]
+pub unsafe fn free<B: Backend>(
+    allocator: *mut FfiAllocator<B>,
+    device: *const B::Device,
+    handle: u64,
+) -> i32 {
+    if allocator.is_null() || device.is_null() {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    let allocator = &mut *allocator;
+    match allocator.blocks.remove(&handle) {
+        Some(block) => match allocator.allocator.try_free(&*device, block) {
+            Ok(()) => GFX_MEM_SUCCESS,
+            Err(error) => error_code(error),
+        },
+        None => GFX_MEM_ERROR_INVALID_FREE,
+    }
+}
+
+#[doc(hidden)]
+pub unsafe fn heap_stats<B: Backend>(
+    allocator: *const FfiAllocator<B>,
+    heap: usize,
+    out: *mut GfxMemHeapStats,
+) -> i32 {
+    if allocator.is_null() || out.is_null() {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    match (*allocator).allocator.stats().heaps.get(heap) {
+        Some(stats) => {
+            *out = GfxMemHeapStats {
+                size: stats.size,
+                used: stats.used,
+                reserved: stats.reserved,
+            };
+            GFX_MEM_SUCCESS
+        }
+        None => GFX_MEM_ERROR_INVALID_ARGUMENT,
+    }
+}
+
+#[doc(hidden)]
+pub unsafe fn type_stats<B: Backend>(
+    allocator: *const FfiAllocator<B>,
+    memory_type: usize,
+    out: *mut GfxMemTypeStats,
+) -> i32 {
+    if allocator.is_null() || out.is_null() {
+        return GFX_MEM_ERROR_INVALID_ARGUMENT;
+    }
+    match (*allocator).allocator.stats().types.get(memory_type) {
+        Some(stats) => {
+            *out = GfxMemTypeStats {
+                used: stats.used,
+                blocks: stats.blocks as u64,
+                allocated: stats.allocated,
+                allocations: stats.allocations as u64,
+            };
+            GFX_MEM_SUCCESS
+        }
+        None => GFX_MEM_ERROR_INVALID_ARGUMENT,
+    }
+}
+
+/// Export C API for the specified backend.
+/// Can be used only once per linked binary since exported names are fixed.
+#[macro_export]
+macro_rules! gfx_mem_ffi {
+    ($backend:ty) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn gfx_mem_create(
+            types: *const $crate::ffi::GfxMemType,
+            type_count: usize,
+            heaps: *const u64,
+            heap_count: usize,
+            config: *const $crate::ffi::GfxMemConfig,
+            out: *mut *mut $crate::ffi::FfiAllocator<$backend>,
+        ) -> i32 {
+            $crate::ffi::guard(|| {
+                $crate::ffi::create::<$backend>(types, type_count, heaps, heap_count, config, out)
+            })
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn gfx_mem_destroy(
+            allocator: *mut $crate::ffi::FfiAllocator<$backend>,
+            device: *const <$backend as $crate::ffi::Backend>::Device,
+        ) -> i32 {
+            $crate::ffi::guard(|| $crate::ffi::destroy::<$backend>(allocator, device))
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn gfx_mem_alloc(
+            allocator: *mut $crate::ffi::FfiAllocator<$backend>,
+            device: *const <$backend as $crate::ffi::Backend>::Device,
+            request: *const $crate::ffi::GfxMemRequest,
+            out: *mut $crate::ffi::GfxMemBlock,
+        ) -> i32 {
+            $crate::ffi::guard(|| $crate::ffi::alloc::<$backend>(allocator, device, request, out))
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn gfx_mem_free(
+            allocator: *mut $crate::ffi::FfiAllocator<$backend>,
+            device: *const <$backend as $crate::ffi::Backend>::Device,
+            handle: u64,
+        ) -> i32 {
+            $crate::ffi::guard(|| $crate::ffi::free::<$backend>(allocator, device, handle))
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn gfx_mem_heap_stats(
+            allocator: *const $crate::ffi::FfiAllocator<$backend>,
+            heap: usize,
+            out: *mut $crate::ffi::GfxMemHeapStats,
+        ) -> i32 {
+            $crate::ffi::guard(|| $crate::ffi::heap_stats::<$backend>(allocator, heap, out))
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn gfx_mem_type_stats(
+            allocator: *const $crate::ffi::FfiAllocator<$backend>,
+            memory_type: usize,
+            out: *mut $crate::ffi::GfxMemTypeStats,
+        ) -> i32 {
+            $crate::ffi::guard(|| $crate::ffi::type_stats::<$backend>(allocator, memory_type, out))
+        }
+    };
+}
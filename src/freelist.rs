@@ -0,0 +1,296 @@
+use std::mem::replace;
+use std::ops::Range;
+
+use gfx_hal::{Backend, MemoryTypeId};
+use gfx_hal::memory::Requirements;
+
+use {alignment_shift, freed, validate, MemoryAllocator, MemoryError, MemorySubAllocator};
+use block::{Block, RawBlock};
+use virt::VirtualAllocator;
+
+/// Allocator for medium sized blocks of arbitrary lifetime.
+///
+/// Pages are allocated from the owner and managed with a first-fit free-list, see
+/// `VirtualAllocator`. Blocks are not rounded up, which wastes less memory than
+/// `ChunkedAllocator` for sizes that are far from powers of two. Pages are returned to the
+/// owner as soon as their last block is freed.
+///
+/// ### Type parameters:
+///
+/// - `T`: type of blocks allocated from the owner
+#[derive(Debug)]
+pub struct FreeListAllocator<T> {
+    id: MemoryTypeId,
+    page_size: u64,
+    /// Pages allocated from the owner. Slots of freed pages are reused.
+    pages: Vec<Option<(T, VirtualAllocator)>>,
+}
+
+impl<T> FreeListAllocator<T> {
+    /// Create a new free-list allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `page_size`: size in bytes of pages allocated from the owner, larger requests get
+    ///   pages of the multiple of this size
+    /// - `id`: hal memory type
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `page_size` is `0`.
+    pub fn new(page_size: u64, id: MemoryTypeId) -> Self {
+        assert_ne!(page_size, 0, "Page size must not be 0");
+        FreeListAllocator {
+            id,
+            page_size,
+            pages: Vec::new(),
+        }
+    }
+
+    /// Check if any of the blocks allocated by this allocator are still in use.
+    /// If this function returns `false`, the allocator can be `dispose`d.
+    pub fn is_used(&self) -> bool {
+        self.pages.iter().any(Option::is_some)
+    }
+
+    /// Get number of blocks in use.
+    pub fn live_blocks(&self) -> usize {
+        self.pages
+            .iter()
+            .filter_map(Option::as_ref)
+            .map(|&(_, ref ranges)| ranges.allocations())
+            .sum()
+    }
+
+    /// Get memory type of the allocator
+    pub fn memory_type(&self) -> MemoryTypeId {
+        self.id
+    }
+
+    /// Get size of the page
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    /// Set size of pages allocated after the call.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `page_size` is `0`.
+    pub fn set_page_size(&mut self, page_size: u64) {
+        assert_ne!(page_size, 0, "Page size must not be 0");
+        self.page_size = page_size;
+    }
+
+    /// Estimate how many bytes would be requested from the owner to allocate blocks
+    /// for all `reqs`, starting from an empty allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `reqs`: requirements of the blocks
+    pub fn estimate_footprint(&self, reqs: &[Requirements]) -> u64 {
+        let mut total = 0;
+        let mut page = 0;
+        let mut used = 0;
+        for reqs in reqs {
+            let size = reqs.size.saturating_add(alignment_shift(reqs.alignment, used));
+            if page.saturating_sub(used) < size {
+                page = self.page_size_for(reqs.size).unwrap_or(!0);
+                used = reqs.size;
+                total = total.saturating_add(page);
+            } else {
+                used += size;
+            }
+        }
+        total
+    }
+
+    /// Dispose of this allocator, returning all memory to the owner, even if blocks allocated
+    /// from it are still in use.
+    ///
+    /// Blocks that are still in use are invalidated. They must not be used or freed afterwards
+    /// and should be leaked with `std::mem::forget`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the pages
+    /// - `device`: must be the same device all allocations have been made against
+    pub fn force_dispose<B, A>(self, owner: &mut A, device: &B::Device)
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        for (block, _) in self.pages.into_iter().filter_map(|page| page) {
+            owner.free(device, block);
+        }
+    }
+
+    /// Extend the block in place, if the space right after it in its page is free.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `block`: block allocated from this allocator
+    /// - `size`: new size of the block, not less than the current one
+    ///
+    /// ### Returns
+    ///
+    /// Returns `true` if the block was extended.
+    pub fn grow_in_place<B>(&mut self, block: &mut FreeListBlock<B>, size: u64) -> bool
+    where
+        B: Backend,
+        T: Block<B>,
+    {
+        self.grow_raw(&mut block.0, block.1, size)
+    }
+
+    /// Extend the block with the tag in place. See `grow_in_place`.
+    pub(crate) fn grow_raw<B>(&mut self, block: &mut RawBlock<B>, tag: usize, size: u64) -> bool
+    where
+        B: Backend,
+        T: Block<B>,
+    {
+        let (page, free) = match self.pages.get_mut(tag) {
+            Some(&mut Some((ref page, ref mut free))) => (page, free),
+            _ => return false,
+        };
+        if !page.contains(&*block) {
+            return false;
+        }
+        let start = page.range().start;
+        let range = block.range();
+        match free.grow(range.start - start..range.end - start, size) {
+            Ok(grown) => {
+                let grown = RawBlock::new(page.memory(), start + grown.start..start + grown.end);
+                unsafe { replace(block, grown).dispose() };
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Get size of the page for a block of specified size, `None` if it overflows.
+    fn page_size_for(&self, size: u64) -> Option<u64> {
+        (size.saturating_sub(1) / self.page_size + 1).checked_mul(self.page_size)
+    }
+}
+
+impl<B, O, T> MemorySubAllocator<B, O> for FreeListAllocator<T>
+where
+    B: Backend,
+    T: Block<B>,
+    O: MemoryAllocator<B, Block = T>,
+{
+    type Request = O::Request;
+    type Block = FreeListBlock<B>;
+
+    fn alloc(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        request: O::Request,
+        reqs: Requirements,
+    ) -> Result<FreeListBlock<B>, MemoryError> {
+        validate(&reqs, 1 << self.id.0)?;
+        for (index, page) in self.pages.iter_mut().enumerate() {
+            if let Some((ref block, ref mut free)) = *page {
+                if let Ok(range) = free.alloc(reqs.size, reqs.alignment) {
+                    let start = block.range().start;
+                    let range = start + range.start..start + range.end;
+                    return Ok(FreeListBlock(RawBlock::new(block.memory(), range), index));
+                }
+            }
+        }
+
+        let page_requirements = Requirements {
+            type_mask: 1 << self.id.0,
+            size: self.page_size_for(reqs.size)
+                .ok_or(MemoryError::OutOfMemory)?,
+            alignment: reqs.alignment,
+        };
+        let page = owner.alloc(device, request, page_requirements)?;
+        let mut free = VirtualAllocator::new(page.size());
+        let range = free
+            .alloc(reqs.size, reqs.alignment)
+            .expect("Page fits the block");
+        let start = page.range().start;
+        let block = RawBlock::new(page.memory(), start + range.start..start + range.end);
+        let index = match self.pages.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.pages.push(None);
+                self.pages.len() - 1
+            }
+        };
+        self.pages[index] = Some((page, free));
+        Ok(FreeListBlock(block, index))
+    }
+
+    fn free(&mut self, owner: &mut O, device: &B::Device, block: FreeListBlock<B>) {
+        freed(self.try_free(owner, device, block));
+    }
+
+    fn try_free(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        block: FreeListBlock<B>,
+    ) -> Result<(), MemoryError> {
+        let FreeListBlock(block, index) = block;
+        let range = match self.pages.get(index) {
+            Some(&Some((ref page, _))) if page.contains(&block) => {
+                let start = page.range().start;
+                block.range().start - start..block.range().end - start
+            }
+            _ => {
+                unsafe { block.dispose() };
+                return Err(MemoryError::InvalidFree);
+            }
+        };
+        unsafe { block.dispose() };
+
+        let empty = {
+            let free = &mut self.pages[index].as_mut().expect("Checked above").1;
+            free.try_free(range).map_err(|_| MemoryError::InvalidFree)?;
+            !free.is_used()
+        };
+        if empty {
+            let (page, _) = self.pages[index].take().expect("Checked above");
+            owner.free(device, page);
+        }
+        Ok(())
+    }
+
+    fn dispose(self, _owner: &mut O, _device: &B::Device) -> Result<(), Self> {
+        if self.is_used() {
+            Err(self)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Opaque type for `Block` tag used by the `FreeListAllocator`.
+///
+/// `FreeListAllocator` places this tag on the memory blocks, and then use it in
+/// `free` to find the page the block was allocated from.
+#[derive(Debug)]
+pub struct FreeListBlock<B: Backend>(pub(crate) RawBlock<B>, pub(crate) usize);
+
+impl<B> Block<B> for FreeListBlock<B>
+where
+    B: Backend,
+{
+    /// Get memory of the block.
+    #[inline(always)]
+    fn memory(&self) -> &B::Memory {
+        // Has to be valid
+        self.0.memory()
+    }
+
+    /// Get memory range of the block.
+    #[inline(always)]
+    fn range(&self) -> Range<u64> {
+        self.0.range()
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use gfx_hal::Backend;
+use gfx_hal::memory::Properties;
+
+use layout::RootBlock;
+use observer::BlockInfo;
+use registry::LiveAllocation;
+use smart::{SmartAllocator, SmartBlock};
+use stats::AllocatorStats;
+use usage::MemoryUsage;
+
+/// `SmartAllocator` that can't allocate or free blocks until it is thawed.
+/// See `SmartAllocator::freeze`.
+///
+/// All methods take `&self`, so the allocator can be shared between threads by reference,
+/// i.e. ones recording command buffers in parallel, to look up block metadata and statistics.
+/// Blocks are mapped through their memory and don't need the allocator.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+#[derive(Debug)]
+pub struct FrozenAllocator<B: Backend> {
+    allocator: SmartAllocator<B>,
+}
+
+impl<B> FrozenAllocator<B>
+where
+    B: Backend,
+{
+    pub(crate) fn new(allocator: SmartAllocator<B>) -> Self {
+        FrozenAllocator { allocator }
+    }
+
+    /// Resume allocating and freeing blocks.
+    pub fn thaw(self) -> SmartAllocator<B> {
+        self.allocator
+    }
+
+    /// Collect statistics of the allocator. See `SmartAllocator::stats`.
+    pub fn stats(&self) -> AllocatorStats {
+        self.allocator.stats()
+    }
+
+    /// Get memory usage counters. See `SmartAllocator::usage`.
+    pub fn usage(&self) -> Arc<MemoryUsage> {
+        self.allocator.usage()
+    }
+
+    /// Get number of frames marked before the allocator was frozen.
+    pub fn frame(&self) -> u64 {
+        self.allocator.frame()
+    }
+
+    /// Get information about the block. See `SmartAllocator::block_info`.
+    pub fn block_info(&self, block: &SmartBlock<B>) -> BlockInfo {
+        self.allocator.block_info(block)
+    }
+
+    /// Get properties of the memory type the block is allocated from.
+    pub fn properties(&self, block: &SmartBlock<B>) -> Properties {
+        self.allocator.properties(block)
+    }
+
+    /// Get all live blocks in allocation order. See `SmartAllocator::live_allocations`.
+    pub fn live_allocations(&self) -> Vec<LiveAllocation<B>> {
+        self.allocator.live_allocations()
+    }
+
+    /// Get memory objects with live blocks and free ranges in them.
+    /// See `SmartAllocator::root_blocks`.
+    pub fn root_blocks(&self) -> Vec<RootBlock<B>> {
+        self.allocator.root_blocks()
+    }
+}
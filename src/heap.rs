@@ -0,0 +1,257 @@
+use std::cmp::min;
+
+use observer::PRESSURE_LEVELS;
+
+/// Policy of `SmartAllocator` for a memory heap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeapPolicy {
+    /// Allow allocating beyond the heap size, trusting the driver to page memory out.
+    /// Otherwise allocations fail with `MemoryError::OutOfMemory` once the heap is full.
+    pub oversubscribe: bool,
+
+    /// Fraction of the heap size at which usage warning is emitted, `None` disables the warning.
+    pub warn_threshold: Option<f32>,
+
+    /// Bytes of usage to try to stay below, i.e. the share of the heap the driver recommends.
+    /// Crossing it emits `PressureEvent::SoftTarget` and returns unused memory of the heap
+    /// to the device. `None` disables the target.
+    pub soft_target: Option<u64>,
+
+    /// Bytes of usage allocations fail beyond with `MemoryError::OutOfMemory`, even if the
+    /// heap is oversubscribed. `None` limits usage by the heap size only.
+    pub hard_limit: Option<u64>,
+}
+
+impl Default for HeapPolicy {
+    fn default() -> Self {
+        HeapPolicy {
+            oversubscribe: false,
+            warn_threshold: Some(0.9),
+            soft_target: None,
+            hard_limit: None,
+        }
+    }
+}
+
+/// Heap space set aside for allocations made outside of the allocator, i.e. by the driver
+/// or other libraries. See `SmartAllocator::reserve_system_budget`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SystemBudget {
+    /// Label of the system the space is set aside for.
+    pub label: String,
+
+    /// Number of bytes set aside.
+    pub bytes: u64,
+}
+
+/// Memory heap usage tracked by `SmartAllocator`.
+#[derive(Debug)]
+pub(crate) struct Heap {
+    size: u64,
+    /// Used bytes per physical device.
+    used: Vec<u64>,
+    /// Bytes held back by reservations.
+    reserved: u64,
+    /// Bytes of live blocks beyond their requested sizes, included in `used`.
+    padding: u64,
+    /// Space permanently set aside for other systems.
+    budgets: Vec<SystemBudget>,
+    policy: HeapPolicy,
+    /// Usage is above the warning threshold.
+    pressure: bool,
+    /// Number of `PRESSURE_LEVELS` usage is above.
+    level: usize,
+    /// Usage is above the soft target.
+    over_target: bool,
+}
+
+impl Heap {
+    pub(crate) fn new(size: u64, devices: usize) -> Self {
+        Heap {
+            size,
+            used: vec![0; devices],
+            reserved: 0,
+            padding: 0,
+            budgets: Vec::new(),
+            policy: HeapPolicy::default(),
+            pressure: false,
+            level: 0,
+            over_target: false,
+        }
+    }
+
+    pub(crate) fn policy(&self) -> HeapPolicy {
+        self.policy
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: HeapPolicy) {
+        self.policy = policy;
+    }
+
+    /// Check if there is enough available memory for an allocation, or the policy allows
+    /// oversubscription, and the allocation stays within the hard limit.
+    pub(crate) fn fits(&self, available: u64, size: u64) -> bool {
+        let limited = self.policy
+            .hard_limit
+            .map_or(true, |limit| {
+                self.used()
+                    .checked_add(size)
+                    .map_or(false, |used| used <= limit)
+            });
+        limited && (self.policy.oversubscribe || available >= size)
+    }
+
+    /// Update soft target state after usage changed.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `true` if usage just crossed the soft target upwards.
+    pub(crate) fn update_target(&mut self) -> bool {
+        let over_target = self.policy
+            .soft_target
+            .map_or(false, |target| self.used() > target);
+        let crossed = over_target && !self.over_target;
+        self.over_target = over_target;
+        crossed
+    }
+
+    /// Get bytes of usage above the soft target, `0` if there is no target.
+    pub(crate) fn above_target(&self) -> u64 {
+        self.policy
+            .soft_target
+            .map_or(0, |target| self.used().saturating_sub(target))
+    }
+
+    /// Update pressure state after usage changed.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `true` if usage just crossed the warning threshold.
+    pub(crate) fn update_pressure(&mut self) -> bool {
+        let pressure = match self.policy.warn_threshold {
+            Some(threshold) => self.used() as f64 >= threshold as f64 * self.size as f64,
+            None => false,
+        };
+        let crossed = pressure && !self.pressure;
+        self.pressure = pressure;
+        crossed
+    }
+
+    /// Get size of the heap without system budgets.
+    pub(crate) fn budget(&self) -> u64 {
+        self.size.saturating_sub(self.budgeted())
+    }
+
+    /// Update pressure level after usage changed.
+    ///
+    /// ### Returns
+    ///
+    /// Returns the highest of `PRESSURE_LEVELS` usage just crossed upwards.
+    pub(crate) fn update_level(&mut self) -> Option<f32> {
+        let used = self.used() as f64;
+        let budget = self.budget() as f64;
+        let level = PRESSURE_LEVELS
+            .iter()
+            .take_while(|&&level| used >= level as f64 * budget)
+            .count();
+        let crossed = if level > self.level {
+            Some(PRESSURE_LEVELS[level - 1])
+        } else {
+            None
+        };
+        self.level = level;
+        crossed
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn devices(&self) -> usize {
+        self.used.len()
+    }
+
+    /// Get used bytes, maximum across physical devices.
+    pub(crate) fn used(&self) -> u64 {
+        self.used.iter().cloned().max().unwrap_or(0)
+    }
+
+    pub(crate) fn reserved(&self) -> u64 {
+        self.reserved
+    }
+
+    pub(crate) fn padding(&self) -> u64 {
+        self.padding
+    }
+
+    pub(crate) fn budgets(&self) -> &[SystemBudget] {
+        &self.budgets
+    }
+
+    /// Get bytes set aside for other systems.
+    pub(crate) fn budgeted(&self) -> u64 {
+        self.budgets.iter().map(|budget| budget.bytes).sum()
+    }
+
+    /// Set aside space for the labelled system. Budgets with the same label add up.
+    pub(crate) fn add_budget(&mut self, label: &str, bytes: u64) {
+        if let Some(budget) = self.budgets
+            .iter_mut()
+            .find(|budget| budget.label == label)
+        {
+            budget.bytes += bytes;
+            return;
+        }
+        self.budgets.push(SystemBudget {
+            label: label.to_owned(),
+            bytes,
+        });
+    }
+
+    pub(crate) fn available(&self, mask: u32) -> u64 {
+        let mut available = self.size;
+        for (index, &used) in self.used.iter().enumerate() {
+            if mask & (1 << index) != 0 {
+                available = min(available, self.size.saturating_sub(used));
+            }
+        }
+        available.saturating_sub(self.reserved + self.budgeted())
+    }
+
+    /// Account a block of `size` bytes, `padding` of which are beyond the requested size.
+    pub(crate) fn alloc(&mut self, mask: u32, size: u64, padding: u64) {
+        for (index, used) in self.used.iter_mut().enumerate() {
+            if mask & (1 << index) != 0 {
+                *used += size;
+            }
+        }
+        self.padding += padding;
+    }
+
+    pub(crate) fn free(&mut self, mask: u32, size: u64, padding: u64) {
+        for (index, used) in self.used.iter_mut().enumerate() {
+            if mask & (1 << index) != 0 {
+                *used -= size;
+            }
+        }
+        self.padding -= padding;
+    }
+
+    pub(crate) fn reserve(&mut self, size: u64) {
+        self.reserved += size;
+    }
+
+    pub(crate) fn unreserve(&mut self, size: u64) {
+        self.reserved -= size;
+    }
+
+    pub(crate) fn usage(&self, mask: u32) -> f32 {
+        let mut usage = 0.0f32;
+        for (index, &used) in self.used.iter().enumerate() {
+            if mask & (1 << index) != 0 {
+                usage = usage.max(used as f32 / self.size as f32);
+            }
+        }
+        usage
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use gfx_hal::{Backend, MemoryTypeId};
+use gfx_hal::memory::Requirements;
+
+use {alignment_shift, freed, MemoryAllocator, MemoryError};
+use block::{Block, RawBlock};
+
+/// Allocator of host memory standing in for device memory, so that asset pipelines and
+/// tests can run the same resource code on machines without GPUs, i.e. with
+/// `gfx-backend-empty`.
+///
+/// Blocks are placed in a placeholder memory object at offsets that satisfy requested
+/// alignments, and their contents live in host memory accessed with `bytes` and
+/// `bytes_mut`. The device is never used.
+///
+/// Can serve as the memory source of a `RootAllocator`, so that whole allocator stacks run
+/// on host memory. See `SmartAllocator::set_host_memory`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+#[derive(Debug)]
+pub struct HostAllocator<B: Backend> {
+    id: MemoryTypeId,
+    /// Placeholder memory object the blocks refer to.
+    memory: Box<B::Memory>,
+    /// End of the last block in the placeholder memory object.
+    cursor: u64,
+    /// Contents of live blocks by their offsets.
+    live: BTreeMap<u64, HostData>,
+}
+
+/// Contents of a block, over-allocated to align the data.
+#[derive(Debug)]
+struct HostData {
+    data: Box<[u8]>,
+    /// Offset of the aligned data.
+    offset: usize,
+    size: usize,
+}
+
+impl<B> HostAllocator<B>
+where
+    B: Backend,
+    B::Memory: Default,
+{
+    /// Create a host allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `id`: memory type to report for the blocks
+    pub fn new(id: MemoryTypeId) -> Self {
+        HostAllocator {
+            id,
+            memory: Box::new(B::Memory::default()),
+            cursor: 0,
+            live: BTreeMap::new(),
+        }
+    }
+}
+
+impl<B> HostAllocator<B>
+where
+    B: Backend,
+{
+    /// Get memory type of the blocks.
+    pub fn memory_type(&self) -> MemoryTypeId {
+        self.id
+    }
+
+    /// Get total size of live blocks.
+    pub fn used(&self) -> u64 {
+        self.live.values().map(|data| data.size as u64).sum()
+    }
+
+    /// Get number of live blocks.
+    pub fn live_blocks(&self) -> usize {
+        self.live.len()
+    }
+
+    /// Get contents of the block or of a block sub-allocated from it. Data of blocks allocated
+    /// from this allocator is aligned to the alignment they were requested with.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidFree` if the block is not within a block allocated from
+    /// this allocator.
+    pub fn bytes<T>(&self, block: &T) -> Result<&[u8], MemoryError>
+    where
+        T: Block<B>,
+    {
+        let (start, range) = self.locate(block)?;
+        let data = &self.live[&start];
+        Ok(&data.data[data.offset + range.start..data.offset + range.end])
+    }
+
+    /// Get mutable contents of the block. See `bytes`.
+    pub fn bytes_mut<T>(&mut self, block: &T) -> Result<&mut [u8], MemoryError>
+    where
+        T: Block<B>,
+    {
+        let (start, range) = self.locate(block)?;
+        let data = self.live.get_mut(&start).expect("Located above");
+        Ok(&mut data.data[data.offset + range.start..data.offset + range.end])
+    }
+
+    /// Find the live block containing the block. Returns its offset and the range of the block
+    /// relative to it.
+    fn locate<T>(&self, block: &T) -> Result<(u64, Range<usize>), MemoryError>
+    where
+        T: Block<B>,
+    {
+        if !::std::ptr::eq(block.memory(), &*self.memory) {
+            return Err(MemoryError::InvalidFree);
+        }
+        let range = block.range();
+        match self.live.range(..range.start + 1).next_back() {
+            Some((&start, data)) if range.end <= start + data.size as u64 => {
+                Ok((start, (range.start - start) as usize..(range.end - start) as usize))
+            }
+            _ => Err(MemoryError::InvalidFree),
+        }
+    }
+}
+
+impl<B> MemoryAllocator<B> for HostAllocator<B>
+where
+    B: Backend,
+{
+    type Request = ();
+    type Block = RawBlock<B>;
+
+    fn alloc(
+        &mut self,
+        _device: &B::Device,
+        _: (),
+        reqs: Requirements,
+    ) -> Result<RawBlock<B>, MemoryError> {
+        if (1 << self.id.0) & reqs.type_mask == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        let alignment = reqs.alignment.max(1);
+        let start = self.cursor + alignment_shift(alignment, self.cursor);
+        // Blocks never share offsets, even empty ones.
+        let end = start + reqs.size.max(1);
+        let data = vec![0u8; (reqs.size + alignment - 1) as usize].into_boxed_slice();
+        let offset = alignment_shift(alignment, data.as_ptr() as u64) as usize;
+        self.cursor = end;
+        self.live.insert(
+            start,
+            HostData {
+                data,
+                offset,
+                size: reqs.size as usize,
+            },
+        );
+        Ok(RawBlock::new(&*self.memory, start..start + reqs.size))
+    }
+
+    fn free(&mut self, device: &B::Device, block: RawBlock<B>) {
+        freed(self.try_free(device, block));
+    }
+
+    fn try_free(&mut self, _device: &B::Device, block: RawBlock<B>) -> Result<(), MemoryError> {
+        let range = block.range();
+        let owned = ::std::ptr::eq(block.memory(), &*self.memory)
+            && self.live
+                .get(&range.start)
+                .map_or(false, |data| data.size as u64 == range.end - range.start);
+        unsafe { block.dispose() };
+        if !owned {
+            return Err(MemoryError::InvalidFree);
+        }
+        self.live.remove(&range.start);
+        Ok(())
+    }
+
+    fn is_used(&self) -> bool {
+        !self.live.is_empty()
+    }
+
+    fn dispose(self, _device: &B::Device) -> Result<(), Self> {
+        if self.is_used() {
+            Err(self)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::ops::Range;
+
+use gfx_hal::Backend;
+use gfx_hal::memory::{Properties, Requirements};
+
+use {MemoryAllocator, MemoryError};
+use block::Block;
+use combined::Type;
+use smart::{SmartAllocator, SmartBlock, SmartRequest};
+
+/// Intended usage of the memory in the style of usage enums of other GPU allocators.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UsageHint {
+    /// Memory accessed by the device only. Requires `DEVICE_LOCAL`.
+    GpuOnly,
+
+    /// Memory written by the host once and read by the device, i.e. staging buffers.
+    /// Requires `CPU_VISIBLE`, allocated as `Type::ShortLived`.
+    CpuOnly,
+
+    /// Memory written by the host frequently and read by the device, i.e. uniform buffers.
+    /// Requires `CPU_VISIBLE`, prefers `DEVICE_LOCAL`.
+    CpuToGpu,
+
+    /// Memory written by the device and read by the host, i.e. readback buffers.
+    /// Requires `CPU_VISIBLE`, prefers `CPU_CACHED`.
+    GpuToCpu,
+}
+
+impl UsageHint {
+    /// Get sub-allocator type the usage is served with.
+    pub fn ty(&self) -> Type {
+        match *self {
+            UsageHint::CpuOnly => Type::ShortLived,
+            UsageHint::GpuOnly | UsageHint::CpuToGpu | UsageHint::GpuToCpu => Type::General,
+        }
+    }
+
+    /// Get properties the memory type must have.
+    pub fn required(&self) -> Properties {
+        match *self {
+            UsageHint::GpuOnly => Properties::DEVICE_LOCAL,
+            UsageHint::CpuOnly | UsageHint::CpuToGpu | UsageHint::GpuToCpu => {
+                Properties::CPU_VISIBLE
+            }
+        }
+    }
+
+    /// Get properties the memory type should have if possible.
+    pub fn preferred(&self) -> Properties {
+        match *self {
+            UsageHint::GpuOnly | UsageHint::CpuOnly => Properties::empty(),
+            UsageHint::CpuToGpu => Properties::DEVICE_LOCAL,
+            UsageHint::GpuToCpu => Properties::CPU_CACHED,
+        }
+    }
+}
+
+/// Allocation handle in the style of other GPU allocators: memory object, offset and size.
+///
+/// Memory object and size are available through the `Block` trait.
+#[derive(Debug)]
+pub struct Allocation<B: Backend> {
+    block: SmartBlock<B>,
+}
+
+impl<B> Allocation<B>
+where
+    B: Backend,
+{
+    /// Get offset of the allocation in the memory object.
+    pub fn offset(&self) -> u64 {
+        self.block.range().start
+    }
+
+    /// Get the underlying block.
+    pub fn block(&self) -> &SmartBlock<B> {
+        &self.block
+    }
+
+    /// Unwrap the underlying block.
+    pub fn into_block(self) -> SmartBlock<B> {
+        self.block
+    }
+}
+
+impl<B> Block<B> for Allocation<B>
+where
+    B: Backend,
+{
+    #[inline(always)]
+    fn memory(&self) -> &B::Memory {
+        self.block.memory()
+    }
+
+    #[inline(always)]
+    fn range(&self) -> Range<u64> {
+        self.block.range()
+    }
+}
+
+/// Allocator interface in the style of other GPU allocators: requests are described with
+/// usage hints instead of sub-allocator types and memory properties.
+///
+/// Middleware written against this interface can be backed by the `SmartAllocator`.
+pub trait GpuAllocator<B: Backend> {
+    /// Allocate memory for the resource with specified requirements.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `reqs`: the requirements of the resource
+    /// - `usage`: intended usage of the memory
+    fn allocate(
+        &mut self,
+        device: &B::Device,
+        reqs: Requirements,
+        usage: UsageHint,
+    ) -> Result<Allocation<B>, MemoryError>;
+
+    /// Free memory allocated with `allocate`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the memory
+    /// - `allocation`: allocation to free
+    fn deallocate(&mut self, device: &B::Device, allocation: Allocation<B>);
+}
+
+impl<B> GpuAllocator<B> for SmartAllocator<B>
+where
+    B: Backend,
+{
+    /// Allocate from memory type with preferred properties if possible, and fall back to
+    /// memory type with required properties only.
+    fn allocate(
+        &mut self,
+        device: &B::Device,
+        reqs: Requirements,
+        usage: UsageHint,
+    ) -> Result<Allocation<B>, MemoryError> {
+        let required = usage.required();
+        let preferred = required | usage.preferred();
+        let block = if preferred != required {
+            match self.alloc(device, SmartRequest::new(usage.ty(), preferred), reqs) {
+                Ok(block) => block,
+                Err(_) => self.alloc(device, SmartRequest::new(usage.ty(), required), reqs)?,
+            }
+        } else {
+            self.alloc(device, SmartRequest::new(usage.ty(), required), reqs)?
+        };
+        Ok(Allocation { block })
+    }
+
+    fn deallocate(&mut self, device: &B::Device, allocation: Allocation<B>) {
+        self.free(device, allocation.block);
+    }
+}
@@ -0,0 +1,98 @@
+use std::ops::Range;
+use std::slice;
+
+use gfx_hal::{Backend, MemoryTypeId};
+
+use registry::LiveAllocation;
+
+/// Range of a memory object in its layout. See `RootBlock::layout`.
+#[derive(Debug)]
+pub enum Occupancy<'a, B: Backend> {
+    /// Range of a live block, with its name and category.
+    Used(LiveAllocation<'a, B>),
+
+    /// Range without live blocks. Memory kept by sub-allocators for future blocks, padding
+    /// and alignment gaps are all reported as free.
+    Free(Range<u64>),
+}
+
+impl<'a, B> Occupancy<'a, B>
+where
+    B: Backend,
+{
+    /// Get the range relative to the memory object.
+    pub fn range(&self) -> Range<u64> {
+        match *self {
+            Occupancy::Used(ref allocation) => allocation.info.range.clone(),
+            Occupancy::Free(ref range) => range.clone(),
+        }
+    }
+}
+
+/// Memory object allocated from the device, with live blocks and free ranges in it, i.e. to
+/// draw memory maps in debug UIs. See `SmartAllocator::root_blocks`.
+#[derive(Debug)]
+pub struct RootBlock<'a, B: Backend> {
+    /// Memory object.
+    pub memory: &'a B::Memory,
+
+    /// Memory type of the memory object.
+    pub memory_type: MemoryTypeId,
+
+    /// Size of the memory object.
+    pub size: u64,
+
+    layout: Vec<Occupancy<'a, B>>,
+    /// End of the last pushed range.
+    cursor: u64,
+}
+
+impl<'a, B> RootBlock<'a, B>
+where
+    B: Backend,
+{
+    pub(crate) fn new(memory: &'a B::Memory, memory_type: MemoryTypeId, size: u64) -> Self {
+        RootBlock {
+            memory,
+            memory_type,
+            size,
+            layout: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    /// Add a live block. Blocks must be added in order of their offsets.
+    pub(crate) fn push(&mut self, allocation: LiveAllocation<'a, B>) {
+        let range = allocation.info.range.clone();
+        if range.start > self.cursor {
+            self.layout.push(Occupancy::Free(self.cursor..range.start));
+        }
+        self.cursor = self.cursor.max(range.end);
+        self.layout.push(Occupancy::Used(allocation));
+    }
+
+    /// Add the free range after the last live block.
+    pub(crate) fn finish(&mut self) {
+        if self.size > self.cursor {
+            self.layout.push(Occupancy::Free(self.cursor..self.size));
+            self.cursor = self.size;
+        }
+    }
+
+    /// Iterate over live blocks and free ranges in order of their offsets.
+    /// Blocks aliasing the same memory may overlap.
+    pub fn layout(&self) -> slice::Iter<Occupancy<'a, B>> {
+        self.layout.iter()
+    }
+
+    /// Get total size of free ranges.
+    pub fn free(&self) -> u64 {
+        self.layout
+            .iter()
+            .filter_map(|occupancy| match *occupancy {
+                Occupancy::Free(ref range) => Some(range.end - range.start),
+                Occupancy::Used(_) => None,
+            })
+            .sum()
+    }
+}
@@ -25,7 +25,7 @@
 //!     // Ger memory requirements for the buffer.
 //!     let reqs = device.get_buffer_requirements(&ubuf);
 //!     // Allocate block of device-local memory that satisfy requirements for buffer.
-//!     let block = allocator.alloc(device, (Type::General, Properties::DEVICE_LOCAL), reqs).map_err(Box::new)?;
+//!     let block = allocator.alloc(device, (Type::General, Properties::DEVICE_LOCAL).into(), reqs).map_err(Box::new)?;
 //!     // Bind memory block to the buffer.
 //!     Ok(device.bind_buffer_memory(block.memory(), block.range().start, ubuf)
 //!              .map(|buffer| (block, buffer))
@@ -41,15 +41,56 @@
 #![deny(unused_must_use)]
 
 extern crate gfx_hal;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "parking_lot")]
+extern crate parking_lot;
 extern crate relevant;
 
+pub use adopted::AdoptedAllocator;
+pub use alias::{alias_barriers, AliasBarrier, AliasedResource};
 pub use arena::{ArenaAllocator, ArenaBlock};
 pub use block::{Block, RawBlock};
 pub use chunked::{ChunkedAllocator, ChunkedBlock};
-pub use combined::{CombinedAllocator, CombinedBlock, Type};
+pub use combined::{CombinedAllocator, CombinedBlock, DisposeError, LeakMode, Routing,
+                   SubAllocatorKind, Type};
+pub use config::{ColdEviction, PartialConfig, SmartAllocatorConfig, TrimStep};
+pub use debug::DebugAllocator;
+pub use defrag::{record_move_copies, Compaction, CompactionReport, MoveCopy};
 pub use factory::{Factory, FactoryError, Item};
-pub use root::RootAllocator;
-pub use smart::{SmartAllocator, SmartBlock};
+pub use freelist::{FreeListAllocator, FreeListBlock};
+pub use frozen::FrozenAllocator;
+pub use heap::{HeapPolicy, SystemBudget};
+pub use host::HostAllocator;
+pub use interop::{Allocation, GpuAllocator, UsageHint};
+pub use layout::{Occupancy, RootBlock};
+pub use linear::{LinearAllocator, LinearFactory};
+pub use mapping::{Coherence, MappedRange, MappedSlice, Pod, Upload};
+pub use multi::MultiBuffered;
+pub use names::NameIds;
+pub use observer::{AllocObserver, AllocRetry, BlockInfo, GrowthHint, PressureEvent,
+                   SubAllocatorGrowth, PRESSURE_LEVELS};
+pub use plugin::{CustomSubAllocator, SubAllocatorFactory};
+pub use plan::{PlannedAllocation, ReallocationPlan};
+pub use quirks::{find_quirk, Quirk, TypeSpread};
+pub use readback::{ReadbackCallback, ReadbackPool};
+pub use registry::{LeakReport, LiveAllocation, StaleBlock};
+pub use request::{request_for_buffer, request_for_image, Dedicated, HostAccess, Lifetime,
+                  ResourceKind};
+pub use reservation::Reservation;
+pub use root::{RetryPolicy, RootAllocator};
+pub use shared::{Freer, SharedAllocator, SharedBlock};
+pub use simulate::{simulate_configs, SimReport, TraceEvent};
+pub use smart::{Footprint, MisuseMode, Realloc, SmartAllocator, SmartBlock, SmartRequest};
+pub use stats::{AllocatorStats, CategoryStats, HeapDelta, HeapStats, LifetimeStats,
+                MeasureReport, RequestHistogram, ResourceStats, SizeClassStats, StatsAggregator,
+                StatsDelta, StatsSummary, TypeDelta, TypeStats};
+pub use sparse::{SparseBlock, SparsePageAllocator};
+pub use transfer::TransferManager;
+pub use trend::{HeapTrend, TrendReport, UsageHistory};
+pub use usage::MemoryUsage;
+pub use virt::VirtualAllocator;
+pub use warm::WarmState;
 
 use std::cmp::PartialOrd;
 use std::error::Error;
@@ -60,13 +101,49 @@ use gfx_hal::Backend;
 use gfx_hal::device::OutOfMemory;
 use gfx_hal::memory::Requirements;
 
+mod adopted;
+mod alias;
 mod arena;
 mod block;
+mod category;
 mod chunked;
 mod combined;
+mod config;
+mod debug;
+mod defrag;
 mod factory;
+mod freelist;
+mod frozen;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod heap;
+mod host;
+mod interop;
+mod layout;
+mod linear;
+mod mapping;
+mod multi;
+mod names;
+mod observer;
+mod plugin;
+mod plan;
+mod quirks;
+mod readback;
+mod registry;
+mod request;
+mod reservation;
 mod root;
+mod shared;
+mod simulate;
 mod smart;
+mod sparse;
+mod stats;
+pub mod testing;
+mod transfer;
+mod trend;
+mod usage;
+mod virt;
+mod warm;
 
 /// Possible errors that may be returned from allocators.
 #[derive(Debug, Clone)]
@@ -74,8 +151,34 @@ pub enum MemoryError {
     /// Allocator doesn't have compatible memory types.
     NoCompatibleMemoryType,
 
+    /// Requested size is `0`.
+    ZeroSize,
+
+    /// Requested alignment is not a power of two.
+    InvalidAlignment,
+
     /// All compatible memory is exhausted.
     OutOfMemory,
+
+    /// Block is inconsistent with the allocator it is freed to, i.e. it has unexpected size or
+    /// offset.
+    CorruptedBlock,
+
+    /// Block is not allocated from the allocator it is freed to, or is already freed.
+    InvalidFree,
+
+    /// Range is out of the block bounds or doesn't suit the accessed type.
+    InvalidRange,
+
+    /// Memory can't be mapped.
+    MappingFailed,
+
+    /// Memory would have to be allocated from the device after the allocator was sealed.
+    /// See `SmartAllocator::seal`.
+    Sealed,
+
+    /// Device mask of the request contains devices beyond `SmartAllocator::device_count`.
+    InvalidDeviceMask,
 }
 
 impl From<OutOfMemory> for MemoryError {
@@ -84,18 +187,32 @@ impl From<OutOfMemory> for MemoryError {
     }
 }
 
+impl MemoryError {
+    fn message(&self) -> &'static str {
+        match *self {
+            MemoryError::NoCompatibleMemoryType => "No compatible memory",
+            MemoryError::ZeroSize => "Requested size is zero",
+            MemoryError::InvalidAlignment => "Requested alignment is not a power of two",
+            MemoryError::OutOfMemory => "Out of memory",
+            MemoryError::CorruptedBlock => "Corrupted block",
+            MemoryError::InvalidFree => "Block is not allocated from this allocator",
+            MemoryError::InvalidRange => "Invalid range",
+            MemoryError::MappingFailed => "Failed to map memory",
+            MemoryError::Sealed => "Allocator is sealed",
+            MemoryError::InvalidDeviceMask => "Device mask contains unknown devices",
+        }
+    }
+}
+
 impl fmt::Display for MemoryError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str(self.description())
+        fmt.write_str(self.message())
     }
 }
 
 impl Error for MemoryError {
     fn description(&self) -> &str {
-        match *self {
-            MemoryError::NoCompatibleMemoryType => "No compatible memory",
-            MemoryError::OutOfMemory => "Out of memory",
-        }
+        self.message()
     }
 }
 
@@ -139,8 +256,31 @@ pub trait MemoryAllocator<B: Backend>: Debug {
     ///
     /// - `device`: same device that was used to allocate the block of memory
     /// - `block`: block of memory to free
+    ///
+    /// ### Panics
+    ///
+    /// Allocators that check blocks in `try_free` panic if the block can't be freed.
     fn free(&mut self, device: &B::Device, block: Self::Block);
 
+    /// Free a block of memory, checking that the block is allocated from this allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the block of memory
+    /// - `block`: block of memory to free
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidFree` or `MemoryError::CorruptedBlock` if the block is not
+    /// allocated from this allocator. In this case the allocator is left unchanged and the
+    /// memory of the block is leaked.
+    ///
+    /// The default implementation doesn't check the block and frees it with `free`.
+    fn try_free(&mut self, device: &B::Device, block: Self::Block) -> Result<(), MemoryError> {
+        self.free(device, block);
+        Ok(())
+    }
+
     /// Check if any of the blocks allocated by this allocator are still in use.
     /// If this function returns `false`, the allocator can be `dispose`d.
     fn is_used(&self) -> bool;
@@ -205,8 +345,37 @@ pub trait MemorySubAllocator<B: Backend, O> {
     /// - `owner`: allocator that was used to allocate the inner memory blocks
     /// - `device`: same device that was used to allocate the block of memory
     /// - `block`: block of memory to free
+    ///
+    /// ### Panics
+    ///
+    /// Sub-allocators that check blocks in `try_free` panic if the block can't be freed.
     fn free(&mut self, owner: &mut O, device: &B::Device, block: Self::Block);
 
+    /// Free a block of memory, checking that the block is allocated from this allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the inner memory blocks
+    /// - `device`: same device that was used to allocate the block of memory
+    /// - `block`: block of memory to free
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidFree` or `MemoryError::CorruptedBlock` if the block is not
+    /// allocated from this allocator. In this case the allocator is left unchanged and the
+    /// memory of the block is leaked.
+    ///
+    /// The default implementation doesn't check the block and frees it with `free`.
+    fn try_free(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        block: Self::Block,
+    ) -> Result<(), MemoryError> {
+        self.free(owner, device, block);
+        Ok(())
+    }
+
     /// Attempt to dispose of this allocator.
     ///
     /// Allocators must be disposed using this function, dropping them before this might result in
@@ -225,6 +394,37 @@ pub trait MemorySubAllocator<B: Backend, O> {
         Self: Sized;
 }
 
+/// Check that requirements can be satisfied before allocating, so that degenerate inputs fail
+/// with precise errors instead of misbehaving deeper in the allocators.
+///
+/// ### Parameters:
+///
+/// - `reqs`: requirements to check
+/// - `type_mask`: mask of memory types available to the allocator
+///
+/// ### Returns
+///
+/// Returns `MemoryError::ZeroSize`, `MemoryError::InvalidAlignment`, or
+/// `MemoryError::NoCompatibleMemoryType` if no available memory type is in the type mask.
+pub(crate) fn validate(reqs: &Requirements, type_mask: u64) -> Result<(), MemoryError> {
+    if reqs.size == 0 {
+        Err(MemoryError::ZeroSize)
+    } else if !reqs.alignment.is_power_of_two() {
+        Err(MemoryError::InvalidAlignment)
+    } else if reqs.type_mask & type_mask == 0 {
+        Err(MemoryError::NoCompatibleMemoryType)
+    } else {
+        Ok(())
+    }
+}
+
+/// Panic if `try_free` failed, for allocators implementing `free` on top of it.
+pub(crate) fn freed(result: Result<(), MemoryError>) {
+    if let Err(error) = result {
+        panic!("Failed to free block: {}", error);
+    }
+}
+
 /// Calculate shift from specified offset required to satisfy alignment.
 pub fn alignment_shift<T>(alignment: T, offset: T) -> T
 where
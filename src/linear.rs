@@ -0,0 +1,203 @@
+use gfx_hal::{Backend, MemoryTypeId};
+use gfx_hal::memory::Requirements;
+
+use {alignment_shift, MemoryAllocator, MemoryError};
+use block::{Block, RawBlock};
+use plugin::{CustomSubAllocator, SubAllocatorFactory};
+use root::RootAllocator;
+
+/// Linear sub-allocator for `Type::Custom` kinds with uniform lifetimes, i.e. all blocks of a
+/// level or of a streaming batch.
+///
+/// Blocks are bump-allocated from root blocks and never reused one by one, so the only
+/// bookkeeping per root block is a pointer and a counter of blocks in use. A root block is
+/// recycled whole once its last block is freed. Blocks of mixed lifetimes pin root blocks,
+/// chunks suit them better.
+///
+/// Register a `LinearFactory` to serve a kind with it.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+#[derive(Debug)]
+pub struct LinearAllocator<B: Backend> {
+    id: MemoryTypeId,
+    block_size: u64,
+    /// Root blocks with blocks in use, the last one is allocated from.
+    nodes: Vec<LinearNode<B>>,
+    /// Root block without blocks in use, reused before allocating a new one.
+    spare: Option<RawBlock<B>>,
+}
+
+impl<B> LinearAllocator<B>
+where
+    B: Backend,
+{
+    /// Create a new linear allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `block_size`: size of root blocks, larger blocks get root blocks of multiples of it
+    /// - `id`: hal memory type
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `block_size` is `0`.
+    pub fn new(block_size: u64, id: MemoryTypeId) -> Self {
+        assert_ne!(block_size, 0, "Root block size must not be 0");
+        LinearAllocator {
+            id,
+            block_size,
+            nodes: Vec::new(),
+            spare: None,
+        }
+    }
+
+    /// Get memory type of the allocator.
+    pub fn memory_type(&self) -> MemoryTypeId {
+        self.id
+    }
+
+    /// Get size of root blocks.
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Get number of root blocks with blocks in use.
+    pub fn used_blocks(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+impl<B> CustomSubAllocator<B> for LinearAllocator<B>
+where
+    B: Backend,
+{
+    fn alloc(
+        &mut self,
+        root: &mut RootAllocator<B>,
+        device: &B::Device,
+        reqs: Requirements,
+    ) -> Result<RawBlock<B>, MemoryError> {
+        if (1 << self.id.0) & reqs.type_mask == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        if let Some(block) = self.nodes.last_mut().and_then(|node| node.alloc(reqs)) {
+            return Ok(block);
+        }
+
+        let size = (reqs.size.max(1) - 1) / self.block_size * self.block_size + self.block_size;
+        if self.spare.as_ref().map_or(false, |spare| spare.size() < size) {
+            let spare = self.spare.take().expect("Checked above");
+            root.free(device, spare);
+        }
+        let block = match self.spare.take() {
+            Some(spare) => spare,
+            None => root.alloc(
+                device,
+                (),
+                Requirements {
+                    type_mask: 1 << self.id.0,
+                    size,
+                    alignment: reqs.alignment,
+                },
+            )?,
+        };
+        let mut node = LinearNode {
+            block,
+            used: 0,
+            live: 0,
+        };
+        let block = node.alloc(reqs).expect("Root block is large enough");
+        self.nodes.push(node);
+        Ok(block)
+    }
+
+    fn try_free(
+        &mut self,
+        root: &mut RootAllocator<B>,
+        device: &B::Device,
+        block: RawBlock<B>,
+    ) -> Result<(), MemoryError> {
+        let index = self.nodes
+            .iter()
+            .rposition(|node| node.block.contains(&block));
+        unsafe { block.dispose() };
+        let index = match index {
+            Some(index) => index,
+            None => return Err(MemoryError::InvalidFree),
+        };
+        self.nodes[index].live -= 1;
+        if self.nodes[index].live == 0 {
+            let node = self.nodes.remove(index);
+            if self.spare.is_some() {
+                root.free(device, node.block);
+            } else {
+                self.spare = Some(node.block);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_used(&self) -> bool {
+        !self.nodes.is_empty()
+    }
+
+    fn dispose(&mut self, root: &mut RootAllocator<B>, device: &B::Device) {
+        debug_assert!(self.nodes.is_empty());
+        if let Some(spare) = self.spare.take() {
+            root.free(device, spare);
+        }
+    }
+
+    fn force_dispose(&mut self, root: &mut RootAllocator<B>, device: &B::Device) {
+        for node in self.nodes.drain(..) {
+            root.free(device, node.block);
+        }
+        self.dispose(root, device);
+    }
+}
+
+/// Root block of `LinearAllocator` with a bump pointer.
+#[derive(Debug)]
+struct LinearNode<B: Backend> {
+    block: RawBlock<B>,
+    /// Bytes allocated from the start of the root block, including alignment gaps.
+    used: u64,
+    /// Number of blocks in use.
+    live: usize,
+}
+
+impl<B> LinearNode<B>
+where
+    B: Backend,
+{
+    fn alloc(&mut self, reqs: Requirements) -> Option<RawBlock<B>> {
+        let offset = self.block.range().start + self.used;
+        let total_size = reqs.size + alignment_shift(reqs.alignment, offset);
+        if self.block.size() - self.used < total_size {
+            return None;
+        }
+        self.used += total_size;
+        self.live += 1;
+        let end = self.block.range().start + self.used;
+        Some(RawBlock::new(self.block.memory(), end - reqs.size..end))
+    }
+}
+
+/// Factory of `LinearAllocator`s, so that a `Type::Custom` kind is served in linear mode.
+/// See `SmartAllocator::register_sub_allocator`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinearFactory {
+    /// Size of root blocks of created allocators.
+    pub block_size: u64,
+}
+
+impl<B> SubAllocatorFactory<B> for LinearFactory
+where
+    B: Backend,
+{
+    fn create(&self, memory_type: MemoryTypeId) -> Box<CustomSubAllocator<B>> {
+        Box::new(LinearAllocator::new(self.block_size, memory_type))
+    }
+}
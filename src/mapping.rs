@@ -0,0 +1,397 @@
+use std::fmt::{self, Debug};
+use std::mem::{align_of, size_of};
+use std::ops::{Deref, DerefMut, Range};
+use std::ptr::copy_nonoverlapping;
+use std::slice;
+
+use gfx_hal::{Backend, Device};
+
+use MemoryError;
+use block::Block;
+
+/// Types that can be copied to and from mapped memory as raw bytes.
+///
+/// ### Safety
+///
+/// Any bit pattern must be a valid value of the type. The type must not contain padding,
+/// references or pointers.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+macro_rules! impl_pod_arrays {
+    ($($size:expr),*) => {
+        $(unsafe impl<T: Pod> Pod for [T; $size] {})*
+    };
+}
+
+impl_pod_arrays!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 32, 64);
+
+/// Coherence of mapped memory, deciding how mapped ranges are flushed and invalidated.
+/// See `SmartAllocator::coherence`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coherence {
+    /// Memory is coherent, flushes and invalidations are skipped.
+    Coherent,
+
+    /// Memory is not coherent. Mapped ranges are widened to multiples of the atom size, i.e.
+    /// `Limits::non_coherent_atom_size`, clamped to the block, as flushes and invalidations
+    /// require.
+    NonCoherent {
+        /// Non-coherent atom size, a power of two.
+        atom: u64,
+    },
+}
+
+impl Coherence {
+    /// Widen the range of the memory to multiples of the atom size within the block range.
+    fn widen(&self, range: Range<u64>, block: Range<u64>) -> Range<u64> {
+        match *self {
+            Coherence::Coherent => range,
+            Coherence::NonCoherent { atom } => {
+                let mask = atom.max(1) - 1;
+                let start = (range.start & !mask).max(block.start);
+                let end = range
+                    .end
+                    .checked_add(mask)
+                    .map_or(block.end, |end| end & !mask)
+                    .min(block.end);
+                start..end
+            }
+        }
+    }
+}
+
+/// Map the range of the block.
+///
+/// The mapped range of the memory is widened for flushes and invalidations of the coherence.
+///
+/// ### Returns
+///
+/// Returns mapped memory, mapped range of the memory and pointer to the start of the
+/// requested range.
+fn map<'a, B, K>(
+    device: &B::Device,
+    block: &'a K,
+    range: Range<u64>,
+    coherence: Coherence,
+) -> Result<(&'a B::Memory, Range<u64>, *mut u8), MemoryError>
+where
+    B: Backend,
+    K: Block<B>,
+{
+    // Devices can't map empty ranges.
+    if range.start >= range.end || range.end > block.size() {
+        return Err(MemoryError::InvalidRange);
+    }
+    let start = block.range().start;
+    let range = start + range.start..start + range.end;
+    let mapped = coherence.widen(range.clone(), block.range());
+    let memory = block.memory();
+    let ptr = device
+        .map_memory(memory, mapped.clone())
+        .map_err(|_| MemoryError::MappingFailed)?;
+    let ptr = unsafe { ptr.offset((range.start - mapped.start) as isize) };
+    Ok((memory, mapped, ptr))
+}
+
+/// Flush the mapped range unless the memory is coherent.
+fn flush<B: Backend>(
+    device: &B::Device,
+    memory: &B::Memory,
+    range: &Range<u64>,
+    coherence: Coherence,
+) {
+    if coherence != Coherence::Coherent {
+        device.flush_mapped_memory_ranges(Some((memory, range.clone())));
+    }
+}
+
+/// Get number of `T` values in mapped bytes.
+///
+/// ### Returns
+///
+/// Returns `MemoryError::InvalidRange` if size of bytes is not a multiple of `T` size or
+/// the pointer is not aligned for `T`.
+fn typed_len<T: Pod>(ptr: *mut u8, bytes: u64) -> Result<usize, MemoryError> {
+    let size = size_of::<T>() as u64;
+    assert_ne!(size, 0, "Zero-sized types can't be mapped");
+    if bytes % size != 0 || ptr as usize % align_of::<T>() != 0 {
+        Err(MemoryError::InvalidRange)
+    } else {
+        Ok((bytes / size) as usize)
+    }
+}
+
+/// Get values as raw bytes.
+pub(crate) fn as_bytes<T: Pod>(data: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * size_of::<T>()) }
+}
+
+/// Write bytes to the start of the block.
+///
+/// ### Parameters:
+///
+/// - `device`: device the block was allocated from
+/// - `block`: block to write to
+/// - `bytes`: bytes to write
+/// - `coherence`: coherence of the memory of the block
+pub(crate) fn write_bytes<B, K>(
+    device: &B::Device,
+    block: &mut K,
+    bytes: &[u8],
+    coherence: Coherence,
+) -> Result<(), MemoryError>
+where
+    B: Backend,
+    K: Block<B>,
+{
+    let (memory, range, ptr) = map(device, &*block, 0..bytes.len() as u64, coherence)?;
+    unsafe { copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+    flush::<B>(device, memory, &range, coherence);
+    device.unmap_memory(memory);
+    Ok(())
+}
+
+/// Result of `SmartAllocator::upload`.
+#[derive(Debug)]
+pub enum Upload<T> {
+    /// Data is written to the block directly.
+    Done,
+
+    /// The block is not host-visible. Data is written to the staging block instead.
+    /// The user must record copy from the staging block to the destination block
+    /// and free the staging block once the copy is complete.
+    Staged(T),
+}
+
+/// Slice of `T` mapped from the memory of a block.
+///
+/// The memory is flushed, unless it is coherent, and unmapped when the slice is dropped.
+/// Other blocks that share the memory object with the block must not be mapped
+/// while the slice is alive.
+pub struct MappedSlice<'a, B: Backend, T: Pod> {
+    device: &'a B::Device,
+    memory: &'a B::Memory,
+    /// Mapped range of the memory, widened for flushes.
+    range: Range<u64>,
+    coherence: Coherence,
+    slice: &'a mut [T],
+}
+
+impl<'a, B, T> MappedSlice<'a, B, T>
+where
+    B: Backend,
+    T: Pod,
+{
+    /// Map the range of the block.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `block`: block to map
+    /// - `range`: range to map, relative to the block start
+    /// - `coherence`: coherence of the memory of the block
+    pub(crate) fn new<K>(
+        device: &'a B::Device,
+        block: &'a mut K,
+        range: Range<u64>,
+        coherence: Coherence,
+    ) -> Result<Self, MemoryError>
+    where
+        K: Block<B>,
+    {
+        let bytes = range.end.saturating_sub(range.start);
+        let (memory, range, ptr) = map(device, &*block, range, coherence)?;
+        let len = match typed_len::<T>(ptr, bytes) {
+            Ok(len) => len,
+            Err(error) => {
+                device.unmap_memory(memory);
+                return Err(error);
+            }
+        };
+        Ok(MappedSlice {
+            device,
+            memory,
+            range,
+            coherence,
+            slice: unsafe { slice::from_raw_parts_mut(ptr as *mut T, len) },
+        })
+    }
+}
+
+impl<'a, B, T> Deref for MappedSlice<'a, B, T>
+where
+    B: Backend,
+    T: Pod,
+{
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice
+    }
+}
+
+impl<'a, B, T> DerefMut for MappedSlice<'a, B, T>
+where
+    B: Backend,
+    T: Pod,
+{
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.slice
+    }
+}
+
+impl<'a, B, T> Debug for MappedSlice<'a, B, T>
+where
+    B: Backend,
+    T: Pod,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MappedSlice")
+            .field("range", &self.range)
+            .field("len", &self.slice.len())
+            .finish()
+    }
+}
+
+impl<'a, B, T> Drop for MappedSlice<'a, B, T>
+where
+    B: Backend,
+    T: Pod,
+{
+    fn drop(&mut self) {
+        flush::<B>(self.device, self.memory, &self.range, self.coherence);
+        self.device.unmap_memory(self.memory);
+    }
+}
+
+/// Range of block memory mapped to the host.
+///
+/// Reading with `read` invalidates the range first, so writes made by the device are visible.
+/// Writing with `write` makes the range flushed when the guard is dropped.
+/// Flushes and invalidations are skipped for coherent memory.
+/// The memory is unmapped when the guard is dropped.
+///
+/// Other blocks that share the memory object with the block must not be mapped
+/// while the range is alive.
+pub struct MappedRange<'a, B: Backend> {
+    device: &'a B::Device,
+    memory: &'a B::Memory,
+    /// Requested range of the memory.
+    range: Range<u64>,
+    /// Mapped range of the memory, widened for flushes and invalidations.
+    mapped: Range<u64>,
+    coherence: Coherence,
+    ptr: *mut u8,
+    dirty: bool,
+}
+
+impl<'a, B> MappedRange<'a, B>
+where
+    B: Backend,
+{
+    /// Map the range of the block.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `block`: block to map
+    /// - `range`: range to map, relative to the block start
+    /// - `coherence`: coherence of the memory of the block
+    pub(crate) fn new<K>(
+        device: &'a B::Device,
+        block: &'a mut K,
+        range: Range<u64>,
+        coherence: Coherence,
+    ) -> Result<Self, MemoryError>
+    where
+        K: Block<B>,
+    {
+        let (memory, mapped, ptr) = map(device, &*block, range.clone(), coherence)?;
+        let start = block.range().start;
+        let requested = start + range.start..start + range.end;
+        Ok(MappedRange {
+            device,
+            memory,
+            range: requested,
+            mapped,
+            coherence,
+            ptr,
+            dirty: false,
+        })
+    }
+
+    /// Get mapped range of the memory object.
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+
+    /// Invalidate the range and get it as a slice of `T` for reading.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidRange` if the range size is not a multiple of `T` size
+    /// or the mapped pointer is not aligned for `T`.
+    pub fn read<T: Pod>(&mut self) -> Result<&[T], MemoryError> {
+        let len = typed_len::<T>(self.ptr, self.range.end - self.range.start)?;
+        self.invalidate();
+        Ok(unsafe { slice::from_raw_parts(self.ptr as *const T, len) })
+    }
+
+    /// Get the range as a slice of `T` for writing. The range is flushed on drop.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidRange` if the range size is not a multiple of `T` size
+    /// or the mapped pointer is not aligned for `T`.
+    pub fn write<T: Pod>(&mut self) -> Result<&mut [T], MemoryError> {
+        let len = typed_len::<T>(self.ptr, self.range.end - self.range.start)?;
+        self.dirty = true;
+        Ok(unsafe { slice::from_raw_parts_mut(self.ptr as *mut T, len) })
+    }
+
+    /// Make host writes to the range visible to the device. Does nothing for coherent memory.
+    pub fn flush(&mut self) {
+        flush::<B>(self.device, self.memory, &self.mapped, self.coherence);
+        self.dirty = false;
+    }
+
+    /// Make device writes to the range visible to the host. Does nothing for coherent memory.
+    pub fn invalidate(&mut self) {
+        if self.coherence != Coherence::Coherent {
+            self.device
+                .invalidate_mapped_memory_ranges(Some((self.memory, self.mapped.clone())));
+        }
+    }
+}
+
+impl<'a, B> Debug for MappedRange<'a, B>
+where
+    B: Backend,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MappedRange")
+            .field("range", &self.range)
+            .field("dirty", &self.dirty)
+            .finish()
+    }
+}
+
+impl<'a, B> Drop for MappedRange<'a, B>
+where
+    B: Backend,
+{
+    fn drop(&mut self) {
+        if self.dirty {
+            self.flush();
+        }
+        self.device.unmap_memory(self.memory);
+    }
+}
@@ -0,0 +1,135 @@
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::ops::Range;
+
+use gfx_hal::Backend;
+use gfx_hal::memory::{Properties, Requirements};
+
+use {alignment_shift, MemoryAllocator, MemoryError};
+use block::Block;
+use combined::Type;
+use mapping::{Coherence, MappedSlice, Pod};
+use smart::{SmartAllocator, SmartBlock, SmartRequest};
+
+/// N copies of host-visible data, one per frame in flight, i.e. per-frame constants.
+///
+/// The host writes the copy of the current frame while the device reads copies of previous
+/// frames. All copies share one block, each copy is flushed separately when its mapping is
+/// dropped.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `T`: type of values in each copy
+#[derive(Debug)]
+pub struct MultiBuffered<B: Backend, T: Pod> {
+    block: SmartBlock<B>,
+    coherence: Coherence,
+    stride: u64,
+    len: usize,
+    copies: usize,
+    pd: PhantomData<T>,
+}
+
+impl<B, T> MultiBuffered<B, T>
+where
+    B: Backend,
+    T: Pod,
+{
+    /// Allocate the copies.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator to allocate the block from
+    /// - `device`: device to allocate the memory from
+    /// - `copies`: number of copies, usually number of frames in flight
+    /// - `len`: number of values in each copy
+    /// - `alignment`: alignment of each copy, i.e. the larger of the uniform buffer offset
+    ///   alignment and the non-coherent atom size
+    /// - `type_mask`: memory types the resource using the copies can be bound to
+    ///
+    /// ### Returns
+    ///
+    /// `MemoryError::OutOfMemory` if the size of the copies overflows.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `copies` is `0`.
+    pub fn new(
+        allocator: &mut SmartAllocator<B>,
+        device: &B::Device,
+        copies: usize,
+        len: usize,
+        alignment: u64,
+        type_mask: u64,
+    ) -> Result<Self, MemoryError> {
+        assert_ne!(copies, 0, "Number of copies must not be 0");
+        let alignment = alignment.max(align_of::<T>() as u64);
+        let size = (len as u64).checked_mul(size_of::<T>() as u64);
+        let stride = size
+            .and_then(|size| {
+                size.checked_add(alignment)
+                    .map(|_| size + alignment_shift(alignment, size))
+            })
+            .ok_or(MemoryError::OutOfMemory)?;
+        let reqs = Requirements {
+            size: stride
+                .checked_mul(copies as u64)
+                .ok_or(MemoryError::OutOfMemory)?,
+            alignment,
+            type_mask,
+        };
+        let request = SmartRequest::new(Type::General, Properties::CPU_VISIBLE);
+        let block = allocator.alloc(device, request, reqs)?;
+        Ok(MultiBuffered {
+            coherence: allocator.coherence(&block),
+            block,
+            stride,
+            len,
+            copies,
+            pd: PhantomData,
+        })
+    }
+
+    /// Get number of copies.
+    pub fn copies(&self) -> usize {
+        self.copies
+    }
+
+    /// Get the block all copies are allocated in.
+    pub fn block(&self) -> &SmartBlock<B> {
+        &self.block
+    }
+
+    /// Get range of the frame's copy, relative to the block start.
+    /// Copies are used round-robin.
+    pub fn range(&self, frame: u64) -> Range<u64> {
+        let start = (frame % self.copies as u64) * self.stride;
+        start..start + (self.len * size_of::<T>()) as u64
+    }
+
+    /// Map the copy of the frame. The copy is flushed when the mapping is dropped.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `frame`: index of the frame, copies are used round-robin
+    pub fn current_mut<'a>(
+        &'a mut self,
+        device: &'a B::Device,
+        frame: u64,
+    ) -> Result<MappedSlice<'a, B, T>, MemoryError> {
+        let range = self.range(frame);
+        self.block.mapped_slice_with(device, range, self.coherence)
+    }
+
+    /// Free the copies.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator the copies were allocated from
+    /// - `device`: device the copies were allocated from
+    pub fn dispose(self, allocator: &mut SmartAllocator<B>, device: &B::Device) {
+        allocator.free(device, self.block);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Stable ids of allocation names, so that the same logical allocation, i.e.
+/// "gbuffer.albedo", gets the same id across runs and traces of different builds can be
+/// diffed. See `SmartAllocator::set_name_ids`.
+///
+/// New names get ids above all known ones. Ids are kept across runs by saving the registry
+/// with `to_string` and loading it with `parse` on the next run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NameIds {
+    ids: BTreeMap<String, u64>,
+    next: u64,
+}
+
+impl NameIds {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        NameIds::default()
+    }
+
+    /// Load a registry saved with `to_string`: one `id name` pair per line. Backslashes and
+    /// line breaks in names are escaped as `\\`, `\n` and `\r`.
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of the first malformed line, counting from one, if the text is not
+    /// a valid registry. Lines with duplicate ids or names, or with id `u64::MAX`, are
+    /// malformed.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut names = NameIds::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, ' ');
+            let id = parts.next().and_then(|id| id.parse::<u64>().ok());
+            let name = parts.next().map(unescape);
+            match (id, name) {
+                (Some(id), Some(name))
+                    if !names.ids.contains_key(&name) && names.name(id).is_none() =>
+                {
+                    let next = match id.checked_add(1) {
+                        Some(next) => next,
+                        None => return Err(index + 1),
+                    };
+                    names.ids.insert(name, id);
+                    names.next = names.next.max(next);
+                }
+                _ => return Err(index + 1),
+            }
+        }
+        Ok(names)
+    }
+
+    /// Get id of the name, assigning a new one if the name is not known yet.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if all ids are assigned.
+    pub fn id(&mut self, name: &str) -> u64 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.next;
+        self.next = id.checked_add(1).expect("Name ids are exhausted");
+        self.ids.insert(name.to_owned(), id);
+        id
+    }
+
+    /// Get id of the name if the name is known.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.ids.get(name).cloned()
+    }
+
+    /// Get name with the id.
+    pub fn name(&self, id: u64) -> Option<&str> {
+        self.ids
+            .iter()
+            .find(|&(_, &known)| known == id)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Get number of known names.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Check if no names are known.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Write the registry in the format read by `NameIds::parse`, ordered by id.
+impl fmt::Display for NameIds {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut pairs: Vec<_> = self.ids.iter().map(|(name, &id)| (id, name)).collect();
+        pairs.sort();
+        for (id, name) in pairs {
+            writeln!(fmt, "{} {}", id, escape(name))?;
+        }
+        Ok(())
+    }
+}
+
+/// Escape backslashes and line breaks, so that a name stays on its line.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Undo `escape`. Unknown escapes are kept as they are.
+fn unescape(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
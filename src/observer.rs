@@ -0,0 +1,216 @@
+use std::fmt::Debug;
+use std::ops::Range;
+use std::time::Duration;
+
+use gfx_hal::MemoryTypeId;
+use gfx_hal::memory::Requirements;
+
+use MemoryError;
+use combined::{SubAllocatorKind, Type};
+use request::ResourceKind;
+use smart::{PoolKey, SmartRequest};
+
+/// Information about a block passed to `AllocObserver`.
+#[derive(Clone, Debug)]
+pub struct BlockInfo {
+    /// Id of the allocation, increasing in allocation order and unique within the allocator.
+    /// Log lines about the allocation, including its failure, refer to it by this id.
+    pub id: u64,
+
+    /// Memory type the block is allocated from.
+    pub memory_type: MemoryTypeId,
+
+    /// Heap of the memory type.
+    pub heap: usize,
+
+    /// Sub-allocator the block is allocated with.
+    pub ty: Type,
+
+    /// Kind of resource the block is bound to. See `SmartRequest::resource`.
+    pub resource: ResourceKind,
+
+    /// Range of the memory the block occupies.
+    pub range: Range<u64>,
+
+    /// Opaque value provided by the user, i.e. asset id. See `SmartRequest::user_data`.
+    pub user_data: u64,
+
+    /// Version of the block's contents, bumped by the user with `SmartAllocator::bump_version`.
+    /// Together with `id` it tells whether cached bindings keyed on memory and offset still
+    /// refer to the same contents.
+    pub version: u64,
+}
+
+/// Fractions of heap budget, heap size without system budgets, at which
+/// `PressureEvent::HeapUsage` is emitted.
+pub const PRESSURE_LEVELS: [f32; 3] = [0.75, 0.9, 1.0];
+
+/// Memory pressure event passed to `AllocObserver::on_pressure`, so that engines can react,
+/// i.e. by reducing texture quality or trimming caches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PressureEvent {
+    /// Heap usage crossed one of `PRESSURE_LEVELS` upwards.
+    HeapUsage {
+        /// Index of the heap.
+        heap: usize,
+
+        /// Highest level crossed.
+        level: f32,
+
+        /// Bytes used in the heap.
+        used: u64,
+
+        /// Size of the heap without system budgets.
+        budget: u64,
+    },
+
+    /// Heap usage crossed the soft target of `HeapPolicy` upwards.
+    SoftTarget {
+        /// Index of the heap.
+        heap: usize,
+
+        /// Bytes used in the heap.
+        used: u64,
+
+        /// Soft target of the heap.
+        target: u64,
+    },
+
+    /// Allocation fell back from the preferred memory type to another one for the first time,
+    /// including falling back to `SmartRequest::fallback_properties`, in which case the preferred
+    /// memory type is the first one compatible with the requested properties.
+    Fallback {
+        /// Memory type that couldn't serve the allocation.
+        preferred: MemoryTypeId,
+
+        /// Memory type the block was allocated from.
+        chosen: MemoryTypeId,
+    },
+
+    /// Number of memory objects allocated from the device crossed the threshold set with
+    /// `SmartAllocator::set_allocation_count_warning`.
+    AllocationCount {
+        /// Number of memory objects.
+        count: usize,
+    },
+}
+
+/// Retry of an allocation of memory from the device that failed because the device was out
+/// of memory. See `RootAllocator::set_retry_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocRetry {
+    /// Memory type of the memory.
+    pub memory_type: MemoryTypeId,
+
+    /// Size of the memory.
+    pub size: u64,
+
+    /// Number of the retry, counting from one.
+    pub retry: u32,
+
+    /// Delay before the retry.
+    pub delay: Duration,
+}
+
+/// Memory a sub-allocator of `SmartAllocator` is about to allocate from the device.
+/// See `AllocObserver::on_low_watermark`.
+#[derive(Clone, Copy, Debug)]
+pub struct GrowthHint {
+    /// Memory type to allocate the memory of.
+    pub memory_type: MemoryTypeId,
+
+    /// Heap of the memory type.
+    pub heap: usize,
+
+    /// Size of the memory to allocate.
+    pub size: u64,
+
+    /// Key of the pool of the sub-allocator. Pools can be recreated before the memory is
+    /// provided, so they are not identified by index.
+    pub(crate) pool: PoolKey,
+}
+
+/// Block a sub-allocator of `SmartAllocator` allocated from its owner to grow, i.e. a new
+/// arena or chunk. See `AllocObserver::on_sub_allocator_grow`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubAllocatorGrowth {
+    /// Memory type of the sub-allocator.
+    pub memory_type: MemoryTypeId,
+
+    /// Sub-allocator that grew, `SubAllocatorKind::Arena` or `SubAllocatorKind::Chunked`.
+    pub kind: SubAllocatorKind,
+
+    /// Block size of the size class that grew, `None` for arenas.
+    pub block_size: Option<u64>,
+
+    /// Size of the block requested from the owner.
+    pub size: u64,
+}
+
+/// Observer of `SmartAllocator` events.
+///
+/// All methods do nothing by default, so implementations can pick events they care about.
+/// Observers are called synchronously from within the allocator and must not block for long.
+pub trait AllocObserver: Debug + Send + Sync {
+    /// Called after a block was allocated.
+    fn on_alloc(&mut self, _info: &BlockInfo) {}
+
+    /// Called after a block was freed.
+    fn on_free(&mut self, _info: &BlockInfo) {}
+
+    /// Called after the allocator allocated memory from the device.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_type`: memory type of the allocated memory
+    /// - `heap`: heap of the memory type
+    /// - `size`: size of memory allocated from the device
+    fn on_grow(&mut self, _memory_type: MemoryTypeId, _heap: usize, _size: u64) {}
+
+    /// Called after an arena or chunked size class allocated a block from its owner, whether
+    /// the owner allocated memory from the device or not, so that hitches can be attributed
+    /// to sub-allocators and size classes.
+    fn on_sub_allocator_grow(&mut self, _growth: &SubAllocatorGrowth) {}
+
+    /// Called after an allocation leaves a chunk size class or arena nearly exhausted,
+    /// see `SmartAllocator::set_growth_watermark`.
+    ///
+    /// A grower can allocate the memory on another thread, i.e. a loading thread, and give it
+    /// to `SmartAllocator::provide_memory`, so that the allocator doesn't have to allocate
+    /// from the device when the sub-allocator runs out. The call is repeated after every
+    /// allocation until the memory is provided, so requests in flight should be deduplicated.
+    fn on_low_watermark(&mut self, _hint: &GrowthHint) {}
+
+    /// Called on memory pressure events.
+    fn on_pressure(&mut self, _event: &PressureEvent) {}
+
+    /// Called by `SmartAllocator::maintain` for a live block that outlived its time-to-live,
+    /// see `SmartRequest::ttl`. Called once per block.
+    fn on_expired(&mut self, _info: &BlockInfo) {}
+
+    /// Called when the trim order runs, i.e. in `SmartAllocator::maintain`, for a live block
+    /// the application should evict, i.e. destroy the resource and free the block,
+    /// see `TrimStep::ColdBlocks`. Called every time the step runs until the block is freed.
+    fn on_evict(&mut self, _info: &BlockInfo) {}
+
+    /// Called after an allocation of memory from the device was retried,
+    /// see `SmartAllocator::set_retry_policy`. Retries of an allocation are reported after
+    /// it succeeds or fails for good.
+    fn on_alloc_retry(&mut self, _retry: &AllocRetry) {}
+
+    /// Called when an allocation fails because memory is exhausted.
+    fn on_oom(&mut self, _request: &SmartRequest, _reqs: &Requirements) {}
+
+    /// Called when heap usage crosses the warning threshold of `HeapPolicy`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `heap`: index of the heap
+    /// - `used`: bytes used in the heap
+    /// - `size`: size of the heap
+    fn on_heap_pressure(&mut self, _heap: usize, _used: u64, _size: u64) {}
+
+    /// Called when number of memory objects allocated from the device crosses the threshold
+    /// set with `SmartAllocator::set_allocation_count_warning`.
+    fn on_allocation_count(&mut self, _count: usize) {}
+}
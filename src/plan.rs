@@ -0,0 +1,204 @@
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use gfx_hal::MemoryTypeId;
+use gfx_hal::memory::Properties;
+
+use combined::Type;
+use smart::SmartRequest;
+use warm::{access_code, access_from_code, lifetime_code, lifetime_from_code, resource_code,
+           resource_from_code};
+
+/// Live allocation to recreate against a fresh allocator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedAllocation {
+    /// Id of the allocation in the old allocator. See `BlockInfo::id`.
+    pub id: u64,
+
+    /// Sub-allocator the block was allocated with.
+    pub ty: Type,
+
+    /// Memory type the block was allocated from.
+    pub memory_type: MemoryTypeId,
+
+    /// Properties of the memory type.
+    pub properties: Properties,
+
+    /// Requested size of the block.
+    pub size: u64,
+
+    /// Opaque value stored with the allocation. See `SmartRequest::user_data`.
+    pub user_data: u64,
+
+    /// Path of the category the block was allocated in.
+    pub category: Option<String>,
+
+    /// Name given with `SmartAllocator::set_name`.
+    pub name: Option<String>,
+
+    /// Request the block was allocated with.
+    pub request: SmartRequest,
+}
+
+impl PlannedAllocation {
+    /// Create a request for the block, the same as the block was allocated with.
+    ///
+    /// Memory type is left for the new allocator to pick, since memory types of the new
+    /// device may differ.
+    pub fn request(&self) -> SmartRequest {
+        SmartRequest {
+            memory_type: None,
+            ..self.request
+        }
+    }
+}
+
+/// Live allocations of a `SmartAllocator` in allocation order, i.e. to recreate resources
+/// after the device is lost. See `SmartAllocator::reallocation_plan`.
+///
+/// The plan can be written to and read from a text format with one tab-separated allocation
+/// per line, so it survives recreation of the whole renderer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReallocationPlan {
+    /// Allocations in allocation order.
+    pub allocations: Vec<PlannedAllocation>,
+}
+
+impl ReallocationPlan {
+    /// Get total requested size of the allocations.
+    pub fn bytes(&self) -> u64 {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.size)
+            .sum()
+    }
+
+    /// Write the plan. Tabs and line breaks in names and categories are replaced by spaces.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for allocation in &self.allocations {
+            let request = &allocation.request;
+            writeln!(
+                writer,
+                "{}\t{:?}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                allocation.id,
+                allocation.ty,
+                allocation.memory_type.0,
+                allocation.properties.bits(),
+                allocation.size,
+                allocation.user_data,
+                escape(&allocation.category),
+                escape(&allocation.name),
+                request.properties.bits(),
+                optional_field(request.fallback_properties.map(|properties| properties.bits())),
+                request.protected as u8,
+                optional_field(request.priority),
+                lifetime_code(request.lifetime),
+                request.device_mask,
+                request.dedicated as u8,
+                optional_field(request.ttl),
+                resource_code(request.resource),
+                access_code(request.access)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read the plan written by `write`.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `io::ErrorKind::InvalidData` error if a line is malformed.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut allocations = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            allocations.push(parse(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Malformed allocation: {}", line),
+                )
+            })?);
+        }
+        Ok(ReallocationPlan { allocations })
+    }
+}
+
+fn escape(value: &Option<String>) -> String {
+    match *value {
+        Some(ref value) => value.replace(|c| c == '\t' || c == '\n' || c == '\r', " "),
+        None => String::new(),
+    }
+}
+
+fn optional_field<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+fn parse_optional<T: FromStr>(field: &str) -> Option<Option<T>> {
+    if field.is_empty() {
+        Some(None)
+    } else {
+        field.parse().ok().map(Some)
+    }
+}
+
+fn parse_flag(field: &str) -> Option<bool> {
+    match field {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    }
+}
+
+fn optional(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+fn parse(line: &str) -> Option<PlannedAllocation> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 18 {
+        return None;
+    }
+    let ty = match fields[1] {
+        "ShortLived" => Type::ShortLived,
+        "General" => Type::General,
+        field if field.starts_with("Custom(") && field.ends_with(')') => {
+            Type::Custom(field["Custom(".len()..field.len() - 1].parse().ok()?)
+        }
+        _ => return None,
+    };
+    let user_data = fields[5].parse().ok()?;
+    let request = SmartRequest {
+        fallback_properties: parse_optional(fields[9])?.map(Properties::from_bits_truncate),
+        protected: parse_flag(fields[10])?,
+        priority: parse_optional(fields[11])?,
+        lifetime: lifetime_from_code(fields[12].parse().ok()?)?,
+        device_mask: fields[13].parse().ok()?,
+        dedicated: parse_flag(fields[14])?,
+        ttl: parse_optional(fields[15])?,
+        resource: resource_from_code(fields[16].parse().ok()?)?,
+        access: access_from_code(fields[17].parse().ok()?)?,
+        ..SmartRequest::new(ty, Properties::from_bits_truncate(fields[8].parse().ok()?))
+    };
+    Some(PlannedAllocation {
+        id: fields[0].parse().ok()?,
+        ty,
+        memory_type: MemoryTypeId(fields[2].parse().ok()?),
+        properties: Properties::from_bits_truncate(fields[3].parse().ok()?),
+        size: fields[4].parse().ok()?,
+        user_data,
+        category: optional(fields[6]),
+        name: optional(fields[7]),
+        request: SmartRequest {
+            user_data,
+            ..request
+        },
+    })
+}
@@ -0,0 +1,68 @@
+use std::fmt::Debug;
+
+use gfx_hal::{Backend, MemoryTypeId};
+use gfx_hal::memory::Requirements;
+
+use MemoryError;
+use block::RawBlock;
+use root::RootAllocator;
+
+/// Sub-allocator plugged into `CombinedAllocator` to serve `Type::Custom` requests,
+/// i.e. a ring for skinning matrices.
+///
+/// It allocates memory from the same `RootAllocator` as the built-in sub-allocators,
+/// so its memory is accounted, reported and freed along with theirs.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+pub trait CustomSubAllocator<B: Backend>: Debug + Send + Sync {
+    /// Allocate a block.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `root`: allocator to allocate memory from
+    /// - `device`: device to allocate the memory from
+    /// - `reqs`: requirements of the block
+    fn alloc(
+        &mut self,
+        root: &mut RootAllocator<B>,
+        device: &B::Device,
+        reqs: Requirements,
+    ) -> Result<RawBlock<B>, MemoryError>;
+
+    /// Free a block allocated with `alloc`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `root`: allocator the memory was allocated from
+    /// - `device`: device the memory was allocated from
+    /// - `block`: block to free
+    fn try_free(
+        &mut self,
+        root: &mut RootAllocator<B>,
+        device: &B::Device,
+        block: RawBlock<B>,
+    ) -> Result<(), MemoryError>;
+
+    /// Check if any blocks allocated by this allocator are still in use.
+    fn is_used(&self) -> bool;
+
+    /// Return all memory to the root allocator. Called only when no blocks are in use.
+    fn dispose(&mut self, root: &mut RootAllocator<B>, device: &B::Device);
+
+    /// Return all memory to the root allocator, even if blocks are still in use.
+    /// Such blocks are leaked by the user afterwards.
+    fn force_dispose(&mut self, root: &mut RootAllocator<B>, device: &B::Device);
+}
+
+/// Factory of `CustomSubAllocator`s registered for a `Type::Custom` kind.
+/// See `CombinedAllocator::register_sub_allocator`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+pub trait SubAllocatorFactory<B: Backend>: Debug + Send + Sync {
+    /// Create a sub-allocator for the memory type. Called on the first request of the kind.
+    fn create(&self, memory_type: MemoryTypeId) -> Box<CustomSubAllocator<B>>;
+}
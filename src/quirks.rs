@@ -0,0 +1,88 @@
+/// How `SmartAllocator` distributes allocations across equivalent memory types, i.e. ones of
+/// the same heap with identical properties. See `SmartAllocator::set_type_spread`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeSpread {
+    /// Allocate from the equivalent memory type with the lowest index, falling back to others.
+    First,
+
+    /// Rotate the first equivalent memory type to try with every allocation.
+    RoundRobin,
+
+    /// Try the equivalent memory type with the least memory allocated from it first.
+    LeastAllocated,
+}
+
+impl Default for TypeSpread {
+    fn default() -> Self {
+        TypeSpread::First
+    }
+}
+
+/// Workarounds for a device, adjusting `SmartAllocator` defaults to known driver behaviour.
+/// See `SmartAllocator::apply_quirks`.
+///
+/// Tables of quirks keep such knowledge in one place instead of scattering device checks
+/// over the renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirk {
+    /// PCI vendor id of the device.
+    pub vendor: usize,
+
+    /// PCI device id of the device, `None` stands for all devices of the vendor.
+    pub device: Option<usize>,
+
+    /// Distribution of allocations across equivalent memory types, i.e. for drivers that
+    /// limit number of memory objects per memory type.
+    pub spread: TypeSpread,
+
+    /// Largest memory object to allocate for sub-allocation, i.e. for drivers that fail or
+    /// stall on large allocations. Arenas and chunks are clamped to it, must be a power of
+    /// two not below the minimal block size.
+    pub max_root_block: Option<u64>,
+
+    /// Memory types to allocate from only if no other memory type can serve a request,
+    /// i.e. ones that are slow or broken on the device. Indices of 64 and above are ignored.
+    pub avoided_types: &'static [usize],
+
+    /// Size at or above which blocks always get dedicated memory objects, i.e. for drivers
+    /// that handle large render targets better this way. Applies to every request.
+    pub dedicated_threshold: Option<u64>,
+}
+
+impl Quirk {
+    /// Create a quirk of the device that doesn't change any defaults.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `vendor`: PCI vendor id of the device
+    /// - `device`: PCI device id of the device, `None` stands for all devices of the vendor
+    pub fn new(vendor: usize, device: Option<usize>) -> Self {
+        Quirk {
+            vendor,
+            device,
+            spread: TypeSpread::First,
+            max_root_block: None,
+            avoided_types: &[],
+            dedicated_threshold: None,
+        }
+    }
+}
+
+/// Find the quirk of the device in the table.
+/// Quirks of the exact device take precedence over ones for all devices of the vendor.
+///
+/// ### Parameters:
+///
+/// - `quirks`: table of quirks
+/// - `vendor`: PCI vendor id of the device
+/// - `device`: PCI device id of the device
+pub fn find_quirk(quirks: &[Quirk], vendor: usize, device: usize) -> Option<&Quirk> {
+    quirks
+        .iter()
+        .find(|quirk| quirk.vendor == vendor && quirk.device == Some(device))
+        .or_else(|| {
+            quirks
+                .iter()
+                .find(|quirk| quirk.vendor == vendor && quirk.device.is_none())
+        })
+}
@@ -0,0 +1,241 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::mem::replace;
+use std::ops::Range;
+
+use gfx_hal::{Backend, Device};
+use gfx_hal::buffer::Usage;
+use gfx_hal::command::{BufferCopy, RawCommandBuffer};
+use gfx_hal::memory::Properties;
+
+use MemoryError;
+use block::Block;
+use combined::Type;
+use factory::{Factory, FactoryError, Item};
+use request::HostAccess;
+use smart::{SmartAllocator, SmartBlock, SmartRequest};
+
+type ReadbackBuffer<B> = Item<<B as Backend>::Buffer, SmartBlock<B>>;
+
+/// Callback receiving data read back by `ReadbackPool`, or the error if the readback buffer
+/// can't be mapped.
+pub type ReadbackCallback = Box<FnMut(Result<&[u8], MemoryError>) + Send>;
+
+struct Readback<B: Backend> {
+    buffer: ReadbackBuffer<B>,
+    callback: ReadbackCallback,
+}
+
+/// Schedules reads of device data back to the host, the reverse of `TransferManager`.
+///
+/// Copy commands into readback buffers are recorded with `read_buffer`, allocated from
+/// host-visible and preferably cached memory, and associated with a fence owned by the pool
+/// with `flush`. Once the fence is signalled, `maintain` invalidates the buffers, delivers
+/// the data to the callbacks and frees the buffers.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+pub struct ReadbackPool<B: Backend> {
+    recorded: Vec<Readback<B>>,
+    /// Readbacks of submitted command buffers with the fence of the submission.
+    in_flight: Vec<(B::Fence, Vec<Readback<B>>)>,
+    /// Unsignalled fences ready for reuse.
+    fences: Vec<B::Fence>,
+}
+
+impl<B> ReadbackPool<B>
+where
+    B: Backend,
+{
+    /// Create an empty readback pool.
+    pub fn new() -> Self {
+        ReadbackPool {
+            recorded: Vec::new(),
+            in_flight: Vec::new(),
+            fences: Vec::new(),
+        }
+    }
+
+    /// Get number of readbacks recorded but not flushed yet.
+    pub fn pending(&self) -> usize {
+        self.recorded.len()
+    }
+
+    /// Get number of readbacks the device may be still working on.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+            .iter()
+            .map(|&(_, ref readbacks)| readbacks.len())
+            .sum()
+    }
+
+    /// Allocate a readback buffer and record copy of the buffer range into it.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to create the readback buffer on
+    /// - `allocator`: allocator to allocate the readback buffer from
+    /// - `cbuf`: command buffer of a queue that supports transfer operations, in recording
+    ///   state
+    /// - `buffer`: source buffer, created with `Usage::TRANSFER_SRC`
+    /// - `range`: range of the source buffer to read
+    /// - `callback`: callback to deliver the data to from `maintain`
+    pub fn read_buffer(
+        &mut self,
+        device: &B::Device,
+        allocator: &mut SmartAllocator<B>,
+        cbuf: &mut B::CommandBuffer,
+        buffer: &B::Buffer,
+        range: Range<u64>,
+        callback: ReadbackCallback,
+    ) -> Result<(), FactoryError> {
+        assert!(range.start <= range.end, "Invalid range {:?}", range);
+        let size = range.end - range.start;
+        let request = SmartRequest {
+            fallback_properties: Some(Properties::CPU_VISIBLE),
+            access: HostAccess::Readback,
+            ..SmartRequest::new(
+                Type::ShortLived,
+                Properties::CPU_VISIBLE | Properties::CPU_CACHED,
+            )
+        };
+        let readback = <SmartAllocator<B> as Factory<B>>::create_buffer(
+            allocator,
+            device,
+            request,
+            size,
+            Usage::TRANSFER_DST,
+        )?;
+        cbuf.copy_buffer(
+            buffer,
+            readback.borrow(),
+            Some(BufferCopy {
+                src: range.start,
+                dst: 0,
+                size,
+            }),
+        );
+        self.recorded.push(Readback {
+            buffer: readback,
+            callback,
+        });
+        Ok(())
+    }
+
+    /// Associate recorded readbacks with a fence.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to create the fence on
+    ///
+    /// ### Returns
+    ///
+    /// Returns the fence to signal by the submission of the command buffers the readbacks
+    /// were recorded into.
+    pub fn flush(&mut self, device: &B::Device) -> &B::Fence {
+        let fence = self.fences
+            .pop()
+            .unwrap_or_else(|| device.create_fence(false));
+        let recorded = replace(&mut self.recorded, Vec::new());
+        self.in_flight.push((fence, recorded));
+        &self.in_flight.last().expect("Pushed above").0
+    }
+
+    /// Invalidate readback buffers of signalled fences, deliver their data to the callbacks
+    /// and free them.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the readback buffers were created on
+    /// - `allocator`: allocator the readback buffers were allocated from
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of readbacks delivered.
+    pub fn maintain(&mut self, device: &B::Device, allocator: &mut SmartAllocator<B>) -> usize {
+        let mut delivered = 0;
+        let mut index = 0;
+        while index < self.in_flight.len() {
+            if !device.get_fence_status(&self.in_flight[index].0) {
+                index += 1;
+                continue;
+            }
+            let (fence, readbacks) = self.in_flight.remove(index);
+            for Readback {
+                mut buffer,
+                mut callback,
+            } in readbacks
+            {
+                {
+                    let size = <ReadbackBuffer<B> as Block<B>>::size(&buffer);
+                    let coherence = allocator.coherence(buffer.block());
+                    let mapped = <ReadbackBuffer<B> as Block<B>>::mapped_range_with(
+                        &mut buffer,
+                        device,
+                        0..size,
+                        coherence,
+                    );
+                    match mapped {
+                        // Reading invalidates the range first, unless the memory is coherent.
+                        Ok(mut range) => callback(range.read::<u8>()),
+                        Err(error) => callback(Err(error)),
+                    }
+                }
+                <SmartAllocator<B> as Factory<B>>::destroy_buffer(allocator, device, buffer);
+                delivered += 1;
+            }
+            device.reset_fence(&fence);
+            self.fences.push(fence);
+        }
+        delivered
+    }
+
+    /// Dispose of the pool, freeing all readback buffers without delivering their data and
+    /// destroying the fences. The device must be done with all submissions.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the fences and readback buffers were created on
+    /// - `allocator`: allocator the readback buffers were allocated from
+    pub fn dispose(self, device: &B::Device, allocator: &mut SmartAllocator<B>) {
+        for (fence, readbacks) in self.in_flight {
+            for readback in readbacks {
+                <SmartAllocator<B> as Factory<B>>::destroy_buffer(
+                    allocator,
+                    device,
+                    readback.buffer,
+                );
+            }
+            device.destroy_fence(fence);
+        }
+        for readback in self.recorded {
+            <SmartAllocator<B> as Factory<B>>::destroy_buffer(allocator, device, readback.buffer);
+        }
+        for fence in self.fences {
+            device.destroy_fence(fence);
+        }
+    }
+}
+
+impl<B> Default for ReadbackPool<B>
+where
+    B: Backend,
+{
+    fn default() -> Self {
+        ReadbackPool::new()
+    }
+}
+
+impl<B> fmt::Debug for ReadbackPool<B>
+where
+    B: Backend,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ReadbackPool")
+            .field("pending", &self.recorded.len())
+            .field("in_flight", &self.in_flight())
+            .field("fences", &(self.in_flight.len() + self.fences.len()))
+            .finish()
+    }
+}
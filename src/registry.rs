@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::btree_map::Values;
+use std::fmt;
+use std::mem::replace;
+use std::ops::Range;
+
+use gfx_hal::Backend;
+
+use combined::Type;
+use observer::BlockInfo;
+use smart::SmartRequest;
+use stats::{log2_class, LifetimeStats};
+
+/// Live allocation tracked by the registry.
+#[derive(Clone, Debug)]
+pub(crate) struct Allocation {
+    pub(crate) info: BlockInfo,
+    pub(crate) frame: u64,
+    /// Address of the memory object, stable while the block is alive.
+    pub(crate) memory: usize,
+    pub(crate) category: Option<usize>,
+    pub(crate) name: Option<String>,
+    /// Bytes of the block beyond the requested size.
+    pub(crate) padding: u64,
+    /// Block must not be moved by compaction or migration.
+    pub(crate) pinned: bool,
+    /// Request the block was allocated with, user data is kept in sync with `info`.
+    pub(crate) request: SmartRequest,
+    /// Frame the block expires in, see `Registry::set_expiry`.
+    pub(crate) expiry: Option<u64>,
+}
+
+impl Allocation {
+    /// Get priority the block was requested with.
+    pub(crate) fn priority(&self) -> f32 {
+        self.request.priority.unwrap_or(0.5)
+    }
+}
+
+/// Block that outlived the watchdog threshold.
+#[derive(Clone, Debug)]
+pub struct StaleBlock {
+    /// Information about the block.
+    pub info: BlockInfo,
+
+    /// Number of frames since the block was allocated.
+    pub age: u64,
+}
+
+/// Live block of the `SmartAllocator`, i.e. to annotate GPU captures.
+/// See `SmartAllocator::live_allocations`.
+#[derive(Debug)]
+pub struct LiveAllocation<'a, B: Backend> {
+    /// Memory object the block is allocated from.
+    pub memory: &'a B::Memory,
+
+    /// Information about the block. The range is relative to the memory object.
+    pub info: &'a BlockInfo,
+
+    /// Name given with `SmartAllocator::set_name`.
+    pub name: Option<&'a str>,
+
+    /// Path of the category the block was allocated in.
+    pub category: Option<&'a str>,
+
+    /// Block is pinned with `SmartAllocator::pin` and must not be moved.
+    pub pinned: bool,
+}
+
+/// Live blocks that prevented an allocator from being disposed.
+#[derive(Clone, Debug)]
+pub struct LeakReport {
+    /// Information about live blocks in allocation order.
+    pub blocks: Vec<BlockInfo>,
+}
+
+impl LeakReport {
+    /// Get total size of live blocks.
+    pub fn bytes(&self) -> u64 {
+        self.blocks
+            .iter()
+            .map(|info| info.range.end - info.range.start)
+            .sum()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "{} blocks ({} bytes) are still in use",
+            self.blocks.len(),
+            self.bytes()
+        )?;
+        for info in &self.blocks {
+            writeln!(
+                fmt,
+                "  #{} {:?} block {:?} of memory type {:?}, user data {}",
+                info.id, info.ty, info.range, info.memory_type, info.user_data
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Registry of live allocations of the `SmartAllocator`.
+#[derive(Debug, Default)]
+pub(crate) struct Registry {
+    next_id: u64,
+    frame: u64,
+    live: BTreeMap<u64, Allocation>,
+    /// Short-lived allocations in allocation order, may contain already freed ones.
+    short_lived: VecDeque<(u64, u64)>,
+    /// Short-lived allocations already reported as stale.
+    reported: BTreeSet<u64>,
+    lifetimes: Vec<LifetimeStats>,
+    /// Frames live allocations with time-to-live expire in and their ids.
+    expiring: BTreeSet<(u64, u64)>,
+}
+
+impl Registry {
+    /// Get current frame.
+    pub(crate) fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Take id for a new allocation. Ids of failed allocations are not reused.
+    pub(crate) fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Register new allocation with the id taken by `next_id`.
+    pub(crate) fn insert(
+        &mut self,
+        info: BlockInfo,
+        memory: usize,
+        category: Option<usize>,
+        padding: u64,
+        request: SmartRequest,
+    ) {
+        let id = info.id;
+        if let Type::ShortLived = info.ty {
+            self.short_lived.push_back((id, self.frame));
+        }
+        self.live.insert(
+            id,
+            Allocation {
+                info,
+                frame: self.frame,
+                memory,
+                category,
+                name: None,
+                padding,
+                pinned: false,
+                request,
+                expiry: None,
+            },
+        );
+    }
+
+    /// Set frame the live allocation expires in.
+    pub(crate) fn set_expiry(&mut self, id: u64, frame: u64) {
+        if let Some(allocation) = self.live.get_mut(&id) {
+            if let Some(old) = replace(&mut allocation.expiry, Some(frame)) {
+                self.expiring.remove(&(old, id));
+            }
+            self.expiring.insert((frame, id));
+        }
+    }
+
+    /// Take live allocations that expire in frames up to `now`.
+    pub(crate) fn take_expired(&mut self, now: u64) -> Vec<BlockInfo> {
+        let mut expired = Vec::new();
+        while let Some(&(frame, id)) = self.expiring.iter().next() {
+            if frame > now {
+                break;
+            }
+            self.expiring.remove(&(frame, id));
+            if let Some(allocation) = self.live.get_mut(&id) {
+                allocation.expiry = None;
+                expired.push(allocation.info.clone());
+            }
+        }
+        expired
+    }
+
+    /// Get live allocation.
+    pub(crate) fn get(&self, id: u64) -> Option<&Allocation> {
+        self.live.get(&id)
+    }
+
+    /// Replace user data of live allocation.
+    pub(crate) fn set_user_data(&mut self, id: u64, user_data: u64) {
+        if let Some(allocation) = self.live.get_mut(&id) {
+            allocation.info.user_data = user_data;
+            allocation.request.user_data = user_data;
+        }
+    }
+
+    /// Increment version of live allocation.
+    ///
+    /// ### Returns
+    ///
+    /// Returns the new version, `None` if the allocation is not live.
+    pub(crate) fn bump_version(&mut self, id: u64) -> Option<u64> {
+        self.live.get_mut(&id).map(|allocation| {
+            allocation.info.version += 1;
+            allocation.info.version
+        })
+    }
+
+    /// Replace name of live allocation.
+    pub(crate) fn set_name(&mut self, id: u64, name: Option<String>) {
+        if let Some(allocation) = self.live.get_mut(&id) {
+            allocation.name = name;
+        }
+    }
+
+    /// Replace request of live allocation, keeping its user data.
+    pub(crate) fn set_request(&mut self, id: u64, request: SmartRequest) {
+        if let Some(allocation) = self.live.get_mut(&id) {
+            allocation.request = SmartRequest {
+                user_data: allocation.info.user_data,
+                ..request
+            };
+        }
+    }
+
+    /// Replace category of live allocation.
+    pub(crate) fn set_category(&mut self, id: u64, category: Option<usize>) {
+        if let Some(allocation) = self.live.get_mut(&id) {
+            allocation.category = category;
+        }
+    }
+
+    /// Get frame the allocation expires in, if it was given a time-to-live.
+    pub(crate) fn expiry(&self, id: u64) -> Option<u64> {
+        self.live.get(&id).and_then(|allocation| allocation.expiry)
+    }
+
+    /// Update range and padding of live allocation resized in place.
+    ///
+    /// ### Returns
+    ///
+    /// Returns previous padding of the allocation.
+    pub(crate) fn resize(&mut self, id: u64, range: Range<u64>, padding: u64) -> u64 {
+        match self.live.get_mut(&id) {
+            Some(allocation) => {
+                allocation.info.range = range;
+                replace(&mut allocation.padding, padding)
+            }
+            None => 0,
+        }
+    }
+
+    /// Pin or unpin live allocation.
+    ///
+    /// ### Returns
+    ///
+    /// Returns previous state of the allocation.
+    pub(crate) fn set_pinned(&mut self, id: u64, pinned: bool) -> bool {
+        match self.live.get_mut(&id) {
+            Some(allocation) => replace(&mut allocation.pinned, pinned),
+            None => false,
+        }
+    }
+
+    /// Get live allocations in allocation order.
+    pub(crate) fn live(&self) -> Values<u64, Allocation> {
+        self.live.values()
+    }
+
+    /// Unregister freed allocation and record its lifetime.
+    pub(crate) fn remove(&mut self, id: u64) -> Allocation {
+        self.reported.remove(&id);
+        let allocation = self.live
+            .remove(&id)
+            .expect("Block must be allocated from this allocator");
+        if let Some(frame) = allocation.expiry {
+            self.expiring.remove(&(frame, id));
+        }
+
+        let ty = allocation.info.ty;
+        let size = allocation.info.range.end - allocation.info.range.start;
+        let size_class = log2_class(size.saturating_sub(1));
+        let bucket = log2_class(self.frame - allocation.frame) as usize;
+        let index = match self.lifetimes
+            .iter()
+            .position(|stats| stats.ty == ty && stats.size_class == size_class)
+        {
+            Some(index) => index,
+            None => {
+                self.lifetimes.push(LifetimeStats {
+                    ty,
+                    size_class,
+                    lifetimes: Vec::new(),
+                });
+                self.lifetimes.len() - 1
+            }
+        };
+        let lifetimes = &mut self.lifetimes[index].lifetimes;
+        if lifetimes.len() <= bucket {
+            lifetimes.resize(bucket + 1, 0);
+        }
+        lifetimes[bucket] += 1;
+
+        allocation
+    }
+
+    /// Get lifetime histograms sorted by request type and size class.
+    pub(crate) fn lifetimes(&self) -> Vec<LifetimeStats> {
+        let mut lifetimes = self.lifetimes.clone();
+        lifetimes.sort_by_key(|stats| (stats.ty == Type::General, stats.size_class));
+        lifetimes
+    }
+
+    /// Advance frame counter.
+    /// Warns about short-lived allocations older than `watchdog` frames.
+    pub(crate) fn mark_frame(&mut self, watchdog: Option<u64>) {
+        self.frame += 1;
+        while self.short_lived
+            .front()
+            .map_or(false, |&(id, _)| !self.live.contains_key(&id))
+        {
+            self.short_lived.pop_front();
+        }
+
+        if let Some(frames) = watchdog {
+            for &(id, frame) in &self.short_lived {
+                if self.frame - frame <= frames {
+                    break;
+                }
+                if let Some(allocation) = self.live.get(&id) {
+                    if self.reported.insert(id) {
+                        warn!(
+                            "Short-lived block #{} {:?} of memory type {:?} is alive for {} frames",
+                            id,
+                            allocation.info.range,
+                            allocation.info.memory_type,
+                            self.frame - allocation.frame
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Collect report about all live allocations.
+    pub(crate) fn leaks(&self) -> LeakReport {
+        LeakReport {
+            blocks: self.live
+                .values()
+                .map(|allocation| allocation.info.clone())
+                .collect(),
+        }
+    }
+
+    /// Collect short-lived allocations older than `frames` frames.
+    pub(crate) fn stale(&self, frames: u64) -> Vec<StaleBlock> {
+        self.short_lived
+            .iter()
+            .take_while(|&&(_, frame)| self.frame - frame > frames)
+            .filter_map(|&(id, frame)| {
+                self.live.get(&id).map(|allocation| StaleBlock {
+                    info: allocation.info.clone(),
+                    age: self.frame - frame,
+                })
+            })
+            .collect()
+    }
+}
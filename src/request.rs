@@ -0,0 +1,137 @@
+use gfx_hal::{buffer, image};
+use gfx_hal::memory::Properties;
+
+use combined::Type;
+
+/// Whether the driver prefers or requires a dedicated memory object for a resource,
+/// as reported alongside its `Requirements`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Dedicated {
+    /// The resource can be sub-allocated.
+    No,
+
+    /// The driver prefers a dedicated memory object, i.e. for render targets.
+    Preferred,
+
+    /// The driver requires a dedicated memory object, i.e. for some external resources.
+    Required,
+}
+
+/// Expected lifetime of a block. See `SmartRequest::lifetime`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lifetime {
+    /// No hint, i.e. for resources of a level.
+    Unspecified,
+
+    /// The block lives for the whole session, i.e. for global buffers and font atlases.
+    Persistent,
+
+    /// The block lives for minutes, i.e. for streamed textures and meshes.
+    Streaming,
+}
+
+impl Default for Lifetime {
+    fn default() -> Self {
+        Lifetime::Unspecified
+    }
+}
+
+/// Kind of resource a block is bound to. See `SmartRequest::resource`.
+///
+/// Devices require linear resources (buffers and linear images) and optimal images to be
+/// separated by the buffer-image granularity when they share a memory object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    /// Not specified, the block may be bound to any resource. The allocator doesn't separate
+    /// such blocks by the buffer-image granularity, the user is responsible for it.
+    Unspecified,
+
+    /// The block is bound to a buffer.
+    Buffer,
+
+    /// The block is bound to an image with linear tiling.
+    LinearImage,
+
+    /// The block is bound to an image with optimal tiling.
+    OptimalImage,
+}
+
+impl Default for ResourceKind {
+    fn default() -> Self {
+        ResourceKind::Unspecified
+    }
+}
+
+/// Tiling of linear resources in masks returned by `ResourceKind::tiling`.
+pub(crate) const TILING_LINEAR: u8 = 1;
+
+/// Tiling of optimal images in masks returned by `ResourceKind::tiling`.
+pub(crate) const TILING_OPTIMAL: u8 = 2;
+
+impl ResourceKind {
+    /// Get tiling of the resources, `0` if the kind is not specified.
+    pub(crate) fn tiling(self) -> u8 {
+        match self {
+            ResourceKind::Unspecified => 0,
+            ResourceKind::Buffer | ResourceKind::LinearImage => TILING_LINEAR,
+            ResourceKind::OptimalImage => TILING_OPTIMAL,
+        }
+    }
+}
+
+/// Direction of host traffic through a block. See `SmartRequest::access`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HostAccess {
+    /// No hint, the host doesn't access the block or accesses it both ways.
+    Unspecified,
+
+    /// The host writes the block and the device reads it, i.e. staging buffers.
+    /// Memory types without `CPU_CACHED` are preferred, since write-combined writes are
+    /// faster there.
+    Upload,
+
+    /// The device writes the block and the host reads it, i.e. readback buffers.
+    /// Memory types with `CPU_CACHED` are preferred, since uncached reads are slow.
+    Readback,
+}
+
+impl Default for HostAccess {
+    fn default() -> Self {
+        HostAccess::Unspecified
+    }
+}
+
+/// Pick sub-allocator type and memory properties suitable for the buffer usage.
+///
+/// - Buffers used only as transfer source are staging buffers, they are allocated as
+///   `Type::ShortLived` from `CPU_VISIBLE` memory.
+/// - Buffers used only as transfer destination are readback buffers, they are allocated from
+///   `CPU_VISIBLE | CPU_CACHED` memory.
+/// - All other buffers are allocated from `DEVICE_LOCAL` memory.
+///
+/// The result can be converted into `SmartRequest` with `into()`.
+pub fn request_for_buffer(usage: buffer::Usage) -> (Type, Properties) {
+    if usage == buffer::Usage::TRANSFER_SRC {
+        (Type::ShortLived, Properties::CPU_VISIBLE)
+    } else if usage == buffer::Usage::TRANSFER_DST {
+        (Type::General, Properties::CPU_VISIBLE | Properties::CPU_CACHED)
+    } else {
+        (Type::General, Properties::DEVICE_LOCAL)
+    }
+}
+
+/// Pick sub-allocator type and memory properties suitable for the image usage.
+///
+/// - Images used only as transfer source are linear staging images, they are allocated as
+///   `Type::ShortLived` from `CPU_VISIBLE` memory.
+/// - All other images are allocated from `DEVICE_LOCAL` memory. Transient attachments don't
+///   require `LAZILY_ALLOCATED` memory since not all devices provide it.
+///
+/// The result can be converted into `SmartRequest` with `into()`.
+pub fn request_for_image(usage: image::Usage) -> (Type, Properties) {
+    if usage == image::Usage::TRANSFER_SRC {
+        (Type::ShortLived, Properties::CPU_VISIBLE)
+    } else {
+        (Type::General, Properties::DEVICE_LOCAL)
+    }
+}
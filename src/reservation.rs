@@ -0,0 +1,80 @@
+use relevant::Relevant;
+
+/// Capacity reserved in a memory heap, see `SmartAllocator::reserve_bytes`.
+///
+/// Other allocations can't use reserved capacity, only allocations made inside the reservation
+/// with `SmartAllocator::alloc_reserved` can.
+///
+/// A `Reservation` must never be silently dropped, that will result in a panic.
+/// It must be released by returning it to the same allocator it came from.
+#[derive(Debug)]
+pub struct Reservation {
+    relevant: Relevant,
+    id: u64,
+    heap: usize,
+}
+
+impl Reservation {
+    pub(crate) fn new(id: u64, heap: usize) -> Self {
+        Reservation {
+            relevant: Relevant,
+            id,
+            heap,
+        }
+    }
+
+    /// Get index of the heap the capacity is reserved in.
+    pub fn heap(&self) -> usize {
+        self.heap
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn dispose(self) -> u64 {
+        self.relevant.dispose();
+        self.id
+    }
+}
+
+/// Allocator side state of the `Reservation`.
+#[derive(Debug)]
+pub(crate) struct ReservationState {
+    pub(crate) id: u64,
+    pub(crate) heap: usize,
+    size: u64,
+    used: u64,
+}
+
+impl ReservationState {
+    pub(crate) fn new(id: u64, heap: usize, size: u64) -> Self {
+        ReservationState {
+            id,
+            heap,
+            size,
+            used: 0,
+        }
+    }
+
+    /// Get reserved bytes not used by allocations made inside the reservation.
+    pub(crate) fn remaining(&self) -> u64 {
+        self.size.saturating_sub(self.used)
+    }
+
+    /// Account allocation made inside the reservation.
+    /// Returns how many of reserved bytes the allocation consumed.
+    pub(crate) fn charge(&mut self, size: u64) -> u64 {
+        let before = self.remaining();
+        self.used += size;
+        before - self.remaining()
+    }
+
+    /// Account freeing of allocation made inside the reservation.
+    /// Returns how many bytes returned to the reservation.
+    pub(crate) fn refund(&mut self, size: u64) -> u64 {
+        let before = self.remaining();
+        self.used -= size;
+        self.remaining() - before
+    }
+}
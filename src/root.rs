@@ -1,26 +1,89 @@
-use std::marker::PhantomData;
+use std::cmp::min;
+use std::thread;
+use std::time::Duration;
 
 use gfx_hal::{Backend, Device, MemoryTypeId};
 use gfx_hal::memory::Requirements;
 
-use {MemoryAllocator, MemoryError};
+use {freed, MemoryAllocator, MemoryError};
 use block::{Block, RawBlock};
-use relevant::Relevant;
+use combined::{report_leak, LeakMode};
+use host::HostAllocator;
+use observer::AllocRetry;
+
+/// Policy of retrying allocations of memory from the device that fail because the device is
+/// out of memory. Some drivers fail transiently under pressure and succeed shortly after.
+/// See `RootAllocator::set_retry_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries of one allocation.
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled before every next one.
+    pub backoff: Duration,
+
+    /// Longest delay before a retry.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Get delay before the retry, counting from zero.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = if retry < 32 {
+            self.backoff.checked_mul(1 << retry)
+        } else {
+            None
+        };
+        min(delay.unwrap_or(self.max_backoff), self.max_backoff)
+    }
+}
 
 /// Allocator that allocates memory directly from device.
 ///
+/// Dropping the allocator with memory allocated leaks the memory and is reported according
+/// to its `LeakMode`, see `set_leak_mode`.
+///
 /// ### Type parameters:
 ///
 /// - `B`: hal `Backend`
 #[derive(Debug)]
-pub struct RootAllocator<B> {
-    relevant: Relevant,
+pub struct RootAllocator<B: Backend> {
+    leak: LeakMode,
     id: MemoryTypeId,
-    allocations: usize,
-    pd: PhantomData<B>,
+    allocated: u64,
+    /// Total size of memory allocated from the device so far, including freed memory.
+    grown: u64,
+    /// Memory objects in use with their sizes.
+    memories: Vec<(*mut B::Memory, u64)>,
+    deferred: bool,
+    sealed: bool,
+    /// Freed memory objects waiting for `flush_frees` with their sizes.
+    pending: Vec<(*mut B::Memory, u64)>,
+    /// Memory objects provided with `provide_memory` with their sizes.
+    spare: Vec<(*mut B::Memory, u64)>,
+    retry: Option<RetryPolicy>,
+    /// Retries made since the last `take_retries`.
+    retries: Vec<AllocRetry>,
+    /// Host memory serving allocations instead of the device. See `set_host`.
+    host: Option<HostAllocator<B>>,
 }
 
-impl<B> RootAllocator<B> {
+unsafe impl<B> Send for RootAllocator<B>
+where
+    B: Backend,
+{
+}
+
+unsafe impl<B> Sync for RootAllocator<B>
+where
+    B: Backend,
+{
+}
+
+impl<B> RootAllocator<B>
+where
+    B: Backend,
+{
     /// Create new allocator that will allocate memory of specified type.
     ///
     /// ### Parameters:
@@ -28,10 +91,18 @@ impl<B> RootAllocator<B> {
     /// - `id`: hal memory type
     pub fn new(id: MemoryTypeId) -> Self {
         RootAllocator {
-            relevant: Relevant,
+            leak: LeakMode::default(),
             id,
-            allocations: 0,
-            pd: PhantomData,
+            allocated: 0,
+            grown: 0,
+            memories: Vec::new(),
+            deferred: false,
+            sealed: false,
+            pending: Vec::new(),
+            spare: Vec::new(),
+            retry: None,
+            retries: Vec::new(),
+            host: None,
         }
     }
 
@@ -39,6 +110,187 @@ impl<B> RootAllocator<B> {
     pub fn memory_type(&self) -> MemoryTypeId {
         self.id
     }
+
+    /// Get total size of memory currently allocated from the device.
+    pub fn allocated(&self) -> u64 {
+        self.allocated
+    }
+
+    /// Get total size of memory allocated from the device so far, including memory freed
+    /// since. Unlike `allocated` it never decreases, so that growth can be measured across
+    /// calls that both allocate and free memory.
+    pub fn grown(&self) -> u64 {
+        self.grown
+    }
+
+    /// Get number of memory objects currently allocated from the device.
+    /// Includes freed memory objects waiting for `flush_frees`, spare memory objects and
+    /// blocks of host memory.
+    pub fn allocations(&self) -> usize {
+        self.memories.len() + self.pending.len() + self.spare.len()
+            + self.host.as_ref().map_or(0, |host| host.live_blocks())
+    }
+
+    /// Serve allocations from host memory instead of the device, i.e. to run the allocator
+    /// stack without a GPU. `None`, the default, allocates from the device.
+    ///
+    /// Blocks are placed in the placeholder memory object of the host allocator, so they can't
+    /// be bound or mapped. Their contents are accessed with `host`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the allocator is in use.
+    pub fn set_host(&mut self, host: Option<HostAllocator<B>>) {
+        assert!(!self.is_used(), "Memory source of allocator in use can't be changed");
+        self.host = host;
+    }
+
+    /// Get host allocator serving allocations, if any. See `set_host`.
+    pub fn host(&self) -> Option<&HostAllocator<B>> {
+        self.host.as_ref()
+    }
+
+    /// Get mutable host allocator serving allocations, if any. See `set_host`.
+    pub fn host_mut(&mut self) -> Option<&mut HostAllocator<B>> {
+        self.host.as_mut()
+    }
+
+    /// Get memory objects given out to sub-allocators or users with their sizes,
+    /// in no particular order.
+    pub fn memories(&self) -> Vec<(&B::Memory, u64)> {
+        self.memories
+            .iter()
+            .map(|&(memory, size)| (unsafe { &*memory }, size))
+            .collect()
+    }
+
+    /// Provide memory allocated from the device elsewhere, i.e. on a loading thread.
+    ///
+    /// The next allocation of exactly `size` bytes takes the memory instead of allocating from
+    /// the device, even if the allocator is sealed. Unused spare memory is freed on `dispose`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory`: memory of the allocator's memory type
+    /// - `size`: size of the memory
+    pub fn provide_memory(&mut self, memory: B::Memory, size: u64) {
+        let memory = Box::into_raw(Box::new(memory));
+        self.spare.push((memory, size));
+        self.allocated += size;
+    }
+
+    /// Check if spare memory of the size was provided and not taken yet.
+    pub fn has_spare(&self, size: u64) -> bool {
+        self.spare.iter().any(|&(_, spare)| spare == size)
+    }
+
+    /// Enable or disable deferred frees.
+    ///
+    /// When enabled, memory objects are not freed in `free` but queued until `flush_frees`,
+    /// so that bursts of frees can be executed at a controlled point.
+    /// Disabling doesn't flush already queued frees.
+    pub fn set_deferred_frees(&mut self, deferred: bool) {
+        self.deferred = deferred;
+    }
+
+    /// Set what the allocator does when dropped without being disposed, i.e. `Warn` in crash
+    /// paths, so that the report doesn't mask the original error.
+    pub fn set_leak_mode(&mut self, mode: LeakMode) {
+        self.leak = mode;
+    }
+
+    /// Get what the allocator does when dropped without being disposed.
+    pub fn leak_mode(&self) -> LeakMode {
+        self.leak
+    }
+
+    /// Seal or unseal the allocator.
+    ///
+    /// Sealed allocator fails to allocate with `MemoryError::Sealed` instead of allocating
+    /// memory from the device. Freeing is not affected.
+    pub fn set_sealed(&mut self, sealed: bool) {
+        self.sealed = sealed;
+    }
+
+    /// Set policy of retrying allocations that fail because the device is out of memory,
+    /// `None`, the default, fails them right away.
+    ///
+    /// The allocating thread sleeps between retries, so the policy should be used only where
+    /// a stall is preferable to the failure. Retries are recorded for `take_retries`.
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+    }
+
+    /// Take retries made since the last call, i.e. to report them to observers.
+    pub fn take_retries(&mut self) -> Vec<AllocRetry> {
+        self.retries.drain(..).collect()
+    }
+
+    /// Check if the allocator is sealed.
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// Get number of freed memory objects waiting for `flush_frees`.
+    pub fn pending_frees(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Free queued memory objects.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the memory
+    /// - `max`: maximum number of memory objects to free
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of memory objects freed.
+    pub fn flush_frees(&mut self, device: &B::Device, max: usize) -> usize {
+        let count = max.min(self.pending.len());
+        for (memory, size) in self.pending.drain(..count) {
+            device.free_memory(*unsafe { Box::from_raw(memory) });
+            self.allocated -= size;
+        }
+        count
+    }
+
+    /// Dispose of this allocator, freeing all memory allocated from the device, even if blocks
+    /// allocated from it are still in use.
+    ///
+    /// Blocks that are still in use are invalidated. They must not be used or freed afterwards
+    /// and should be leaked with `std::mem::forget`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    pub fn force_dispose(mut self, device: &B::Device) {
+        let memories = self.memories
+            .drain(..)
+            .chain(self.pending.drain(..))
+            .chain(self.spare.drain(..));
+        for (memory, _) in memories {
+            device.free_memory(*unsafe { Box::from_raw(memory) });
+        }
+        self.host = None;
+        self.allocated = 0;
+    }
+
+    /// Dispose of this allocator after the device is lost, leaking all memory allocated from
+    /// the device without reporting it. Blocks that are still in use are invalidated and
+    /// should be leaked with `std::mem::forget`.
+    pub fn dispose_lost(mut self) {
+        self.leak();
+    }
+
+    /// Leak all memory allocated from the device, so the allocator can be dropped.
+    pub(crate) fn leak(&mut self) {
+        self.memories.clear();
+        self.pending.clear();
+        self.spare.clear();
+        self.host = None;
+        self.allocated = 0;
+    }
 }
 
 impl<B> MemoryAllocator<B> for RootAllocator<B>
@@ -54,29 +306,126 @@ where
         _: (),
         reqs: Requirements,
     ) -> Result<RawBlock<B>, MemoryError> {
-        let memory = device.allocate_memory(self.id, reqs.size)?;
+        if let Some(ref mut host) = self.host {
+            let block = host.alloc(device, (), reqs)?;
+            self.allocated += reqs.size;
+            self.grown += reqs.size;
+            return Ok(block);
+        }
+        if let Some(index) = self.spare.iter().position(|&(_, size)| size == reqs.size) {
+            let (memory, size) = self.spare.swap_remove(index);
+            self.memories.push((memory, size));
+            return Ok(RawBlock::new(memory, 0..size));
+        }
+        if self.sealed {
+            warn!(
+                "Allocation of {} bytes of {:?} from the sealed allocator",
+                reqs.size, self.id
+            );
+            return Err(MemoryError::Sealed);
+        }
+        let mut retry = 0;
+        let memory = loop {
+            match device.allocate_memory(self.id, reqs.size) {
+                Ok(memory) => break memory,
+                Err(error) => match self.retry {
+                    Some(policy) if retry < policy.max_retries => {
+                        let delay = policy.delay(retry);
+                        retry += 1;
+                        debug!(
+                            "Retry {} of allocation of {} bytes of {:?} in {:?}",
+                            retry, reqs.size, self.id, delay
+                        );
+                        self.retries.push(AllocRetry {
+                            memory_type: self.id,
+                            size: reqs.size,
+                            retry,
+                            delay,
+                        });
+                        thread::sleep(delay);
+                    }
+                    _ => return Err(error.into()),
+                },
+            }
+        };
         let memory = Box::into_raw(Box::new(memory)); // Suboptimal
-        self.allocations += 1;
+        self.memories.push((memory, reqs.size));
+        self.allocated += reqs.size;
+        self.grown += reqs.size;
         Ok(RawBlock::new(memory, 0..reqs.size))
     }
 
     fn free(&mut self, device: &B::Device, block: RawBlock<B>) {
-        assert_eq!(block.range().start, 0);
-        device.free_memory(*unsafe { Box::from_raw(block.memory() as *const _ as *mut _) });
+        freed(self.try_free(device, block));
+    }
+
+    fn try_free(&mut self, device: &B::Device, block: RawBlock<B>) -> Result<(), MemoryError> {
+        let size = block.size();
+        if let Some(ref mut host) = self.host {
+            host.try_free(device, block)?;
+            self.allocated -= size;
+            return Ok(());
+        }
+        let memory = block.memory() as *const B::Memory as *mut B::Memory;
+        let found = self.memories.iter().position(|&(m, _)| m == memory);
+        let index = match found {
+            Some(index) if block.range().start == 0 => index,
+            Some(_) => {
+                unsafe { block.dispose() };
+                return Err(MemoryError::CorruptedBlock);
+            }
+            None => {
+                unsafe { block.dispose() };
+                return Err(MemoryError::InvalidFree);
+            }
+        };
+        self.memories.swap_remove(index);
         unsafe { block.dispose() };
-        self.allocations -= 1;
+        if self.deferred {
+            self.pending.push((memory, size));
+        } else {
+            device.free_memory(*unsafe { Box::from_raw(memory) });
+            self.allocated -= size;
+        }
+        Ok(())
     }
 
     fn is_used(&self) -> bool {
-        self.allocations != 0
+        !self.memories.is_empty() || self.host.as_ref().map_or(false, |host| host.is_used())
     }
 
-    fn dispose(self, _: &B::Device) -> Result<(), Self> {
+    fn dispose(mut self, device: &B::Device) -> Result<(), Self> {
         if self.is_used() {
             Err(self)
         } else {
-            self.relevant.dispose();
+            let pending = self.pending.len();
+            self.flush_frees(device, pending);
+            for (memory, size) in self.spare.drain(..) {
+                device.free_memory(*unsafe { Box::from_raw(memory) });
+                self.allocated -= size;
+            }
             Ok(())
         }
     }
 }
+
+impl<B> Drop for RootAllocator<B>
+where
+    B: Backend,
+{
+    fn drop(&mut self) {
+        if self.memories.is_empty() && self.pending.is_empty() && self.spare.is_empty() {
+            // Disposed or nothing to leak
+            return;
+        }
+        let report = format!(
+            "RootAllocator of {:?} dropped without being disposed, leaking {} bytes in {} \
+             memory objects",
+            self.id,
+            self.allocated,
+            self.memories.len() + self.pending.len() + self.spare.len()
+        );
+        report_leak(self.leak, &report);
+        self.leak();
+    }
+}
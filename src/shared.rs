@@ -0,0 +1,231 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot::{Mutex, MutexGuard};
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use std::sync::{Mutex, MutexGuard};
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::PoisonError;
+
+use gfx_hal::Backend;
+use gfx_hal::memory::Requirements;
+
+use {MemoryAllocator, MemoryError};
+use block::Block;
+use smart::{SmartAllocator, SmartBlock, SmartRequest};
+use stats::AllocatorStats;
+
+/// Thread-safe handle to a `SmartAllocator`.
+///
+/// Handles are cheap to clone and can be sent to loading threads. Every call locks the
+/// allocator for its duration. The lock is `std::sync::Mutex` by default, or
+/// `parking_lot::Mutex` with the `parking_lot` feature enabled, which is cheaper to acquire
+/// when there are many small allocations.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+#[derive(Clone, Debug)]
+pub struct SharedAllocator<B: Backend> {
+    inner: Arc<Mutex<SmartAllocator<B>>>,
+}
+
+impl<B> SharedAllocator<B>
+where
+    B: Backend,
+{
+    /// Wrap the allocator to share it between threads.
+    pub fn new(allocator: SmartAllocator<B>) -> Self {
+        SharedAllocator {
+            inner: Arc::new(Mutex::new(allocator)),
+        }
+    }
+
+    /// Allocate a block. See `SmartAllocator`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `request`: information required to allocate the block
+    /// - `reqs`: the requirements of the block
+    pub fn alloc(
+        &self,
+        device: &B::Device,
+        request: SmartRequest,
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        self.lock().alloc(device, request, reqs)
+    }
+
+    /// Free a block. See `MemoryAllocator::free`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the block
+    /// - `block`: block to free
+    pub fn free(&self, device: &B::Device, block: SmartBlock<B>) {
+        self.lock().free(device, block)
+    }
+
+    /// Free a block. See `MemoryAllocator::try_free`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the block
+    /// - `block`: block to free
+    pub fn try_free(&self, device: &B::Device, block: SmartBlock<B>) -> Result<(), MemoryError> {
+        self.lock().try_free(device, block)
+    }
+
+    /// Get statistics of the allocator.
+    pub fn stats(&self) -> AllocatorStats {
+        self.lock().stats()
+    }
+
+    /// Call the function with the allocator locked, i.e. to change its settings.
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut SmartAllocator<B>) -> R,
+    {
+        f(&mut self.lock())
+    }
+
+    /// Unwrap the allocator, i.e. to dispose of it.
+    ///
+    /// ### Returns
+    ///
+    /// Returns the handle back if other handles to the allocator exist.
+    pub fn try_unwrap(self) -> Result<SmartAllocator<B>, Self> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(mutex) => Ok(into_inner(mutex)),
+            Err(inner) => Err(SharedAllocator { inner }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<SmartAllocator<B>> {
+        lock(&self.inner)
+    }
+}
+
+/// Block shared by several owners, i.e. a texture referenced by many materials.
+///
+/// Handles are cheap to clone and can be sent to other threads. When the last handle is
+/// dropped the block is queued to the allocator it was shared by, and freed by its
+/// `flush_frees` or `free_dropped`. See `SmartAllocator::share`.
+#[derive(Clone, Debug)]
+pub struct SharedBlock<B: Backend> {
+    inner: Arc<SharedInner<B>>,
+}
+
+#[derive(Debug)]
+struct SharedInner<B: Backend> {
+    block: Option<SmartBlock<B>>,
+    dropped: Arc<Mutex<Vec<SmartBlock<B>>>>,
+}
+
+impl<B> SharedBlock<B>
+where
+    B: Backend,
+{
+    pub(crate) fn new(block: SmartBlock<B>, dropped: Arc<Mutex<Vec<SmartBlock<B>>>>) -> Self {
+        SharedBlock {
+            inner: Arc::new(SharedInner {
+                block: Some(block),
+                dropped,
+            }),
+        }
+    }
+
+    /// Get number of handles to the block.
+    pub fn handles(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+
+    /// Unwrap the block if this is the only handle, i.e. to free it right away.
+    pub fn try_unwrap(self) -> Result<SmartBlock<B>, Self> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(mut inner) => Ok(inner.block.take().expect("Taken on drop only")),
+            Err(inner) => Err(SharedBlock { inner }),
+        }
+    }
+
+    fn block(&self) -> &SmartBlock<B> {
+        self.inner.block.as_ref().expect("Taken on drop only")
+    }
+}
+
+impl<B> Block<B> for SharedBlock<B>
+where
+    B: Backend,
+{
+    #[inline(always)]
+    fn memory(&self) -> &B::Memory {
+        self.block().memory()
+    }
+
+    #[inline(always)]
+    fn range(&self) -> Range<u64> {
+        self.block().range()
+    }
+}
+
+impl<B> Drop for SharedInner<B>
+where
+    B: Backend,
+{
+    fn drop(&mut self) {
+        if let Some(block) = self.block.take() {
+            lock(&self.dropped).push(block);
+        }
+    }
+}
+
+/// Handle that frees blocks from any thread without access to the allocator,
+/// i.e. to destroy resources on the render thread.
+///
+/// Blocks are queued and freed by `flush_frees` or `free_dropped` of the allocator the handle
+/// was created by. See `SmartAllocator::free_handle`.
+#[derive(Clone, Debug)]
+pub struct Freer<B: Backend> {
+    dropped: Arc<Mutex<Vec<SmartBlock<B>>>>,
+}
+
+impl<B> Freer<B>
+where
+    B: Backend,
+{
+    pub(crate) fn new(dropped: Arc<Mutex<Vec<SmartBlock<B>>>>) -> Self {
+        Freer { dropped }
+    }
+
+    /// Queue the block to be freed.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `block`: block allocated from the allocator the handle was created by
+    pub fn free(&self, block: SmartBlock<B>) {
+        lock(&self.dropped).push(block);
+    }
+}
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    // Allocator state is consistent between calls, poisoning can be ignored.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(feature = "parking_lot")]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock()
+}
+
+#[cfg(not(feature = "parking_lot"))]
+fn into_inner<T>(mutex: Mutex<T>) -> T {
+    mutex.into_inner().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(feature = "parking_lot")]
+fn into_inner<T>(mutex: Mutex<T>) -> T {
+    mutex.into_inner()
+}
@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use gfx_hal::memory::Requirements;
+
+use {alignment_shift, validate, MemoryError};
+use combined::Type;
+use config::SmartAllocatorConfig;
+
+/// Event of a recorded allocation trace, replayed by `simulate_configs`.
+#[derive(Clone, Copy, Debug)]
+pub enum TraceEvent {
+    /// Block was allocated.
+    Alloc {
+        /// Id of the block, unique among live blocks of the trace, i.e. `BlockInfo::id`.
+        id: u64,
+
+        /// Type of the request.
+        ty: Type,
+
+        /// Requirements of the block.
+        reqs: Requirements,
+    },
+
+    /// Block allocated by the `Alloc` event with the same id was freed.
+    Free {
+        /// Id of the block.
+        id: u64,
+    },
+}
+
+/// Outcome of replaying a trace against one configuration.
+#[derive(Clone, Copy, Debug)]
+pub struct SimReport {
+    /// Configuration the trace was replayed against.
+    pub config: SmartAllocatorConfig,
+
+    /// Largest number of bytes allocated from the device at once.
+    pub peak_allocated: u64,
+
+    /// Largest number of bytes requested by live blocks at once.
+    pub peak_used: u64,
+
+    /// Fraction of allocated bytes not requested by live blocks at the moment of
+    /// `peak_allocated`.
+    pub fragmentation: f32,
+
+    /// Number of memory objects allocated from the device over the trace.
+    pub device_allocations: usize,
+
+    /// Largest number of memory objects allocated from the device at once.
+    pub peak_device_objects: usize,
+}
+
+/// Replay the trace against each of the configurations, without a device.
+///
+/// Allocations are modelled after `CombinedAllocator` with default routing: short-lived
+/// blocks go to arenas returned in allocation order, general blocks that fit `max_chunk_size`
+/// go to chunks that are never trimmed, larger blocks are allocated from the device directly.
+/// Blocks of custom sub-allocators are modelled as allocated from the device directly too.
+/// All blocks are assumed to come from the same memory type.
+///
+/// ### Parameters:
+///
+/// - `trace`: allocation events in order they happened
+/// - `configs`: configurations to compare
+///
+/// ### Returns
+///
+/// Returns one report per configuration, in the same order.
+/// Returns `MemoryError::ZeroSize` if any value of a configuration is `0`.
+/// Returns an error if requirements of an `Alloc` event are invalid,
+/// i.e. of zero size or non-power-of-two alignment.
+pub fn simulate_configs(
+    trace: &[TraceEvent],
+    configs: &[SmartAllocatorConfig],
+) -> Result<Vec<SimReport>, MemoryError> {
+    for config in configs {
+        config.check()?;
+    }
+    for event in trace {
+        if let TraceEvent::Alloc { ref reqs, .. } = *event {
+            validate(reqs, !0)?;
+        }
+    }
+    Ok(configs
+        .iter()
+        .map(|&config| {
+            let mut sim = Simulation::new(config);
+            for event in trace {
+                match *event {
+                    TraceEvent::Alloc { id, ty, reqs } => sim.alloc(id, ty, reqs),
+                    TraceEvent::Free { id } => sim.free(id),
+                }
+            }
+            sim.report()
+        })
+        .collect())
+}
+
+/// Where a simulated block lives.
+#[derive(Clone, Copy, Debug)]
+enum Place {
+    /// Index of the arena counted from the first arena ever allocated.
+    Arena(u64),
+    /// Size class and chunk index.
+    Chunk(usize, usize),
+    /// Size of the memory object.
+    Root(u64),
+}
+
+#[derive(Debug)]
+struct SimArena {
+    size: u64,
+    offset: u64,
+    live: usize,
+}
+
+#[derive(Debug)]
+struct Simulation {
+    config: SmartAllocatorConfig,
+    live: BTreeMap<u64, (Place, u64)>,
+    /// Arenas in allocation order, the last one is hot.
+    arenas: VecDeque<SimArena>,
+    freed_arenas: u64,
+    /// Live block counts of chunks per size class.
+    chunks: Vec<Vec<usize>>,
+    allocated: u64,
+    used: u64,
+    objects: usize,
+    report: SimReport,
+}
+
+impl Simulation {
+    fn new(config: SmartAllocatorConfig) -> Self {
+        Simulation {
+            config,
+            live: BTreeMap::new(),
+            arenas: VecDeque::new(),
+            freed_arenas: 0,
+            chunks: Vec::new(),
+            allocated: 0,
+            used: 0,
+            objects: 0,
+            report: SimReport {
+                config,
+                peak_allocated: 0,
+                peak_used: 0,
+                fragmentation: 0.0,
+                device_allocations: 0,
+                peak_device_objects: 0,
+            },
+        }
+    }
+
+    fn alloc(&mut self, id: u64, ty: Type, reqs: Requirements) {
+        let place = match ty {
+            Type::ShortLived => self.alloc_arena(reqs),
+            Type::General if reqs.size <= self.config.max_chunk_size => self.alloc_chunk(reqs),
+            Type::General | Type::Custom(_) => {
+                self.grow(reqs.size);
+                Place::Root(reqs.size)
+            }
+        };
+        self.used += reqs.size;
+        self.report.peak_used = self.report.peak_used.max(self.used);
+        if self.allocated == self.report.peak_allocated && self.allocated != 0 {
+            self.report.fragmentation = 1.0 - self.used as f32 / self.allocated as f32;
+        }
+        if self.live.insert(id, (place, reqs.size)).is_some() {
+            warn!("Block {} allocated twice in the trace", id);
+        }
+    }
+
+    fn free(&mut self, id: u64) {
+        let (place, size) = match self.live.remove(&id) {
+            Some(block) => block,
+            None => {
+                warn!("Block {} freed without being allocated in the trace", id);
+                return;
+            }
+        };
+        self.used -= size;
+        match place {
+            Place::Arena(index) => {
+                self.arenas[(index - self.freed_arenas) as usize].live -= 1;
+                // Arenas are returned in order, the hot one is kept.
+                while self.arenas.len() > 1 && self.arenas[0].live == 0 {
+                    let arena = self.arenas.pop_front().expect("Checked above");
+                    self.shrink(arena.size);
+                    self.freed_arenas += 1;
+                }
+            }
+            Place::Chunk(class, chunk) => self.chunks[class][chunk] -= 1,
+            Place::Root(size) => self.shrink(size),
+        }
+    }
+
+    fn alloc_arena(&mut self, reqs: Requirements) -> Place {
+        let fits = self.arenas.back().map_or(false, |arena| {
+            let offset = arena.offset + alignment_shift(reqs.alignment, arena.offset);
+            offset + reqs.size <= arena.size
+        });
+        if !fits {
+            let arena_size = self.config.arena_size;
+            let size = ((reqs.size - 1) / arena_size + 1) * arena_size;
+            self.grow(size);
+            self.arenas.push_back(SimArena {
+                size,
+                offset: 0,
+                live: 0,
+            });
+        }
+        let arena = self.arenas.back_mut().expect("Just grown");
+        arena.offset += alignment_shift(reqs.alignment, arena.offset) + reqs.size;
+        arena.live += 1;
+        Place::Arena(self.freed_arenas + self.arenas.len() as u64 - 1)
+    }
+
+    fn alloc_chunk(&mut self, reqs: Requirements) -> Place {
+        let size = reqs.size.max(reqs.alignment).max(1);
+        let mut class = 0;
+        while self.config.min_block_size << class < size {
+            class += 1;
+        }
+        let block_size = self.config.min_block_size << class;
+        let chunk_size = (block_size * self.config.blocks_per_chunk as u64)
+            .min(self.config.max_chunk_size)
+            .max(block_size);
+        let per_chunk = (chunk_size / block_size) as usize;
+
+        if self.chunks.len() <= class {
+            self.chunks.resize(class + 1, Vec::new());
+        }
+        let chunk = match self.chunks[class]
+            .iter()
+            .position(|&live| live < per_chunk)
+        {
+            Some(chunk) => chunk,
+            None => {
+                self.grow(chunk_size);
+                self.chunks[class].push(0);
+                self.chunks[class].len() - 1
+            }
+        };
+        self.chunks[class][chunk] += 1;
+        Place::Chunk(class, chunk)
+    }
+
+    fn grow(&mut self, size: u64) {
+        self.allocated += size;
+        self.objects += 1;
+        self.report.device_allocations += 1;
+        self.report.peak_device_objects = self.report.peak_device_objects.max(self.objects);
+        self.report.peak_allocated = self.report.peak_allocated.max(self.allocated);
+    }
+
+    fn shrink(&mut self, size: u64) {
+        self.allocated -= size;
+        self.objects -= 1;
+    }
+
+    fn report(self) -> SimReport {
+        self.report
+    }
+}
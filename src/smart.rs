@@ -1,20 +1,365 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::mem::{align_of, forget, replace};
 use std::ops::Range;
+use std::sync::Arc;
 
-use gfx_hal::{Backend, MemoryProperties, MemoryType, MemoryTypeId};
+use gfx_hal::{Adapter, Backend, Device, MemoryProperties, MemoryType, MemoryTypeId,
+              PhysicalDevice};
+use gfx_hal::command::BufferCopy;
 use gfx_hal::memory::{Properties, Requirements};
 
-use {MemoryAllocator, MemoryError};
-use block::Block;
-use combined::{CombinedAllocator, CombinedBlock, Type};
+use {alignment_shift, validate, MemoryAllocator, MemoryError};
+use arena::ArenaAllocator;
+use block::{Block, RawBlock};
+use category::Categories;
+use chunked::ChunkedAllocator;
+use combined::{estimate_footprint, report_leak, CombinedAllocator, CombinedBlock, LeakMode,
+               Routing, Type};
+use config::{ColdEviction, PartialConfig, SmartAllocatorConfig, TrimStep};
+use defrag::{Compaction, CompactionReport};
+use freelist::FreeListAllocator;
+use frozen::FrozenAllocator;
+use heap::{Heap, HeapPolicy};
+use host::HostAllocator;
+use layout::RootBlock;
+use mapping::{as_bytes, write_bytes, Coherence, Pod, Upload};
+use names::NameIds;
+use observer::{AllocObserver, BlockInfo, GrowthHint, PressureEvent};
+use plugin::SubAllocatorFactory;
+use quirks::{find_quirk, Quirk, TypeSpread};
+use plan::{PlannedAllocation, ReallocationPlan};
+use registry::{LeakReport, LiveAllocation, Registry, StaleBlock};
+use request::{Dedicated, HostAccess, Lifetime, ResourceKind};
+use reservation::{Reservation, ReservationState};
+use root::RetryPolicy;
+use shared::{lock, Freer, Mutex, SharedBlock};
+use stats::{AllocatorStats, HeapStats, MeasureReport, Measurement, RequestHistogram,
+            ResourceStats, SizeClassStats, TypeStats};
+use usage::MemoryUsage;
+use warm::{WarmEntry, WarmState};
+
+/// Information required by `SmartAllocator` to allocate a block.
+///
+/// Can be created from a `(Type, Properties)` pair, leaving other options at their defaults.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SmartRequest {
+    /// Sub-allocator to use for the allocation.
+    pub ty: Type,
+
+    /// Properties the memory type must have.
+    pub properties: Properties,
+
+    /// Properties to retry with if no memory type with `properties` can serve the request,
+    /// i.e. `CPU_VISIBLE` for a `DEVICE_LOCAL | CPU_VISIBLE` request.
+    pub fallback_properties: Option<Properties>,
+
+    /// Allocate from protected memory types only.
+    /// Non-protected requests are never served from protected memory types.
+    pub protected: bool,
+
+    /// Priority of the allocation in range `0.0 ..= 1.0`, `None` stands for default `0.5`.
+    ///
+    /// `gfx_hal` can't pass the priority to the driver yet, but allocations are grouped into
+    /// root blocks of similar priority, so the hint can be applied per root block later.
+    pub priority: Option<f32>,
+
+    /// Expected lifetime of the block. Blocks of different lifetimes are kept in separate
+    /// root blocks, so that a persistent block never pins a chunk that streaming blocks
+    /// left otherwise empty.
+    pub lifetime: Lifetime,
+
+    /// Mask of physical devices of the device group the memory is allocated on,
+    /// `0` stands for all devices of the group.
+    ///
+    /// `gfx_hal` doesn't expose device groups yet, so the mask is used to account heap usage
+    /// per physical device and to keep allocations with different masks in separate root blocks.
+    ///
+    /// Allocations fail with `MemoryError::InvalidDeviceMask` if it contains bits for devices
+    /// beyond `SmartAllocator::device_count`.
+    pub device_mask: u32,
+
+    /// Memory type to try first if it is compatible with the request and its heap has enough
+    /// memory available, i.e. when replaying captures or co-locating related resources.
+    /// Otherwise the memory type is selected as usual.
+    pub memory_type: Option<MemoryTypeId>,
+
+    /// Opaque value stored with the allocation and reported in `BlockInfo`, so that leak
+    /// reports and observers can map blocks back to engine objects.
+    pub user_data: u64,
+
+    /// Allocate a dedicated memory object for the block. See `SmartAllocator::alloc_for_resource`.
+    pub dedicated: bool,
+
+    /// Number of frames the block is expected to live, i.e. for entries of transient texture
+    /// caches. Once the frame it expires in is completed, `SmartAllocator::maintain` reports
+    /// the block with `AllocObserver::on_expired` and releases it if it is retained with
+    /// `SmartAllocator::cache`. `None` stands for no limit.
+    pub ttl: Option<u64>,
+
+    /// Kind of resource the block is bound to. Reported in `TypeStats::resources`.
+    /// Blocks of pools that already hold resources of the other tiling are rounded up to
+    /// the buffer-image granularity, see `SmartAllocator::set_buffer_image_granularity`.
+    /// With `SmartAllocator::set_resource_segregation` blocks of different kinds are kept in
+    /// separate root blocks, which never pay it.
+    pub resource: ResourceKind,
+
+    /// Direction of host traffic through the block. Memory types suiting the direction are
+    /// tried first, and uploads and readbacks are kept in separate root blocks.
+    /// Reported in `TypeStats::upload` and `TypeStats::readback`.
+    pub access: HostAccess,
+}
+
+impl SmartRequest {
+    /// Create a request with default options.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `ty`: sub-allocator to use
+    /// - `properties`: properties the memory type must have
+    pub fn new(ty: Type, properties: Properties) -> Self {
+        SmartRequest {
+            ty,
+            properties,
+            fallback_properties: None,
+            protected: false,
+            priority: None,
+            lifetime: Lifetime::Unspecified,
+            device_mask: 0,
+            memory_type: None,
+            user_data: 0,
+            dedicated: false,
+            ttl: None,
+            resource: ResourceKind::Unspecified,
+            access: HostAccess::Unspecified,
+        }
+    }
+}
+
+impl From<(Type, Properties)> for SmartRequest {
+    fn from((ty, properties): (Type, Properties)) -> Self {
+        SmartRequest::new(ty, properties)
+    }
+}
+
+/// Result of `SmartAllocator::realloc`.
+#[derive(Debug)]
+pub enum Realloc<B: Backend> {
+    /// The block fits the new requirements in place.
+    InPlace,
+
+    /// The block is replaced by a new one.
+    /// The user must record the copy from the old block to the new one, i.e. between buffers
+    /// bound to them, and free the old block once the copy is complete.
+    Moved {
+        /// The old block.
+        old: SmartBlock<B>,
+
+        /// Region to copy from the old block to the new one.
+        copy: BufferCopy,
+    },
+}
+
+/// How `SmartAllocator` handles blocks that can't be freed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MisuseMode {
+    /// Panic in `free`.
+    Strict,
+
+    /// Log an error in `free` and leak the block. Panics in debug builds anyway.
+    Lenient,
+}
+
+/// Default threshold of memory object count warning.
+/// 90% of 4096, the minimal `maxMemoryAllocationCount` guaranteed by Vulkan.
+const DEFAULT_ALLOCATION_COUNT_WARNING: usize = 3686;
+
+/// Number of classes allocation priorities are quantized into.
+const PRIORITY_CLASSES: u8 = 5;
+
+/// Get bytes of the block not covered by the request placed at the first offset of the block
+/// aligned for it, i.e. the alignment shift before the request and the rounding after it.
+fn padding(range: Range<u64>, reqs: &Requirements) -> u64 {
+    let aligned = (range.start + alignment_shift(reqs.alignment, range.start)).min(range.end);
+    let end = aligned.saturating_add(reqs.size).min(range.end);
+    (aligned - range.start) + (range.end - end)
+}
+
+fn priority_class(priority: Option<f32>) -> u8 {
+    let priority = priority.unwrap_or(0.5).max(0.0).min(1.0);
+    (priority * (PRIORITY_CLASSES - 1) as f32).round() as u8
+}
+
+/// Merge size class statistics of the pools, matching classes by block size.
+fn size_class_stats<B: Backend>(pools: &[(PoolKey, CombinedAllocator<B>)]) -> Vec<SizeClassStats> {
+    let mut merged: Vec<SizeClassStats> = Vec::new();
+    for &(_, ref pool) in pools {
+        for stats in pool.size_class_stats() {
+            match merged
+                .iter()
+                .position(|merged| merged.block_size == stats.block_size)
+            {
+                Some(index) => merged[index].merge(&stats),
+                None => merged.push(stats),
+            }
+        }
+    }
+    merged.sort_by_key(|stats| stats.block_size);
+    merged
+}
+
+/// Key that separates allocations of one memory type into distinct root blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PoolKey {
+    priority: u8,
+    lifetime: Lifetime,
+    device_mask: u32,
+    /// Kind of resources in the pool, `ResourceKind::Unspecified` if kinds are mixed.
+    resource: ResourceKind,
+    access: HostAccess,
+}
+
+impl PoolKey {
+    fn new(request: &SmartRequest, device_mask: u32, segregated: bool) -> Self {
+        PoolKey {
+            priority: priority_class(request.priority),
+            lifetime: request.lifetime,
+            device_mask,
+            resource: if segregated {
+                request.resource
+            } else {
+                ResourceKind::Unspecified
+            },
+            access: request.access,
+        }
+    }
+}
+
+/// Get usage of the resource kind, adding it on first use.
+fn resource_stats(
+    resources: &mut Vec<ResourceStats>,
+    resource: ResourceKind,
+) -> &mut ResourceStats {
+    match resources.iter().position(|stats| stats.resource == resource) {
+        Some(index) => &mut resources[index],
+        None => {
+            resources.push(ResourceStats {
+                resource,
+                ..ResourceStats::default()
+            });
+            resources.last_mut().expect("Pushed above")
+        }
+    }
+}
+
+/// Allocators for single memory type.
+#[derive(Debug)]
+struct TypeAllocator<B: Backend> {
+    memory_type: MemoryType,
+    pools: Vec<(PoolKey, CombinedAllocator<B>)>,
+    used: u64,
+    blocks: usize,
+    /// Bytes of pinned blocks.
+    pinned: u64,
+    /// Bytes of live blocks wasted to rounding.
+    padding: u64,
+    /// Sub-allocated blocks are rounded up to multiples of it.
+    granularity: u64,
+    /// An allocation preferring the memory type fell back to another one.
+    fell_back: bool,
+    requests: RequestHistogram,
+    /// Usage per resource kind, without bytes allocated from the device.
+    resources: Vec<ResourceStats>,
+    /// Tilings of resources placed in each pool so far, indexed as `pools`.
+    tilings: Vec<u8>,
+    /// Bytes of live upload blocks.
+    upload: u64,
+    /// Bytes of live readback blocks.
+    readback: u64,
+    /// Configuration the pools were created with.
+    config: SmartAllocatorConfig,
+}
+
+impl<B> TypeAllocator<B>
+where
+    B: Backend,
+{
+    fn is_used(&self) -> bool {
+        self.pools
+            .iter()
+            .any(|&(_, ref allocator)| allocator.is_used())
+    }
+
+    /// Get bytes of live blocks of the host access direction, `None` if it is not tracked.
+    fn access_used(&mut self, access: HostAccess) -> Option<&mut u64> {
+        match access {
+            HostAccess::Unspecified => None,
+            HostAccess::Upload => Some(&mut self.upload),
+            HostAccess::Readback => Some(&mut self.readback),
+        }
+    }
+}
 
 /// Allocator that can choose memory type based on requirements, and keeps track of allocators
 /// for all given memory types.
 ///
 /// Allocates memory blocks from the least used memory type from those which satisfy requirements.
+/// If that memory type runs out of memory, the other ones are tried in order of their usage.
 #[derive(Debug)]
 pub struct SmartAllocator<B: Backend> {
-    allocators: Vec<(MemoryType, CombinedAllocator<B>)>,
+    allocators: Vec<TypeAllocator<B>>,
     heaps: Vec<Heap>,
+    protected: u64,
+    /// Memory types tried only after all others, see `Quirk::avoided_types`.
+    avoided: u64,
+    quirk: Option<Quirk>,
+    dedicated_threshold: Option<u64>,
+    config: SmartAllocatorConfig,
+    auto_config: Option<(SmartAllocatorConfig, SmartAllocatorConfig)>,
+    reservations: Vec<ReservationState>,
+    next_reservation: u64,
+    observers: Vec<Box<AllocObserver>>,
+    categories: Categories,
+    registry: Registry,
+    name_ids: NameIds,
+    watchdog: Option<u64>,
+    misuse: MisuseMode,
+    leak: LeakMode,
+    usage: Arc<MemoryUsage>,
+    deferred_frees: bool,
+    sealed: bool,
+    spanning: bool,
+    retry: Option<RetryPolicy>,
+    spread: TypeSpread,
+    /// Rotation of equivalent memory types for `TypeSpread::RoundRobin`.
+    spread_cursor: usize,
+    micro_threshold: u64,
+    low_watermark: usize,
+    out_of_order_frees: bool,
+    epoch_retirement: bool,
+    routing: Option<Routing>,
+    /// Blocks queued by `Freer` handles or dropped by the last `SharedBlock` handle.
+    dropped: Arc<Mutex<Vec<SmartBlock<B>>>>,
+    /// Handles retained until their blocks expire by ids of the blocks. See `cache`.
+    cached: BTreeMap<u64, SharedBlock<B>>,
+    allocation_count_warning: Option<usize>,
+    /// Memory object count is above the warning threshold.
+    allocation_count_warned: bool,
+    growth_watermark: Option<f32>,
+    eviction: Option<ColdEviction>,
+    trim_order: Vec<TrimStep>,
+    /// Measurements in progress, innermost last. See `measure`.
+    measurements: Vec<Measurement>,
+    /// Blocks of different resource kinds are kept in separate pools.
+    segregated: bool,
+    /// Sub-allocated blocks of pools mixing resource tilings are rounded up to multiples of it.
+    buffer_image_granularity: u64,
+    /// Flushed and invalidated ranges of non-coherent memory are widened to multiples of it.
+    non_coherent_atom_size: u64,
+    /// Factories of sub-allocators for `Type::Custom` kinds.
+    factories: Vec<(u32, Arc<SubAllocatorFactory<B>>)>,
+    /// Creates host allocators serving pools instead of the device. See `set_host_memory`.
+    host: Option<fn(MemoryTypeId) -> HostAllocator<B>>,
 }
 
 impl<B> SmartAllocator<B>
@@ -37,147 +382,2858 @@ where
         min_block_size: u64,
         max_chunk_size: u64,
     ) -> Self {
+        SmartAllocator::with_config(
+            memory_properties,
+            SmartAllocatorConfig {
+                arena_size,
+                blocks_per_chunk,
+                min_block_size,
+                max_chunk_size,
+            },
+        )
+    }
+
+    /// Create a new smart allocator from `MemoryProperties` given by a device.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `config`: configuration of sub-allocators
+    pub fn with_config(memory_properties: MemoryProperties, config: SmartAllocatorConfig) -> Self {
+        SmartAllocator::with_properties(
+            memory_properties.memory_types,
+            memory_properties.memory_heaps,
+            config,
+        )
+    }
+
+    /// Create a new smart allocator from memory types and heaps, i.e. fabricated to test
+    /// memory type selection without a device. See `testing` for common layouts.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `types`: memory types, heap indices must be within `heaps`
+    /// - `heaps`: sizes of memory heaps
+    /// - `config`: configuration of sub-allocators
+    ///
+    /// ### Panics
+    ///
+    /// Panics if a memory type refers to a missing heap.
+    pub fn with_properties(
+        types: Vec<MemoryType>,
+        heaps: Vec<u64>,
+        config: SmartAllocatorConfig,
+    ) -> Self {
+        assert!(
+            types.iter().all(|memory_type| memory_type.heap_index < heaps.len()),
+            "Memory types refer to missing heaps"
+        );
+        let usage = MemoryUsage::new(heaps.len(), types.len());
         SmartAllocator {
-            allocators: memory_properties
-                .memory_types
+            allocators: types
                 .into_iter()
-                .enumerate()
-                .map(|(index, memory_type)| {
-                    (
-                        memory_type,
-                        CombinedAllocator::new(
-                            MemoryTypeId(index),
-                            arena_size,
-                            blocks_per_chunk,
-                            min_block_size,
-                            max_chunk_size,
-                        ),
-                    )
+                .map(|memory_type| TypeAllocator {
+                    memory_type,
+                    pools: Vec::new(),
+                    used: 0,
+                    blocks: 0,
+                    pinned: 0,
+                    padding: 0,
+                    granularity: 1,
+                    fell_back: false,
+                    requests: RequestHistogram::default(),
+                    resources: Vec::new(),
+                    tilings: Vec::new(),
+                    upload: 0,
+                    readback: 0,
+                    config,
                 })
                 .collect(),
-            heaps: memory_properties
-                .memory_heaps
+            heaps: heaps
                 .into_iter()
-                .map(|size| Heap { size, used: 0 })
+                .map(|size| Heap::new(size, 1))
                 .collect(),
+            protected: 0,
+            avoided: 0,
+            quirk: None,
+            dedicated_threshold: None,
+            config,
+            auto_config: None,
+            reservations: Vec::new(),
+            next_reservation: 0,
+            observers: Vec::new(),
+            categories: Categories::default(),
+            registry: Registry::default(),
+            name_ids: NameIds::new(),
+            watchdog: None,
+            misuse: MisuseMode::Strict,
+            leak: LeakMode::default(),
+            usage: Arc::new(usage),
+            deferred_frees: false,
+            sealed: false,
+            spanning: false,
+            retry: None,
+            spread: TypeSpread::default(),
+            spread_cursor: 0,
+            micro_threshold: 0,
+            low_watermark: 0,
+            out_of_order_frees: false,
+            epoch_retirement: false,
+            routing: None,
+            dropped: Arc::new(Mutex::new(Vec::new())),
+            cached: BTreeMap::new(),
+            allocation_count_warning: Some(DEFAULT_ALLOCATION_COUNT_WARNING),
+            allocation_count_warned: false,
+            growth_watermark: None,
+            eviction: None,
+            trim_order: vec![TrimStep::ColdChunks],
+            measurements: Vec::new(),
+            segregated: false,
+            buffer_image_granularity: 1,
+            non_coherent_atom_size: 1,
+            factories: Vec::new(),
+            host: None,
         }
     }
-}
 
-impl<B> MemoryAllocator<B> for SmartAllocator<B>
-where
-    B: Backend,
-{
-    type Request = (Type, Properties);
-    type Block = SmartBlock<B>;
+    /// Create a new smart allocator for the adapter, seeding defaults with its `Limits`.
+    ///
+    /// - Buffer-image granularity is set, so that linear and optimal resources never share
+    ///   a granule. See `set_buffer_image_granularity`.
+    /// - Granularity of host-visible memory types that are not coherent is set to the
+    ///   non-coherent atom size, so that flushes and invalidations of a block never touch
+    ///   its neighbours. See `set_granularity` and `set_non_coherent_atom_size`.
+    /// - Memory object count warning is set to 90% of the maximal memory allocation count,
+    ///   if the backend reports it. See `set_allocation_count_warning`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `adapter`: adapter the device is opened from
+    /// - `config`: configuration of sub-allocators
+    pub fn from_adapter(adapter: &Adapter<B>, config: SmartAllocatorConfig) -> Self {
+        let limits = adapter.physical_device.limits();
+        let mut allocator =
+            SmartAllocator::with_config(adapter.physical_device.memory_properties(), config);
+        allocator.set_buffer_image_granularity(
+            limits.buffer_image_granularity.max(1).next_power_of_two(),
+        );
+        let atom = (limits.non_coherent_atom_size as u64).max(1).next_power_of_two();
+        allocator.set_non_coherent_atom_size(atom);
+        for index in 0..allocator.allocators.len() {
+            let properties = allocator.allocators[index].memory_type.properties;
+            if properties.contains(Properties::CPU_VISIBLE)
+                && !properties.contains(Properties::COHERENT)
+            {
+                allocator.set_granularity(MemoryTypeId(index), atom);
+            }
+        }
+        // Backends that don't report the limit keep the default threshold.
+        let count = limits.max_memory_allocation_count as usize;
+        if count != 0 {
+            allocator.set_allocation_count_warning(Some(count - count / 10));
+        }
+        allocator
+    }
 
-    fn alloc(
-        &mut self,
-        device: &B::Device,
-        (ty, prop): (Type, Properties),
-        reqs: Requirements,
-    ) -> Result<SmartBlock<B>, MemoryError> {
-        let mut compatible = false;
-        let mut candidate = None;
+    /// Get configuration of sub-allocators.
+    pub fn config(&self) -> SmartAllocatorConfig {
+        self.config
+    }
 
-        // Find compatible memory type with least used heap with enough available memory
-        for index in 0..self.allocators.len() {
-            let memory_type = self.allocators[index].0;
-            // filter out non-compatible
-            if ((1 << index) & reqs.type_mask) != (1 << index)
-                || !memory_type.properties.contains(prop)
-            {
-                continue;
+    /// Change configuration of sub-allocators while blocks are live.
+    ///
+    /// Changes apply to arenas and size classes created afterwards, live blocks stay valid.
+    /// Heap policies apply to allocations made afterwards.
+    /// In auto-configuration mode the configuration is still adapted by `mark_frame`.
+    ///
+    /// ### Returns
+    ///
+    /// `MemoryError::ZeroSize` if a specified value is `0`, the configuration is unchanged.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if a heap index of `heap_policies` is out of range.
+    pub fn reconfigure(&mut self, partial: PartialConfig) -> Result<(), MemoryError> {
+        partial.apply(self.config).check()?;
+        assert!(
+            partial.heap_policies.iter().all(|&(heap, _)| heap < self.heaps.len()),
+            "Heap index is out of range"
+        );
+        self.config = partial.apply(self.config);
+        if let Some(chunks) = partial.low_watermark {
+            self.set_low_watermark(chunks);
+        }
+        for &(heap, policy) in &partial.heap_policies {
+            self.set_heap_policy(heap, policy);
+        }
+        for allocator in &mut self.allocators {
+            allocator.config = partial.apply(allocator.config);
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                if let Some(arena_size) = partial.arena_size {
+                    pool.set_arena_size(arena_size);
+                }
+                if let Some(blocks_per_chunk) = partial.blocks_per_chunk {
+                    pool.set_blocks_per_chunk(blocks_per_chunk);
+                }
             }
-            compatible = true;
-            // filter out if heap has not enough memory available
-            if self.heaps[memory_type.heap_index].available() < (reqs.size + reqs.alignment) {
-                continue;
+        }
+        Ok(())
+    }
+
+    /// Suggest configuration of sub-allocators based on requests observed so far.
+    ///
+    /// Requests to all memory types are taken into account. The suggestion applies to
+    /// allocators created later with `with_config`, this allocator keeps its configuration.
+    pub fn suggest_config(&self) -> SmartAllocatorConfig {
+        let mut requests = RequestHistogram::default();
+        for allocator in &self.allocators {
+            requests.merge(&allocator.requests);
+        }
+        self.config.suggest(&requests, self.registry.frame())
+    }
+
+    /// Enable auto-configuration mode.
+    ///
+    /// The allocator starts with `min` configuration and adapts it in `mark_frame` to the
+    /// configuration `suggest_config` recommends, clamped into the bounds.
+    /// Pools of a memory type are recreated with the new configuration once no blocks
+    /// allocated from that memory type are in use.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `min`: lower bounds of configuration values
+    /// - `max`: upper bounds of configuration values
+    ///
+    /// ### Panics
+    ///
+    /// Panics if any value of `min` is greater than the one of `max`.
+    pub fn enable_auto_config(&mut self, min: SmartAllocatorConfig, max: SmartAllocatorConfig) {
+        assert!(min.bounded_by(&max));
+        self.config = min.clamp(&min, &max);
+        self.auto_config = Some((min, max));
+    }
+
+    /// Disable auto-configuration mode, keeping the current configuration.
+    pub fn disable_auto_config(&mut self) {
+        self.auto_config = None;
+    }
+
+    /// Register an observer that will be notified about allocation events.
+    pub fn add_observer(&mut self, observer: Box<AllocObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Attempt to dispose of this allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    ///
+    /// ### Returns
+    ///
+    /// If the allocator contains memory blocks that are still in use, this will return the
+    /// allocator along with the report about the live blocks.
+    pub fn try_dispose(self, device: &B::Device) -> Result<(), (Self, LeakReport)> {
+        self.dispose(device).map_err(|allocator| {
+            let report = allocator.registry.leaks();
+            (allocator, report)
+        })
+    }
+
+    /// Dispose of this allocator, freeing all memory allocated from the device, even if blocks
+    /// allocated from it are still in use.
+    ///
+    /// Intended for shutdown paths. Blocks that are still in use are invalidated.
+    /// They must not be used or freed afterwards and should be leaked with `std::mem::forget`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    pub fn force_dispose(mut self, device: &B::Device) {
+        for allocator in self.allocators.drain(..) {
+            for (_, pool) in allocator.pools {
+                pool.force_dispose(device);
+            }
+        }
+    }
+
+    /// Dispose of the allocator after the device is lost, leaking all memory allocated from
+    /// the device without reporting it, so that the shutdown doesn't mask the original error.
+    /// Blocks that are still in use are invalidated and should be leaked with
+    /// `std::mem::forget`. See `reallocation_plan` to recreate them against a new device.
+    pub fn dispose_lost(mut self) {
+        self.leak = LeakMode::Silent;
+    }
+
+    /// Advance frame counter.
+    ///
+    /// In auto-configuration mode adapts the configuration to the observed requests.
+    /// If the watchdog is enabled, warns about short-lived blocks that outlived it.
+    pub fn mark_frame(&mut self) {
+        self.registry.mark_frame(self.watchdog);
+        let frame = self.registry.frame();
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_epoch(frame);
             }
-            // Compare with candidate. Replace if this one is less used.
-            let this_usage = self.heaps[memory_type.heap_index].usage();
-            match candidate {
-                Some((ref mut candidate, ref mut usage)) if *usage > this_usage => {
-                    *candidate = index;
-                    *usage = this_usage;
+        }
+        if let Some((min, max)) = self.auto_config {
+            let config = self.suggest_config().clamp(&min, &max);
+            if config != self.config {
+                debug!("Adapting sub-allocators configuration to {:?}", config);
+                self.config = config;
+            }
+        }
+    }
+
+    /// Get number of frames marked with `mark_frame`.
+    pub fn frame(&self) -> u64 {
+        self.registry.frame()
+    }
+
+    /// Enable or disable the short-lived block watchdog.
+    ///
+    /// Short-lived blocks pin whole arenas, so blocks allocated with `Type::ShortLived` that
+    /// survive for more than specified number of frames are almost always bugs.
+    /// Such blocks are reported with a warning in `mark_frame` once.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `frames`: number of frames short-lived blocks may survive, `None` disables the watchdog
+    pub fn set_short_lived_watchdog(&mut self, frames: Option<u64>) {
+        self.watchdog = frames;
+    }
+
+    /// Collect short-lived blocks that survived for more than specified number of frames.
+    pub fn stale_short_lived(&self, frames: u64) -> Vec<StaleBlock> {
+        self.registry.stale(frames)
+    }
+
+    /// Enter a category nested in the current one.
+    /// Blocks allocated until the matching `pop_category` are accounted in this category.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `name`: name of the category, must not be empty or contain `/`
+    pub fn push_category(&mut self, name: &str) {
+        self.categories.push(name);
+    }
+
+    /// Leave the current category.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if there is no category to leave.
+    pub fn pop_category(&mut self) {
+        self.categories.pop();
+    }
+
+    /// Get path of the current category, names of nested categories separated by `/`.
+    pub fn current_category(&self) -> Option<&str> {
+        self.categories
+            .current()
+            .map(|index| self.categories.path(index))
+    }
+
+    /// Get memory usage counters that can be read from other threads without locking
+    /// the allocator.
+    pub fn usage(&self) -> Arc<MemoryUsage> {
+        self.usage.clone()
+    }
+
+    /// Record the shape of warmed pools, i.e. at shutdown, to restore it with `warm` at the next
+    /// startup. Only chunked size classes are recorded.
+    pub fn warm_state(&self) -> WarmState {
+        let mut state = WarmState::new();
+        for (index, allocator) in self.allocators.iter().enumerate() {
+            for &(key, ref pool) in &allocator.pools {
+                for (block_size, chunks) in pool.warm_state() {
+                    if chunks == 0 {
+                        continue;
+                    }
+                    state.push(WarmEntry {
+                        memory_type: index,
+                        priority: key.priority,
+                        lifetime: key.lifetime,
+                        device_mask: key.device_mask,
+                        resource: key.resource,
+                        access: key.access,
+                        block_size,
+                        chunks,
+                    });
                 }
-                ref mut candidate @ None => *candidate = Some((index, this_usage)),
-                _ => {}
             }
         }
+        state
+    }
 
-        match candidate {
-            Some((chosen, _)) => {
-                // Allocate from final candidate
-                let block = self.allocators[chosen].1.alloc(device, ty, reqs)?;
-                self.heaps[self.allocators[chosen].0.heap_index].alloc(block.size());
-                Ok(SmartBlock(block, chosen))
+    /// Grow pools to the shape recorded with `warm_state`, i.e. in the previous run, so that
+    /// the steady-state memory layout is reached immediately. Entries of memory types or
+    /// size classes the allocator doesn't have, i.e. after a driver update, are skipped.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the chunks from
+    /// - `state`: recorded shape of the pools
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of chunks allocated, or the error of the first failed allocation.
+    pub fn warm(&mut self, device: &B::Device, state: &WarmState) -> Result<usize, MemoryError> {
+        let mut grown = 0;
+        for entry in state.entries() {
+            if entry.memory_type >= self.allocators.len() || entry.priority >= PRIORITY_CLASSES {
+                debug!("Skip warming of {:?}", entry);
+                continue;
             }
-            None => {
-                // No candidates
-                Err(if !compatible {
-                    MemoryError::NoCompatibleMemoryType
-                } else {
-                    MemoryError::OutOfMemory
+            let key = PoolKey {
+                priority: entry.priority,
+                lifetime: entry.lifetime,
+                device_mask: entry.device_mask,
+                resource: entry.resource,
+                access: entry.access,
+            };
+            let pool = self.pool(entry.memory_type, key);
+            let result = self.allocators[entry.memory_type].pools[pool]
+                .1
+                .warm(device, entry.block_size, entry.chunks);
+            self.report_growths(entry.memory_type, pool);
+            grown += result?;
+        }
+        Ok(grown)
+    }
+
+    /// Record allocations, frees and peak additional usage while the closure runs, i.e. to find
+    /// out how much memory loading an asset takes end-to-end. Measurements can be nested.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `f`: closure to measure, allocating and freeing blocks with the allocator
+    pub fn measure<F>(&mut self, f: F) -> MeasureReport
+    where
+        F: FnOnce(&mut Self),
+    {
+        let measurement = Measurement::new(self.used(), self.allocated());
+        self.measurements.push(measurement);
+        f(self);
+        self.measurements
+            .pop()
+            .expect("Measurement is pushed above")
+            .finish()
+    }
+
+    /// Collect statistics of the allocator.
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            heaps: self.heaps
+                .iter()
+                .map(|heap| HeapStats {
+                    size: heap.size(),
+                    used: heap.used(),
+                    reserved: heap.reserved(),
+                    padding: heap.padding(),
+                    system_budgets: heap.budgets().to_vec(),
                 })
-            }
+                .collect(),
+            types: self.allocators
+                .iter()
+                .map(|allocator| TypeStats {
+                    used: allocator.used,
+                    blocks: allocator.blocks,
+                    pinned: allocator.pinned,
+                    padding: allocator.padding,
+                    allocated: allocator
+                        .pools
+                        .iter()
+                        .map(|&(_, ref pool)| pool.allocated())
+                        .sum(),
+                    allocations: allocator
+                        .pools
+                        .iter()
+                        .map(|&(_, ref pool)| pool.allocations())
+                        .sum(),
+                    requests: allocator.requests.clone(),
+                    size_classes: size_class_stats(&allocator.pools),
+                    resources: {
+                        let mut resources = allocator.resources.clone();
+                        for &(key, ref pool) in &allocator.pools {
+                            resource_stats(&mut resources, key.resource).allocated +=
+                                pool.allocated();
+                        }
+                        resources
+                    },
+                    upload: allocator.upload,
+                    readback: allocator.readback,
+                })
+                .collect(),
+            categories: self.categories.stats(),
+            lifetimes: self.registry.lifetimes(),
         }
     }
 
-    fn free(&mut self, device: &B::Device, block: SmartBlock<B>) {
-        let SmartBlock(block, index) = block;
-        self.heaps[self.allocators[index].0.heap_index].free(block.size());
-        self.allocators[index].1.free(device, block);
+    /// Get information about the block allocated from this allocator.
+    pub fn block_info(&self, block: &SmartBlock<B>) -> BlockInfo {
+        let memory_type = self.allocators[block.index].memory_type;
+        BlockInfo {
+            id: block.id,
+            memory_type: MemoryTypeId(block.index),
+            heap: memory_type.heap_index,
+            ty: block.block.ty(),
+            resource: self.registry
+                .get(block.id)
+                .map_or(ResourceKind::Unspecified, |allocation| allocation.info.resource),
+            range: block.range(),
+            user_data: self.registry
+                .get(block.id)
+                .map_or(0, |allocation| allocation.info.user_data),
+            version: self.version(block),
+        }
     }
 
-    fn is_used(&self) -> bool {
-        self.allocators
-            .iter()
-            .any(|&(_, ref allocator)| allocator.is_used())
+    /// Get version of the block's contents. New blocks start at version `0`.
+    /// See `bump_version`.
+    pub fn version(&self, block: &SmartBlock<B>) -> u64 {
+        self.registry
+            .get(block.id)
+            .map_or(0, |allocation| allocation.info.version)
     }
 
-    fn dispose(mut self, device: &B::Device) -> Result<(), Self> {
-        if self.is_used() {
-            Err(self)
-        } else {
-            for (_, allocator) in self.allocators.drain(..) {
-                allocator.dispose(device).unwrap();
+    /// Increment version of the block's contents, i.e. after rewriting them, so that systems
+    /// caching descriptor sets or bindings keyed on memory and offset can tell stale entries
+    /// apart. Recycled blocks are told apart by `BlockInfo::id` already.
+    ///
+    /// ### Returns
+    ///
+    /// Returns the new version.
+    pub fn bump_version(&mut self, block: &SmartBlock<B>) -> u64 {
+        self.registry.bump_version(block.id).unwrap_or(0)
+    }
+
+    /// Replace opaque value stored with the block. See `SmartRequest::user_data`.
+    pub fn set_user_data(&mut self, block: &SmartBlock<B>, user_data: u64) {
+        self.registry.set_user_data(block.id, user_data);
+    }
+
+    /// Name the block, i.e. after the resource bound to it. See `live_allocations`.
+    ///
+    /// The name gets a stable id from the registry of name ids. See `set_name_ids`.
+    pub fn set_name(&mut self, block: &SmartBlock<B>, name: &str) {
+        self.name_ids.id(name);
+        self.registry.set_name(block.id, Some(name.to_owned()));
+    }
+
+    /// Replace the registry of name ids, i.e. with one loaded from a previous run, so that
+    /// blocks named the same get the same stable ids across runs.
+    pub fn set_name_ids(&mut self, name_ids: NameIds) {
+        self.name_ids = name_ids;
+        for allocation in self.registry.live() {
+            if let Some(ref name) = allocation.name {
+                self.name_ids.id(name);
             }
-            Ok(())
         }
     }
-}
 
-#[derive(Debug)]
-struct Heap {
-    size: u64,
-    used: u64,
-}
+    /// Get the registry of name ids, i.e. to save it for the next run.
+    pub fn name_ids(&self) -> &NameIds {
+        &self.name_ids
+    }
+
+    /// Get stable id of the block's name, i.e. to record in traces instead of `BlockInfo::id`.
+    /// Returns `None` if the block is not named.
+    pub fn stable_id(&self, block: &SmartBlock<B>) -> Option<u64> {
+        self.registry
+            .get(block.id)
+            .and_then(|allocation| allocation.name.as_ref())
+            .and_then(|name| self.name_ids.get(name))
+    }
 
-impl Heap {
-    fn available(&self) -> u64 {
-        self.size - self.used
+    /// Pin the block, i.e. memory exported to another API or a persistently mapped ring
+    /// buffer, so that compaction and migration passes never move it.
+    ///
+    /// Pinned blocks are marked in `live_allocations` and their bytes are reported in
+    /// `TypeStats::pinned`.
+    pub fn pin(&mut self, block: &SmartBlock<B>) {
+        if !self.registry.set_pinned(block.id, true) {
+            self.allocators[block.index].pinned += block.size();
+        }
     }
 
-    fn alloc(&mut self, size: u64) {
-        self.used += size;
+    /// Unpin the block pinned with `pin`.
+    pub fn unpin(&mut self, block: &SmartBlock<B>) {
+        if self.registry.set_pinned(block.id, false) {
+            self.allocators[block.index].pinned -= block.size();
+        }
     }
 
-    fn free(&mut self, size: u64) {
-        self.used -= size;
+    /// Check if the block is pinned with `pin`.
+    pub fn is_pinned(&self, block: &SmartBlock<B>) -> bool {
+        self.registry
+            .get(block.id)
+            .map_or(false, |allocation| allocation.pinned)
     }
 
-    fn usage(&self) -> f32 {
-        self.used as f32 / self.size as f32
+    /// Collect live allocations to recreate them against a fresh allocator, i.e. after the
+    /// device is lost.
+    pub fn reallocation_plan(&self) -> ReallocationPlan {
+        ReallocationPlan {
+            allocations: self.registry
+                .live()
+                .map(|allocation| {
+                    let info = &allocation.info;
+                    PlannedAllocation {
+                        id: info.id,
+                        ty: info.ty,
+                        memory_type: info.memory_type,
+                        properties: self.allocators[info.memory_type.0].memory_type.properties,
+                        size: info.range.end - info.range.start - allocation.padding,
+                        user_data: info.user_data,
+                        category: allocation
+                            .category
+                            .map(|category| self.categories.path(category).to_owned()),
+                        name: allocation.name.clone(),
+                        request: allocation.request,
+                    }
+                })
+                .collect(),
+        }
     }
-}
 
-/// Opaque type for `Block` tag used by the `SmartAllocator`.
-///
-/// `SmartAllocator` places this tag on the memory blocks, and then use it in
-/// `free` to find the memory node the block was allocated from.
-#[derive(Debug)]
-pub struct SmartBlock<B: Backend>(CombinedBlock<B>, usize);
+    /// Freeze the allocator, i.e. for a phase of parallel command recording.
+    ///
+    /// Frozen allocator can't allocate or free blocks, but can be shared between threads to
+    /// look up block metadata and statistics. Call `FrozenAllocator::thaw` to resume.
+    pub fn freeze(self) -> FrozenAllocator<B> {
+        FrozenAllocator::new(self)
+    }
+
+    /// Get all live blocks in allocation order, i.e. to annotate GPU captures or draw
+    /// memory overlays.
+    pub fn live_allocations(&self) -> Vec<LiveAllocation<B>> {
+        self.registry
+            .live()
+            .map(|allocation| LiveAllocation {
+                // Memory objects are boxed and stay in place while blocks in them are alive.
+                memory: unsafe { &*(allocation.memory as *const B::Memory) },
+                info: &allocation.info,
+                name: allocation.name.as_ref().map(String::as_str),
+                category: allocation
+                    .category
+                    .map(|category| self.categories.path(category)),
+                pinned: allocation.pinned,
+            })
+            .collect()
+    }
+
+    /// Rank live blocks as candidates for eviction, i.e. to decide which resources a streaming
+    /// system destroys when an allocation fails.
+    ///
+    /// Blocks of the lowest priority come first, then ones allocated in the earliest frames,
+    /// then the largest ones. Pinned blocks are never candidates.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `bytes_needed`: bytes to free, candidates are returned until their sizes add up to it
+    /// - `filter`: predicate selecting blocks that may be evicted, i.e. ones of a heap
+    ///
+    /// ### Returns
+    ///
+    /// Returns candidates in order of eviction. Returns all candidates if they don't add up to
+    /// `bytes_needed`.
+    pub fn enumerate_eviction_candidates<F>(&self, bytes_needed: u64, filter: F) -> Vec<BlockInfo>
+    where
+        F: FnMut(&BlockInfo) -> bool,
+    {
+        self.eviction_candidates(bytes_needed, u64::max_value(), filter)
+    }
+
+    /// Rank live blocks allocated not later than `max_frame` as candidates for eviction.
+    /// See `enumerate_eviction_candidates`.
+    fn eviction_candidates<F>(
+        &self,
+        bytes_needed: u64,
+        max_frame: u64,
+        mut filter: F,
+    ) -> Vec<BlockInfo>
+    where
+        F: FnMut(&BlockInfo) -> bool,
+    {
+        let mut candidates: Vec<_> = self.registry
+            .live()
+            .filter(|allocation| {
+                !allocation.pinned && allocation.frame <= max_frame && filter(&allocation.info)
+            })
+            .collect();
+        candidates.sort_by(|left, right| {
+            left.priority()
+                .partial_cmp(&right.priority())
+                .unwrap_or(Ordering::Equal)
+                .then(left.frame.cmp(&right.frame))
+                .then(
+                    (right.info.range.end - right.info.range.start)
+                        .cmp(&(left.info.range.end - left.info.range.start)),
+                )
+        });
+        let mut freed = 0;
+        candidates
+            .into_iter()
+            .take_while(|allocation| {
+                let needed = freed < bytes_needed;
+                freed += allocation.info.range.end - allocation.info.range.start;
+                needed
+            })
+            .map(|allocation| allocation.info.clone())
+            .collect()
+    }
+
+    /// Get memory objects allocated from the device with live blocks and free ranges in them,
+    /// i.e. to draw memory maps in debug UIs. Memory objects are ordered by memory type.
+    pub fn root_blocks(&self) -> Vec<RootBlock<B>> {
+        let mut blocks = Vec::new();
+        for (index, allocator) in self.allocators.iter().enumerate() {
+            for &(_, ref pool) in &allocator.pools {
+                for (memory, size) in pool.memories() {
+                    blocks.push(RootBlock::new(memory, MemoryTypeId(index), size));
+                }
+            }
+        }
+        let address = |memory: &B::Memory| memory as *const B::Memory as usize;
+        let mut order: Vec<usize> = (0..blocks.len()).collect();
+        order.sort_by_key(|&block| address(blocks[block].memory));
+        let mut live = self.live_allocations();
+        live.sort_by_key(|allocation| allocation.info.range.start);
+        for allocation in live {
+            let found = order.binary_search_by_key(&address(allocation.memory), |&block| {
+                address(blocks[block].memory)
+            });
+            if let Ok(position) = found {
+                blocks[order[position]].push(allocation);
+            }
+        }
+        for block in &mut blocks {
+            block.finish();
+        }
+        blocks
+    }
+
+    /// Allocate a block for a resource, taking the driver's dedicated allocation preference
+    /// into account.
+    ///
+    /// Required dedicated allocations always get their own memory object. Preferred ones do
+    /// unless the memory object count has reached the warning threshold, see
+    /// `set_allocation_count_warning`. Blocks at least `Quirk::dedicated_threshold` big get
+    /// their own memory object regardless, as with any request.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `request`: information required to allocate the block
+    /// - `reqs`: the requirements of the resource
+    /// - `dedicated`: dedicated allocation preference of the resource
+    pub fn alloc_for_resource(
+        &mut self,
+        device: &B::Device,
+        mut request: SmartRequest,
+        reqs: Requirements,
+        dedicated: Dedicated,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        request.dedicated |= match dedicated {
+            Dedicated::No => false,
+            Dedicated::Preferred => self.allocation_count_warning
+                .map_or(true, |threshold| self.allocation_count() < threshold),
+            Dedicated::Required => true,
+        };
+        self.alloc(device, request, reqs)
+    }
+
+    /// Get number of memory objects currently allocated from the device.
+    pub fn allocation_count(&self) -> usize {
+        self.allocators
+            .iter()
+            .flat_map(|allocator| allocator.pools.iter())
+            .map(|&(_, ref pool)| pool.allocations())
+            .sum()
+    }
+
+    /// Set number of memory objects at which a warning is emitted.
+    ///
+    /// Devices limit number of memory objects that can exist at once, exceeding the limit
+    /// is a hard failure. Set the threshold slightly below the device limit.
+    /// Defaults to 90% of 4096, the minimal limit guaranteed by Vulkan.
+    ///
+    /// The warning is emitted each time the count crosses the threshold upwards, i.e. again
+    /// after memory objects are freed below the threshold and allocated anew.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `threshold`: number of memory objects, `None` disables the warning
+    pub fn set_allocation_count_warning(&mut self, threshold: Option<usize>) {
+        self.allocation_count_warning = threshold;
+        self.allocation_count_warned = false;
+    }
+
+    /// Enable or disable deferred frees.
+    ///
+    /// When enabled, memory objects are not freed when the last block in them is freed, but
+    /// queued until `flush_frees`, so that bursts of frees (i.e. level unload) don't cause
+    /// hitches mid-frame.
+    pub fn set_deferred_frees(&mut self, deferred: bool) {
+        self.deferred_frees = deferred;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_deferred_frees(deferred);
+            }
+        }
+    }
+
+    /// Set fraction of a chunk or an arena left free at which observers are notified with
+    /// `AllocObserver::on_low_watermark`, `None` disables notifications.
+    ///
+    /// Together with `provide_memory` this allows growing sub-allocators on another thread,
+    /// so that the thread allocating blocks never waits for the device to allocate memory.
+    pub fn set_growth_watermark(&mut self, watermark: Option<f32>) {
+        self.growth_watermark = watermark;
+    }
+
+    /// Provide memory allocated in response to `AllocObserver::on_low_watermark`.
+    ///
+    /// The sub-allocator takes the memory when it grows next, instead of allocating from the
+    /// device. See `RootAllocator::provide_memory`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the memory was allocated from, used to free the memory if the
+    ///   pool of the sub-allocator doesn't exist anymore
+    /// - `hint`: hint the memory was allocated for
+    /// - `memory`: memory of `hint.memory_type` and `hint.size` bytes
+    pub fn provide_memory(&mut self, device: &B::Device, hint: &GrowthHint, memory: B::Memory) {
+        match self.allocators[hint.memory_type.0]
+            .pools
+            .iter_mut()
+            .find(|&&mut (key, _)| key == hint.pool)
+        {
+            Some(&mut (_, ref mut pool)) => pool.provide_memory(memory, hint.size),
+            None => {
+                device.free_memory(memory);
+                return;
+            }
+        }
+        for observer in &mut self.observers {
+            observer.on_grow(hint.memory_type, hint.heap, hint.size);
+        }
+        self.check_allocation_count();
+    }
+
+    /// Register factory of the sub-allocator serving `Type::Custom(kind)` requests,
+    /// i.e. a ring for skinning matrices.
+    ///
+    /// Every pool creates its own sub-allocator on the first request of the kind. Blocks are
+    /// accounted in heaps, stats and categories the same way as blocks of built-in types.
+    /// See `CombinedAllocator::register_sub_allocator`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if a factory is already registered for the kind.
+    pub fn register_sub_allocator(&mut self, kind: u32, factory: Arc<SubAllocatorFactory<B>>) {
+        assert!(
+            self.factories.iter().all(|&(registered, _)| registered != kind),
+            "Sub-allocator for kind {} is already registered",
+            kind
+        );
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.register_sub_allocator(kind, factory.clone());
+            }
+        }
+        self.factories.push((kind, factory));
+    }
+
+    /// Set number of chunks without blocks in use each chunk size class keeps ahead.
+    ///
+    /// Size classes grow as soon as fewer chunks are left unused, instead of only when they
+    /// run out of blocks, smoothing latency spikes of allocations at the cost of some unused
+    /// memory. Zero, the default, disables growing ahead. See `ChunkedAllocator::pre_grow`.
+    pub fn set_low_watermark(&mut self, chunks: usize) {
+        self.low_watermark = chunks;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_low_watermark(chunks);
+            }
+        }
+    }
+
+    /// Enable or disable spanning allocations of `Type::General` blocks.
+    ///
+    /// When enabled, blocks slightly larger than a power of two are allocated as adjacent
+    /// blocks of a smaller size class, wasting less memory. See `ChunkedAllocator::set_spanning`.
+    pub fn set_spanning(&mut self, spanning: bool) {
+        self.spanning = spanning;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_spanning(spanning);
+            }
+        }
+    }
+
+    /// Set size at or below which `Type::General` blocks are packed into shared micro chunks,
+    /// i.e. small uniform buffers. Zero, the default, disables micro blocks.
+    ///
+    /// Micro blocks take `threshold` bytes each instead of a whole block of the smallest
+    /// size class. Pools with minimal block size not above the threshold are not affected.
+    /// See `ChunkedAllocator::set_micro_threshold`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `threshold` is not zero and not a power of two.
+    pub fn set_micro_threshold(&mut self, threshold: u64) {
+        assert!(
+            threshold == 0 || threshold.is_power_of_two(),
+            "Micro threshold {} must be a power of two",
+            threshold
+        );
+        self.micro_threshold = threshold;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_micro_threshold(threshold);
+            }
+        }
+    }
+
+    /// Set policy of retrying allocations from the device that fail because the device is out
+    /// of memory, `None`, the default, fails them right away.
+    ///
+    /// Observers are notified about every retry with `AllocObserver::on_alloc_retry`.
+    /// See `RootAllocator::set_retry_policy`.
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_retry_policy(retry);
+            }
+        }
+    }
+
+    /// Set how allocations are distributed across equivalent memory types, i.e. ones of the
+    /// same heap with identical properties. `TypeSpread::First`, the default, allocates from
+    /// the one with the lowest index.
+    ///
+    /// Spreading works around drivers that limit memory objects or allocated bytes per memory
+    /// type. It applies only among memory types equally preferred otherwise, a memory type
+    /// requested with `SmartRequest::memory_type` is still tried first.
+    pub fn set_type_spread(&mut self, spread: TypeSpread) {
+        self.spread = spread;
+    }
+
+    /// Get how allocations are distributed across equivalent memory types.
+    pub fn type_spread(&self) -> TypeSpread {
+        self.spread
+    }
+
+    /// Apply the quirk of the device from the table, if there is one. See `Quirk`.
+    ///
+    /// Should be called before the first allocation, pools already created keep their
+    /// configuration.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `quirks`: table of quirks
+    /// - `vendor`: PCI vendor id of the device, i.e. `AdapterInfo::vendor`
+    /// - `device`: PCI device id of the device, i.e. `AdapterInfo::device`
+    ///
+    /// ### Returns
+    ///
+    /// Returns `true` if a quirk was applied.
+    pub fn apply_quirks(&mut self, quirks: &[Quirk], vendor: usize, device: usize) -> bool {
+        let quirk = match find_quirk(quirks, vendor, device) {
+            Some(&quirk) => quirk,
+            None => return false,
+        };
+        debug!(
+            "Applying quirk {:?} for device {:04x}:{:04x}",
+            quirk, vendor, device
+        );
+        if let Some(max_root_block) = quirk.max_root_block {
+            let clamp = |config: &mut SmartAllocatorConfig| {
+                config.arena_size = config.arena_size.min(max_root_block);
+                config.max_chunk_size = config.max_chunk_size.min(max_root_block);
+                config.min_block_size = config.min_block_size.min(config.max_chunk_size);
+            };
+            clamp(&mut self.config);
+            for allocator in &mut self.allocators {
+                clamp(&mut allocator.config);
+            }
+        }
+        self.set_type_spread(quirk.spread);
+        for &index in quirk.avoided_types {
+            if index < 64 {
+                self.avoided |= 1 << index;
+            } else {
+                warn!("Quirk avoids memory type {} out of range", index);
+            }
+        }
+        self.dedicated_threshold = quirk.dedicated_threshold;
+        self.quirk = Some(quirk);
+        true
+    }
+
+    /// Get the quirk applied with `apply_quirks`.
+    pub fn quirk(&self) -> Option<Quirk> {
+        self.quirk
+    }
+
+    /// Seal the allocator, i.e. after loading is done.
+    ///
+    /// Afterwards any allocation that would have to allocate memory from the device fails
+    /// with `MemoryError::Sealed`, allocations from already allocated memory still succeed.
+    /// Use it to prove no device memory is allocated during gameplay.
+    pub fn seal(&mut self) {
+        self.set_sealed(true);
+    }
+
+    /// Unseal the allocator, i.e. to load the next level.
+    pub fn unseal(&mut self) {
+        self.set_sealed(false);
+    }
+
+    /// Check if the allocator is sealed.
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    fn set_sealed(&mut self, sealed: bool) {
+        self.sealed = sealed;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_sealed(sealed);
+            }
+        }
+    }
+
+    /// Enable or disable out-of-order frees of `Type::ShortLived` arenas.
+    ///
+    /// When enabled, an arena is returned as soon as its last block is freed, so a long-lived
+    /// straggler pins only its own arena instead of all arenas allocated after it.
+    pub fn set_out_of_order_frees(&mut self, out_of_order: bool) {
+        self.out_of_order_frees = out_of_order;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_out_of_order_frees(out_of_order);
+            }
+        }
+    }
+
+    /// Enable or disable epoch retirement of `Type::ShortLived` arenas.
+    ///
+    /// When enabled, arenas without blocks in use are kept until `maintain` is called with
+    /// the frame they were retired in, see `frame`. This way blocks can be freed right after
+    /// commands reading them are submitted.
+    pub fn set_epoch_retirement(&mut self, enabled: bool) {
+        self.epoch_retirement = enabled;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_epoch_retirement(enabled);
+            }
+        }
+    }
+
+    /// Report blocks that expired in completed frames, see `SmartRequest::ttl`, and free the
+    /// ones retained with `cache`. Then return arenas retired in completed frames and run
+    /// steps of the trim order. By default only cold chunks are returned, if eviction is
+    /// enabled. See `set_trim_order`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the memory
+    /// - `completed`: last frame the device is done with, i.e. of the last signalled fence
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of arenas, chunks and memory objects returned.
+    pub fn maintain(&mut self, device: &B::Device, completed: u64) -> usize {
+        self.expire(device, completed);
+        let mut returned = 0;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                returned += pool.maintain(device, completed);
+            }
+        }
+        for index in 0..self.trim_order.len() {
+            let step = self.trim_order[index];
+            returned += self.trim_step(device, step, None, None);
+        }
+        returned
+    }
+
+    /// Run steps of the trim order until enough memory is returned to the device,
+    /// i.e. when the application is about to be suspended or a heap is over budget.
+    /// Steps are run whole, so more memory than requested may be returned.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the memory
+    /// - `bytes`: amount of memory to return, `0` stops before the first step
+    ///
+    /// ### Returns
+    ///
+    /// Returns amount of memory returned.
+    pub fn shrink(&mut self, device: &B::Device, bytes: u64) -> u64 {
+        let before = self.allocated();
+        for index in 0..self.trim_order.len() {
+            let returned = before - self.allocated();
+            if returned >= bytes {
+                break;
+            }
+            let step = self.trim_order[index];
+            self.trim_step(device, step, None, Some(bytes - returned));
+        }
+        before - self.allocated()
+    }
+
+    /// Set steps run by `maintain` and `shrink` to return unused memory, in order.
+    /// Steps not listed are never run. Defaults to `TrimStep::ColdChunks` alone.
+    ///
+    /// Which memory to give up first is a trade-off between footprint and allocation
+    /// stalls, i.e. editors may flush everything eagerly while shipping builds keep chunk
+    /// caches warm and return only cold ones.
+    pub fn set_trim_order(&mut self, order: &[TrimStep]) {
+        self.trim_order = order.to_vec();
+    }
+
+    /// Get steps run by `maintain` and `shrink` to return unused memory, in order.
+    pub fn trim_order(&self) -> &[TrimStep] {
+        &self.trim_order
+    }
+
+    /// Run steps of the trim order over pools of the heap, i.e. when its usage crosses the
+    /// soft target. Returns number of chunks or memory objects returned.
+    fn trim_heap(&mut self, device: &B::Device, heap: usize) -> usize {
+        let mut returned = 0;
+        for index in 0..self.trim_order.len() {
+            let step = self.trim_order[index];
+            returned += self.trim_step(device, step, Some(heap), None);
+        }
+        returned
+    }
+
+    /// Run one step of the trim order over pools of the heap, `None` stands for all heaps.
+    /// `bytes` is the amount of memory still to be returned, if there is a target.
+    /// Returns number of chunks or memory objects returned.
+    fn trim_step(
+        &mut self,
+        device: &B::Device,
+        step: TrimStep,
+        heap: Option<usize>,
+        bytes: Option<u64>,
+    ) -> usize {
+        let eviction = match (step, self.eviction) {
+            (TrimStep::ColdChunks, None) | (TrimStep::ColdBlocks, None) => return 0,
+            (TrimStep::ColdBlocks, Some(eviction)) => {
+                self.evict_cold(eviction.age, heap, bytes);
+                return 0;
+            }
+            (_, eviction) => eviction,
+        };
+        let mut returned = 0;
+        if step == TrimStep::RootCache {
+            returned += self.free_dropped(device);
+        }
+        for allocator in &mut self.allocators {
+            if heap.map_or(false, |heap| heap != allocator.memory_type.heap_index) {
+                continue;
+            }
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                returned += match (step, eviction) {
+                    (TrimStep::ChunkCaches, _) => pool.trim(device),
+                    (TrimStep::RootCache, _) => pool.flush_frees(device, usize::max_value()),
+                    (TrimStep::ColdChunks, Some(eviction)) => {
+                        pool.trim_cold(device, eviction.age, eviction.max_chunks)
+                    }
+                    (TrimStep::ColdChunks, None) | (TrimStep::ColdBlocks, _) => 0,
+                };
+            }
+        }
+        self.rearm_allocation_count();
+        returned
+    }
+
+    /// Ask observers to evict blocks allocated at least `age` frames ago until they cover
+    /// `bytes`, or usage of each heap above its soft target if there is no target.
+    fn evict_cold(&mut self, age: u64, heap: Option<usize>, bytes: Option<u64>) {
+        let max_frame = match self.frame().checked_sub(age) {
+            Some(max_frame) => max_frame,
+            None => return,
+        };
+        let targets: Vec<(Option<usize>, u64)> = match bytes {
+            Some(bytes) => vec![(heap, bytes)],
+            None => (0..self.heaps.len())
+                .filter(|&index| heap.map_or(true, |heap| heap == index))
+                .map(|index| (Some(index), self.heaps[index].above_target()))
+                .collect(),
+        };
+        for (heap, bytes) in targets {
+            if bytes == 0 {
+                continue;
+            }
+            let candidates = {
+                let allocators = &self.allocators;
+                self.eviction_candidates(bytes, max_frame, |info| {
+                    let index = allocators[info.memory_type.0].memory_type.heap_index;
+                    heap.map_or(true, |heap| heap == index)
+                })
+            };
+            for info in &candidates {
+                debug!("Block #{} {:?} is offered for eviction", info.id, info.range);
+                for observer in &mut self.observers {
+                    observer.on_evict(info);
+                }
+            }
+        }
+    }
+
+    /// Get total size of memory allocated from the device by all pools.
+    fn allocated(&self) -> u64 {
+        self.allocators
+            .iter()
+            .flat_map(|allocator| allocator.pools.iter())
+            .map(|&(_, ref pool)| pool.allocated())
+            .sum()
+    }
+
+    /// Get bytes used by live blocks of all memory types.
+    fn used(&self) -> u64 {
+        self.allocators.iter().map(|allocator| allocator.used).sum()
+    }
+
+    /// Record an event in measurements in progress and update their peaks.
+    fn record<F>(&mut self, event: F)
+    where
+        F: Fn(&mut Measurement),
+    {
+        if self.measurements.is_empty() {
+            return;
+        }
+        let used = self.used();
+        let device = self.allocated();
+        for measurement in &mut self.measurements {
+            event(measurement);
+            measurement.sample(used, device);
+        }
+    }
+
+    /// Get number of memory objects allocated from the device by all pools.
+    fn allocations(&self) -> usize {
+        self.allocators
+            .iter()
+            .flat_map(|allocator| allocator.pools.iter())
+            .map(|&(_, ref pool)| pool.allocations())
+            .sum()
+    }
+
+    /// Recycle all arenas of `Type::ShortLived` blocks for reuse without freeing their memory,
+    /// i.e. at the start of a frame. See `ArenaAllocator::reset`.
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of arenas available for reuse.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `Type::ShortLived` blocks are still in use.
+    pub fn reset_arenas(&mut self, device: &B::Device) -> usize {
+        let mut recycled = 0;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                recycled += pool.reset_arenas(device);
+            }
+        }
+        recycled
+    }
+
+    /// Set policy of returning chunks without blocks in use to the device in `maintain`,
+    /// `None` keeps such chunks until the allocator is disposed.
+    ///
+    /// Chunks are tagged with the frame their blocks were last allocated or freed in, see
+    /// `mark_frame`, so that chunks unused for the longest time are returned first, like
+    /// entries of a cache. Ages are reported in `SizeClassStats::cold_frames`.
+    /// The policy also enables `TrimStep::ColdBlocks`, if it is in the trim order.
+    pub fn set_cold_eviction(&mut self, eviction: Option<ColdEviction>) {
+        self.eviction = eviction;
+    }
+
+    /// Set size thresholds for routing `Type::General` allocations. See `Routing`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `routing`: thresholds, `None` routes blocks larger than `max_chunk_size` to dedicated
+    ///   memory objects and the rest to chunked allocators
+    pub fn set_routing(&mut self, routing: Option<Routing>) {
+        self.routing = routing;
+        for allocator in &mut self.allocators {
+            let max_chunk_size = allocator.config.max_chunk_size;
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                pool.set_routing(routing.unwrap_or(Routing::chunked(max_chunk_size)));
+            }
+        }
+    }
+
+    /// Get size thresholds for routing `Type::General` allocations.
+    pub fn routing(&self) -> Option<Routing> {
+        self.routing
+    }
+
+    /// Get number of retired arenas pinned by blocks that are still in use.
+    pub fn pinned_arenas(&self) -> usize {
+        self.allocators
+            .iter()
+            .flat_map(|allocator| allocator.pools.iter())
+            .map(|&(_, ref pool)| pool.pinned_arenas().len())
+            .sum()
+    }
+
+    /// Get number of freed memory objects waiting for `flush_frees`.
+    pub fn pending_frees(&self) -> usize {
+        self.allocators
+            .iter()
+            .flat_map(|allocator| allocator.pools.iter())
+            .map(|&(_, ref pool)| pool.pending_frees())
+            .sum()
+    }
+
+    /// Free queued memory objects.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: same device that was used to allocate the memory
+    /// - `max`: maximum number of memory objects to free, bounds time spent in the call
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of memory objects freed.
+    pub fn flush_frees(&mut self, device: &B::Device, max: usize) -> usize {
+        self.free_dropped(device);
+        let mut freed = 0;
+        for allocator in &mut self.allocators {
+            for &mut (_, ref mut pool) in &mut allocator.pools {
+                freed += pool.flush_frees(device, max - freed);
+            }
+        }
+        self.rearm_allocation_count();
+        freed
+    }
+
+    /// Share the block between several owners.
+    ///
+    /// When the last handle is dropped, the block is queued and freed by `flush_frees`
+    /// or `free_dropped`.
+    pub fn share(&self, block: SmartBlock<B>) -> SharedBlock<B> {
+        SharedBlock::new(block, self.dropped.clone())
+    }
+
+    /// Get a handle that frees blocks from any thread without `&mut` access to the allocator.
+    ///
+    /// Blocks passed to the handle are queued and freed by `flush_frees` or `free_dropped`.
+    pub fn free_handle(&self) -> Freer<B> {
+        Freer::new(self.dropped.clone())
+    }
+
+    /// Share the block like `share`, retaining a handle until the block expires, so that
+    /// a transient cache doesn't have to track its entries. Once its time-to-live is over the
+    /// block is freed by `maintain` as soon as all other handles are dropped.
+    /// Blocks allocated without `SmartRequest::ttl` are retained until the allocator is
+    /// disposed.
+    pub fn cache(&mut self, block: SmartBlock<B>) -> SharedBlock<B> {
+        let id = block.id;
+        let shared = self.share(block);
+        self.cached.insert(id, shared.clone());
+        shared
+    }
+
+    /// Report blocks expiring in frames up to `completed` and release their retained handles.
+    fn expire(&mut self, device: &B::Device, completed: u64) {
+        let expired = self.registry.take_expired(completed);
+        if expired.is_empty() {
+            return;
+        }
+        for info in &expired {
+            debug!("Block #{} {:?} has expired", info.id, info.range);
+            for observer in &mut self.observers {
+                observer.on_expired(info);
+            }
+        }
+        for info in &expired {
+            self.cached.remove(&info.id);
+        }
+        self.free_dropped(device);
+    }
+
+    /// Free blocks queued by `Freer` handles or whose last `SharedBlock` handle was dropped.
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of blocks freed.
+    pub fn free_dropped(&mut self, device: &B::Device) -> usize {
+        let dropped = replace(&mut *lock(&self.dropped), Vec::new());
+        let count = dropped.len();
+        for block in dropped {
+            self.free(device, block);
+        }
+        count
+    }
+
+    /// Set what the allocator does when dropped without being disposed.
+    ///
+    /// Dropped allocator reports blocks still in use and memory leaked, then leaks the memory
+    /// instead of relying on its pools to panic.
+    pub fn set_leak_mode(&mut self, mode: LeakMode) {
+        self.leak = mode;
+    }
+
+    /// Get what the allocator does when dropped without being disposed.
+    pub fn leak_mode(&self) -> LeakMode {
+        self.leak
+    }
+
+    /// Set how blocks that can't be freed are handled by `free`.
+    /// `try_free` always returns an error for such blocks.
+    pub fn set_misuse_mode(&mut self, mode: MisuseMode) {
+        self.misuse = mode;
+    }
+
+    /// Get how blocks that can't be freed are handled by `free`.
+    pub fn misuse_mode(&self) -> MisuseMode {
+        self.misuse
+    }
+
+    /// Check if allocations are served from host memory. See `set_host_memory`.
+    pub fn host_memory(&self) -> bool {
+        self.host.is_some()
+    }
+
+    /// Get contents of the block allocated from host memory. See `set_host_memory`.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidFree` if the block is not allocated from host memory of
+    /// this allocator.
+    pub fn host_bytes(&self, block: &SmartBlock<B>) -> Result<&[u8], MemoryError> {
+        match self.allocators[block.index].pools.get(block.pool) {
+            Some(&(_, ref pool)) => match pool.host() {
+                Some(host) => host.bytes(block),
+                None => Err(MemoryError::InvalidFree),
+            },
+            None => Err(MemoryError::InvalidFree),
+        }
+    }
+
+    /// Get mutable contents of the block allocated from host memory. See `host_bytes`.
+    pub fn host_bytes_mut(&mut self, block: &SmartBlock<B>) -> Result<&mut [u8], MemoryError> {
+        match self.allocators[block.index].pools.get_mut(block.pool) {
+            Some(&mut (_, ref mut pool)) => match pool.host_mut() {
+                Some(host) => host.bytes_mut(block),
+                None => Err(MemoryError::InvalidFree),
+            },
+            None => Err(MemoryError::InvalidFree),
+        }
+    }
+
+    /// Get properties of the memory type the block is allocated from.
+    pub fn properties(&self, block: &SmartBlock<B>) -> Properties {
+        self.allocators[block.index].memory_type.properties
+    }
+
+    /// Grow the block to fit new requirements, i.e. of a growing vertex pool.
+    ///
+    /// The block stays in place if it is large enough already or the space right after it is
+    /// free. Otherwise a new block is allocated with the request the block was allocated with,
+    /// preferably from the same memory type, and replaces the block. It keeps user data, name,
+    /// category and expiry of the block.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `block`: block to grow, replaced if it is moved
+    /// - `new_reqs`: requirements of the grown resource
+    ///
+    /// ### Returns
+    ///
+    /// Returns `Realloc::Moved` with the old block and the region to copy from it if the block
+    /// is moved. On error the block is left unchanged.
+    pub fn realloc(
+        &mut self,
+        device: &B::Device,
+        block: &mut SmartBlock<B>,
+        new_reqs: Requirements,
+    ) -> Result<Realloc<B>, MemoryError> {
+        let old_size = block.size();
+        let resource = self.registry
+            .get(block.id)
+            .map_or(ResourceKind::Unspecified, |allocation| allocation.info.resource);
+        let granular = self.granular(block.index, block.pool, &new_reqs, resource)?;
+        if (1 << block.index) & new_reqs.type_mask != 0
+            && self.allocators[block.index].pools[block.pool]
+                .1
+                .grow_in_place(&mut block.block, &granular)
+        {
+            self.resized(block, old_size, &new_reqs);
+            return Ok(Realloc::InPlace);
+        }
+        // Reuse the original request, so that the block lands in a pool of the same key.
+        let (request, name) = match self.registry.get(block.id) {
+            Some(allocation) => (allocation.request, allocation.name.clone()),
+            None => (SmartRequest::new(block.block.ty(), self.properties(block)), None),
+        };
+        let request = SmartRequest {
+            memory_type: Some(MemoryTypeId(block.index)),
+            ..request
+        };
+        let mut new = self.alloc(device, request, new_reqs)?;
+        if name.is_some() {
+            self.registry.set_name(new.id, name);
+        }
+        if new.category != block.category {
+            let size = new.size();
+            if let Some(category) = new.category {
+                self.categories.free(category, size);
+            }
+            if let Some(category) = block.category {
+                self.categories.alloc(category, size);
+            }
+            new.category = block.category;
+            self.registry.set_category(new.id, block.category);
+        }
+        if let Some(frame) = self.registry.expiry(block.id) {
+            self.registry.set_expiry(new.id, frame);
+        }
+        debug!(
+            "Block #{} of {} bytes is moved to #{} of {} bytes",
+            block.id,
+            old_size,
+            new.id,
+            new_reqs.size
+        );
+        Ok(Realloc::Moved {
+            old: replace(block, new),
+            copy: BufferCopy {
+                src: 0,
+                dst: 0,
+                size: old_size.min(new_reqs.size),
+            },
+        })
+    }
+
+    /// Begin a defragmentation pass. Record relocated blocks in the returned `Compaction`
+    /// and pass it to `end_compaction` once done.
+    pub fn begin_compaction(&self) -> Compaction {
+        Compaction::new(self.allocations())
+    }
+
+    /// End a defragmentation pass. Old locations of relocated blocks should be freed and
+    /// returned to the device, i.e. with `shrink`, before the pass ends to be reported.
+    ///
+    /// ### Returns
+    ///
+    /// Returns the report of the pass.
+    pub fn end_compaction(&self, compaction: Compaction) -> CompactionReport {
+        let allocated = self.allocated();
+        let used: u64 = self.allocators
+            .iter()
+            .map(|allocator| allocator.used)
+            .sum();
+        let fragmentation = if allocated == 0 {
+            0.0
+        } else {
+            1.0 - used as f32 / allocated as f32
+        };
+        compaction.finish(self.allocations(), fragmentation)
+    }
+
+    /// Write data to the start of the block, doing the right thing for its memory type.
+    ///
+    /// - Host-visible memory is written directly. Writes to coherent memory are not flushed.
+    ///   On UMA devices device-local memory is usually host-visible too.
+    /// - For other memory a staging block is allocated as `Type::ShortLived` from host-visible
+    ///   memory and the data is written to it instead.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `block`: block to write to
+    /// - `data`: data to write
+    ///
+    /// ### Returns
+    ///
+    /// Returns `Upload::Staged` with the staging block if the data has to be copied by the
+    /// device. Returns `Upload::Done` right away if the data is empty.
+    /// Returns `MemoryError::InvalidRange` if the data doesn't fit into the block.
+    pub fn upload<T: Pod>(
+        &mut self,
+        device: &B::Device,
+        block: &mut SmartBlock<B>,
+        data: &[T],
+    ) -> Result<Upload<SmartBlock<B>>, MemoryError> {
+        let bytes = as_bytes(data);
+        if bytes.len() as u64 > block.size() {
+            return Err(MemoryError::InvalidRange);
+        }
+        if bytes.is_empty() {
+            return Ok(Upload::Done);
+        }
+        let properties = self.properties(block);
+        if properties.contains(Properties::CPU_VISIBLE) {
+            let coherence = self.coherence(block);
+            write_bytes(device, block, bytes, coherence)?;
+            return Ok(Upload::Done);
+        }
+
+        let reqs = Requirements {
+            size: bytes.len() as u64,
+            alignment: align_of::<T>() as u64,
+            type_mask: !0,
+        };
+        let request = SmartRequest {
+            access: HostAccess::Upload,
+            ..SmartRequest::new(Type::ShortLived, Properties::CPU_VISIBLE)
+        };
+        let mut staging = self.alloc(device, request, reqs)?;
+        let coherence = self.coherence(&staging);
+        if let Err(error) = write_bytes(device, &mut staging, bytes, coherence) {
+            self.free(device, staging);
+            return Err(error);
+        }
+        Ok(Upload::Staged(staging))
+    }
+
+    /// Set policy for the heap.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `heap`: index of the heap
+    /// - `policy`: oversubscription, usage warning, soft target and hard limit policy
+    pub fn set_heap_policy(&mut self, heap: usize, policy: HeapPolicy) {
+        self.heaps[heap].set_policy(policy);
+    }
+
+    /// Get policy of the heap.
+    pub fn heap_policy(&self, heap: usize) -> HeapPolicy {
+        self.heaps[heap].policy()
+    }
+
+    /// Mark memory types that provide protected memory.
+    ///
+    /// `gfx_hal` doesn't report protected memory types, so the user has to supply them.
+    /// Protected requests are served only from these types, and other requests never are.
+    /// Because protected and non-protected memory never share a memory type they never share
+    /// root blocks either.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `mask`: bit mask of protected memory types, bit `i` stands for `MemoryTypeId(i)`
+    pub fn set_protected_types(&mut self, mask: u64) {
+        self.protected = mask;
+    }
+
+    /// Set minimal granularity of blocks sub-allocated from the memory type, i.e. 1 KiB for
+    /// device-local memory. Sizes and alignments of such blocks are rounded up to multiples
+    /// of it, so that sub-allocators track fewer distinct sizes and offsets suit any
+    /// resource. Rounding is reported as padding in `TypeStats` and `HeapStats`.
+    /// Affects blocks allocated afterwards only, dedicated blocks are never rounded.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_type`: memory type to configure
+    /// - `granularity`: granularity in bytes, `1` disables rounding
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `granularity` is not a power of two.
+    pub fn set_granularity(&mut self, memory_type: MemoryTypeId, granularity: u64) {
+        assert!(
+            granularity.is_power_of_two(),
+            "Granularity must be a power of two, got {}",
+            granularity
+        );
+        self.allocators[memory_type.0].granularity = granularity;
+    }
+
+    /// Get minimal granularity of blocks sub-allocated from the memory type.
+    pub fn granularity(&self, memory_type: MemoryTypeId) -> u64 {
+        self.allocators[memory_type.0].granularity
+    }
+
+    /// Set buffer-image granularity of the device, i.e. `Limits::buffer_image_granularity`.
+    ///
+    /// Blocks of linear resources sub-allocated from pools that already hold optimal images,
+    /// and vice versa, are rounded up to multiples of it, like with `set_granularity`, so that
+    /// linear and optimal resources never share a granule. Blocks of pools holding a single
+    /// tiling and blocks of unspecified kind don't pay it. See `SmartRequest::resource` and
+    /// `set_resource_segregation`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `granularity` is not a power of two.
+    pub fn set_buffer_image_granularity(&mut self, granularity: u64) {
+        assert!(
+            granularity.is_power_of_two(),
+            "Granularity must be a power of two, got {}",
+            granularity
+        );
+        self.buffer_image_granularity = granularity;
+    }
+
+    /// Get buffer-image granularity of the device.
+    pub fn buffer_image_granularity(&self) -> u64 {
+        self.buffer_image_granularity
+    }
+
+    /// Set non-coherent atom size of the device, i.e. `Limits::non_coherent_atom_size`.
+    ///
+    /// Ranges of non-coherent memory flushed and invalidated by `upload`, `MultiBuffered` and
+    /// mappings made with `coherence` are widened to multiples of it.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `atom` is not a power of two.
+    pub fn set_non_coherent_atom_size(&mut self, atom: u64) {
+        assert!(
+            atom.is_power_of_two(),
+            "Non-coherent atom size must be a power of two, got {}",
+            atom
+        );
+        self.non_coherent_atom_size = atom;
+    }
+
+    /// Get non-coherent atom size of the device.
+    pub fn non_coherent_atom_size(&self) -> u64 {
+        self.non_coherent_atom_size
+    }
+
+    /// Get coherence of the memory of the block, i.e. to map it with
+    /// `Block::mapped_slice_with`.
+    pub fn coherence(&self, block: &SmartBlock<B>) -> Coherence {
+        if self.properties(block).contains(Properties::COHERENT) {
+            Coherence::Coherent
+        } else {
+            Coherence::NonCoherent {
+                atom: self.non_coherent_atom_size,
+            }
+        }
+    }
+
+    /// Enable or disable segregation of pools by resource kind.
+    ///
+    /// When enabled, blocks are kept in separate root blocks per `SmartRequest::resource`,
+    /// so that no block pays the buffer-image granularity, and `TypeStats::resources` reports
+    /// memory allocated per kind. Affects blocks allocated
+    /// afterwards only.
+    pub fn set_resource_segregation(&mut self, segregated: bool) {
+        self.segregated = segregated;
+    }
+
+    /// Check if pools are segregated by resource kind.
+    pub fn resource_segregation(&self) -> bool {
+        self.segregated
+    }
+
+    /// Get mask of memory types marked as protected.
+    pub fn protected_types(&self) -> u64 {
+        self.protected
+    }
+
+    /// Set number of physical devices in the device group.
+    /// Heap usage is accounted separately for each physical device.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the allocator is in use or `count` is not in range `1 ..= 32`.
+    pub fn set_device_count(&mut self, count: usize) {
+        assert!(!self.is_used());
+        assert!(count > 0 && count <= 32);
+        for heap in &mut self.heaps {
+            let policy = heap.policy();
+            *heap = Heap::new(heap.size(), count);
+            heap.set_policy(policy);
+        }
+    }
+
+    /// Get number of physical devices in the device group.
+    pub fn device_count(&self) -> usize {
+        self.heaps.first().map(Heap::devices).unwrap_or(1)
+    }
+
+    /// Resolve device mask of the request.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidDeviceMask` if the mask contains unknown devices.
+    fn device_mask(&self, mask: u32) -> Result<u32, MemoryError> {
+        let all = self.all_devices();
+        if mask & !all != 0 {
+            Err(MemoryError::InvalidDeviceMask)
+        } else if mask == 0 {
+            Ok(all)
+        } else {
+            Ok(mask)
+        }
+    }
+
+    /// Get device mask of all physical devices of the device group.
+    fn all_devices(&self) -> u32 {
+        !0u32 >> (32 - self.device_count())
+    }
+
+    /// Check if allocation would currently succeed without actually allocating anything.
+    ///
+    /// The query has no side effects, no pools are created or grown.
+    /// The answer doesn't account for the device failing to allocate memory that heap
+    /// accounting considers available.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `request`: information required to allocate a block of memory
+    /// - `reqs`: the requirements the memory block must meet
+    ///
+    /// ### Returns
+    ///
+    /// Returns memory type the block would be allocated from, or the error `alloc` would
+    /// return.
+    pub fn can_alloc(
+        &self,
+        request: SmartRequest,
+        reqs: Requirements,
+    ) -> Result<MemoryTypeId, MemoryError> {
+        let device_mask = self.device_mask(request.device_mask)?;
+        self.pick(&request, &reqs, device_mask, None).map(MemoryTypeId)
+    }
+
+    /// Estimate how many bytes per heap would be required to allocate all `items`.
+    ///
+    /// Includes rounding of sizes and growth of sub-allocators, but doesn't take free space
+    /// in already grown pools into account, so the estimate errs on the safe side.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `items`: requests and requirements of the blocks
+    ///
+    /// ### Returns
+    ///
+    /// Returns estimated footprint, or `MemoryError::NoCompatibleMemoryType` if any of the
+    /// items can't be allocated from any memory type, or `MemoryError::InvalidDeviceMask` if
+    /// device mask of any of the items contains unknown devices.
+    pub fn estimate_footprint(
+        &self,
+        items: &[(SmartRequest, Requirements)],
+    ) -> Result<Footprint, MemoryError> {
+        let mut per_type = vec![Vec::new(); self.allocators.len()];
+        for &(request, reqs) in items {
+            let device_mask = self.device_mask(request.device_mask)?;
+            let index = match self.pick(&request, &reqs, device_mask, None) {
+                Ok(index) => index,
+                Err(MemoryError::OutOfMemory) => (0..self.allocators.len())
+                    .find(|&index| self.compatible(index, &request, &reqs))
+                    .expect("Compatible type exists"),
+                Err(error) => return Err(error),
+            };
+            per_type[index].push((request.ty, reqs));
+        }
+
+        let mut required = vec![0; self.heaps.len()];
+        for (index, items) in per_type.into_iter().enumerate() {
+            if items.is_empty() {
+                continue;
+            }
+            let config = self.config;
+            let arenas = ArenaAllocator::<RawBlock<B>>::new(config.arena_size, MemoryTypeId(index));
+            let chunks = ChunkedAllocator::<RawBlock<B>>::new(
+                config.blocks_per_chunk,
+                config.min_block_size,
+                config.max_chunk_size,
+                MemoryTypeId(index),
+            );
+            let routing = self.routing
+                .unwrap_or(Routing::chunked(config.max_chunk_size))
+                .clamp(config.max_chunk_size);
+            let free_list =
+                FreeListAllocator::<RawBlock<B>>::new(routing.page_size(), MemoryTypeId(index));
+            required[self.allocators[index].memory_type.heap_index] +=
+                estimate_footprint(&arenas, &chunks, &free_list, routing, &items);
+        }
+
+        let all = self.all_devices();
+        Ok(Footprint {
+            required,
+            available: self.heaps.iter().map(|heap| heap.available(all)).collect(),
+        })
+    }
+
+    /// Check if memory type is compatible with the request.
+    fn compatible(&self, index: usize, request: &SmartRequest, reqs: &Requirements) -> bool {
+        ((1 << index) & reqs.type_mask) == (1 << index)
+            && self.allocators[index]
+                .memory_type
+                .properties
+                .contains(request.properties)
+            && ((self.protected >> index) & 1 == 1) == request.protected
+    }
+
+    /// Reserve capacity in the heap.
+    ///
+    /// Reserved capacity is not available for allocations made with `alloc`, only for
+    /// allocations made inside the reservation with `alloc_reserved`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `heap`: index of the heap
+    /// - `bytes`: number of bytes to reserve
+    ///
+    /// ### Returns
+    ///
+    /// Returns reservation or `MemoryError::OutOfMemory` if the heap doesn't have enough
+    /// available memory.
+    pub fn reserve_bytes(&mut self, heap: usize, bytes: u64) -> Result<Reservation, MemoryError> {
+        let all = self.all_devices();
+        if self.heaps[heap].available(all) < bytes {
+            return Err(MemoryError::OutOfMemory);
+        }
+        self.heaps[heap].reserve(bytes);
+        let id = self.next_reservation;
+        self.next_reservation += 1;
+        self.reservations.push(ReservationState::new(id, heap, bytes));
+        Ok(Reservation::new(id, heap))
+    }
+
+    /// Permanently set aside heap space for allocations made outside of the allocator,
+    /// i.e. by the driver or other libraries.
+    ///
+    /// The space is never available for allocations and is listed under the label in
+    /// `HeapStats::system_budgets`. Budgets with the same label add up.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `heap`: index of the heap
+    /// - `bytes`: number of bytes to set aside
+    /// - `label`: name of the system the space is set aside for
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::OutOfMemory` if the heap doesn't have enough available memory.
+    pub fn reserve_system_budget(
+        &mut self,
+        heap: usize,
+        bytes: u64,
+        label: &str,
+    ) -> Result<(), MemoryError> {
+        let all = self.all_devices();
+        if self.heaps[heap].available(all) < bytes {
+            return Err(MemoryError::OutOfMemory);
+        }
+        self.heaps[heap].add_budget(label, bytes);
+        Ok(())
+    }
+
+    /// Reserve heap capacity to recreate a block after resize, i.e. of a swapchain-sized
+    /// attachment.
+    ///
+    /// This is best-effort: bytes are reserved in the heap budget of the block, not as
+    /// contiguous space, so allocate the new block with `alloc_reserved` and
+    /// `SmartRequest::dedicated` set, so that it gets its own memory object instead of space
+    /// in a shared one. The device may still fail the allocation if its own memory is
+    /// fragmented. If the old block has its own memory object, freeing it makes its bytes
+    /// available again, so only bytes the new block needs beyond it are reserved. The freed new
+    /// block returns its size to the reservation, ready for the next resize.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `old`: block of the resource to be resized
+    /// - `new_reqs`: requirements of the largest resized resource
+    ///
+    /// ### Returns
+    ///
+    /// Returns reservation, `MemoryError::NoCompatibleMemoryType` if the memory type of the
+    /// block doesn't satisfy new requirements, or `MemoryError::OutOfMemory` if the heap
+    /// doesn't have enough available memory.
+    pub fn reserve_heap_for_resize(
+        &mut self,
+        old: &SmartBlock<B>,
+        new_reqs: Requirements,
+    ) -> Result<Reservation, MemoryError> {
+        if (1 << old.index) & new_reqs.type_mask == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        let heap = self.allocators[old.index].memory_type.heap_index;
+        let freed = if old.block.is_dedicated() {
+            old.size()
+        } else {
+            0
+        };
+        let bytes = new_reqs
+            .size
+            .saturating_add(new_reqs.alignment)
+            .saturating_sub(freed);
+        self.reserve_bytes(heap, bytes)
+    }
+
+    /// Get reserved bytes not yet used by allocations made inside the reservation.
+    pub fn reservation_remaining(&self, reservation: &Reservation) -> u64 {
+        self.reservation(reservation.id()).remaining()
+    }
+
+    /// Allocate a block of memory inside the reservation.
+    ///
+    /// The block is allocated from a memory type of the reserved heap. Allocation may exceed
+    /// the remaining reserved capacity as long as the heap has enough available memory.
+    /// When the block is freed its size returns to the reservation.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `reservation`: reservation made by this allocator
+    /// - `request`: information required to allocate a block of memory
+    /// - `reqs`: the requirements the memory block must meet
+    pub fn alloc_reserved(
+        &mut self,
+        device: &B::Device,
+        reservation: &Reservation,
+        request: SmartRequest,
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        self.alloc_from(device, request, reqs, Some(reservation.id()))
+    }
+
+    /// Release the reservation, making remaining reserved capacity available again.
+    ///
+    /// Blocks allocated inside the reservation stay valid.
+    pub fn release_reservation(&mut self, reservation: Reservation) {
+        let id = reservation.dispose();
+        let index = self.reservations
+            .iter()
+            .position(|state| state.id == id)
+            .expect("Reservation must be made by this allocator");
+        let state = self.reservations.swap_remove(index);
+        self.heaps[state.heap].unreserve(state.remaining());
+    }
+
+    fn reservation(&self, id: u64) -> &ReservationState {
+        self.reservations
+            .iter()
+            .find(|state| state.id == id)
+            .expect("Reservation must be made by this allocator")
+    }
+
+    fn alloc_from(
+        &mut self,
+        device: &B::Device,
+        request: SmartRequest,
+        reqs: Requirements,
+        reservation: Option<u64>,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        let id = self.registry.next_id();
+        let result = self.alloc_impl(device, request, reqs, reservation, id);
+        if let Err(ref error) = result {
+            warn!(
+                "Allocation #{} of {} bytes ({:?}) failed: {}",
+                id, reqs.size, request.ty, error
+            );
+        }
+        match result {
+            Ok(ref block) if !self.observers.is_empty() => {
+                let info = self.block_info(block);
+                for observer in &mut self.observers {
+                    observer.on_alloc(&info);
+                }
+            }
+            Err(MemoryError::OutOfMemory) => for observer in &mut self.observers {
+                observer.on_oom(&request, &reqs);
+            },
+            _ => {}
+        }
+        result
+    }
+
+    fn alloc_impl(
+        &mut self,
+        device: &B::Device,
+        request: SmartRequest,
+        reqs: Requirements,
+        reservation: Option<u64>,
+        id: u64,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        let type_mask = (0..self.allocators.len()).fold(0, |mask, index| mask | 1 << index);
+        validate(&reqs, type_mask)?;
+        let result = self.alloc_candidates(device, request, reqs, reservation, id);
+        let result = match (result, request.fallback_properties) {
+            (Err(MemoryError::OutOfMemory), Some(properties))
+            | (Err(MemoryError::NoCompatibleMemoryType), Some(properties)) => {
+                debug!(
+                    "Allocation #{} falls back from {:?} to {:?}",
+                    id, request.properties, properties
+                );
+                let fallback = SmartRequest {
+                    properties,
+                    fallback_properties: None,
+                    ..request
+                };
+                let block = self.alloc_candidates(device, fallback, reqs, reservation, id)?;
+                self.registry.set_request(id, request);
+                let preferred = (0..self.allocators.len())
+                    .find(|&index| self.compatible(index, &request, &reqs));
+                if let Some(preferred) = preferred {
+                    self.report_fallback(preferred, block.index);
+                }
+                Ok(block)
+            }
+            (result, _) => result,
+        };
+        // Record the request once, not per memory type it was tried with.
+        if let Ok(ref block) = result {
+            self.allocators[block.index].requests.record(request.ty, &reqs);
+        }
+        result
+    }
+
+    /// Try compatible memory types in order of preference until one of them succeeds.
+    fn alloc_candidates(
+        &mut self,
+        device: &B::Device,
+        request: SmartRequest,
+        reqs: Requirements,
+        reservation: Option<u64>,
+        id: u64,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        let device_mask = self.device_mask(request.device_mask)?;
+        let reserved = reservation.map(|id| {
+            let state = self.reservation(id);
+            (state.heap, state.remaining())
+        });
+        let candidates = self.candidates(&request, &reqs, device_mask, reserved)?;
+        self.spread_cursor = self.spread_cursor.wrapping_add(1);
+        let preferred = candidates.first().cloned();
+        let mut result = Err(MemoryError::OutOfMemory);
+        for chosen in candidates {
+            result = self.alloc_type(device, chosen, request, reqs, reservation, id);
+            match result {
+                Err(MemoryError::OutOfMemory) | Err(MemoryError::Sealed) => debug!(
+                    "Memory type {} can't serve allocation #{}, trying next candidate",
+                    chosen, id
+                ),
+                _ => break,
+            }
+        }
+        if let (&Ok(ref block), Some(preferred)) = (&result, preferred) {
+            self.report_fallback(preferred, block.index);
+        }
+        result
+    }
+
+    /// Emit `PressureEvent::Fallback` the first time an allocation preferring the memory type
+    /// is served from another one.
+    fn report_fallback(&mut self, preferred: usize, chosen: usize) {
+        if chosen == preferred || self.allocators[preferred].fell_back {
+            return;
+        }
+        self.allocators[preferred].fell_back = true;
+        let event = PressureEvent::Fallback {
+            preferred: MemoryTypeId(preferred),
+            chosen: MemoryTypeId(chosen),
+        };
+        for observer in &mut self.observers {
+            observer.on_pressure(&event);
+        }
+    }
+
+    /// Allocate from the memory type.
+    fn alloc_type(
+        &mut self,
+        device: &B::Device,
+        chosen: usize,
+        request: SmartRequest,
+        reqs: Requirements,
+        reservation: Option<u64>,
+        id: u64,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        let device_mask = self.device_mask(request.device_mask)?;
+        self.recreate_pools(device, chosen);
+
+        // Allocate from final candidate
+        let key = PoolKey::new(&request, device_mask, self.segregated);
+        let pool = self.pool(chosen, key);
+        // Memory freed during the allocation, i.e. of a retired arena, must not hide growth.
+        let grown = self.allocators[chosen].pools[pool].1.grown();
+        let dedicated = request.dedicated
+            || self.dedicated_threshold
+                .map_or(false, |threshold| reqs.size >= threshold);
+        let granular = if dedicated {
+            reqs
+        } else {
+            self.granular(chosen, pool, &reqs, request.resource)?
+        };
+        let result = if dedicated {
+            self.allocators[chosen].pools[pool]
+                .1
+                .alloc_dedicated(device, reqs)
+        } else {
+            self.allocators[chosen].pools[pool]
+                .1
+                .alloc(device, request.ty, granular)
+        };
+        let retries = self.allocators[chosen].pools[pool].1.take_retries();
+        for retry in &retries {
+            for observer in &mut self.observers {
+                observer.on_alloc_retry(retry);
+            }
+        }
+        self.report_growths(chosen, pool);
+        let block = result?;
+        if !dedicated {
+            self.allocators[chosen].tilings[pool] |= request.resource.tiling();
+        }
+        let heap = self.allocators[chosen].memory_type.heap_index;
+        let grown = self.allocators[chosen].pools[pool].1.grown() - grown;
+        if grown > 0 {
+            for observer in &mut self.observers {
+                observer.on_grow(MemoryTypeId(chosen), heap, grown);
+            }
+            self.check_allocation_count();
+        }
+        if let (Some(watermark), false) = (self.growth_watermark, dedicated) {
+            let size = self.allocators[chosen].pools[pool]
+                .1
+                .growth_hint(request.ty, &granular, watermark);
+            if let Some(size) = size {
+                let hint = GrowthHint {
+                    memory_type: MemoryTypeId(chosen),
+                    heap,
+                    size,
+                    pool: key,
+                };
+                for observer in &mut self.observers {
+                    observer.on_low_watermark(&hint);
+                }
+            }
+        }
+        let padding = padding(block.range(), &reqs);
+        self.allocators[chosen].padding += padding;
+        self.heaps[heap].alloc(device_mask, block.size(), padding);
+        if self.heaps[heap].update_pressure() {
+            let used = self.heaps[heap].used();
+            let size = self.heaps[heap].size();
+            warn!(
+                "Heap {} usage is high after allocation #{}: {} of {} bytes used",
+                heap, id, used, size
+            );
+            for observer in &mut self.observers {
+                observer.on_heap_pressure(heap, used, size);
+            }
+        }
+        if let Some(level) = self.heaps[heap].update_level() {
+            let event = PressureEvent::HeapUsage {
+                heap,
+                level,
+                used: self.heaps[heap].used(),
+                budget: self.heaps[heap].budget(),
+            };
+            for observer in &mut self.observers {
+                observer.on_pressure(&event);
+            }
+        }
+        if self.heaps[heap].update_target() {
+            let event = PressureEvent::SoftTarget {
+                heap,
+                used: self.heaps[heap].used(),
+                target: self.heaps[heap].policy().soft_target.unwrap_or(0),
+            };
+            for observer in &mut self.observers {
+                observer.on_pressure(&event);
+            }
+            let returned = self.trim_heap(device, heap);
+            debug!(
+                "Heap {} is above its soft target after allocation #{}, {} chunks and memory \
+                 objects returned",
+                heap, id, returned
+            );
+        }
+        self.allocators[chosen].used += block.size();
+        self.allocators[chosen].blocks += 1;
+        {
+            let stats = resource_stats(&mut self.allocators[chosen].resources, request.resource);
+            stats.used += block.size();
+            stats.blocks += 1;
+        }
+        if let Some(used) = self.allocators[chosen].access_used(request.access) {
+            *used += block.size();
+        }
+        self.publish_usage(chosen);
+        let size = block.size();
+        self.record(|measurement| measurement.alloc(size, true));
+        let category = self.categories.current();
+        if let Some(category) = category {
+            self.categories.alloc(category, block.size());
+        }
+        if let Some(id) = reservation {
+            if let Some(state) = self.reservations.iter_mut().find(|state| state.id == id) {
+                let charged = state.charge(block.size());
+                self.heaps[heap].unreserve(charged);
+            }
+        }
+        let block = SmartBlock {
+            block,
+            index: chosen,
+            pool,
+            reservation,
+            category,
+            id,
+        };
+        let info = BlockInfo {
+            resource: request.resource,
+            user_data: request.user_data,
+            ..self.block_info(&block)
+        };
+        let memory = block.memory() as *const B::Memory as usize;
+        self.registry.insert(info, memory, category, padding, request);
+        if let Some(ttl) = request.ttl {
+            let frame = self.registry.frame();
+            self.registry.set_expiry(id, frame.saturating_add(ttl));
+        }
+        Ok(block)
+    }
+
+    /// Find compatible memory type with least used heap with enough available memory.
+    ///
+    /// If `reserved` heap and bytes are specified only memory types of that heap are considered
+    /// and reserved bytes are added to the available memory.
+    fn pick(
+        &self,
+        request: &SmartRequest,
+        reqs: &Requirements,
+        device_mask: u32,
+        reserved: Option<(usize, u64)>,
+    ) -> Result<usize, MemoryError> {
+        self.candidates(request, reqs, device_mask, reserved)
+            .map(|candidates| candidates[0])
+    }
+
+    /// Find compatible memory types with enough available memory, least used heaps first.
+    /// The memory type of the request comes first if it is one of them.
+    ///
+    /// If `reserved` heap and bytes are specified only memory types of that heap are considered
+    /// and reserved bytes are added to the available memory.
+    fn candidates(
+        &self,
+        request: &SmartRequest,
+        reqs: &Requirements,
+        device_mask: u32,
+        reserved: Option<(usize, u64)>,
+    ) -> Result<Vec<usize>, MemoryError> {
+        let mut compatible = false;
+        let mut candidates = Vec::new();
+
+        for index in 0..self.allocators.len() {
+            let memory_type = self.allocators[index].memory_type;
+            // filter out non-compatible
+            if !self.compatible(index, request, reqs) {
+                continue;
+            }
+            let mut available = self.heaps[memory_type.heap_index].available(device_mask);
+            if let Some((heap, bytes)) = reserved {
+                if memory_type.heap_index != heap {
+                    continue;
+                }
+                available += bytes;
+            }
+            compatible = true;
+            // filter out if heap has not enough memory available
+            let fits = reqs.size
+                .checked_add(reqs.alignment)
+                .map_or(false, |size| self.heaps[memory_type.heap_index].fits(available, size));
+            if !fits {
+                continue;
+            }
+            let usage = self.heaps[memory_type.heap_index].usage(device_mask);
+            candidates.push((index, usage));
+        }
+
+        if candidates.is_empty() {
+            return Err(if !compatible {
+                MemoryError::NoCompatibleMemoryType
+            } else {
+                MemoryError::OutOfMemory
+            });
+        }
+
+        // Stable sort keeps memory types of equally used heaps in order.
+        candidates.sort_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap_or(Ordering::Equal));
+        let mut candidates: Vec<usize> = candidates.into_iter().map(|(index, _)| index).collect();
+        self.spread(&mut candidates);
+        // Stable sort puts memory types suiting the host access direction first.
+        if request.access != HostAccess::Unspecified {
+            let cached = request.access == HostAccess::Readback;
+            candidates.sort_by_key(|&index| {
+                let properties = self.allocators[index].memory_type.properties;
+                properties.contains(Properties::CPU_CACHED) != cached
+            });
+        }
+        // Stable sort keeps order of avoided memory types and of the others.
+        let avoided = self.avoided;
+        candidates.sort_by_key(|&index| (avoided >> index) & 1);
+        if let Some(MemoryTypeId(preferred)) = request.memory_type {
+            if let Some(position) = candidates.iter().position(|&index| index == preferred) {
+                candidates.remove(position);
+                candidates.insert(0, preferred);
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Reorder equivalent memory types among candidates according to `TypeSpread`.
+    /// Positions of other memory types are kept.
+    fn spread(&self, candidates: &mut [usize]) {
+        if self.spread == TypeSpread::First {
+            return;
+        }
+        let mut grouped = vec![false; candidates.len()];
+        for start in 0..candidates.len() {
+            if grouped[start] {
+                continue;
+            }
+            let first = self.allocators[candidates[start]].memory_type;
+            let positions: Vec<usize> = (start..candidates.len())
+                .filter(|&position| {
+                    let memory_type = self.allocators[candidates[position]].memory_type;
+                    memory_type.heap_index == first.heap_index
+                        && memory_type.properties == first.properties
+                })
+                .collect();
+            if positions.len() < 2 {
+                continue;
+            }
+            let mut types: Vec<usize> = positions
+                .iter()
+                .map(|&position| candidates[position])
+                .collect();
+            types.sort();
+            match self.spread {
+                TypeSpread::First => {}
+                TypeSpread::RoundRobin => {
+                    let shift = self.spread_cursor % types.len();
+                    types = (0..types.len())
+                        .map(|index| types[(index + shift) % types.len()])
+                        .collect();
+                }
+                TypeSpread::LeastAllocated => {
+                    types.sort_by_key(|&index| {
+                        self.allocators[index]
+                            .pools
+                            .iter()
+                            .map(|&(_, ref pool)| pool.allocated())
+                            .sum::<u64>()
+                    });
+                }
+            }
+            for (&position, index) in positions.iter().zip(types) {
+                candidates[position] = index;
+                grouped[position] = true;
+            }
+        }
+    }
+
+    /// Round requirements of a sub-allocated block up to the granularity of the memory type,
+    /// and the buffer-image granularity if the pool already holds resources of the other
+    /// tiling. Rounded blocks occupy whole granules, so they never share one with blocks
+    /// placed before or after them.
+    ///
+    /// ### Returns
+    ///
+    /// `MemoryError::OutOfMemory` if the rounded size overflows.
+    fn granular(
+        &self,
+        index: usize,
+        pool: usize,
+        reqs: &Requirements,
+        resource: ResourceKind,
+    ) -> Result<Requirements, MemoryError> {
+        let allocator = &self.allocators[index];
+        let tiling = resource.tiling();
+        let granularity = if tiling != 0 && allocator.tilings[pool] & !tiling != 0 {
+            allocator.granularity.max(self.buffer_image_granularity)
+        } else {
+            allocator.granularity
+        };
+        let size = reqs.size
+            .checked_add(granularity - 1)
+            .ok_or(MemoryError::OutOfMemory)?;
+        Ok(Requirements {
+            size: size & !(granularity - 1),
+            alignment: reqs.alignment.max(granularity),
+            type_mask: reqs.type_mask,
+        })
+    }
+
+    /// Notify observers of blocks sub-allocators of the pool allocated from its root allocator.
+    fn report_growths(&mut self, index: usize, pool: usize) {
+        let growths = self.allocators[index].pools[pool].1.take_growths();
+        for growth in &growths {
+            for observer in &mut self.observers {
+                observer.on_sub_allocator_grow(growth);
+            }
+        }
+    }
+
+    /// Account the block resized in place from `old_size` to fit `reqs`.
+    fn resized(&mut self, block: &SmartBlock<B>, old_size: u64, reqs: &Requirements) {
+        let new_size = block.size();
+        let padding = padding(block.range(), reqs);
+        let old_padding = self.registry.resize(block.id, block.range(), padding);
+        self.allocators[block.index].padding += padding;
+        self.allocators[block.index].padding -= old_padding;
+        let device_mask = self.allocators[block.index].pools[block.pool].0.device_mask;
+        let heap = self.allocators[block.index].memory_type.heap_index;
+        self.heaps[heap].free(device_mask, old_size, old_padding);
+        self.heaps[heap].alloc(device_mask, new_size, padding);
+        self.heaps[heap].update_pressure();
+        self.heaps[heap].update_level();
+        self.heaps[heap].update_target();
+        let grown = new_size - old_size;
+        if grown == 0 {
+            return;
+        }
+        self.allocators[block.index].used += grown;
+        self.record(|measurement| measurement.alloc(grown, false));
+        let (pinned, resource) = self.registry
+            .get(block.id)
+            .map_or((false, ResourceKind::Unspecified), |allocation| {
+                (allocation.pinned, allocation.info.resource)
+            });
+        if pinned {
+            self.allocators[block.index].pinned += grown;
+        }
+        resource_stats(&mut self.allocators[block.index].resources, resource).used += grown;
+        let access = self.allocators[block.index].pools[block.pool].0.access;
+        if let Some(used) = self.allocators[block.index].access_used(access) {
+            *used += grown;
+        }
+        self.publish_usage(block.index);
+        if let Some(category) = block.category {
+            self.categories.alloc(category, grown);
+        }
+    }
+
+    /// Warn once memory object count crosses the warning threshold upwards.
+    fn check_allocation_count(&mut self) {
+        let threshold = match self.allocation_count_warning {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        let count = self.allocation_count();
+        let above = count >= threshold;
+        if above && !self.allocation_count_warned {
+            warn!(
+                "{} memory objects are allocated, approaching device limit",
+                count
+            );
+            let event = PressureEvent::AllocationCount { count };
+            for observer in &mut self.observers {
+                observer.on_allocation_count(count);
+                observer.on_pressure(&event);
+            }
+        }
+        self.allocation_count_warned = above;
+    }
+
+    /// Re-arm the memory object count warning if memory objects were freed below the threshold.
+    fn rearm_allocation_count(&mut self) {
+        if self.allocation_count_warned {
+            self.check_allocation_count();
+        }
+    }
+
+    /// Update usage counters of the memory type and its heap.
+    fn publish_usage(&self, index: usize) {
+        let allocator = &self.allocators[index];
+        let heap = allocator.memory_type.heap_index;
+        self.usage.set_heap(heap, self.heaps[heap].used());
+        self.usage.set_type(index, allocator.used, allocator.blocks);
+    }
+
+    /// Recreate pools of the memory type with current configuration if it has changed
+    /// and none of the pools are in use.
+    fn recreate_pools(&mut self, device: &B::Device, index: usize) {
+        if self.sealed {
+            // Recreated pools would have to allocate memory from the device again.
+            return;
+        }
+        let config = self.config;
+        let allocator = &mut self.allocators[index];
+        if allocator.config == config || allocator.is_used() {
+            return;
+        }
+        for (_, pool) in allocator.pools.drain(..) {
+            pool.dispose(device).unwrap();
+        }
+        allocator.tilings.clear();
+        allocator.config = config;
+    }
+
+    /// Find pool of the memory type for the key. Create one if there is none yet.
+    fn pool(&mut self, index: usize, key: PoolKey) -> usize {
+        if let Some(pool) = self.allocators[index]
+            .pools
+            .iter()
+            .position(|&(k, _)| k == key)
+        {
+            return pool;
+        }
+        let config = self.allocators[index].config;
+        let mut allocator = CombinedAllocator::new(
+            MemoryTypeId(index),
+            config.arena_size,
+            config.blocks_per_chunk,
+            config.min_block_size,
+            config.max_chunk_size,
+        );
+        // Leaks are reported by the `SmartAllocator` itself.
+        allocator.set_leak_mode(LeakMode::Silent);
+        allocator.set_deferred_frees(self.deferred_frees);
+        allocator.set_sealed(self.sealed);
+        allocator.set_retry_policy(self.retry);
+        allocator.set_spanning(self.spanning);
+        allocator.set_micro_threshold(self.micro_threshold);
+        allocator.set_low_watermark(self.low_watermark);
+        for &(kind, ref factory) in &self.factories {
+            allocator.register_sub_allocator(kind, factory.clone());
+        }
+        allocator.set_out_of_order_frees(self.out_of_order_frees);
+        allocator.set_epoch_retirement(self.epoch_retirement);
+        allocator.set_epoch(self.registry.frame());
+        if let Some(routing) = self.routing {
+            allocator.set_routing(routing);
+        }
+        if let Some(host) = self.host {
+            allocator.set_host(Some(host(MemoryTypeId(index))));
+        }
+        self.allocators[index].tilings.push(0);
+        let pools = &mut self.allocators[index].pools;
+        pools.push((key, allocator));
+        pools.len() - 1
+    }
+}
+
+impl<B> SmartAllocator<B>
+where
+    B: Backend,
+    B::Memory: Default,
+{
+    /// Serve all allocations from host memory instead of the device, i.e. to run asset
+    /// pipelines and tests through the same allocator on machines without GPUs.
+    /// See `HostAllocator`.
+    ///
+    /// Blocks can't be bound or mapped, their contents are accessed with `host_bytes` and
+    /// `host_bytes_mut`. Heap sizes, policies and statistics apply as usual.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if any pool was created already, i.e. after the first allocation.
+    pub fn set_host_memory(&mut self, enabled: bool) {
+        assert!(
+            self.allocators.iter().all(|allocator| allocator.pools.is_empty()),
+            "Memory source can't be changed after pools are created"
+        );
+        self.host = if enabled {
+            Some(HostAllocator::new as fn(MemoryTypeId) -> HostAllocator<B>)
+        } else {
+            None
+        };
+    }
+}
+
+/// Estimated memory footprint returned by `SmartAllocator::estimate_footprint`.
+#[derive(Clone, Debug)]
+pub struct Footprint {
+    /// Bytes required per heap.
+    pub required: Vec<u64>,
+
+    /// Bytes currently available per heap.
+    pub available: Vec<u64>,
+}
+
+impl Footprint {
+    /// Check if required memory fits into available memory for all heaps.
+    pub fn fits(&self) -> bool {
+        self.required
+            .iter()
+            .zip(&self.available)
+            .all(|(required, available)| required <= available)
+    }
+}
+
+impl<B> MemoryAllocator<B> for SmartAllocator<B>
+where
+    B: Backend,
+{
+    type Request = SmartRequest;
+    type Block = SmartBlock<B>;
+
+    fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: SmartRequest,
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B>, MemoryError> {
+        self.alloc_from(device, request, reqs, None)
+    }
+
+    fn free(&mut self, device: &B::Device, block: SmartBlock<B>) {
+        let id = block.id;
+        if let Err(error) = self.try_free(device, block) {
+            if self.misuse == MisuseMode::Strict || cfg!(debug_assertions) {
+                panic!("Failed to free block #{}: {}", id, error);
+            }
+            error!("Failed to free block #{}: {}", id, error);
+        }
+    }
+
+    fn try_free(&mut self, device: &B::Device, block: SmartBlock<B>) -> Result<(), MemoryError> {
+        let check = match self.registry.get(block.id) {
+            Some(allocation) => {
+                if allocation.info.memory_type.0 == block.index
+                    && allocation.info.range == block.range()
+                    && block.pool < self.allocators[block.index].pools.len()
+                {
+                    Ok(())
+                } else {
+                    Err(MemoryError::CorruptedBlock)
+                }
+            }
+            None => Err(MemoryError::InvalidFree),
+        };
+        if let Err(error) = check {
+            unsafe { block.block.0.dispose() };
+            return Err(error);
+        }
+
+        let info = self.block_info(&block);
+        let SmartBlock {
+            block,
+            index,
+            pool,
+            reservation,
+            category,
+            id,
+        } = block;
+        let size = block.size();
+        self.allocators[index].pools[pool].1.try_free(device, block)?;
+        for observer in &mut self.observers {
+            observer.on_free(&info);
+        }
+        let allocation = self.registry.remove(id);
+        if allocation.pinned {
+            self.allocators[index].pinned -= size;
+        }
+        let padding = allocation.padding;
+        self.allocators[index].padding -= padding;
+        let device_mask = self.allocators[index].pools[pool].0.device_mask;
+        let heap = self.allocators[index].memory_type.heap_index;
+        self.heaps[heap].free(device_mask, size, padding);
+        self.heaps[heap].update_pressure();
+        self.heaps[heap].update_level();
+        self.heaps[heap].update_target();
+        self.allocators[index].used -= size;
+        self.allocators[index].blocks -= 1;
+        {
+            let resource = allocation.info.resource;
+            let stats = resource_stats(&mut self.allocators[index].resources, resource);
+            stats.used -= size;
+            stats.blocks -= 1;
+        }
+        let access = self.allocators[index].pools[pool].0.access;
+        if let Some(used) = self.allocators[index].access_used(access) {
+            *used -= size;
+        }
+        self.publish_usage(index);
+        self.record(|measurement| measurement.free(size));
+        if let Some(category) = category {
+            self.categories.free(category, size);
+        }
+        if let Some(id) = reservation {
+            if let Some(state) = self.reservations.iter_mut().find(|state| state.id == id) {
+                let refunded = state.refund(size);
+                self.heaps[heap].reserve(refunded);
+            }
+        }
+        self.rearm_allocation_count();
+        Ok(())
+    }
+
+    fn is_used(&self) -> bool {
+        self.allocators.iter().any(TypeAllocator::is_used)
+    }
+
+    fn dispose(mut self, device: &B::Device) -> Result<(), Self> {
+        self.cached.clear();
+        self.free_dropped(device);
+        if self.is_used() {
+            Err(self)
+        } else {
+            for allocator in self.allocators.drain(..) {
+                for (_, pool) in allocator.pools {
+                    pool.dispose(device).unwrap();
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+impl<B> Drop for SmartAllocator<B>
+where
+    B: Backend,
+{
+    fn drop(&mut self) {
+        // Queued blocks are leaked along with their pools.
+        self.cached.clear();
+        for block in lock(&self.dropped).drain(..) {
+            forget(block);
+        }
+        let allocated = self.allocated();
+        if allocated == 0 {
+            // Disposed or nothing to leak
+            return;
+        }
+        let report = format!(
+            "SmartAllocator dropped without being disposed, leaking {} bytes. {}",
+            allocated,
+            self.registry.leaks()
+        );
+        report_leak(self.leak, &report);
+    }
+}
+
+/// Opaque type for `Block` tag used by the `SmartAllocator`.
+///
+/// `SmartAllocator` places this tag on the memory blocks, and then use it in
+/// `free` to find the memory node the block was allocated from.
+#[derive(Debug)]
+pub struct SmartBlock<B: Backend> {
+    block: CombinedBlock<B>,
+    index: usize,
+    pool: usize,
+    reservation: Option<u64>,
+    category: Option<usize>,
+    id: u64,
+}
+
+impl<B> SmartBlock<B>
+where
+    B: Backend,
+{
+    /// Get id of the allocation, unique among blocks allocated from the same allocator.
+    /// See `BlockInfo::id`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Get memory type the block is allocated from.
+    pub fn memory_type(&self) -> MemoryTypeId {
+        MemoryTypeId(self.index)
+    }
+}
 
 impl<B> Block<B> for SmartBlock<B>
 where
@@ -187,12 +3243,12 @@ where
     #[inline(always)]
     fn memory(&self) -> &B::Memory {
         // Has to be valid
-        self.0.memory()
+        self.block.memory()
     }
 
     /// Get memory range of the block.
     #[inline(always)]
     fn range(&self) -> Range<u64> {
-        self.0.range()
+        self.block.range()
     }
 }
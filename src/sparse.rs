@@ -0,0 +1,425 @@
+use std::ops::Range;
+
+use gfx_hal::{Backend, MemoryTypeId};
+use gfx_hal::memory::Requirements;
+
+use {alignment_shift, freed, MemoryAllocator, MemoryError, MemorySubAllocator};
+use block::{Block, RawBlock};
+
+/// Allocator that hands out fixed size pages suitable for sparse binding.
+///
+/// Pages are sub-allocated from bigger blocks requested from the owner.
+/// Requests are rounded up to whole pages, so this allocator should be used only for
+/// resources that are bound page by page.
+///
+/// ### Type parameters:
+///
+/// - `T`: type of blocks allocated from the owner
+#[derive(Debug)]
+pub struct SparsePageAllocator<T> {
+    id: MemoryTypeId,
+    page_size: u64,
+    pages_per_block: u64,
+    resident: u64,
+    nodes: Vec<SparseNode<T>>,
+}
+
+impl<T> SparsePageAllocator<T> {
+    /// Create a new sparse page allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `page_size`: size of the page in bytes, should be the sparse binding granularity
+    ///                reported by the device (typically 64 KiB)
+    /// - `pages_per_block`: number of pages in blocks requested from the owner
+    /// - `id`: hal memory type
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `page_size` or `pages_per_block` is zero.
+    pub fn new(page_size: u64, pages_per_block: u64, id: MemoryTypeId) -> Self {
+        assert_ne!(page_size, 0);
+        assert_ne!(pages_per_block, 0);
+        SparsePageAllocator {
+            id,
+            page_size,
+            pages_per_block,
+            resident: 0,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Check if any of the pages allocated by this allocator are still in use.
+    /// If this function returns `false`, the allocator can be `dispose`d.
+    pub fn is_used(&self) -> bool {
+        self.resident != 0
+    }
+
+    /// Get memory type of the allocator
+    pub fn memory_type(&self) -> MemoryTypeId {
+        self.id
+    }
+
+    /// Get size of the page
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    /// Get number of pages in blocks requested from the owner
+    pub fn pages_per_block(&self) -> u64 {
+        self.pages_per_block
+    }
+
+    /// Get number of pages currently handed out.
+    pub fn resident_pages(&self) -> u64 {
+        self.resident
+    }
+
+    /// Get number of pages allocated from the owner.
+    pub fn total_pages(&self) -> u64 {
+        self.nodes.len() as u64 * self.pages_per_block
+    }
+
+    /// Get number of pages allocated from the owner but not handed out.
+    pub fn unused_pages(&self) -> u64 {
+        self.total_pages() - self.resident
+    }
+
+    /// Allocate `count` pages.
+    /// Pages are not necessarily contiguous, one block is returned per contiguous run of pages.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator used to allocate memory in bigger blocks
+    /// - `device`: device to allocate the memory from
+    /// - `request`: information required by the owner to allocate a block of memory
+    /// - `count`: number of pages to allocate
+    ///
+    /// ### Returns
+    ///
+    /// Returns blocks holding `count` pages in total. If not enough pages could be allocated,
+    /// no pages are allocated and a `MemoryError` is returned.
+    pub fn alloc_pages<B, A>(
+        &mut self,
+        owner: &mut A,
+        device: &B::Device,
+        request: A::Request,
+        count: u64,
+    ) -> Result<Vec<SparseBlock<B>>, MemoryError>
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+        A::Request: Clone,
+    {
+        let mut blocks = Vec::new();
+        let mut left = count;
+        let mut index = 0;
+        while left > 0 {
+            if index == self.nodes.len() {
+                if let Err(error) = self.grow(owner, device, request.clone()) {
+                    self.free_pages(owner, device, blocks);
+                    return Err(error);
+                }
+            }
+            while let Some(pages) = self.nodes[index].free.take_first(left) {
+                left -= pages.end - pages.start;
+                blocks.push(self.make_block(index, pages));
+                if left == 0 {
+                    break;
+                }
+            }
+            index += 1;
+        }
+        Ok(blocks)
+    }
+
+    /// Free pages allocated by `alloc_pages` or `alloc`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the bigger blocks
+    /// - `device`: same device that was used to allocate the pages
+    /// - `blocks`: blocks of pages to free
+    pub fn free_pages<B, A, I>(&mut self, owner: &mut A, device: &B::Device, blocks: I)
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+        I: IntoIterator<Item = SparseBlock<B>>,
+    {
+        for block in blocks {
+            MemorySubAllocator::<B, A>::free(self, owner, device, block);
+        }
+    }
+
+    fn grow<B, A>(
+        &mut self,
+        owner: &mut A,
+        device: &B::Device,
+        request: A::Request,
+    ) -> Result<(), MemoryError>
+    where
+        B: Backend,
+        T: Block<B>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        let reqs = Requirements {
+            type_mask: 1 << self.id.0,
+            size: self.page_size * self.pages_per_block,
+            alignment: self.page_size,
+        };
+        let block = owner.alloc(device, request, reqs)?;
+        assert!(block.size() >= reqs.size);
+        self.nodes.push(SparseNode {
+            block,
+            free: FreeRanges::new(0..self.pages_per_block),
+        });
+        Ok(())
+    }
+
+    fn make_block<B>(&mut self, index: usize, pages: Range<u64>) -> SparseBlock<B>
+    where
+        B: Backend,
+        T: Block<B>,
+    {
+        self.resident += pages.end - pages.start;
+        let node = &self.nodes[index].block;
+        let start = node.range().start + pages.start * self.page_size;
+        let end = node.range().start + pages.end * self.page_size;
+        SparseBlock(RawBlock::new(node.memory(), start..end), index)
+    }
+}
+
+impl<B, O, T> MemorySubAllocator<B, O> for SparsePageAllocator<T>
+where
+    B: Backend,
+    T: Block<B>,
+    O: MemoryAllocator<B, Block = T>,
+{
+    type Request = O::Request;
+    type Block = SparseBlock<B>;
+
+    /// Allocate contiguous run of pages large enough for `reqs.size`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `reqs.alignment` is larger than page size.
+    fn alloc(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        request: O::Request,
+        reqs: Requirements,
+    ) -> Result<SparseBlock<B>, MemoryError> {
+        if (1 << self.id.0) & reqs.type_mask == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        assert!(reqs.alignment <= self.page_size);
+        let count = (reqs.size + self.page_size - 1) / self.page_size;
+        if count > self.pages_per_block {
+            return Err(MemoryError::OutOfMemory);
+        }
+
+        let found = self.nodes
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, node)| node.free.take(count, 1).map(|pages| (index, pages)))
+            .next();
+
+        let (index, pages) = match found {
+            Some(found) => found,
+            None => {
+                self.grow(owner, device, request)?;
+                let index = self.nodes.len() - 1;
+                let pages = self.nodes[index].free.take(count, 1).expect("Just growed");
+                (index, pages)
+            }
+        };
+        Ok(self.make_block(index, pages))
+    }
+
+    fn free(&mut self, owner: &mut O, device: &B::Device, block: SparseBlock<B>) {
+        freed(self.try_free(owner, device, block));
+    }
+
+    fn try_free(
+        &mut self,
+        _owner: &mut O,
+        _device: &B::Device,
+        block: SparseBlock<B>,
+    ) -> Result<(), MemoryError> {
+        let SparseBlock(block, index) = block;
+        if index >= self.nodes.len() || !self.nodes[index].block.contains(&block) {
+            unsafe { block.dispose() };
+            return Err(MemoryError::InvalidFree);
+        }
+        let node = &mut self.nodes[index];
+        let offset = block.range().start - node.block.range().start;
+        if offset % self.page_size != 0 || block.size() % self.page_size != 0 {
+            unsafe { block.dispose() };
+            return Err(MemoryError::CorruptedBlock);
+        }
+        let start = offset / self.page_size;
+        let end = start + block.size() / self.page_size;
+        unsafe { block.dispose() };
+        if !node.free.give(start..end) {
+            return Err(MemoryError::InvalidFree);
+        }
+        self.resident -= end - start;
+        Ok(())
+    }
+
+    fn dispose(mut self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
+        if self.is_used() {
+            Err(self)
+        } else {
+            for node in self.nodes.drain(..) {
+                owner.free(device, node.block);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SparseNode<T> {
+    block: T,
+    /// Runs of free pages.
+    free: FreeRanges,
+}
+
+/// Sorted non-adjacent free ranges, i.e. runs of free pages or free byte ranges.
+/// Also used by `VirtualAllocator`.
+#[derive(Clone, Debug)]
+pub(crate) struct FreeRanges(Vec<Range<u64>>);
+
+impl FreeRanges {
+    /// Create free ranges consisting of the range, or of nothing if it is empty.
+    pub(crate) fn new(range: Range<u64>) -> Self {
+        FreeRanges(if range.start < range.end { vec![range] } else { Vec::new() })
+    }
+
+    /// Get length of the largest free range.
+    pub(crate) fn largest(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|range| range.end - range.start)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Take up to `count` units from the first free range.
+    pub(crate) fn take_first(&mut self, count: u64) -> Option<Range<u64>> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let start = self.0[0].start;
+        let end = start + count.min(self.0[0].end - start);
+        self.0[0].start = end;
+        if end == self.0[0].end {
+            self.0.remove(0);
+        }
+        Some(start..end)
+    }
+
+    /// Take `count` contiguous units starting at `alignment` from the first free range that is
+    /// large enough. `0` and `1` mean no alignment.
+    pub(crate) fn take(&mut self, count: u64, alignment: u64) -> Option<Range<u64>> {
+        let (index, start) = self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(index, free)| {
+                let start = free.start + alignment_shift(alignment, free.start);
+                if start <= free.end && count <= free.end - start {
+                    Some((index, start))
+                } else {
+                    None
+                }
+            })
+            .next()?;
+        let free = self.0.remove(index);
+        let range = start..start + count;
+        if range.end < free.end {
+            self.0.insert(index, range.end..free.end);
+        }
+        if free.start < range.start {
+            self.0.insert(index, free.start..range.start);
+        }
+        Some(range)
+    }
+
+    /// Take the range `start..end` if the free range that starts at `start` covers it.
+    pub(crate) fn take_at(&mut self, start: u64, end: u64) -> bool {
+        let index = match self.0
+            .iter()
+            .position(|free| free.start == start && free.end >= end)
+        {
+            Some(index) => index,
+            None => return false,
+        };
+        if self.0[index].end == end {
+            self.0.remove(index);
+        } else {
+            self.0[index].start = end;
+        }
+        true
+    }
+
+    /// Return the range, merging adjacent free ranges.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `false` and leaves the free ranges unchanged if the range overlaps any of them.
+    pub(crate) fn give(&mut self, range: Range<u64>) -> bool {
+        let index = self.0
+            .iter()
+            .position(|free| free.start > range.start)
+            .unwrap_or(self.0.len());
+        if (index > 0 && self.0[index - 1].end > range.start)
+            || (index < self.0.len() && self.0[index].start < range.end)
+        {
+            return false;
+        }
+        debug_assert!(index == 0 || self.0[index - 1].end <= range.start);
+        debug_assert!(index == self.0.len() || self.0[index].start >= range.end);
+        let merge_prev = index > 0 && self.0[index - 1].end == range.start;
+        let merge_next = index < self.0.len() && self.0[index].start == range.end;
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                self.0[index - 1].end = self.0[index].end;
+                self.0.remove(index);
+            }
+            (true, false) => self.0[index - 1].end = range.end,
+            (false, true) => self.0[index].start = range.start,
+            (false, false) => self.0.insert(index, range),
+        }
+        debug_assert!(self.0.windows(2).all(|pair| pair[0].end < pair[1].start));
+        true
+    }
+}
+
+/// Opaque type for `Block` tag used by the `SparsePageAllocator`.
+///
+/// `SparsePageAllocator` places this tag on the memory blocks, and then use it in
+/// `free` to find the memory node the pages were allocated from.
+#[derive(Debug)]
+pub struct SparseBlock<B: Backend>(pub(crate) RawBlock<B>, pub(crate) usize);
+
+impl<B> Block<B> for SparseBlock<B>
+where
+    B: Backend,
+{
+    /// Get memory of the block.
+    #[inline(always)]
+    fn memory(&self) -> &B::Memory {
+        // Has to be valid
+        self.0.memory()
+    }
+
+    /// Get memory range of the block.
+    #[inline(always)]
+    fn range(&self) -> Range<u64> {
+        self.0.range()
+    }
+}
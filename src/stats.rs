@@ -0,0 +1,605 @@
+use std::fmt;
+
+use gfx_hal::memory::Requirements;
+
+use combined::Type;
+use heap::SystemBudget;
+use request::ResourceKind;
+
+/// Snapshot of `SmartAllocator` statistics.
+#[derive(Clone, Debug, Default)]
+pub struct AllocatorStats {
+    /// Usage per memory heap.
+    pub heaps: Vec<HeapStats>,
+
+    /// Usage per memory type.
+    pub types: Vec<TypeStats>,
+
+    /// Usage per allocation category.
+    pub categories: Vec<CategoryStats>,
+
+    /// Lifetimes of freed blocks per request type and size class.
+    pub lifetimes: Vec<LifetimeStats>,
+}
+
+impl AllocatorStats {
+    /// Compute difference between this snapshot and an older one.
+    ///
+    /// Heaps and memory types missing in either snapshot are treated as unused.
+    pub fn diff(&self, older: &AllocatorStats) -> StatsDelta {
+        StatsDelta {
+            heaps: pairs(&self.heaps, &older.heaps)
+                .map(|(new, old)| HeapDelta {
+                    used: delta(new.used, old.used),
+                    reserved: delta(new.reserved, old.reserved),
+                    padding: delta(new.padding, old.padding),
+                })
+                .collect(),
+            types: pairs(&self.types, &older.types)
+                .map(|(new, old)| TypeDelta {
+                    used: delta(new.used, old.used),
+                    blocks: delta(new.blocks as u64, old.blocks as u64),
+                    allocated: delta(new.allocated, old.allocated),
+                    allocations: delta(new.allocations as u64, old.allocations as u64),
+                })
+                .collect(),
+        }
+    }
+
+    /// Get totals over all heaps and memory types.
+    pub fn summary(&self) -> StatsSummary {
+        StatsSummary {
+            used: self.types.iter().map(|ty| ty.used).sum(),
+            blocks: self.types.iter().map(|ty| ty.blocks).sum(),
+            allocated: self.types.iter().map(|ty| ty.allocated).sum(),
+            allocations: self.types.iter().map(|ty| ty.allocations).sum(),
+            reserved: self.heaps.iter().map(|heap| heap.reserved).sum(),
+            padding: self.heaps.iter().map(|heap| heap.padding).sum(),
+        }
+    }
+}
+
+/// Totals of an `AllocatorStats` snapshot, or of several ones. See `StatsAggregator`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsSummary {
+    /// Bytes used by live blocks.
+    pub used: u64,
+
+    /// Number of live blocks.
+    pub blocks: usize,
+
+    /// Bytes allocated from the device.
+    pub allocated: u64,
+
+    /// Number of memory objects allocated from the device.
+    pub allocations: usize,
+
+    /// Bytes held back by reservations.
+    pub reserved: u64,
+
+    /// Bytes of live blocks beyond their requested sizes, included in `used`.
+    pub padding: u64,
+}
+
+impl StatsSummary {
+    /// Add totals of another snapshot.
+    pub fn merge(&mut self, other: &StatsSummary) {
+        self.used += other.used;
+        self.blocks += other.blocks;
+        self.allocated += other.allocated;
+        self.allocations += other.allocations;
+        self.reserved += other.reserved;
+        self.padding += other.padding;
+    }
+}
+
+/// Snapshots of several allocators merged into one report keyed by source, i.e. by physical
+/// device or pool, so that telemetry doesn't depend on how the application splits its
+/// allocators.
+#[derive(Clone, Debug, Default)]
+pub struct StatsAggregator {
+    sources: Vec<(String, AllocatorStats)>,
+}
+
+impl StatsAggregator {
+    /// Create an empty aggregator.
+    pub fn new() -> Self {
+        StatsAggregator::default()
+    }
+
+    /// Add the snapshot of a source, replacing the previous snapshot of the same source.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `key`: name of the source, i.e. "device0" or "streaming"
+    /// - `stats`: snapshot of the source's allocator
+    pub fn add(&mut self, key: &str, stats: AllocatorStats) {
+        match self.sources.iter().position(|&(ref known, _)| known == key) {
+            Some(index) => self.sources[index].1 = stats,
+            None => self.sources.push((key.to_owned(), stats)),
+        }
+    }
+
+    /// Remove all snapshots, i.e. before collecting the next report.
+    pub fn clear(&mut self) {
+        self.sources.clear();
+    }
+
+    /// Get the snapshot of the source.
+    pub fn get(&self, key: &str) -> Option<&AllocatorStats> {
+        self.sources
+            .iter()
+            .find(|&&(ref known, _)| known == key)
+            .map(|&(_, ref stats)| stats)
+    }
+
+    /// Get totals per source in order of addition.
+    pub fn report(&self) -> Vec<(&str, StatsSummary)> {
+        self.sources
+            .iter()
+            .map(|&(ref key, ref stats)| (key.as_str(), stats.summary()))
+            .collect()
+    }
+
+    /// Get totals over all sources.
+    pub fn total(&self) -> StatsSummary {
+        let mut total = StatsSummary::default();
+        for &(_, ref stats) in &self.sources {
+            total.merge(&stats.summary());
+        }
+        total
+    }
+}
+
+impl fmt::Display for StatsAggregator {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let report = self.report();
+        let total = self.total();
+        for (key, summary) in report.into_iter().chain(Some(("total", total))) {
+            writeln!(
+                fmt,
+                "{}: used {} bytes in {} blocks, allocated {} bytes in {} objects",
+                key, summary.used, summary.blocks, summary.allocated, summary.allocations
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterate over pairs of entries of two snapshots, substituting missing entries by defaults.
+fn pairs<'a, T>(new: &'a [T], old: &'a [T]) -> Box<Iterator<Item = (T, T)> + 'a>
+where
+    T: Clone + Default,
+{
+    let len = new.len().max(old.len());
+    Box::new((0..len).map(move |index| {
+        (
+            new.get(index).cloned().unwrap_or_default(),
+            old.get(index).cloned().unwrap_or_default(),
+        )
+    }))
+}
+
+fn delta(new: u64, old: u64) -> i64 {
+    new as i64 - old as i64
+}
+
+/// Difference between two `AllocatorStats` snapshots produced by `AllocatorStats::diff`.
+#[derive(Clone, Debug, Default)]
+pub struct StatsDelta {
+    /// Changes per memory heap.
+    pub heaps: Vec<HeapDelta>,
+
+    /// Changes per memory type.
+    pub types: Vec<TypeDelta>,
+}
+
+impl StatsDelta {
+    /// Check if nothing has changed.
+    pub fn is_empty(&self) -> bool {
+        self.heaps.iter().all(HeapDelta::is_empty) && self.types.iter().all(TypeDelta::is_empty)
+    }
+}
+
+impl fmt::Display for StatsDelta {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(fmt, "No changes");
+        }
+        for (index, heap) in self.heaps.iter().enumerate() {
+            if !heap.is_empty() {
+                writeln!(
+                    fmt,
+                    "Heap {}: used {:+} bytes ({:+} padding), reserved {:+} bytes",
+                    index, heap.used, heap.padding, heap.reserved
+                )?;
+            }
+        }
+        for (index, ty) in self.types.iter().enumerate() {
+            if !ty.is_empty() {
+                writeln!(
+                    fmt,
+                    "Type {}: used {:+} bytes in {:+} blocks, allocated {:+} bytes in {:+} objects",
+                    index, ty.used, ty.blocks, ty.allocated, ty.allocations
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Change of memory heap statistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapDelta {
+    /// Change of bytes used by live blocks.
+    pub used: i64,
+
+    /// Change of bytes held back by reservations.
+    pub reserved: i64,
+
+    /// Change of bytes of live blocks beyond their requested sizes.
+    pub padding: i64,
+}
+
+impl HeapDelta {
+    /// Check if nothing has changed.
+    pub fn is_empty(&self) -> bool {
+        self.used == 0 && self.reserved == 0 && self.padding == 0
+    }
+}
+
+/// Change of memory type statistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TypeDelta {
+    /// Change of bytes used by live blocks.
+    pub used: i64,
+
+    /// Change of number of live blocks.
+    pub blocks: i64,
+
+    /// Change of bytes allocated from the device.
+    pub allocated: i64,
+
+    /// Change of number of memory objects allocated from the device.
+    pub allocations: i64,
+}
+
+impl TypeDelta {
+    /// Check if nothing has changed.
+    pub fn is_empty(&self) -> bool {
+        self.used == 0 && self.blocks == 0 && self.allocated == 0 && self.allocations == 0
+    }
+}
+
+/// Allocations, frees and peak usage recorded by `SmartAllocator::measure`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MeasureReport {
+    /// Number of blocks allocated.
+    pub allocations: usize,
+
+    /// Bytes of blocks allocated, including growth of resized blocks.
+    pub allocated: u64,
+
+    /// Number of blocks freed.
+    pub frees: usize,
+
+    /// Bytes of blocks freed.
+    pub freed: u64,
+
+    /// Peak of bytes used by live blocks above the usage at the start of the measurement.
+    pub peak_used: u64,
+
+    /// Peak of bytes allocated from the device above the amount at the start of the
+    /// measurement.
+    pub peak_device: u64,
+}
+
+impl fmt::Display for MeasureReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "Allocated {} bytes in {} blocks, freed {} bytes in {} blocks",
+            self.allocated, self.allocations, self.freed, self.frees
+        )?;
+        writeln!(
+            fmt,
+            "Peak: {} bytes used, {} bytes allocated from the device",
+            self.peak_used, self.peak_device
+        )
+    }
+}
+
+/// Measurement in progress.
+#[derive(Clone, Debug)]
+pub(crate) struct Measurement {
+    /// Bytes used by live blocks at the start.
+    used: u64,
+    /// Bytes allocated from the device at the start.
+    device: u64,
+    report: MeasureReport,
+}
+
+impl Measurement {
+    pub(crate) fn new(used: u64, device: u64) -> Self {
+        Measurement {
+            used,
+            device,
+            report: MeasureReport::default(),
+        }
+    }
+
+    /// Record allocation or growth of a block.
+    pub(crate) fn alloc(&mut self, size: u64, new: bool) {
+        if new {
+            self.report.allocations += 1;
+        }
+        self.report.allocated += size;
+    }
+
+    /// Record free of a block.
+    pub(crate) fn free(&mut self, size: u64) {
+        self.report.frees += 1;
+        self.report.freed += size;
+    }
+
+    /// Update peaks with the current usage.
+    pub(crate) fn sample(&mut self, used: u64, device: u64) {
+        self.report.peak_used = self.report.peak_used.max(used.saturating_sub(self.used));
+        self.report.peak_device = self.report.peak_device.max(device.saturating_sub(self.device));
+    }
+
+    pub(crate) fn finish(self) -> MeasureReport {
+        self.report
+    }
+}
+
+/// Memory heap statistics.
+#[derive(Clone, Debug, Default)]
+pub struct HeapStats {
+    /// Size of the heap.
+    pub size: u64,
+
+    /// Bytes used by live blocks, maximum across physical devices of the device group.
+    pub used: u64,
+
+    /// Bytes held back by reservations.
+    pub reserved: u64,
+
+    /// Bytes of live blocks beyond their requested sizes, i.e. rounding to size classes and
+    /// alignment. Included in `used`, which is what the availability check of an allocation
+    /// (requested size plus alignment) is compared against.
+    pub padding: u64,
+
+    /// Space set aside for other systems with `SmartAllocator::reserve_system_budget`.
+    pub system_budgets: Vec<SystemBudget>,
+}
+
+impl HeapStats {
+    /// Get total bytes set aside for other systems.
+    pub fn budgeted(&self) -> u64 {
+        self.system_budgets.iter().map(|budget| budget.bytes).sum()
+    }
+}
+
+/// Memory type statistics.
+#[derive(Clone, Debug, Default)]
+pub struct TypeStats {
+    /// Bytes used by live blocks.
+    pub used: u64,
+
+    /// Number of live blocks.
+    pub blocks: usize,
+
+    /// Bytes of live blocks pinned with `SmartAllocator::pin`, included in `used`.
+    pub pinned: u64,
+
+    /// Bytes of live blocks wasted to rounding requested sizes up to block sizes and the
+    /// granularity of the memory type, included in `used`.
+    /// See `SmartAllocator::set_granularity`.
+    pub padding: u64,
+
+    /// Bytes allocated from the device.
+    pub allocated: u64,
+
+    /// Number of memory objects allocated from the device.
+    pub allocations: usize,
+
+    /// Histogram of requests served from the memory type.
+    pub requests: RequestHistogram,
+
+    /// Chunked blocks per size class, ordered by block size.
+    /// Only size classes that were requested so far are included.
+    pub size_classes: Vec<SizeClassStats>,
+
+    /// Usage per resource kind, in order of first use. See `SmartRequest::resource`.
+    pub resources: Vec<ResourceStats>,
+
+    /// Bytes used by live upload blocks, included in `used`. See `SmartRequest::access`.
+    pub upload: u64,
+
+    /// Bytes used by live readback blocks, included in `used`. See `SmartRequest::access`.
+    pub readback: u64,
+}
+
+/// Usage of a memory type by resources of one kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceStats {
+    /// Kind of the resources.
+    pub resource: ResourceKind,
+
+    /// Bytes used by live blocks of the kind.
+    pub used: u64,
+
+    /// Number of live blocks of the kind.
+    pub blocks: usize,
+
+    /// Bytes allocated from the device by pools of the kind.
+    /// Pools shared by all kinds are reported as `ResourceKind::Unspecified`.
+    /// See `SmartAllocator::set_resource_segregation`.
+    pub allocated: u64,
+}
+
+/// Statistics of one size class of `ChunkedAllocator`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeClassStats {
+    /// Size of blocks of the class.
+    pub block_size: u64,
+
+    /// Number of blocks in use.
+    pub blocks: usize,
+
+    /// Number of blocks the allocated chunks can hold.
+    pub capacity: usize,
+
+    /// Number of chunks with blocks in use.
+    pub chunks_used: usize,
+
+    /// Number of chunks without blocks in use, see `ChunkedAllocator::trim`.
+    pub chunks_free: usize,
+
+    /// Bytes of blocks in use wasted to rounding requested sizes up to the block size.
+    pub wasted: u64,
+
+    /// Number of chunks allocated from the owner so far.
+    pub growths: usize,
+
+    /// Largest number of frames any chunk without blocks in use has been unused for.
+    /// See `SmartAllocator::set_cold_eviction`.
+    pub cold_frames: u64,
+}
+
+impl SizeClassStats {
+    /// Add statistics of the same size class of another allocator.
+    pub(crate) fn merge(&mut self, other: &SizeClassStats) {
+        debug_assert_eq!(self.block_size, other.block_size);
+        self.blocks += other.blocks;
+        self.capacity += other.capacity;
+        self.chunks_used += other.chunks_used;
+        self.chunks_free += other.chunks_free;
+        self.wasted += other.wasted;
+        self.growths += other.growths;
+        self.cold_frames = self.cold_frames.max(other.cold_frames);
+    }
+
+    /// Get fraction of blocks of the allocated chunks that are in use.
+    pub fn occupancy(&self) -> f32 {
+        if self.capacity == 0 {
+            0.0
+        } else {
+            self.blocks as f32 / self.capacity as f32
+        }
+    }
+}
+
+/// Histogram of requested sizes and alignments.
+///
+/// Requests of size class `c` are larger than `2^(c-1)` bytes and no larger than `2^c` bytes.
+/// Alignment class `c` stands for alignment of `2^c` bytes.
+#[derive(Clone, Debug, Default)]
+pub struct RequestHistogram {
+    /// Number of `Type::General` requests per size class.
+    pub general_sizes: Vec<u64>,
+
+    /// Number of `Type::ShortLived` requests per size class.
+    pub short_lived_sizes: Vec<u64>,
+
+    /// Number of `Type::Custom` requests of any kind per size class.
+    pub custom_sizes: Vec<u64>,
+
+    /// Number of requests per alignment class.
+    pub alignments: Vec<u64>,
+
+    /// Total bytes requested by `Type::ShortLived` requests.
+    pub short_lived_bytes: u64,
+}
+
+impl RequestHistogram {
+    /// Record a request.
+    pub(crate) fn record(&mut self, ty: Type, reqs: &Requirements) {
+        let sizes = match ty {
+            Type::General => &mut self.general_sizes,
+            Type::ShortLived => {
+                self.short_lived_bytes += reqs.size;
+                &mut self.short_lived_sizes
+            }
+            Type::Custom(_) => &mut self.custom_sizes,
+        };
+        bump(sizes, log2_class(reqs.size.saturating_sub(1)));
+        bump(&mut self.alignments, log2_class(reqs.alignment.saturating_sub(1)));
+    }
+
+    /// Add requests recorded by another histogram.
+    pub(crate) fn merge(&mut self, other: &RequestHistogram) {
+        merge(&mut self.general_sizes, &other.general_sizes);
+        merge(&mut self.short_lived_sizes, &other.short_lived_sizes);
+        merge(&mut self.custom_sizes, &other.custom_sizes);
+        merge(&mut self.alignments, &other.alignments);
+        self.short_lived_bytes += other.short_lived_bytes;
+    }
+}
+
+fn bump(histogram: &mut Vec<u64>, class: u8) {
+    let class = class as usize;
+    if histogram.len() <= class {
+        histogram.resize(class + 1, 0);
+    }
+    histogram[class] += 1;
+}
+
+fn merge(histogram: &mut Vec<u64>, other: &[u64]) {
+    if histogram.len() < other.len() {
+        histogram.resize(other.len(), 0);
+    }
+    for (count, other) in histogram.iter_mut().zip(other) {
+        *count += *other;
+    }
+}
+
+/// Allocation category statistics.
+#[derive(Clone, Debug, Default)]
+pub struct CategoryStats {
+    /// Path of the category, names of nested categories separated by `/`.
+    pub path: String,
+
+    /// Bytes used by live blocks allocated in this category.
+    pub used: u64,
+
+    /// Number of live blocks allocated in this category.
+    pub blocks: usize,
+
+    /// Bytes used by live blocks allocated in this category and all nested categories.
+    pub total_used: u64,
+
+    /// Number of live blocks allocated in this category and all nested categories.
+    pub total_blocks: usize,
+}
+
+/// Histogram of lifetimes of freed blocks of one request type and size class.
+///
+/// Lifetimes are measured in frames marked with `SmartAllocator::mark_frame`.
+#[derive(Clone, Debug)]
+pub struct LifetimeStats {
+    /// Type of the request the blocks were allocated with.
+    pub ty: Type,
+
+    /// Size class of the blocks.
+    /// Blocks of class `c` are larger than `2^(c-1)` bytes and no larger than `2^c` bytes.
+    pub size_class: u8,
+
+    /// Number of freed blocks per lifetime bucket.
+    /// Bucket `0` counts blocks freed in the same frame, bucket `i` counts blocks that
+    /// lived at least `2^(i-1)` and less than `2^i` frames.
+    pub lifetimes: Vec<u64>,
+}
+
+impl LifetimeStats {
+    /// Get total number of freed blocks.
+    pub fn count(&self) -> u64 {
+        self.lifetimes.iter().sum()
+    }
+}
+
+/// Get class of the value, the number of bits required to represent it.
+pub(crate) fn log2_class(value: u64) -> u8 {
+    (64 - value.leading_zeros()) as u8
+}
@@ -0,0 +1,161 @@
+//! Fixtures for testing code that uses the allocators without a GPU,
+//! i.e. against `gfx-backend-empty`.
+
+use gfx_hal::{Backend, MemoryProperties, MemoryType, MemoryTypeId};
+use gfx_hal::memory::{Properties, Requirements};
+
+use combined::CombinedAllocator;
+use config::SmartAllocatorConfig;
+use host::HostAllocator;
+use smart::SmartAllocator;
+
+/// Size of the device-local heap of `memory_properties`.
+pub const DEVICE_HEAP_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Size of the host heap of `memory_properties`.
+pub const HOST_HEAP_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Size of the only heap of `uma_memory_properties`.
+pub const UMA_HEAP_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Size of the only heap of `mobile_memory_properties`.
+pub const MOBILE_HEAP_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Memory properties of a typical discrete GPU.
+///
+/// Heap 0 is device-local with memory type 0 (device-local) and memory type 2 (device-local,
+/// host-visible). Heap 1 is host memory with memory type 1 (host-visible, coherent, cached).
+pub fn memory_properties() -> MemoryProperties {
+    MemoryProperties {
+        memory_types: vec![
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL,
+                heap_index: 0,
+            },
+            MemoryType {
+                properties: Properties::CPU_VISIBLE | Properties::COHERENT | Properties::CPU_CACHED,
+                heap_index: 1,
+            },
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE
+                    | Properties::COHERENT,
+                heap_index: 0,
+            },
+        ],
+        memory_heaps: vec![DEVICE_HEAP_SIZE, HOST_HEAP_SIZE],
+    }
+}
+
+/// Memory properties of a typical integrated GPU sharing memory with the host.
+///
+/// Heap 0 is device-local with memory type 0 (device-local), memory type 1 (device-local,
+/// host-visible, coherent) and memory type 2 (device-local, host-visible, coherent, cached).
+pub fn uma_memory_properties() -> MemoryProperties {
+    MemoryProperties {
+        memory_types: vec![
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL,
+                heap_index: 0,
+            },
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE
+                    | Properties::COHERENT,
+                heap_index: 0,
+            },
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE
+                    | Properties::COHERENT | Properties::CPU_CACHED,
+                heap_index: 0,
+            },
+        ],
+        memory_heaps: vec![UMA_HEAP_SIZE],
+    }
+}
+
+/// Memory properties of a typical mobile GPU, where cached memory is not coherent.
+///
+/// Heap 0 is device-local with memory type 0 (device-local), memory type 1 (device-local,
+/// host-visible, coherent) and memory type 2 (device-local, host-visible, cached).
+pub fn mobile_memory_properties() -> MemoryProperties {
+    MemoryProperties {
+        memory_types: vec![
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL,
+                heap_index: 0,
+            },
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE
+                    | Properties::COHERENT,
+                heap_index: 0,
+            },
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE
+                    | Properties::CPU_CACHED,
+                heap_index: 0,
+            },
+        ],
+        memory_heaps: vec![MOBILE_HEAP_SIZE],
+    }
+}
+
+/// Small configuration that makes sub-allocators grow after few allocations.
+pub fn config() -> SmartAllocatorConfig {
+    SmartAllocatorConfig {
+        arena_size: 64 * 1024,
+        blocks_per_chunk: 4,
+        min_block_size: 64,
+        max_chunk_size: 4 * 1024,
+    }
+}
+
+/// Create an allocator from `memory_properties` and `config`.
+pub fn allocator<B: Backend>() -> SmartAllocator<B> {
+    SmartAllocator::with_config(memory_properties(), config())
+}
+
+/// Create an allocator from `memory_properties` and `config` serving allocations from host
+/// memory, so that it works with backends that can't allocate device memory.
+/// See `SmartAllocator::set_host_memory`.
+pub fn host_allocator<B>() -> SmartAllocator<B>
+where
+    B: Backend,
+    B::Memory: Default,
+{
+    let mut allocator = allocator();
+    allocator.set_host_memory(true);
+    allocator
+}
+
+/// Create a combined allocator of memory type 0 from `config` serving allocations from host
+/// memory. See `CombinedAllocator::set_host`.
+pub fn combined_allocator<B>() -> CombinedAllocator<B>
+where
+    B: Backend,
+    B::Memory: Default,
+{
+    let config = config();
+    let mut allocator = CombinedAllocator::new(
+        MemoryTypeId(0),
+        config.arena_size,
+        config.blocks_per_chunk,
+        config.min_block_size,
+        config.max_chunk_size,
+    );
+    allocator.set_host(Some(HostAllocator::new(MemoryTypeId(0))));
+    allocator
+}
+
+/// Requirements compatible with all memory types of `memory_properties`,
+/// `uma_memory_properties` and `mobile_memory_properties`.
+///
+/// ### Parameters:
+///
+/// - `size`: size of the block
+/// - `alignment`: alignment of the block, must be a power of two
+pub fn requirements(size: u64, alignment: u64) -> Requirements {
+    Requirements {
+        size,
+        alignment,
+        type_mask: 0b111,
+    }
+}
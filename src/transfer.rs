@@ -0,0 +1,275 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::mem::replace;
+
+use gfx_hal::{Backend, Device, Transfer};
+use gfx_hal::buffer::Usage;
+use gfx_hal::command::{BufferCopy, Submit};
+use gfx_hal::memory::Properties;
+use gfx_hal::pool::CommandPool;
+use gfx_hal::pso::PipelineStage;
+use gfx_hal::queue::{CommandQueue, Submission};
+
+use block::Block;
+use combined::Type;
+use factory::{Factory, FactoryError, Item};
+use mapping::{as_bytes, write_bytes, Pod};
+use request::HostAccess;
+use smart::{SmartAllocator, SmartBlock, SmartRequest};
+
+type StagingBuffer<B> = Item<<B as Backend>::Buffer, SmartBlock<B>>;
+
+/// Size of the smallest staging buffer.
+const MIN_STAGING_SIZE: u64 = 256;
+
+/// Schedules uploads of host data to device-local buffers on a transfer queue.
+///
+/// Data is written to pooled staging buffers allocated from the `SmartAllocator`, copy
+/// commands are recorded into command buffers of the owned pool and submitted with `flush`.
+/// Staging buffers are recycled by `maintain` once the device is done with the submission.
+///
+/// Staging buffers are created with sizes rounded up to powers of two and recycled ones are
+/// kept bucketed by size, so an upload reuses a buffer of its bucket before a new one is
+/// allocated. Steady-state streaming doesn't grow the host-visible footprint this way.
+/// Recycled buffers are freed with `trim`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+pub struct TransferManager<B: Backend> {
+    pool: CommandPool<B, Transfer>,
+    recorded: Vec<Submit<B, Transfer>>,
+    staging: Vec<StagingBuffer<B>>,
+    /// Staging buffers of submitted uploads tagged with the submission index.
+    in_flight: VecDeque<(u64, Vec<StagingBuffer<B>>)>,
+    /// Recycled staging buffers bucketed by size.
+    free: BTreeMap<u64, Vec<StagingBuffer<B>>>,
+    submitted: u64,
+}
+
+impl<B> TransferManager<B>
+where
+    B: Backend,
+{
+    /// Create a transfer manager.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pool`: command pool of the transfer queue family, must be resettable as a whole
+    pub fn new(pool: CommandPool<B, Transfer>) -> Self {
+        TransferManager {
+            pool,
+            recorded: Vec::new(),
+            staging: Vec::new(),
+            in_flight: VecDeque::new(),
+            free: BTreeMap::new(),
+            submitted: 0,
+        }
+    }
+
+    /// Get number of uploads recorded but not submitted yet.
+    pub fn pending(&self) -> usize {
+        self.recorded.len()
+    }
+
+    /// Get number of submissions the device may be still working on.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Get number and total size of recycled staging buffers.
+    pub fn recycled(&self) -> (usize, u64) {
+        self.free.iter().fold((0, 0), |(count, bytes), (&size, buffers)| {
+            (count + buffers.len(), bytes + size * buffers.len() as u64)
+        })
+    }
+
+    /// Write data to a staging buffer and record copy to the buffer.
+    /// Empty data is not uploaded.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to create the staging buffer on
+    /// - `allocator`: allocator to allocate the staging buffer from
+    /// - `buffer`: destination buffer, created with `Usage::TRANSFER_DST`
+    /// - `offset`: offset in the destination buffer
+    /// - `data`: data to upload
+    pub fn upload_buffer<T: Pod>(
+        &mut self,
+        device: &B::Device,
+        allocator: &mut SmartAllocator<B>,
+        buffer: &B::Buffer,
+        offset: u64,
+        data: &[T],
+    ) -> Result<(), FactoryError> {
+        let bytes = as_bytes(data);
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let size = bytes.len() as u64;
+        let bucket = size.next_power_of_two().max(MIN_STAGING_SIZE);
+        let recycled = self.free.get_mut(&bucket).and_then(|buffers| buffers.pop());
+        let mut staging = match recycled {
+            Some(staging) => staging,
+            None => {
+                let request = SmartRequest {
+                    access: HostAccess::Upload,
+                    ..SmartRequest::new(Type::General, Properties::CPU_VISIBLE)
+                };
+                <SmartAllocator<B> as Factory<B>>::create_buffer(
+                    allocator,
+                    device,
+                    request,
+                    bucket,
+                    Usage::TRANSFER_SRC,
+                )?
+            }
+        };
+        let coherence = allocator.coherence(staging.block());
+        if let Err(error) = write_bytes::<B, _>(device, &mut staging, bytes, coherence) {
+            <SmartAllocator<B> as Factory<B>>::destroy_buffer(allocator, device, staging);
+            return Err(error.into());
+        }
+
+        let submit = {
+            let mut cbuf = self.pool.acquire_command_buffer(false);
+            cbuf.copy_buffer(
+                staging.borrow(),
+                buffer,
+                Some(BufferCopy {
+                    src: 0,
+                    dst: offset,
+                    size,
+                }),
+            );
+            cbuf.finish()
+        };
+        self.recorded.push(submit);
+        self.staging.push(staging);
+        Ok(())
+    }
+
+    /// Submit recorded uploads.
+    ///
+    /// Nothing is submitted if there are no uploads, semaphores or fence, the index of the last
+    /// submission is returned then.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `queue`: transfer queue of the pool family
+    /// - `wait`: semaphores to wait on before the copies
+    /// - `signal`: semaphores to signal when the copies are complete, i.e. for queues that use
+    ///   the uploaded buffers
+    /// - `fence`: fence to signal when the copies are complete
+    ///
+    /// ### Returns
+    ///
+    /// Returns index of the submission to pass to `maintain` once the fence is signalled.
+    pub fn flush(
+        &mut self,
+        queue: &mut CommandQueue<B, Transfer>,
+        wait: &[(&B::Semaphore, PipelineStage)],
+        signal: &[&B::Semaphore],
+        fence: Option<&B::Fence>,
+    ) -> u64 {
+        if self.recorded.is_empty() && wait.is_empty() && signal.is_empty() && fence.is_none() {
+            return self.submitted;
+        }
+        self.submitted += 1;
+        let submission = Submission::new()
+            .wait_on(wait)
+            .signal(signal)
+            .submit(self.recorded.drain(..));
+        queue.submit(submission, fence);
+        let staging = replace(&mut self.staging, Vec::new());
+        self.in_flight.push_back((self.submitted, staging));
+        self.submitted
+    }
+
+    /// Recycle staging buffers of completed submissions.
+    ///
+    /// Command buffers are reclaimed by resetting the pool, which is only done once no
+    /// submission is in flight and no uploads are pending. Wait for the device to catch up
+    /// from time to time when streaming continuously, or the pool keeps growing.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `completed`: index of the last submission the device is done with, returned by `flush`
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of staging buffers recycled.
+    pub fn maintain(&mut self, completed: u64) -> usize {
+        let mut recycled = 0;
+        while self.in_flight
+            .front()
+            .map_or(false, |&(index, _)| index <= completed)
+        {
+            let (_, staging) = self.in_flight.pop_front().expect("Checked above");
+            for buffer in staging {
+                let size = <StagingBuffer<B> as Block<B>>::size(&buffer);
+                self.free.entry(size).or_insert_with(Vec::new).push(buffer);
+                recycled += 1;
+            }
+        }
+        if self.in_flight.is_empty() && self.recorded.is_empty() {
+            // One-shot command buffers are reclaimed by resetting the pool.
+            self.pool.reset();
+        }
+        recycled
+    }
+
+    /// Free recycled staging buffers.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the staging buffers were created on
+    /// - `allocator`: allocator the staging buffers were allocated from
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of staging buffers freed.
+    pub fn trim(&mut self, device: &B::Device, allocator: &mut SmartAllocator<B>) -> usize {
+        let mut freed = 0;
+        for buffer in replace(&mut self.free, BTreeMap::new())
+            .into_iter()
+            .flat_map(|(_, buffers)| buffers)
+        {
+            <SmartAllocator<B> as Factory<B>>::destroy_buffer(allocator, device, buffer);
+            freed += 1;
+        }
+        freed
+    }
+
+    /// Dispose of the manager, freeing all staging buffers and destroying the pool.
+    /// The device must be done with all submissions.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the pool and staging buffers were created on
+    /// - `allocator`: allocator the staging buffers were allocated from
+    pub fn dispose(mut self, device: &B::Device, allocator: &mut SmartAllocator<B>) {
+        self.trim(device, allocator);
+        let in_flight = self.in_flight.drain(..).flat_map(|(_, staging)| staging);
+        for buffer in in_flight.chain(self.staging.drain(..)) {
+            <SmartAllocator<B> as Factory<B>>::destroy_buffer(allocator, device, buffer);
+        }
+        self.recorded.clear();
+        device.destroy_command_pool(self.pool.into_raw());
+    }
+}
+
+impl<B> fmt::Debug for TransferManager<B>
+where
+    B: Backend,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TransferManager")
+            .field("pending", &self.recorded.len())
+            .field("in_flight", &self.in_flight.len())
+            .field("recycled", &self.recycled())
+            .field("submitted", &self.submitted)
+            .finish()
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use stats::AllocatorStats;
+
+/// Per-frame heap usage samples, used to forecast heap exhaustion.
+///
+/// Push a snapshot of `SmartAllocator::stats` every frame and check `report` to throttle
+/// streaming before allocations start failing.
+#[derive(Clone, Debug)]
+pub struct UsageHistory {
+    window: usize,
+    sizes: Vec<u64>,
+    /// Bytes used, reserved or budgeted per heap, oldest sample first.
+    samples: VecDeque<Vec<u64>>,
+}
+
+impl UsageHistory {
+    /// Create empty history.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `window`: number of most recent samples the trend is computed from
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `window` is less than 2.
+    pub fn new(window: usize) -> Self {
+        assert!(window >= 2);
+        UsageHistory {
+            window,
+            sizes: Vec::new(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Get number of samples in the window.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Check if no samples were pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Record usage of the frame. Drops the oldest sample if the window is full.
+    pub fn push(&mut self, stats: &AllocatorStats) {
+        self.sizes = stats.heaps.iter().map(|heap| heap.size).collect();
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(
+            stats
+                .heaps
+                .iter()
+                .map(|heap| heap.used + heap.reserved + heap.budgeted())
+                .collect(),
+        );
+    }
+
+    /// Compute trend of every heap from samples in the window.
+    ///
+    /// Growth rate is the slope of the least-squares line fitted through the samples.
+    pub fn report(&self) -> TrendReport {
+        TrendReport {
+            heaps: self.sizes
+                .iter()
+                .enumerate()
+                .map(|(index, &size)| {
+                    let used = self.samples
+                        .back()
+                        .and_then(|sample| sample.get(index))
+                        .cloned()
+                        .unwrap_or(0);
+                    let growth = self.growth(index);
+                    let frames_left = if growth > 0.0 {
+                        Some((size.saturating_sub(used) as f64 / growth) as u64)
+                    } else {
+                        None
+                    };
+                    HeapTrend {
+                        size,
+                        used,
+                        growth,
+                        frames_left,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn growth(&self, index: usize) -> f64 {
+        let count = self.samples.len();
+        if count < 2 {
+            return 0.0;
+        }
+        let mean_x = (count - 1) as f64 / 2.0;
+        let values = self.samples
+            .iter()
+            .map(|sample| sample.get(index).cloned().unwrap_or(0) as f64);
+        let mean_y = values.clone().sum::<f64>() / count as f64;
+        let (covariance, variance) =
+            values
+                .enumerate()
+                .fold((0.0, 0.0), |(covariance, variance), (x, y)| {
+                    let dx = x as f64 - mean_x;
+                    (covariance + dx * (y - mean_y), variance + dx * dx)
+                });
+        covariance / variance
+    }
+}
+
+/// Usage trends per heap computed by `UsageHistory::report`.
+#[derive(Clone, Debug, Default)]
+pub struct TrendReport {
+    /// Trend per memory heap.
+    pub heaps: Vec<HeapTrend>,
+}
+
+impl TrendReport {
+    /// Get the least number of frames until any heap is exhausted, if usage of any heap grows.
+    pub fn frames_left(&self) -> Option<u64> {
+        self.heaps.iter().filter_map(|heap| heap.frames_left).min()
+    }
+}
+
+impl fmt::Display for TrendReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (index, heap) in self.heaps.iter().enumerate() {
+            write!(
+                fmt,
+                "Heap {}: {} of {} bytes used, {:+.0} bytes per frame",
+                index, heap.used, heap.size, heap.growth
+            )?;
+            match heap.frames_left {
+                Some(frames) => writeln!(fmt, ", exhausted in {} frames", frames)?,
+                None => writeln!(fmt, "")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Usage trend of a memory heap.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeapTrend {
+    /// Size of the heap.
+    pub size: u64,
+
+    /// Bytes used, reserved or budgeted in the latest sample.
+    pub used: u64,
+
+    /// Average change of used bytes per frame, negative if usage shrinks.
+    pub growth: f64,
+
+    /// Estimated number of frames until the heap is full, `None` if usage doesn't grow.
+    pub frames_left: Option<u64>,
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Memory usage of `SmartAllocator` that can be read from any thread.
+///
+/// Counters are updated by the allocator on each allocation and free, reading them doesn't
+/// require locking or borrowing the allocator. Values are stored as `usize`, so they are
+/// truncated on 32-bit platforms if they exceed 4 GiB.
+#[derive(Debug)]
+pub struct MemoryUsage {
+    heaps: Vec<AtomicUsize>,
+    types: Vec<TypeUsage>,
+}
+
+#[derive(Debug)]
+struct TypeUsage {
+    used: AtomicUsize,
+    blocks: AtomicUsize,
+}
+
+impl MemoryUsage {
+    pub(crate) fn new(heaps: usize, types: usize) -> Self {
+        MemoryUsage {
+            heaps: (0..heaps).map(|_| AtomicUsize::new(0)).collect(),
+            types: (0..types)
+                .map(|_| TypeUsage {
+                    used: AtomicUsize::new(0),
+                    blocks: AtomicUsize::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    /// Get number of memory heaps.
+    pub fn heaps(&self) -> usize {
+        self.heaps.len()
+    }
+
+    /// Get number of memory types.
+    pub fn types(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Get bytes used by live blocks in the heap, maximum across physical devices of the
+    /// device group.
+    pub fn heap_used(&self, heap: usize) -> u64 {
+        self.heaps[heap].load(Ordering::Relaxed) as u64
+    }
+
+    /// Get bytes used by live blocks of the memory type.
+    pub fn type_used(&self, memory_type: usize) -> u64 {
+        self.types[memory_type].used.load(Ordering::Relaxed) as u64
+    }
+
+    /// Get number of live blocks of the memory type.
+    pub fn type_blocks(&self, memory_type: usize) -> usize {
+        self.types[memory_type].blocks.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_heap(&self, heap: usize, used: u64) {
+        self.heaps[heap].store(used as usize, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_type(&self, memory_type: usize, used: u64, blocks: usize) {
+        let usage = &self.types[memory_type];
+        usage.used.store(used as usize, Ordering::Relaxed);
+        usage.blocks.store(blocks, Ordering::Relaxed);
+    }
+}
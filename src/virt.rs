@@ -0,0 +1,170 @@
+use std::ops::Range;
+
+use MemoryError;
+use sparse::FreeRanges;
+
+/// Allocator that manages offsets within a range of user-declared size without any `Device`.
+///
+/// Can be used to manage space inside a single big buffer, a descriptor heap or any other
+/// linear resource. Uses first-fit free-list, adjacent free ranges are merged on `free`.
+#[derive(Clone, Debug)]
+pub struct VirtualAllocator {
+    size: u64,
+    used: u64,
+    allocations: usize,
+    free: FreeRanges,
+}
+
+impl VirtualAllocator {
+    /// Create a new virtual allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `size`: size of the managed range
+    pub fn new(size: u64) -> Self {
+        VirtualAllocator {
+            size,
+            used: 0,
+            allocations: 0,
+            free: FreeRanges::new(0..size),
+        }
+    }
+
+    /// Create a new virtual allocator that manages offsets in the range only.
+    ///
+    /// Unlike `new` with the range size, offsets are aligned relative to zero, not to the
+    /// range start.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `range`: the managed range
+    pub fn with_range(range: Range<u64>) -> Self {
+        VirtualAllocator {
+            size: range.end,
+            used: 0,
+            allocations: 0,
+            free: FreeRanges::new(range),
+        }
+    }
+
+    /// Get end of the managed range, which is its size unless created with `with_range`.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get number of bytes currently allocated.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Get number of live allocations.
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    /// Get size of the largest free range.
+    pub fn largest_free(&self) -> u64 {
+        self.free.largest()
+    }
+
+    /// Check if any of the ranges allocated by this allocator are still in use.
+    pub fn is_used(&self) -> bool {
+        self.allocations != 0
+    }
+
+    /// Allocate a range.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `size`: size of the range
+    /// - `alignment`: alignment of the range start, `0` and `1` mean no alignment
+    ///
+    /// ### Returns
+    ///
+    /// Returns allocated range. If there is no free range large enough `MemoryError::OutOfMemory`
+    /// is returned.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn alloc(&mut self, size: u64, alignment: u64) -> Result<Range<u64>, MemoryError> {
+        assert_ne!(size, 0);
+        let range = match self.free.take(size, alignment) {
+            Some(range) => range,
+            None => return Err(MemoryError::OutOfMemory),
+        };
+        self.used += size;
+        self.allocations += 1;
+        Ok(range)
+    }
+
+    /// Extend an allocated range in place, taking the free space right after it.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `range`: range allocated from this allocator
+    /// - `size`: new size of the range, not less than the current one
+    ///
+    /// ### Returns
+    ///
+    /// Returns the extended range, `MemoryError::InvalidRange` if the range is empty or out of
+    /// the managed range, or `MemoryError::OutOfMemory` if the space after the range is not
+    /// free. In both cases the allocator is left unchanged.
+    pub fn grow(&mut self, range: Range<u64>, size: u64) -> Result<Range<u64>, MemoryError> {
+        if range.start >= range.end || range.end > self.size
+            || size < range.end - range.start
+        {
+            return Err(MemoryError::InvalidRange);
+        }
+        let end = range.start + size;
+        if end == range.end {
+            return Ok(range);
+        }
+        if !self.free.take_at(range.end, end) {
+            return Err(MemoryError::OutOfMemory);
+        }
+        self.used += end - range.end;
+        Ok(range.start..end)
+    }
+
+    /// Free a range.
+    ///
+    /// The range must be allocated from this allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `range`: range to free
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the range can't be freed. See `try_free`.
+    pub fn free(&mut self, range: Range<u64>) {
+        if let Err(error) = self.try_free(range) {
+            panic!("Failed to free range: {}", error);
+        }
+    }
+
+    /// Free a range, checking that it is not free already.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `range`: range to free
+    ///
+    /// ### Returns
+    ///
+    /// Returns `MemoryError::InvalidRange` if the range is empty or out of the managed range,
+    /// or `MemoryError::InvalidFree` if the range overlaps free ranges, i.e. is freed twice.
+    /// In both cases the allocator is left unchanged.
+    pub fn try_free(&mut self, range: Range<u64>) -> Result<(), MemoryError> {
+        if range.start >= range.end || range.end > self.size {
+            return Err(MemoryError::InvalidRange);
+        }
+        let size = range.end - range.start;
+        if !self.free.give(range) {
+            return Err(MemoryError::InvalidFree);
+        }
+        self.used -= size;
+        self.allocations -= 1;
+        Ok(())
+    }
+}
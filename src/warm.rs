@@ -0,0 +1,193 @@
+use std::fmt;
+
+use request::{HostAccess, Lifetime, ResourceKind};
+
+/// Shape of warmed pools: number of chunks per size class of every pool.
+///
+/// Recorded with `SmartAllocator::warm_state` at shutdown and restored with
+/// `SmartAllocator::warm` at the next startup, so that the second run reaches its
+/// steady-state memory layout immediately instead of growing size classes one chunk at a time.
+/// Saved with `to_string` and loaded with `parse`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WarmState {
+    entries: Vec<WarmEntry>,
+}
+
+/// Version of the format written by `WarmState::to_string`.
+const VERSION: u32 = 2;
+
+/// Largest number of chunks of a size class `WarmState::parse` accepts, so that a corrupted
+/// state can't request unbounded pre-allocation.
+const MAX_CHUNKS: usize = 1 << 16;
+
+/// Get the first line of the format.
+fn version_line() -> String {
+    format!("warm {}", VERSION)
+}
+
+/// Number of chunks of a size class of a pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct WarmEntry {
+    pub(crate) memory_type: usize,
+    pub(crate) priority: u8,
+    pub(crate) lifetime: Lifetime,
+    pub(crate) device_mask: u32,
+    pub(crate) resource: ResourceKind,
+    pub(crate) access: HostAccess,
+    pub(crate) block_size: u64,
+    pub(crate) chunks: usize,
+}
+
+pub(crate) fn resource_code(resource: ResourceKind) -> u8 {
+    match resource {
+        ResourceKind::Unspecified => 0,
+        ResourceKind::Buffer => 1,
+        ResourceKind::LinearImage => 2,
+        ResourceKind::OptimalImage => 3,
+    }
+}
+
+pub(crate) fn resource_from_code(code: u8) -> Option<ResourceKind> {
+    match code {
+        0 => Some(ResourceKind::Unspecified),
+        1 => Some(ResourceKind::Buffer),
+        2 => Some(ResourceKind::LinearImage),
+        3 => Some(ResourceKind::OptimalImage),
+        _ => None,
+    }
+}
+
+pub(crate) fn access_code(access: HostAccess) -> u8 {
+    match access {
+        HostAccess::Unspecified => 0,
+        HostAccess::Upload => 1,
+        HostAccess::Readback => 2,
+    }
+}
+
+pub(crate) fn access_from_code(code: u8) -> Option<HostAccess> {
+    match code {
+        0 => Some(HostAccess::Unspecified),
+        1 => Some(HostAccess::Upload),
+        2 => Some(HostAccess::Readback),
+        _ => None,
+    }
+}
+
+pub(crate) fn lifetime_code(lifetime: Lifetime) -> u8 {
+    match lifetime {
+        Lifetime::Unspecified => 0,
+        Lifetime::Persistent => 1,
+        Lifetime::Streaming => 2,
+    }
+}
+
+pub(crate) fn lifetime_from_code(code: u8) -> Option<Lifetime> {
+    match code {
+        0 => Some(Lifetime::Unspecified),
+        1 => Some(Lifetime::Persistent),
+        2 => Some(Lifetime::Streaming),
+        _ => None,
+    }
+}
+
+/// Parse a line of `WarmState::to_string`, parsing every field as its own type so that
+/// out-of-range values are rejected instead of wrapping.
+fn parse_entry(line: &str) -> Option<WarmEntry> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    if fields.len() != 8 {
+        return None;
+    }
+    let chunks = fields[7].parse::<usize>().ok()?;
+    if chunks > MAX_CHUNKS {
+        return None;
+    }
+    Some(WarmEntry {
+        memory_type: fields[0].parse().ok()?,
+        priority: fields[1].parse().ok()?,
+        lifetime: lifetime_from_code(fields[2].parse().ok()?)?,
+        device_mask: fields[3].parse().ok()?,
+        resource: resource_from_code(fields[4].parse().ok()?)?,
+        access: access_from_code(fields[5].parse().ok()?)?,
+        block_size: fields[6].parse().ok()?,
+        chunks,
+    })
+}
+
+impl WarmState {
+    /// Create an empty state.
+    pub fn new() -> Self {
+        WarmState::default()
+    }
+
+    /// Load a state saved with `to_string`: a version line, then one pool size class per line,
+    /// as memory type index, priority class, lifetime, device mask, resource kind, host access,
+    /// block size and number of chunks. Empty text is an empty state.
+    ///
+    /// ### Returns
+    ///
+    /// Returns number of the first malformed line, counting from one, if the text is not
+    /// a valid state. States of other versions, fields out of range of their types, and more
+    /// than 65536 chunks per size class are malformed.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut state = WarmState::new();
+        let mut lines = text.lines().enumerate().filter(|&(_, line)| !line.is_empty());
+        match lines.next() {
+            Some((_, line)) if line == version_line() => {}
+            Some((index, _)) => return Err(index + 1),
+            None => return Ok(state),
+        }
+        for (index, line) in lines {
+            match parse_entry(line) {
+                Some(entry) => state.entries.push(entry),
+                None => return Err(index + 1),
+            }
+        }
+        Ok(state)
+    }
+
+    /// Get number of recorded size classes.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no size classes are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get total number of recorded chunks.
+    pub fn chunks(&self) -> usize {
+        self.entries.iter().map(|entry| entry.chunks).sum()
+    }
+
+    pub(crate) fn push(&mut self, entry: WarmEntry) {
+        self.entries.push(entry);
+    }
+
+    pub(crate) fn entries(&self) -> &[WarmEntry] {
+        &self.entries
+    }
+}
+
+/// Write the state in the format read by `WarmState::parse`.
+impl fmt::Display for WarmState {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "{}", version_line())?;
+        for entry in &self.entries {
+            writeln!(
+                fmt,
+                "{} {} {} {} {} {} {} {}",
+                entry.memory_type,
+                entry.priority,
+                lifetime_code(entry.lifetime),
+                entry.device_mask,
+                resource_code(entry.resource),
+                access_code(entry.access),
+                entry.block_size,
+                entry.chunks
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1043 @@
+//! Tests of the allocator stack against `gfx-backend-empty`.
+//!
+//! The empty backend's device doesn't allocate memory, so tests that need memory objects
+//! serve allocations from host memory. See `SmartAllocator::set_host_memory`.
+
+extern crate gfx_backend_empty as empty;
+extern crate gfx_hal;
+extern crate gfx_mem;
+
+use gfx_hal::MemoryTypeId;
+use gfx_hal::memory::Properties;
+use gfx_mem::testing::{allocator, combined_allocator, config, host_allocator, requirements,
+                       DEVICE_HEAP_SIZE};
+use gfx_mem::{ArenaAllocator, Block, Coherence, CombinedAllocator, FreeListAllocator, HostAllocator,
+              Lifetime, MemoryAllocator, MemoryError, MemorySubAllocator, NameIds, PartialConfig,
+              Quirk, ReallocationPlan, ResourceKind, SmartAllocator, SmartAllocatorConfig,
+              SmartRequest, Type, Upload, WarmState};
+
+type Backend = empty::Backend;
+
+fn device_local() -> SmartRequest {
+    SmartRequest::new(Type::General, Properties::DEVICE_LOCAL)
+}
+
+#[test]
+fn no_compatible_memory_type() {
+    let device = empty::Device;
+    let mut allocator = allocator::<Backend>();
+    let mut reqs = requirements(256, 16);
+    reqs.type_mask = 0;
+    match allocator.alloc(&device, device_local(), reqs) {
+        Err(MemoryError::NoCompatibleMemoryType) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn invalid_requirements() {
+    let device = empty::Device;
+    let mut allocator = allocator::<Backend>();
+    match allocator.alloc(&device, device_local(), requirements(0, 16)) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    match allocator.alloc(&device, device_local(), requirements(256, 24)) {
+        Err(MemoryError::InvalidAlignment) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let mut reqs = requirements(256, 16);
+    reqs.type_mask = 1 << 63;
+    match allocator.alloc(&device, device_local(), reqs) {
+        Err(MemoryError::NoCompatibleMemoryType) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn sub_allocators_validate_requirements() {
+    let device = empty::Device;
+    let mut owner = HostAllocator::<Backend>::new(MemoryTypeId(0));
+    let mut arenas = ArenaAllocator::new(1024, MemoryTypeId(0));
+    match arenas.alloc(&mut owner, &device, (), requirements(0, 16)) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let mut free_list = FreeListAllocator::new(1024, MemoryTypeId(0));
+    match free_list.alloc(&mut owner, &device, (), requirements(256, 24)) {
+        Err(MemoryError::InvalidAlignment) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    // Sizes that overflow when rounded up to arenas or pages are out of memory.
+    match arenas.alloc(&mut owner, &device, (), requirements(!0, 16)) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    match free_list.alloc(&mut owner, &device, (), requirements(!0, 16)) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    assert!(!owner.is_used());
+}
+
+#[test]
+#[should_panic]
+fn zero_arena_size() {
+    ArenaAllocator::<()>::new(0, MemoryTypeId(0));
+}
+
+#[test]
+fn out_of_memory() {
+    let device = empty::Device;
+    let mut allocator = allocator::<Backend>();
+    let reqs = requirements(DEVICE_HEAP_SIZE + 1, 16);
+    match allocator.alloc(&device, device_local(), reqs) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    match allocator.alloc(&device, device_local(), requirements(!0, 16)) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    // Host-visible memory types of both heaps are too small.
+    let request = SmartRequest::new(Type::General, Properties::CPU_VISIBLE);
+    match allocator.can_alloc(request, requirements(DEVICE_HEAP_SIZE + 1, 16)) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn estimate_footprint() {
+    let device = empty::Device;
+    let allocator = allocator::<Backend>();
+    let items = vec![(device_local(), requirements(1024, 256)); 16];
+    let footprint = allocator.estimate_footprint(&items).unwrap();
+    assert!(footprint.fits());
+    assert!(footprint.required[0] >= 16 * 1024);
+    assert_eq!(footprint.required[1], 0);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn dispose_unused() {
+    let device = empty::Device;
+    let allocator = allocator::<Backend>();
+    let stats = allocator.stats();
+    assert!(stats.heaps.iter().all(|heap| heap.used == 0));
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn growth() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let blocks = (0..config().blocks_per_chunk * 4)
+        .map(|_| {
+            allocator
+                .alloc(&device, device_local(), requirements(64, 64))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let stats = allocator.stats();
+    let allocations: usize = stats.types.iter().map(|stats| stats.allocations).sum();
+    let allocated: u64 = stats.types.iter().map(|stats| stats.allocated).sum();
+    assert!(allocations > 1);
+    assert!(allocated >= 64 * blocks.len() as u64);
+    for block in blocks {
+        allocator.free(&device, block);
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn trim() {
+    let device = empty::Device;
+    let config = config();
+    let mut allocator = combined_allocator::<Backend>();
+    let blocks = (0..config.blocks_per_chunk * 2)
+        .map(|_| {
+            allocator
+                .alloc(&device, Type::General, requirements(64, 64))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for block in blocks {
+        assert_eq!(block.size(), 64);
+        allocator.free(&device, block);
+    }
+    assert!(allocator.free_chunks() > 0);
+    let allocated = allocator.allocated();
+    assert!(allocator.trim(&device) > 0);
+    assert_eq!(allocator.free_chunks(), 0);
+    assert!(allocator.allocated() < allocated);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn dispose_in_use() {
+    let device = empty::Device;
+    let mut allocator: SmartAllocator<Backend> = host_allocator();
+    let block = allocator
+        .alloc(&device, device_local(), requirements(1024, 256))
+        .unwrap();
+    let mut allocator = match allocator.try_dispose(&device) {
+        Err((allocator, report)) => {
+            assert!(!report.to_string().is_empty());
+            allocator
+        }
+        Ok(()) => panic!("Disposed with a block in use"),
+    };
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn warm_state_format() {
+    let state = WarmState::parse("warm 2\n0 2 0 0 0 0 256 4\n").unwrap();
+    assert_eq!(state.len(), 1);
+    assert_eq!(state.chunks(), 4);
+    assert_eq!(WarmState::parse(&state.to_string()), Ok(state));
+    assert_eq!(WarmState::parse(""), Ok(WarmState::new()));
+    // Missing version line.
+    assert_eq!(WarmState::parse("0 2 0 0 0 0 256 4\n"), Err(1));
+    // Priority out of range of `u8`.
+    assert_eq!(WarmState::parse("warm 2\n0 256 0 0 0 0 256 4\n"), Err(2));
+    // Too many chunks.
+    assert_eq!(WarmState::parse("warm 2\n0 2 0 0 0 0 256 100000000\n"), Err(2));
+}
+
+#[test]
+fn warm_restores_chunks() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let blocks = (0..config().blocks_per_chunk * 2)
+        .map(|_| {
+            allocator
+                .alloc(&device, device_local(), requirements(64, 64))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let state = allocator.warm_state();
+    assert!(state.chunks() >= 2);
+    for block in blocks {
+        allocator.free(&device, block);
+    }
+    allocator.dispose(&device).unwrap();
+
+    let mut warmed = host_allocator::<Backend>();
+    assert_eq!(warmed.warm(&device, &state).unwrap(), state.chunks());
+    assert_eq!(warmed.warm_state(), state);
+    warmed.dispose(&device).unwrap();
+}
+
+#[test]
+fn reconfigure_rejects_zero() {
+    let device = empty::Device;
+    let mut allocator = allocator::<Backend>();
+    let partial = PartialConfig {
+        arena_size: Some(0),
+        ..PartialConfig::default()
+    };
+    match allocator.reconfigure(partial) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let partial = PartialConfig {
+        blocks_per_chunk: Some(0),
+        ..PartialConfig::default()
+    };
+    match allocator.reconfigure(partial) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    assert_eq!(allocator.config(), config());
+    let partial = PartialConfig {
+        arena_size: Some(config().arena_size * 2),
+        ..PartialConfig::default()
+    };
+    allocator.reconfigure(partial).unwrap();
+    assert_eq!(allocator.config().arena_size, config().arena_size * 2);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn reconfigure_heap_policies() {
+    use gfx_mem::HeapPolicy;
+
+    let device = empty::Device;
+    let mut allocator = allocator::<Backend>();
+    let policy = HeapPolicy {
+        soft_target: Some(DEVICE_HEAP_SIZE / 2),
+        hard_limit: Some(DEVICE_HEAP_SIZE / 2),
+        ..HeapPolicy::default()
+    };
+    let partial = PartialConfig {
+        low_watermark: Some(1),
+        heap_policies: vec![(0, policy)],
+        ..PartialConfig::default()
+    };
+    allocator.reconfigure(partial).unwrap();
+    assert_eq!(allocator.heap_policy(0), policy);
+    match allocator.alloc(&device, device_local(), requirements(DEVICE_HEAP_SIZE / 2 + 1, 16)) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn auto_config_keeps_blocks_within_chunks() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let min = SmartAllocatorConfig {
+        min_block_size: 8 * 1024,
+        ..config()
+    };
+    let max = SmartAllocatorConfig {
+        min_block_size: 16 * 1024,
+        ..config()
+    };
+    allocator.enable_auto_config(min, max);
+    assert!(allocator.config().min_block_size <= allocator.config().max_chunk_size);
+    for _ in 0..4 {
+        let block = allocator
+            .alloc(&device, device_local(), requirements(64, 64))
+            .unwrap();
+        allocator.free(&device, block);
+        allocator.mark_frame();
+        assert!(allocator.config().min_block_size <= allocator.config().max_chunk_size);
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn growths_of_last_allocation() {
+    let device = empty::Device;
+    let config = config();
+    let mut allocator = combined_allocator::<Backend>();
+    let mut blocks = (0..config.blocks_per_chunk * 2)
+        .map(|_| {
+            allocator
+                .alloc(&device, Type::General, requirements(64, 64))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    // Growths of earlier allocations were not taken and are dropped.
+    assert!(allocator.take_growths().is_empty());
+    blocks.push(
+        allocator
+            .alloc(&device, Type::General, requirements(64, 64))
+            .unwrap(),
+    );
+    assert_eq!(allocator.take_growths().len(), 1);
+    for block in blocks {
+        allocator.free(&device, block);
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn reserve_heap_for_resize() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let mut request = device_local();
+    request.dedicated = true;
+    let old = allocator
+        .alloc(&device, request, requirements(1024, 256))
+        .unwrap();
+    let reservation = allocator
+        .reserve_heap_for_resize(&old, requirements(2048, 256))
+        .unwrap();
+    // Bytes of the dedicated old block are not reserved twice.
+    assert_eq!(allocator.reservation_remaining(&reservation), 2048 + 256 - 1024);
+    allocator.free(&device, old);
+    let new = allocator
+        .alloc_reserved(&device, &reservation, request, requirements(2048, 256))
+        .unwrap();
+    allocator.free(&device, new);
+    allocator.release_reservation(reservation);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn reallocation_plan_keeps_requests() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let request = SmartRequest {
+        fallback_properties: Some(Properties::CPU_VISIBLE),
+        priority: Some(0.75),
+        lifetime: Lifetime::Persistent,
+        user_data: 7,
+        resource: ResourceKind::Buffer,
+        ..device_local()
+    };
+    let block = allocator
+        .alloc(&device, request, requirements(256, 16))
+        .unwrap();
+    let plan = allocator.reallocation_plan();
+    assert_eq!(plan.allocations.len(), 1);
+    assert_eq!(plan.allocations[0].request(), request);
+
+    let mut text = Vec::new();
+    plan.write(&mut text).unwrap();
+    assert_eq!(ReallocationPlan::read(&text[..]).unwrap(), plan);
+
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn coherence() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    allocator.set_non_coherent_atom_size(64);
+    let block = allocator
+        .alloc(&device, device_local(), requirements(256, 16))
+        .unwrap();
+    let visible = allocator
+        .alloc(
+            &device,
+            SmartRequest::new(Type::General, Properties::CPU_VISIBLE | Properties::COHERENT),
+            requirements(256, 16),
+        )
+        .unwrap();
+    if allocator.properties(&block).contains(Properties::COHERENT) {
+        assert_eq!(allocator.coherence(&block), Coherence::Coherent);
+    } else {
+        assert_eq!(allocator.coherence(&block), Coherence::NonCoherent { atom: 64 });
+    }
+    assert_eq!(allocator.coherence(&visible), Coherence::Coherent);
+    allocator.free(&device, block);
+    allocator.free(&device, visible);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn empty_upload() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let mut block = allocator
+        .alloc(&device, device_local(), requirements(256, 16))
+        .unwrap();
+    match allocator.upload::<u32>(&device, &mut block, &[]) {
+        Ok(Upload::Done) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    match block.mapped_range(&device, 16..16) {
+        Err(MemoryError::InvalidRange) => {}
+        other => panic!("Unexpected result: {:?}", other.map(|range| range.range())),
+    }
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn drop_unused() {
+    let allocator = combined_allocator::<Backend>();
+    // Nothing is allocated, so nothing is reported.
+    drop(allocator);
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_errors() {
+    use gfx_mem::ffi::{self, FfiAllocator, GfxMemConfig, GfxMemType};
+    use std::ptr;
+
+    assert_eq!(ffi::guard(|| panic!("Panics don't unwind")), ffi::GFX_MEM_ERROR_PANIC);
+    let types = [GfxMemType {
+        properties: Properties::DEVICE_LOCAL.bits() as u32,
+        heap_index: 0,
+    }];
+    let heaps = [DEVICE_HEAP_SIZE];
+    let config = GfxMemConfig {
+        arena_size: 0,
+        blocks_per_chunk: 4,
+        min_block_size: 64,
+        max_chunk_size: 4096,
+    };
+    let mut out: *mut FfiAllocator<Backend> = ptr::null_mut();
+    let code = unsafe {
+        ffi::create::<Backend>(types.as_ptr(), 1, heaps.as_ptr(), 1, &config, &mut out)
+    };
+    assert_eq!(code, ffi::GFX_MEM_ERROR_INVALID_ARGUMENT);
+    assert!(out.is_null());
+}
+
+#[test]
+fn quirks() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let quirks = [Quirk {
+        avoided_types: &[1, 100],
+        dedicated_threshold: Some(1024),
+        ..Quirk::new(0x1234, None)
+    }];
+    assert!(!allocator.apply_quirks(&quirks, 0x4321, 1));
+    assert!(allocator.apply_quirks(&quirks, 0x1234, 1));
+    let blocks = (0..2)
+        .map(|_| {
+            allocator
+                .alloc(&device, device_local(), requirements(2048, 256))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    // Blocks above the threshold get their own memory objects.
+    assert_eq!(allocator.allocation_count(), 2);
+    for block in blocks {
+        allocator.free(&device, block);
+    }
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn name_ids_format() {
+    let mut names = NameIds::new();
+    let id = names.id("shadow\nmap\\0");
+    let parsed = NameIds::parse(&names.to_string()).unwrap();
+    assert_eq!(parsed, names);
+    assert_eq!(parsed.get("shadow\nmap\\0"), Some(id));
+    // The id after the largest one doesn't exist.
+    assert_eq!(NameIds::parse("18446744073709551615 last\n"), Err(1));
+}
+
+#[test]
+fn unlimited_ttl() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    allocator.mark_frame();
+    let request = SmartRequest {
+        ttl: Some(!0),
+        ..device_local()
+    };
+    let block = allocator
+        .alloc(&device, request, requirements(256, 16))
+        .unwrap();
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn fallback_pressure() {
+    use gfx_mem::{AllocObserver, HeapPolicy, PressureEvent};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug)]
+    struct Events(Arc<Mutex<Vec<PressureEvent>>>);
+
+    impl AllocObserver for Events {
+        fn on_pressure(&mut self, event: &PressureEvent) {
+            self.0.lock().unwrap().push(*event);
+        }
+    }
+
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    allocator.add_observer(Box::new(Events(events.clone())));
+    allocator.set_heap_policy(
+        0,
+        HeapPolicy {
+            hard_limit: Some(0),
+            ..HeapPolicy::default()
+        },
+    );
+    let request = SmartRequest {
+        fallback_properties: Some(Properties::CPU_VISIBLE),
+        ..device_local()
+    };
+    let block = allocator
+        .alloc(&device, request, requirements(256, 16))
+        .unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![PressureEvent::Fallback {
+            preferred: MemoryTypeId(0),
+            chosen: MemoryTypeId(1),
+        }]
+    );
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn simulate_rejects_invalid_input() {
+    use gfx_mem::{simulate_configs, TraceEvent};
+
+    let alloc = |size| TraceEvent::Alloc {
+        id: 0,
+        ty: Type::ShortLived,
+        reqs: requirements(size, 16),
+    };
+    match simulate_configs(&[alloc(0)], &[config()]) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let zero_blocks = SmartAllocatorConfig {
+        min_block_size: 0,
+        ..config()
+    };
+    match simulate_configs(&[alloc(256)], &[zero_blocks]) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let reports = simulate_configs(&[alloc(256), TraceEvent::Free { id: 0 }], &[config()]);
+    assert_eq!(reports.unwrap()[0].peak_used, 256);
+}
+
+#[test]
+fn virtual_ranges() {
+    use gfx_mem::VirtualAllocator;
+
+    let mut allocator = VirtualAllocator::new(1024);
+    let first = allocator.alloc(100, 1).unwrap();
+    let second = allocator.alloc(100, 256).unwrap();
+    assert_eq!(first, 0..100);
+    assert_eq!(second, 256..356);
+    assert_eq!(allocator.largest_free(), 668);
+    assert_eq!(allocator.grow(second.clone(), 200).unwrap(), 256..456);
+    match allocator.try_free(50..150) {
+        Err(MemoryError::InvalidFree) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    allocator.free(first);
+    allocator.free(256..456);
+    assert!(!allocator.is_used());
+    assert_eq!(allocator.used(), 0);
+    assert_eq!(allocator.largest_free(), 1024);
+}
+
+#[test]
+fn default_try_free() {
+    use gfx_hal::memory::Requirements;
+    use gfx_mem::RawBlock;
+
+    /// Allocator implementing only `free`, as allocators written before `try_free` do.
+    #[derive(Debug)]
+    struct Counting(HostAllocator<Backend>, usize);
+
+    impl MemoryAllocator<Backend> for Counting {
+        type Request = ();
+        type Block = RawBlock<Backend>;
+
+        fn alloc(
+            &mut self,
+            device: &empty::Device,
+            request: (),
+            reqs: Requirements,
+        ) -> Result<RawBlock<Backend>, MemoryError> {
+            self.0.alloc(device, request, reqs)
+        }
+
+        fn free(&mut self, device: &empty::Device, block: RawBlock<Backend>) {
+            self.1 += 1;
+            self.0.free(device, block);
+        }
+
+        fn is_used(&self) -> bool {
+            self.0.is_used()
+        }
+
+        fn dispose(self, device: &empty::Device) -> Result<(), Self> {
+            let Counting(host, freed) = self;
+            host.dispose(device).map_err(|host| Counting(host, freed))
+        }
+    }
+
+    let device = empty::Device;
+    let mut allocator = Counting(HostAllocator::new(MemoryTypeId(0)), 0);
+    let block = allocator.alloc(&device, (), requirements(256, 16)).unwrap();
+    allocator.try_free(&device, block).unwrap();
+    assert_eq!(allocator.1, 1);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn padding_of_live_blocks() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let block = allocator
+        .alloc(&device, device_local(), requirements(100, 16))
+        .unwrap();
+    let stats = allocator.stats();
+    let heap = &stats.heaps[0];
+    assert_eq!(heap.padding, block.size() - 100);
+    assert_eq!(heap.used, block.size());
+    allocator.free(&device, block);
+    assert_eq!(allocator.stats().heaps[0].padding, 0);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn requests_recorded_once() {
+    use gfx_mem::HeapPolicy;
+
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    allocator.set_heap_policy(
+        0,
+        HeapPolicy {
+            hard_limit: Some(0),
+            ..HeapPolicy::default()
+        },
+    );
+    let request = SmartRequest {
+        ty: Type::ShortLived,
+        fallback_properties: Some(Properties::CPU_VISIBLE),
+        ..device_local()
+    };
+    let block = allocator
+        .alloc(&device, request, requirements(256, 16))
+        .unwrap();
+    let stats = allocator.stats();
+    let recorded: u64 = stats
+        .types
+        .iter()
+        .map(|ty| ty.requests.short_lived_bytes)
+        .sum();
+    assert_eq!(recorded, 256);
+    assert_eq!(stats.types[1].requests.short_lived_bytes, 256);
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn invalid_device_mask() {
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    allocator.set_device_count(2);
+    let request = SmartRequest {
+        device_mask: 0b100,
+        ..device_local()
+    };
+    match allocator.alloc(&device, request, requirements(256, 16)) {
+        Err(MemoryError::InvalidDeviceMask) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    match allocator.can_alloc(request, requirements(256, 16)) {
+        Err(MemoryError::InvalidDeviceMask) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let request = SmartRequest {
+        device_mask: 0b10,
+        ..device_local()
+    };
+    let block = allocator
+        .alloc(&device, request, requirements(256, 16))
+        .unwrap();
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn estimate_footprint_of_empty_requests() {
+    let arenas = ArenaAllocator::<()>::new(1024, MemoryTypeId(0));
+    let reqs = [requirements(0, 1), requirements(512, 256), requirements(0, 1)];
+    assert_eq!(arenas.estimate_footprint(&reqs), 1024);
+    let free_list = FreeListAllocator::<()>::new(4096, MemoryTypeId(0));
+    assert_eq!(free_list.estimate_footprint(&reqs[..1]), 0);
+}
+
+#[test]
+fn allocation_count_warning_rearms() {
+    use gfx_mem::{AllocObserver, PressureEvent};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug)]
+    struct Counts(Arc<Mutex<Vec<usize>>>);
+
+    impl AllocObserver for Counts {
+        fn on_pressure(&mut self, event: &PressureEvent) {
+            if let PressureEvent::AllocationCount { count } = *event {
+                self.0.lock().unwrap().push(count);
+            }
+        }
+    }
+
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let counts = Arc::new(Mutex::new(Vec::new()));
+    allocator.add_observer(Box::new(Counts(counts.clone())));
+    allocator.set_allocation_count_warning(Some(1));
+    let request = SmartRequest {
+        dedicated: true,
+        ..device_local()
+    };
+    for _ in 0..2 {
+        let block = allocator
+            .alloc(&device, request, requirements(256, 16))
+            .unwrap();
+        allocator.free(&device, block);
+        allocator.flush_frees(&device, usize::max_value());
+        assert_eq!(allocator.allocation_count(), 0);
+    }
+    assert_eq!(*counts.lock().unwrap(), vec![1, 1]);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn out_of_order_frees_enabled_later() {
+    let device = empty::Device;
+    let mut owner = HostAllocator::<Backend>::new(MemoryTypeId(0));
+    let mut arenas = ArenaAllocator::new(1024, MemoryTypeId(0));
+    let blocks: Vec<_> = (0..3)
+        .map(|_| {
+            arenas
+                .alloc(&mut owner, &device, (), requirements(1024, 16))
+                .unwrap()
+        })
+        .collect();
+    let mut blocks = blocks.into_iter();
+    let first = blocks.next().unwrap();
+    let second = blocks.next().unwrap();
+    let third = blocks.next().unwrap();
+    // The second arena waits for the first one.
+    arenas.free(&mut owner, &device, second);
+    assert_eq!(owner.live_blocks(), 3);
+    arenas.set_out_of_order_frees(true);
+    arenas.maintain(&mut owner, &device, 0);
+    assert_eq!(owner.live_blocks(), 2);
+    arenas.free(&mut owner, &device, first);
+    arenas.free(&mut owner, &device, third);
+    arenas.dispose(&mut owner, &device).unwrap();
+    assert!(!owner.is_used());
+}
+
+#[test]
+fn cold_blocks_eviction() {
+    use gfx_mem::{AllocObserver, BlockInfo, ColdEviction, HeapPolicy, TrimStep};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug)]
+    struct Evictions(Arc<Mutex<Vec<u64>>>);
+
+    impl AllocObserver for Evictions {
+        fn on_evict(&mut self, info: &BlockInfo) {
+            self.0.lock().unwrap().push(info.id);
+        }
+    }
+
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let evictions = Arc::new(Mutex::new(Vec::new()));
+    allocator.add_observer(Box::new(Evictions(evictions.clone())));
+    allocator.set_cold_eviction(Some(ColdEviction {
+        age: 1,
+        max_chunks: 1,
+    }));
+    allocator.set_trim_order(&[TrimStep::ColdBlocks]);
+    allocator.set_heap_policy(
+        0,
+        HeapPolicy {
+            soft_target: Some(0),
+            ..HeapPolicy::default()
+        },
+    );
+    let block = allocator
+        .alloc(&device, device_local(), requirements(256, 16))
+        .unwrap();
+    let id = allocator.block_info(&block).id;
+    // Blocks allocated in the current frame are not cold yet.
+    allocator.maintain(&device, 0);
+    assert!(evictions.lock().unwrap().is_empty());
+    allocator.mark_frame();
+    allocator.maintain(&device, 0);
+    assert_eq!(*evictions.lock().unwrap(), vec![id]);
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn adopted_requirements() {
+    use gfx_mem::AdoptedAllocator;
+
+    let device = empty::Device;
+    let memory = Default::default();
+    let mut adopted =
+        AdoptedAllocator::<Backend>::adopt(memory, MemoryTypeId(0), 0..4096, 256).unwrap();
+    match adopted.alloc(&device, (), requirements(0, 16)) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let block = adopted.alloc(&device, (), requirements(100, 16)).unwrap();
+    assert_eq!(block.range(), 0..256);
+    adopted.free(&device, block);
+    adopted.release().unwrap();
+}
+
+#[test]
+fn free_list_routing() {
+    use gfx_mem::Routing;
+
+    let device = empty::Device;
+    let config = config();
+    let mut allocator = combined_allocator::<Backend>();
+    // Thresholds are clamped to the chunked allocator and never overflow the page size.
+    allocator.set_routing(Routing {
+        chunked: !0,
+        dedicated: !0,
+    });
+    assert_eq!(allocator.routing().chunked, config.max_chunk_size);
+    allocator.set_routing(Routing {
+        chunked: 1024,
+        dedicated: 16 * 1024,
+    });
+    let block = allocator
+        .alloc(&device, Type::General, requirements(2048, 16))
+        .unwrap();
+    assert_eq!(block.size(), 2048);
+    assert_eq!(allocator.allocated(), 64 * 1024);
+    allocator.free(&device, block);
+    assert_eq!(allocator.allocated(), 0);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn spanning_blocks() {
+    let device = empty::Device;
+    let config = config();
+    let mut allocator = combined_allocator::<Backend>();
+    allocator.set_spanning(true);
+    // Three 64 byte blocks instead of one 256 byte block, from a chunk of four 64 byte blocks.
+    let items = [(Type::General, requirements(160, 16))];
+    assert_eq!(allocator.estimate_footprint(&items), 256);
+    let block = allocator
+        .alloc(&device, Type::General, requirements(160, 16))
+        .unwrap();
+    assert_eq!(block.size(), 192);
+    assert_eq!(allocator.allocated(), 256);
+    allocator.free(&device, block);
+    allocator.dispose(&device).unwrap();
+
+    // Size classes are not powers of two if the smallest one isn't.
+    let mut allocator = CombinedAllocator::<Backend>::new(
+        MemoryTypeId(0),
+        config.arena_size,
+        config.blocks_per_chunk,
+        48,
+        config.max_chunk_size,
+    );
+    allocator.set_host(Some(HostAllocator::new(MemoryTypeId(0))));
+    allocator.set_spanning(true);
+    let block = allocator
+        .alloc(&device, Type::General, requirements(90, 16))
+        .unwrap();
+    assert_eq!(block.size(), 96);
+    allocator
+        .try_free(&device, block)
+        .expect("Blocks of size classes are freed to their class");
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn multi_buffered() {
+    use gfx_mem::MultiBuffered;
+
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    match MultiBuffered::<Backend, u32>::new(&mut allocator, &device, 3, !0, 256, 0b111) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let copies = MultiBuffered::<Backend, u32>::new(&mut allocator, &device, 4, 3, 256, 0b111)
+        .unwrap();
+    assert_eq!(copies.block().size(), 4 * 256);
+    assert_eq!(copies.range(0), 0..12);
+    assert_eq!(copies.range(5), 256..268);
+    copies.dispose(&mut allocator, &device);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn alloc_for_resource() {
+    use gfx_mem::Dedicated;
+
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    let required = allocator
+        .alloc_for_resource(&device, device_local(), requirements(100, 16), Dedicated::Required)
+        .unwrap();
+    assert_eq!(required.size(), 100);
+    assert_eq!(allocator.allocation_count(), 1);
+
+    // Preferred dedicated allocations are sub-allocated once the threshold is reached.
+    allocator.set_allocation_count_warning(Some(1));
+    let preferred = allocator
+        .alloc_for_resource(&device, device_local(), requirements(100, 16), Dedicated::Preferred)
+        .unwrap();
+    assert_eq!(preferred.size(), 128);
+    match allocator.alloc_for_resource(
+        &device,
+        device_local(),
+        requirements(0, 16),
+        Dedicated::Required,
+    ) {
+        Err(MemoryError::ZeroSize) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    allocator.free(&device, required);
+    allocator.free(&device, preferred);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn spare_arenas_of_old_size() {
+    let device = empty::Device;
+    let config = config();
+    let mut allocator = host_allocator::<Backend>();
+    let short_lived = SmartRequest::new(Type::ShortLived, Properties::DEVICE_LOCAL);
+    // The first arena waits for its epoch and becomes spare, the second stays hot.
+    allocator.set_epoch_retirement(true);
+    let blocks = [1024, config.arena_size]
+        .iter()
+        .map(|&size| {
+            allocator
+                .alloc(&device, short_lived, requirements(size, 16))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for block in blocks {
+        allocator.free(&device, block);
+    }
+    assert_eq!(allocator.reset_arenas(&device), 2);
+    allocator
+        .reconfigure(PartialConfig {
+            arena_size: Some(config.arena_size * 2),
+            ..PartialConfig::default()
+        })
+        .unwrap();
+    // The spare arena of the old size doesn't fit the block.
+    let block = allocator
+        .alloc(&device, short_lived, requirements(config.arena_size + 1, 16))
+        .unwrap();
+    assert_eq!(block.size(), config.arena_size + 1);
+    allocator.free(&device, block);
+    allocator.reset_arenas(&device);
+    allocator.dispose(&device).unwrap();
+}
+
+#[test]
+fn granularity_overflow() {
+    use gfx_mem::HeapPolicy;
+
+    let device = empty::Device;
+    let mut allocator = host_allocator::<Backend>();
+    allocator.set_heap_policy(
+        0,
+        HeapPolicy {
+            oversubscribe: true,
+            ..HeapPolicy::default()
+        },
+    );
+    allocator.set_granularity(MemoryTypeId(0), 1024);
+    allocator.set_granularity(MemoryTypeId(2), 1024);
+    match allocator.alloc(&device, device_local(), requirements(!0 - 16, 16)) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    allocator.dispose(&device).unwrap();
+}